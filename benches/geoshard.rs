@@ -0,0 +1,124 @@
+//! Benchmarks of the build and lookup paths, run with `cargo bench --features test-util`. Pass a
+//! name to only run the benchmarks containing it, e.g. `cargo bench --features test-util -- lookup`
+//!
+//! Each benchmark is warmed up, then timed over enough iterations to fill its measurement time,
+//! and reports the time per iteration and the throughput in elements (cells, users or lookups)
+//! per second
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use location_based_sharding::{
+    cell_list::CellList,
+    geoshard::{GeoshardBuilder, GeoshardSearcher},
+    testing::{FakeUser, RandCityFactory},
+};
+use s2::{cellid::CellID, latlng::LatLng, s1};
+
+/// runs the benchmarks whose names contain the filter, if any
+struct Bencher {
+    filter: Option<String>,
+    measurement_time: Duration,
+}
+
+impl Bencher {
+    fn from_args() -> Self {
+        Self {
+            filter: std::env::args().skip(1).find(|arg| !arg.starts_with('-')),
+            measurement_time: Duration::from_secs(2),
+        }
+    }
+
+    /// times `routine`, which processes `elements` elements per iteration
+    fn bench<T>(&self, name: &str, elements: u64, mut routine: impl FnMut() -> T) {
+        if let Some(filter) = &self.filter {
+            if !name.contains(filter.as_str()) {
+                return;
+            }
+        }
+        // warm up, and estimate the iterations that fill the measurement time
+        let start = Instant::now();
+        black_box(routine());
+        let estimate = start.elapsed().max(Duration::from_nanos(1));
+        let iterations =
+            (self.measurement_time.as_nanos() / estimate.as_nanos()).clamp(1, 10_000_000) as u32;
+
+        let start = Instant::now();
+        for _ in 0..iterations {
+            black_box(routine());
+        }
+        let per_iteration = start.elapsed() / iterations;
+        let throughput = elements as f64 / per_iteration.as_secs_f64();
+        println!(
+            "{:<44} {:>14.3?}/iter {:>16.0} elem/s ({} iterations)",
+            name, per_iteration, throughput, iterations
+        );
+    }
+}
+
+fn location(lng: f64, lat: f64) -> LatLng {
+    LatLng {
+        lat: s1::Deg(lat).into(),
+        lng: s1::Deg(lng).into(),
+    }
+}
+
+fn main() {
+    let bencher = Bencher::from_args();
+
+    for storage_level in 6..=10u64 {
+        let cell_count = 6 << (2 * storage_level);
+        bencher.bench(
+            &format!("cell_list_new/level_{}", storage_level),
+            cell_count,
+            || CellList::new(storage_level),
+        );
+    }
+
+    for user_count in [1_000, 10_000, 100_000] {
+        let users = FakeUser::seeded(user_count, 1, &RandCityFactory::default());
+        bencher.bench(
+            &format!("build/level_6/users_{}", user_count),
+            user_count as u64,
+            || GeoshardBuilder::user_count_scorer(6, users.iter(), 40, 100).build(),
+        );
+    }
+
+    let users = FakeUser::seeded(10_000, 2, &RandCityFactory::default());
+    let searcher = GeoshardSearcher::from(
+        GeoshardBuilder::user_count_scorer(8, users.iter(), 40, 100)
+            .with_cached_cells()
+            .build(),
+    );
+    let cell_ids: Vec<CellID> = CellList::cached(8).cell_list().keys().copied().collect();
+    let sample: Vec<CellID> = cell_ids.iter().step_by(97).copied().collect();
+    bencher.bench(
+        "get_shard_from_cell_id/level_8",
+        sample.len() as u64,
+        || {
+            for cell_id in sample.iter() {
+                black_box(searcher.get_shard_from_cell_id(cell_id));
+            }
+        },
+    );
+    let searcher = searcher.with_lookup_table().unwrap();
+    bencher.bench(
+        "get_shard_from_cell_id/level_8/lookup_table",
+        sample.len() as u64,
+        || {
+            for cell_id in sample.iter() {
+                black_box(searcher.get_shard_from_cell_id(cell_id));
+            }
+        },
+    );
+
+    let new_york = location(-74.0060, 40.7128);
+    for radius in [1_000, 100_000, 10_000_000] {
+        bencher.bench(
+            &format!("cell_ids_from_radius/level_8/radius_{}", radius),
+            1,
+            || searcher.cell_ids_from_radius(&new_york, radius),
+        );
+    }
+}
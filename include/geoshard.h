@@ -0,0 +1,36 @@
+/* Shard lookups over the location_based_sharding `ffi` feature, see src/ffi.rs */
+
+#ifndef GEOSHARD_H
+#define GEOSHARD_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An opaque handle to a loaded shard map */
+typedef struct GeoshardHandle GeoshardHandle;
+
+/*
+ * Loads a JSON shard map from `len` bytes at `bytes`. Returns a handle to pass to
+ * `geoshard_lookup` and release with `geoshard_free`, or NULL if the map doesn't parse or verify
+ */
+GeoshardHandle *geoshard_load(const uint8_t *bytes, size_t len);
+
+/*
+ * Returns the name of the shard holding the location, in degrees. The string is owned by the
+ * handle and valid until it is freed. Returns NULL if the handle is NULL, the location is out of
+ * range or no shard holds it
+ */
+const char *geoshard_lookup(const GeoshardHandle *handle, double lat, double lng);
+
+/* Releases a handle returned by `geoshard_load`. NULL handles are ignored */
+void geoshard_free(GeoshardHandle *handle);
+
+#ifdef __cplusplus
+}  /* extern "C" */
+#endif
+
+#endif /* GEOSHARD_H */
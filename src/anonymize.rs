@@ -0,0 +1,171 @@
+#![deny(missing_docs)]
+//! anonymize strips a shard map down to its structure, so it can be attached to a public bug
+//! report without revealing shard names, labels, real scores or, optionally, where its cells are
+use s2::{cellid::CellID, cellunion::CellUnion};
+
+use crate::geoshard::{Geoshard, GeoshardCollection};
+
+/// `AnonymizeOptions` controls how `GeoshardCollection::anonymize` disguises a shard map
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnonymizeOptions {
+    /// seed for the score noise and rotation, so a map always anonymizes the same way
+    pub seed: u64,
+    /// the largest relative change made to each score, e.g. 0.1 for up to 10%. 0 keeps scores
+    pub score_noise: f64,
+    /// moves every cell onto another cube face, picked from the seed. Cells keep their position
+    /// and order within a face, so shards keep their shape and neighbours on the same face
+    pub rotate: bool,
+}
+
+impl GeoshardCollection {
+    /// Returns a copy of this map that is safe to share publicly. Shards are renamed
+    /// `geoshard_user_index_{n}` in order, labels, the fallback map and the score window are
+    /// dropped, and scores are perturbed by up to `score_noise`. The storage level, shard
+    /// count, cells per shard and which cells have no score are kept, so issues with a map's
+    /// structure can still be reproduced from it
+    pub fn anonymize(&self, options: &AnonymizeOptions) -> GeoshardCollection {
+        let face_shift = if options.rotate {
+            1 + splitmix64(options.seed) % 5
+        } else {
+            0
+        };
+        let mut noise_index = 0;
+        let mut perturb = |score: i32| {
+            noise_index += 1;
+            let unit = splitmix64(options.seed ^ noise_index) as f64 / u64::MAX as f64;
+            (score as f64 * (1.0 + options.score_noise * (2.0 * unit - 1.0))).round() as i32
+        };
+
+        let mut shards: Vec<_> = self
+            .shards()
+            .iter()
+            .map(|shard| {
+                let has_scores = shard.cell_scores().len() == shard.cell_count();
+                let scores = shard.cell_scores().iter().copied().map(&mut perturb);
+                let mut cells: Vec<(CellID, i32)> = shard
+                    .cell_union()
+                    .0
+                    .iter()
+                    .map(|cell_id| rotate(cell_id, face_shift))
+                    .zip(scores.chain(std::iter::repeat(0)))
+                    .collect();
+                cells.sort();
+                let score = if has_scores {
+                    cells.iter().map(|(_, score)| score).sum()
+                } else {
+                    perturb(shard.score())
+                };
+                (cells, score, has_scores)
+            })
+            .collect();
+        shards.sort_by_key(|(cells, _, _)| cells.first().map(|(cell_id, _)| *cell_id));
+
+        let shards = shards
+            .into_iter()
+            .enumerate()
+            .map(|(index, (cells, score, has_scores))| {
+                let shard = Geoshard::new(
+                    format!("geoshard_user_index_{}", index + 1),
+                    score,
+                    self.storage_level(),
+                    CellUnion(cells.iter().map(|(cell_id, _)| *cell_id).collect()),
+                );
+                if has_scores {
+                    shard.with_cell_scores(cells.into_iter().map(|(_, score)| score).collect())
+                } else {
+                    shard
+                }
+            })
+            .collect();
+        GeoshardCollection::from_shards(self.storage_level(), shards)
+    }
+}
+
+/// moves the cell `face_shift` faces along, keeping its position within the face
+fn rotate(cell_id: &CellID, face_shift: u64) -> CellID {
+    let face = (cell_id.face() as u64 + face_shift) % 6;
+    CellID((cell_id.0 & !(7 << 61)) | (face << 61))
+}
+
+/// the splitmix64 mixing function, used as a small deterministic random number generator
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::cell_list::CellList;
+
+    #[test]
+    fn test_anonymize() {
+        let mut cell_list = CellList::new(3);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 4) as i32 * 100;
+        }
+        let geoshards = GeoshardCollection::new(5000, cell_list.cell_list(), 3)
+            .with_fallback(GeoshardCollection::new(5000, cell_list.cell_list(), 3));
+        let options = AnonymizeOptions {
+            seed: 7,
+            score_noise: 0.2,
+            rotate: true,
+        };
+        let anonymized = geoshards.anonymize(&options);
+
+        assert!(anonymized.fallback().is_none());
+        assert_eq!(anonymized.storage_level(), 3);
+        assert_eq!(anonymized.shards().len(), geoshards.shards().len());
+        let mut cell_counts: Vec<usize> = geoshards
+            .shards()
+            .iter()
+            .map(Geoshard::cell_count)
+            .collect();
+        let mut anonymized_counts: Vec<usize> = anonymized
+            .shards()
+            .iter()
+            .map(Geoshard::cell_count)
+            .collect();
+        cell_counts.sort();
+        anonymized_counts.sort();
+        assert_eq!(cell_counts, anonymized_counts);
+
+        let cells = |collection: &GeoshardCollection| -> Vec<(CellID, i32)> {
+            let mut cells: Vec<(CellID, i32)> = collection
+                .shards()
+                .iter()
+                .flat_map(|shard| {
+                    shard
+                        .cell_union()
+                        .0
+                        .iter()
+                        .copied()
+                        .zip(shard.cell_scores().iter().copied())
+                })
+                .collect();
+            cells.sort();
+            cells
+        };
+        let (original, anonymized_cells) = (cells(&geoshards), cells(&anonymized));
+        assert_eq!(original.len(), anonymized_cells.len());
+        assert_ne!(original, anonymized_cells);
+        let unscored =
+            |cells: &[(CellID, i32)]| cells.iter().filter(|(_, score)| *score == 0).count();
+        assert_eq!(unscored(&original), unscored(&anonymized_cells));
+        for shard in anonymized.shards() {
+            assert!(shard
+                .cell_union()
+                .0
+                .windows(2)
+                .all(|pair| pair[0] < pair[1]));
+            assert_eq!(shard.score(), shard.cell_scores().iter().sum::<i32>());
+        }
+
+        assert_eq!(
+            serde_json::to_string(&anonymized).unwrap(),
+            serde_json::to_string(&geoshards.anonymize(&options)).unwrap()
+        );
+    }
+}
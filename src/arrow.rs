@@ -0,0 +1,616 @@
+#![deny(missing_docs)]
+//! arrow exports scored cell lists and shard tables as columnar `RecordBatch`es, behind the
+//! `arrow` feature, so analytics pipelines (e.g. Spark) can join shard maps against event data
+//! without parsing JSON. Batches are written as Parquet files with `RecordBatch::write_parquet`:
+//! a single row group of required, uncompressed, plain encoded columns, which every Parquet
+//! reader supports. Converting a batch to an `arrow-rs` one is a column by column copy
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use s2::cellid::CellID;
+
+use crate::{cell_list::CellList, geoshard::GeoshardCollection};
+
+/// magic at the start and end of Parquet files
+const PARQUET_MAGIC: &[u8; 4] = b"PAR1";
+
+/// `DataType` is the type of a column of a `RecordBatch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    /// UTF-8 strings, Arrow's `Utf8` and Parquet's `BYTE_ARRAY` annotated as `UTF8`
+    Utf8,
+    /// 64 bit signed integers, Arrow's `Int64` and Parquet's `INT64`
+    Int64,
+}
+
+/// `Column` holds the values of a column of a `RecordBatch`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    /// a column of strings
+    Utf8(Vec<String>),
+    /// a column of 64 bit signed integers
+    Int64(Vec<i64>),
+}
+
+impl Column {
+    /// returns the type of the column
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Column::Utf8(_) => DataType::Utf8,
+            Column::Int64(_) => DataType::Int64,
+        }
+    }
+
+    /// returns the number of values in the column
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Utf8(values) => values.len(),
+            Column::Int64(values) => values.len(),
+        }
+    }
+
+    /// returns true if the column has no values
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// returns the values as Parquet's plain encoding
+    fn plain_encoded(&self) -> Vec<u8> {
+        match self {
+            Column::Utf8(values) => values
+                .iter()
+                .flat_map(|value| {
+                    (value.len() as u32)
+                        .to_le_bytes()
+                        .into_iter()
+                        .chain(value.bytes())
+                })
+                .collect(),
+            Column::Int64(values) => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+        }
+    }
+}
+
+/// `RecordBatch` is a table of named columns of the same length, none of them nullable
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordBatch {
+    columns: Vec<(String, Column)>,
+}
+
+impl RecordBatch {
+    /// Constructs a new `RecordBatch` from its named columns
+    ///
+    /// # Panics
+    ///
+    /// Panics if the columns have different lengths
+    pub fn new(columns: Vec<(String, Column)>) -> Self {
+        if let Some((_, first)) = columns.first() {
+            for (name, column) in columns.iter() {
+                assert_eq!(
+                    column.len(),
+                    first.len(),
+                    "column {} has {} rows rather than {}",
+                    name,
+                    column.len(),
+                    first.len()
+                );
+            }
+        }
+        Self { columns }
+    }
+
+    /// returns the names and types of the columns
+    pub fn schema(&self) -> Vec<(&str, DataType)> {
+        self.columns
+            .iter()
+            .map(|(name, column)| (name.as_str(), column.data_type()))
+            .collect()
+    }
+
+    /// returns the column named `name`
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|(column_name, _)| column_name == name)
+            .map(|(_, column)| column)
+    }
+
+    /// returns the number of rows
+    pub fn num_rows(&self) -> usize {
+        self.columns.first().map_or(0, |(_, column)| column.len())
+    }
+
+    /// Writes the batch as a Parquet file with a single row group, one uncompressed data page
+    /// per column and plain encoding
+    pub fn write_parquet<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(PARQUET_MAGIC)?;
+        let mut offset = PARQUET_MAGIC.len() as i64;
+        let mut chunks = vec![];
+        for (name, column) in self.columns.iter() {
+            let data = column.plain_encoded();
+            let mut header = Compact::default();
+            header.i32(1, 0); // DATA_PAGE
+            header.i32(2, data.len() as i32);
+            header.i32(3, data.len() as i32);
+            header.begin_struct(5);
+            header.i32(1, column.len() as i32);
+            header.i32(2, 0); // PLAIN
+            header.i32(3, 3); // RLE, unused for required columns
+            header.i32(4, 3);
+            header.end_struct();
+            header.stop();
+            writer.write_all(&header.bytes)?;
+            writer.write_all(&data)?;
+            let size = (header.bytes.len() + data.len()) as i64;
+            chunks.push((name, column, offset, size));
+            offset += size;
+        }
+
+        let mut footer = Compact::default();
+        footer.i32(1, 1);
+        footer.list(2, Compact::STRUCT, self.columns.len() + 1);
+        footer.begin_element();
+        footer.binary(4, b"schema");
+        footer.i32(5, self.columns.len() as i32);
+        footer.end_struct();
+        for (name, column) in self.columns.iter() {
+            footer.begin_element();
+            footer.i32(1, physical_type(column.data_type()));
+            footer.i32(3, 0); // REQUIRED
+            footer.binary(4, name.as_bytes());
+            if column.data_type() == DataType::Utf8 {
+                footer.i32(6, 0); // UTF8
+            }
+            footer.end_struct();
+        }
+        footer.i64(3, self.num_rows() as i64);
+        footer.list(4, Compact::STRUCT, 1);
+        footer.begin_element();
+        footer.list(1, Compact::STRUCT, chunks.len());
+        for (name, column, chunk_offset, size) in chunks.iter() {
+            footer.begin_element();
+            footer.i64(2, *chunk_offset);
+            footer.begin_struct(3);
+            footer.i32(1, physical_type(column.data_type()));
+            footer.list(2, Compact::I32, 1);
+            footer.varint(0); // PLAIN
+            footer.list(3, Compact::BINARY, 1);
+            footer.varint(name.len() as u64);
+            footer.bytes.extend_from_slice(name.as_bytes());
+            footer.i32(4, 0); // UNCOMPRESSED
+            footer.i64(5, column.len() as i64);
+            footer.i64(6, *size);
+            footer.i64(7, *size);
+            footer.i64(9, *chunk_offset);
+            footer.end_struct();
+            footer.end_struct();
+        }
+        footer.i64(2, offset - PARQUET_MAGIC.len() as i64);
+        footer.i64(3, self.num_rows() as i64);
+        footer.end_struct();
+        footer.binary(6, b"location_based_sharding");
+        footer.stop();
+
+        writer.write_all(&footer.bytes)?;
+        writer.write_all(&(footer.bytes.len() as u32).to_le_bytes())?;
+        writer.write_all(PARQUET_MAGIC)?;
+        writer.flush()
+    }
+
+    /// writes the batch as a Parquet file at `path`, see `write_parquet`
+    pub fn write_parquet_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_parquet(BufWriter::new(File::create(path)?))
+    }
+}
+
+/// returns the Parquet physical type of a column type
+fn physical_type(data_type: DataType) -> i32 {
+    match data_type {
+        DataType::Int64 => 2,
+        DataType::Utf8 => 6,
+    }
+}
+
+/// `Compact` writes the Thrift compact protocol of Parquet's page headers and footer. Fields are
+/// written in increasing id order within a struct, as the protocol requires
+#[derive(Default)]
+struct Compact {
+    bytes: Vec<u8>,
+    /// id of the last field written in each open struct
+    last_field: Vec<i16>,
+}
+
+impl Compact {
+    const I32: u8 = 5;
+    const I64: u8 = 6;
+    const BINARY: u8 = 8;
+    const LIST: u8 = 9;
+    const STRUCT: u8 = 12;
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn field(&mut self, id: i16, field_type: u8) {
+        let last = self.last_field.last().copied().unwrap_or(0);
+        match id - last {
+            delta @ 1..=15 => self.bytes.push((delta as u8) << 4 | field_type),
+            _ => {
+                self.bytes.push(field_type);
+                self.varint(((id << 1) ^ (id >> 15)) as u16 as u64);
+            }
+        }
+        match self.last_field.last_mut() {
+            Some(last) => *last = id,
+            None => self.last_field.push(id),
+        }
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, Self::I32);
+        self.varint(((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, Self::I64);
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, Self::BINARY);
+        self.varint(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn list(&mut self, id: i16, element_type: u8, size: usize) {
+        self.field(id, Self::LIST);
+        match size {
+            0..=14 => self.bytes.push((size as u8) << 4 | element_type),
+            _ => {
+                self.bytes.push(0xf0 | element_type);
+                self.varint(size as u64);
+            }
+        }
+    }
+
+    /// opens a struct field
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, Self::STRUCT);
+        self.last_field.push(0);
+    }
+
+    /// opens a struct element of a list
+    fn begin_element(&mut self) {
+        self.last_field.push(0);
+    }
+
+    /// closes the innermost struct
+    fn end_struct(&mut self) {
+        self.bytes.push(0);
+        self.last_field.pop();
+    }
+
+    /// ends the outermost struct
+    fn stop(&mut self) {
+        self.bytes.push(0);
+        self.last_field.clear();
+    }
+}
+
+impl CellList {
+    /// Returns the scored cells as a `RecordBatch` in cell order, with the columns `token`,
+    /// `cell_id` (the cell's 64 bit ID as a signed integer, as Spark and BigQuery store them),
+    /// `level` and `score`
+    pub fn to_record_batch(&self) -> RecordBatch {
+        let cells = self.cell_list();
+        RecordBatch::new(vec![
+            (
+                "token".to_owned(),
+                Column::Utf8(cells.keys().map(CellID::to_token).collect()),
+            ),
+            (
+                "cell_id".to_owned(),
+                Column::Int64(cells.keys().map(|cell_id| cell_id.0 as i64).collect()),
+            ),
+            (
+                "level".to_owned(),
+                Column::Int64(cells.keys().map(|cell_id| cell_id.level() as i64).collect()),
+            ),
+            (
+                "score".to_owned(),
+                Column::Int64(cells.values().map(|score| *score as i64).collect()),
+            ),
+        ])
+    }
+}
+
+impl GeoshardCollection {
+    /// Returns the shard table as a `RecordBatch`, one row per shard in the map's order, with the
+    /// columns `name`, `start_token`, `end_token`, `score` and `cell_count`
+    pub fn to_record_batch(&self) -> RecordBatch {
+        let shards = self.shards();
+        RecordBatch::new(vec![
+            (
+                "name".to_owned(),
+                Column::Utf8(shards.iter().map(|shard| shard.name().to_owned()).collect()),
+            ),
+            (
+                "start_token".to_owned(),
+                Column::Utf8(
+                    shards
+                        .iter()
+                        .map(|shard| shard.start().to_token())
+                        .collect(),
+                ),
+            ),
+            (
+                "end_token".to_owned(),
+                Column::Utf8(shards.iter().map(|shard| shard.end().to_token()).collect()),
+            ),
+            (
+                "score".to_owned(),
+                Column::Int64(shards.iter().map(|shard| shard.score() as i64).collect()),
+            ),
+            (
+                "cell_count".to_owned(),
+                Column::Int64(
+                    shards
+                        .iter()
+                        .map(|shard| shard.cell_count() as i64)
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+    };
+
+    /// a value of the Thrift compact protocol, as read back by `Reader`
+    #[derive(Debug, Clone, PartialEq)]
+    enum Thrift {
+        Int(i64),
+        Binary(Vec<u8>),
+        List(Vec<Thrift>),
+        Struct(BTreeMap<i16, Thrift>),
+    }
+
+    impl Thrift {
+        fn field(&self, id: i16) -> &Thrift {
+            match self {
+                Thrift::Struct(fields) => &fields[&id],
+                other => panic!("{:?} is not a struct", other),
+            }
+        }
+
+        fn int(&self, id: i16) -> i64 {
+            match self.field(id) {
+                Thrift::Int(value) => *value,
+                other => panic!("field {} is {:?}", id, other),
+            }
+        }
+
+        fn string(&self, id: i16) -> String {
+            match self.field(id) {
+                Thrift::Binary(value) => String::from_utf8(value.clone()).unwrap(),
+                other => panic!("field {} is {:?}", id, other),
+            }
+        }
+
+        fn list(&self, id: i16) -> &[Thrift] {
+            match self.field(id) {
+                Thrift::List(values) => values,
+                other => panic!("field {} is {:?}", id, other),
+            }
+        }
+    }
+
+    /// reads the Thrift compact protocol from the spec, independently of `Compact`
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl Reader<'_> {
+        fn byte(&mut self) -> u8 {
+            self.position += 1;
+            self.bytes[self.position - 1]
+        }
+
+        fn varint(&mut self) -> u64 {
+            let (mut value, mut shift) = (0, 0);
+            loop {
+                let byte = self.byte();
+                value |= ((byte & 0x7f) as u64) << shift;
+                if byte < 0x80 {
+                    return value;
+                }
+                shift += 7;
+            }
+        }
+
+        fn zigzag(&mut self) -> i64 {
+            let value = self.varint();
+            (value >> 1) as i64 ^ -((value & 1) as i64)
+        }
+
+        fn value(&mut self, value_type: u8) -> Thrift {
+            match value_type {
+                5 | 6 => Thrift::Int(self.zigzag()),
+                8 => {
+                    let length = self.varint() as usize;
+                    self.position += length;
+                    Thrift::Binary(self.bytes[self.position - length..self.position].to_vec())
+                }
+                9 => {
+                    let header = self.byte();
+                    let size = match header >> 4 {
+                        15 => self.varint() as usize,
+                        size => size as usize,
+                    };
+                    Thrift::List((0..size).map(|_| self.value(header & 0x0f)).collect())
+                }
+                12 => {
+                    let (mut fields, mut id) = (BTreeMap::new(), 0i16);
+                    loop {
+                        let header = self.byte();
+                        if header == 0 {
+                            return Thrift::Struct(fields);
+                        }
+                        id = match header >> 4 {
+                            0 => self.zigzag() as i16,
+                            delta => id + delta as i16,
+                        };
+                        fields.insert(id, self.value(header & 0x0f));
+                    }
+                }
+                other => panic!("unexpected type {}", other),
+            }
+        }
+    }
+
+    /// reads the struct at the start of the bytes, returning it and its length
+    fn read_struct(bytes: &[u8]) -> (Thrift, usize) {
+        let mut reader = Reader { bytes, position: 0 };
+        let value = reader.value(12);
+        (value, reader.position)
+    }
+
+    /// decodes the PLAIN encoded values of a page
+    fn decode_plain(data: &[u8], physical_type: i64, count: usize) -> Column {
+        match physical_type {
+            2 => Column::Int64(
+                data.chunks_exact(8)
+                    .map(|value| i64::from_le_bytes(value.try_into().unwrap()))
+                    .collect(),
+            ),
+            6 => {
+                let (mut values, mut position) = (vec![], 0);
+                while values.len() < count {
+                    let length =
+                        u32::from_le_bytes(data[position..position + 4].try_into().unwrap());
+                    position += 4;
+                    values.push(
+                        String::from_utf8(data[position..position + length as usize].to_vec())
+                            .unwrap(),
+                    );
+                    position += length as usize;
+                }
+                assert_eq!(position, data.len());
+                Column::Utf8(values)
+            }
+            other => panic!("unexpected physical type {}", other),
+        }
+    }
+
+    /// reads a Parquet file written by `write_parquet` back into a batch, checking its footer
+    fn read_parquet(bytes: &[u8]) -> RecordBatch {
+        assert_eq!(&bytes[..4], PARQUET_MAGIC);
+        assert_eq!(&bytes[bytes.len() - 4..], PARQUET_MAGIC);
+        let footer_length =
+            u32::from_le_bytes(bytes[bytes.len() - 8..bytes.len() - 4].try_into().unwrap());
+        let footer_start = bytes.len() - 8 - footer_length as usize;
+        let (metadata, length) = read_struct(&bytes[footer_start..]);
+        assert_eq!(length, footer_length as usize);
+        assert_eq!(metadata.int(1), 1);
+        let num_rows = metadata.int(3);
+
+        let schema = metadata.list(2);
+        assert_eq!(schema[0].string(4), "schema");
+        assert_eq!(schema[0].int(5) as usize, schema.len() - 1);
+        let row_groups = metadata.list(4);
+        assert_eq!(row_groups.len(), 1);
+        assert_eq!(row_groups[0].int(3), num_rows);
+        let chunks = row_groups[0].list(1);
+        assert_eq!(chunks.len(), schema.len() - 1);
+
+        let mut columns = vec![];
+        let mut offset = PARQUET_MAGIC.len() as i64;
+        for (element, chunk) in schema[1..].iter().zip(chunks) {
+            let name = element.string(4);
+            assert_eq!(element.int(3), 0);
+            let meta = chunk.field(3);
+            assert_eq!(meta.int(1), element.int(1));
+            assert_eq!(meta.list(3), &[Thrift::Binary(name.clone().into_bytes())]);
+            assert_eq!(meta.int(4), 0);
+            assert_eq!(meta.int(5), num_rows);
+            // chunks are laid out back to back after the magic
+            assert_eq!(meta.int(9), offset);
+            assert_eq!(chunk.int(2), offset);
+
+            let (header, header_length) = read_struct(&bytes[offset as usize..]);
+            assert_eq!(header.int(1), 0);
+            assert_eq!(header.int(2), header.int(3));
+            assert_eq!(header.field(5).int(1), num_rows);
+            assert_eq!(header.field(5).int(2), 0);
+            let data_start = offset as usize + header_length;
+            let data = &bytes[data_start..data_start + header.int(3) as usize];
+            assert_eq!(meta.int(7), (header_length + data.len()) as i64);
+            columns.push((name, decode_plain(data, meta.int(1), num_rows as usize)));
+            offset += meta.int(7);
+        }
+        assert_eq!(offset as usize, footer_start);
+        RecordBatch::new(columns)
+    }
+
+    #[test]
+    fn test_record_batches() {
+        let users = FakeUser::seeded(500, 41, &RandCityFactory::default());
+        let shards = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let batch = shards.to_record_batch();
+        assert_eq!(batch.num_rows(), shards.shards().len());
+        assert_eq!(batch.schema()[4], ("cell_count", DataType::Int64));
+        assert_eq!(
+            batch.column("name"),
+            Some(&Column::Utf8(
+                shards
+                    .shards()
+                    .iter()
+                    .map(|shard| shard.name().to_owned())
+                    .collect()
+            ))
+        );
+
+        let cell_list = CellList::new(2);
+        let cells = cell_list.to_record_batch();
+        assert_eq!(cells.num_rows(), 96);
+        assert_eq!(cells.column("level"), Some(&Column::Int64(vec![2; 96])));
+
+        // both batches read back from their files, footer and pages, as they were written
+        for batch in [batch, cells] {
+            let mut bytes = vec![];
+            batch.write_parquet(&mut bytes).unwrap();
+            assert_eq!(read_parquet(&bytes), batch);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "column score has 1 rows rather than 2")]
+    fn test_record_batch_lengths() {
+        RecordBatch::new(vec![
+            (
+                "token".to_owned(),
+                Column::Utf8(vec!["1".into(), "3".into()]),
+            ),
+            ("score".to_owned(), Column::Int64(vec![1])),
+        ]);
+    }
+}
@@ -0,0 +1,107 @@
+#![deny(missing_docs)]
+//! geoshard runs the crate as a routing sidecar:
+//!
+//! ```text
+//! geoshard serve --map-dir ./maps --http :8080 [--poll-interval 5]
+//! ```
+//!
+//! serves the latest map in `--map-dir` over HTTP (see `location_based_sharding::http`),
+//! checking the directory every `--poll-interval` seconds and hot-swapping to new maps that
+//! verify (see `DirSource`). Lookups are served over HTTP only, as the crate has no gRPC
+//! transport
+use std::{
+    env,
+    net::TcpListener,
+    path::PathBuf,
+    process,
+    sync::{atomic::AtomicBool, Arc},
+    thread,
+    time::Duration,
+};
+
+use location_based_sharding::{
+    http::{serve, HttpRouter},
+    subscriber::{DirSource, ShardMapSubscriber},
+};
+
+const USAGE: &str =
+    "usage: geoshard serve --map-dir <dir> --http <[host]:port> [--poll-interval <seconds>]";
+
+/// the options of `geoshard serve`
+struct ServeOptions {
+    map_dir: PathBuf,
+    address: String,
+    poll_interval: Duration,
+}
+
+impl ServeOptions {
+    /// parses the arguments after `serve`
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let (mut map_dir, mut address, mut poll_interval) = (None, None, Duration::from_secs(5));
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} needs a value", flag))?;
+            match flag.as_str() {
+                "--map-dir" => map_dir = Some(PathBuf::from(value)),
+                // `:8080` listens on every interface
+                "--http" => match value.strip_prefix(':') {
+                    Some(port) => address = Some(format!("0.0.0.0:{}", port)),
+                    None => address = Some(value),
+                },
+                "--poll-interval" => {
+                    let seconds = value
+                        .parse()
+                        .map_err(|_| format!("invalid --poll-interval {}", value))?;
+                    poll_interval = Duration::from_secs(seconds)
+                }
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+        Ok(Self {
+            map_dir: map_dir.ok_or("--map-dir is required")?,
+            address: address.ok_or("--http is required")?,
+            poll_interval,
+        })
+    }
+}
+
+/// serves the latest map of the directory until the listener fails
+fn run(options: ServeOptions) -> Result<(), String> {
+    let subscriber = ShardMapSubscriber::open(DirSource::new(&options.map_dir), ())
+        .map_err(|error| error.to_string())?;
+    let router = Arc::new(HttpRouter::new(subscriber.switcher().shared().clone()));
+    let listener = TcpListener::bind(&options.address)
+        .map_err(|error| format!("can't listen on {}: {}", options.address, error))?;
+    eprintln!(
+        "serving {} on {}",
+        subscriber.version().unwrap_or_default(),
+        options.address
+    );
+
+    let mut subscriber = subscriber.with_on_change(|change| {
+        eprintln!(
+            "switched to generation {} from {}",
+            change.generation, change.version
+        )
+    });
+    // the current generation keeps serving through failed polls
+    thread::spawn(move || {
+        subscriber.run(options.poll_interval, &AtomicBool::new(false), |error| {
+            eprintln!("{}", error)
+        })
+    });
+    serve(router, listener).map_err(|error| error.to_string())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let result = match args.next().as_deref() {
+        Some("serve") => ServeOptions::parse(args).and_then(run),
+        _ => Err(USAGE.to_owned()),
+    };
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
@@ -1,11 +1,20 @@
 #![deny(missing_docs)]
 //! cell_list contains code directly related to CellList
 //! This includes scoring and creation
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Read, Write},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
 
-use s2::cellid::CellID;
+use s2::{
+    cellid::{CellID, MAX_LEVEL},
+    cellunion::CellUnion,
+};
+use serde_derive::Serialize;
 
-use crate::{users::User, utils::ll};
+use crate::users::{ActiveUser, MultiLocationUser, TrajectoryUser, User};
 
 /// CellScorer is the trait for a given scorer, implementing
 /// this will allow you to give a custom heuristic for scoring cells
@@ -29,15 +38,255 @@ impl<UserCollection> CellScorer<UserCollection> for UserCountScorer {
     {
         for user in users {
             let cell_id = CellID::from(user.location()).parent(cell_list.storage_level);
-            let score = cell_list.cell_list.get_mut(&cell_id).unwrap();
-            *score += 1;
+            *cell_list.cell_list.entry(cell_id).or_insert(0) += 1;
         }
         cell_list
     }
 }
 
+/// PreScoredCells scores cells with scores computed elsewhere (e.g. in an analytics warehouse),
+/// ignoring the users entirely. Cells finer than the storage level add their score to their
+/// parent at the storage level
+pub struct PreScoredCells {
+    cells: BTreeMap<CellID, i32>,
+}
+
+impl PreScoredCells {
+    /// Constructs a new `PreScoredCells` from the score of each cell
+    pub fn new(cells: BTreeMap<CellID, i32>) -> Self {
+        Self { cells }
+    }
+
+    /// returns the level of the coarsest cell, or 0 without cells
+    pub fn storage_level(&self) -> u64 {
+        self.cells
+            .keys()
+            .map(|cell_id| cell_id.level())
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+impl<UserCollection> CellScorer<UserCollection> for PreScoredCells {
+    fn score_cell_list<T>(&self, mut cell_list: CellList, _: UserCollection) -> CellList
+    where
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        for (cell_id, score) in self.cells.iter() {
+            let cell_id = if cell_id.level() > cell_list.storage_level {
+                cell_id.parent(cell_list.storage_level)
+            } else {
+                *cell_id
+            };
+            *cell_list.cell_list.entry(cell_id).or_insert(0) += score;
+        }
+        cell_list
+    }
+}
+
+/// TimeDecayScorer scores cells by recently active users. Each user's weight halves every
+/// `half_life` since they were last active, so dormant regions don't outweigh busy ones.
+/// A user active at `now` adds `scale` to their cell's score, and one last active a half life
+/// ago adds half of it. Users active after `now` count as active at `now`
+pub struct TimeDecayScorer {
+    half_life: Duration,
+    now: SystemTime,
+    scale: u32,
+}
+
+impl TimeDecayScorer {
+    /// Constructs a new `TimeDecayScorer` with a scale of 100, decaying users from `now`
+    pub fn new(half_life: Duration, now: SystemTime) -> Self {
+        Self {
+            half_life,
+            now,
+            scale: 100,
+        }
+    }
+
+    /// sets the score of a user active at `now`, the resolution of the decayed scores
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// returns the weight of a user last active at `last_active`, between 0 and 1
+    fn weight(&self, last_active: SystemTime) -> f64 {
+        let age = self.now.duration_since(last_active).unwrap_or_default();
+        0.5f64.powf(age.as_secs_f64() / self.half_life.as_secs_f64())
+    }
+}
+
+impl<UserCollection> CellScorer<UserCollection> for TimeDecayScorer
+where
+    UserCollection: Iterator,
+    UserCollection::Item: ActiveUser,
+{
+    fn score_cell_list<T>(&self, mut cell_list: CellList, users: UserCollection) -> CellList
+    where
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        let mut weights: BTreeMap<CellID, f64> = BTreeMap::new();
+        for user in users {
+            let cell_id = CellID::from(user.location()).parent(cell_list.storage_level);
+            *weights.entry(cell_id).or_insert(0.0) += self.weight(user.last_active());
+        }
+        for (cell_id, weight) in weights {
+            *cell_list.cell_list.entry(cell_id).or_insert(0) +=
+                (weight * self.scale as f64).round() as i32;
+        }
+        cell_list
+    }
+}
+
+/// MultiLocationScorer scores cells by the weighted locations of `MultiLocationUser`s, counting
+/// each user fractionally in the cell of every location. A location of weight 1 adds `scale` to
+/// its cell's score, and one of weight 0.5 adds half of it
+pub struct MultiLocationScorer {
+    scale: u32,
+}
+
+impl MultiLocationScorer {
+    /// Constructs a new `MultiLocationScorer` with a scale of 100
+    pub fn new() -> Self {
+        Self { scale: 100 }
+    }
+
+    /// sets the score of a location of weight 1, the resolution of the fractional scores
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
+impl Default for MultiLocationScorer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<UserCollection> CellScorer<UserCollection> for MultiLocationScorer
+where
+    UserCollection: Iterator,
+    UserCollection::Item: MultiLocationUser,
+{
+    fn score_cell_list<T>(&self, mut cell_list: CellList, users: UserCollection) -> CellList
+    where
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        let mut weights: BTreeMap<CellID, f64> = BTreeMap::new();
+        for user in users {
+            for (location, weight) in user.locations() {
+                let cell_id = CellID::from(location).parent(cell_list.storage_level);
+                *weights.entry(cell_id).or_insert(0.0) += weight;
+            }
+        }
+        for (cell_id, weight) in weights {
+            *cell_list.cell_list.entry(cell_id).or_insert(0) +=
+                (weight * self.scale as f64).round() as i32;
+        }
+        cell_list
+    }
+}
+
+/// TrajectoryScorer scores cells by where `TrajectoryUser`s spend their time, so commute corridors
+/// that users move through count as well as where they currently are. Each user adds `scale` in
+/// total, split between the cells of their trajectory in proportion to the dwell time at each
+/// location: the time until the next location, or until `now` for the last one. Dwell times are
+/// capped at `max_dwell`, so gaps in the history don't outweigh it. A user without dwell time
+/// (e.g. with a single location seen at `now`) adds it all to their last location's cell
+pub struct TrajectoryScorer {
+    now: SystemTime,
+    max_dwell: Duration,
+    scale: u32,
+}
+
+impl TrajectoryScorer {
+    /// Constructs a new `TrajectoryScorer` with a scale of 100 and a maximum dwell time of an
+    /// hour, measuring the last dwell time until `now`
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now,
+            max_dwell: Duration::from_secs(3600),
+            scale: 100,
+        }
+    }
+
+    /// sets the longest dwell time counted at a location
+    pub fn with_max_dwell(mut self, max_dwell: Duration) -> Self {
+        self.max_dwell = max_dwell;
+        self
+    }
+
+    /// sets the score of a user, the resolution of the split scores
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
+impl<UserCollection> CellScorer<UserCollection> for TrajectoryScorer
+where
+    UserCollection: Iterator,
+    UserCollection::Item: TrajectoryUser,
+{
+    fn score_cell_list<T>(&self, mut cell_list: CellList, users: UserCollection) -> CellList
+    where
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        let mut weights: BTreeMap<CellID, f64> = BTreeMap::new();
+        let mut dwells: Vec<(CellID, f64)> = vec![];
+        for user in users {
+            dwells.clear();
+            let mut trajectory = user.trajectory().peekable();
+            while let Some((location, seen)) = trajectory.next() {
+                let until = trajectory.peek().map_or(self.now, |(_, next)| *next);
+                let dwell = until.duration_since(seen).unwrap_or_default();
+                dwells.push((
+                    CellID::from(location).parent(cell_list.storage_level),
+                    dwell.min(self.max_dwell).as_secs_f64(),
+                ));
+            }
+            let total: f64 = dwells.iter().map(|(_, dwell)| dwell).sum();
+            match dwells.last() {
+                None => {}
+                Some((cell_id, _)) if total == 0.0 => {
+                    *weights.entry(*cell_id).or_insert(0.0) += 1.0;
+                }
+                Some(_) => {
+                    for (cell_id, dwell) in dwells.iter() {
+                        *weights.entry(*cell_id).or_insert(0.0) += dwell / total;
+                    }
+                }
+            }
+        }
+        for (cell_id, weight) in weights {
+            *cell_list.cell_list.entry(cell_id).or_insert(0) +=
+                (weight * self.scale as f64).round() as i32;
+        }
+        cell_list
+    }
+}
+
+/// header of a saved `CellList`, followed by a format version
+const SAVED_CELL_LIST_MAGIC: &[u8; 8] = b"CELLLIST";
+
+/// version of the saved `CellList` format
+const SAVED_CELL_LIST_VERSION: u32 = 1;
+
+/// `CellCache` holds the cells generated by `CellList::cached`, by storage level
+type CellCache = Mutex<BTreeMap<u64, Arc<BTreeMap<CellID, i32>>>>;
+
+static CELL_CACHE: OnceLock<CellCache> = OnceLock::new();
+
 /// CellList is a given order map where the key is the CellID
-/// and the value is the cell score
+/// and the value is the cell score. A sparse list (see `CellList::sparse`) only holds the
+/// cells that were scored
+#[derive(Clone)]
 pub struct CellList {
     storage_level: u64,
     cell_list: BTreeMap<CellID, i32>,
@@ -46,14 +295,126 @@ pub struct CellList {
 impl CellList {
     /// Generates a Collection of cells based off of the given storage level
     pub fn new(storage_level: u64) -> Self {
-        let starting_cell_id = CellID::from(ll!(0.00000000, 0.00000000));
-        let cell_list = Self::gather_cells(storage_level, starting_cell_id.parent(storage_level));
         Self {
             storage_level,
-            cell_list,
+            cell_list: Self::gather_cells(storage_level),
+        }
+    }
+
+    /// Constructs an empty `CellList` at the given storage level. Scorers insert cells as users
+    /// hit them, so memory grows with the populated cells rather than every cell at the level
+    /// (e.g. 100 million cells at level 12), and unscored cells are left to the shards built from
+    /// it to cover by range
+    pub fn sparse(storage_level: u64) -> Self {
+        Self {
+            storage_level,
+            cell_list: BTreeMap::new(),
+        }
+    }
+
+    /// returns true if the list is missing some cells at its storage level, e.g. when built by
+    /// `sparse`
+    pub fn is_sparse(&self) -> bool {
+        (self.cell_list.len() as u64) < 6 << (2 * self.storage_level)
+    }
+
+    /// `new`, reusing the cells generated by earlier calls for the same storage level in this
+    /// process. The cells are generated once per level and kept until `clear_cache`, e.g. 393,216
+    /// cells (about 10MB) at level 8 and 6.3 million at level 10
+    pub fn cached(storage_level: u64) -> Self {
+        let cache = CELL_CACHE.get_or_init(Mutex::default);
+        let cells = cache.lock().unwrap().get(&storage_level).cloned();
+        let cells = cells.unwrap_or_else(|| {
+            let cells = Arc::new(Self::new(storage_level).cell_list);
+            cache.lock().unwrap().insert(storage_level, cells.clone());
+            cells
+        });
+        Self {
+            storage_level,
+            cell_list: cells.as_ref().clone(),
         }
     }
 
+    /// drops the cells kept by `cached`
+    pub fn clear_cache() {
+        if let Some(cache) = CELL_CACHE.get() {
+            cache.lock().unwrap().clear();
+        }
+    }
+
+    /// Constructs a `CellList` from a precomputed set of cells, all scored 0. An incomplete set is
+    /// treated as a sparse list
+    pub fn from_cells(storage_level: u64, cells: impl IntoIterator<Item = CellID>) -> Self {
+        Self {
+            storage_level,
+            cell_list: cells.into_iter().map(|cell_id| (cell_id, 0)).collect(),
+        }
+    }
+
+    /// Writes the storage level, cells and scores to `writer` in a compact binary format, to be
+    /// read back by `load`
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(SAVED_CELL_LIST_MAGIC)?;
+        writer.write_all(&SAVED_CELL_LIST_VERSION.to_le_bytes())?;
+        writer.write_all(&self.storage_level.to_le_bytes())?;
+        writer.write_all(&(self.cell_list.len() as u64).to_le_bytes())?;
+        for (cell_id, score) in self.cell_list.iter() {
+            writer.write_all(&cell_id.0.to_le_bytes())?;
+            writer.write_all(&score.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Reads a `CellList` written by `save`. Fails with `io::ErrorKind::InvalidData` if the data
+    /// is not a saved cell list, or has a storage level over the maximum S2 level or cells at
+    /// other levels
+    pub fn load<R: Read>(mut reader: R) -> io::Result<Self> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SAVED_CELL_LIST_MAGIC {
+            return Err(invalid("not a saved cell list".to_owned()));
+        }
+        let mut word = [0u8; 4];
+        let mut long = [0u8; 8];
+        reader.read_exact(&mut word)?;
+        let version = u32::from_le_bytes(word);
+        if version != SAVED_CELL_LIST_VERSION {
+            return Err(invalid(format!(
+                "unsupported cell list version {}",
+                version
+            )));
+        }
+        reader.read_exact(&mut long)?;
+        let storage_level = u64::from_le_bytes(long);
+        if storage_level > MAX_LEVEL {
+            return Err(invalid(format!(
+                "storage level {} is over the maximum S2 level of {}",
+                storage_level, MAX_LEVEL
+            )));
+        }
+        reader.read_exact(&mut long)?;
+        let cell_count = u64::from_le_bytes(long);
+
+        let mut cell_list = BTreeMap::new();
+        for _ in 0..cell_count {
+            reader.read_exact(&mut long)?;
+            let cell_id = CellID(u64::from_le_bytes(long));
+            reader.read_exact(&mut word)?;
+            if !cell_id.is_valid() || cell_id.level() != storage_level {
+                return Err(invalid(format!(
+                    "cell {} is not a valid cell at level {}",
+                    cell_id.0, storage_level
+                )));
+            }
+            cell_list.insert(cell_id, i32::from_le_bytes(word));
+        }
+        Ok(Self {
+            storage_level,
+            cell_list,
+        })
+    }
+
     /// returns an exclusive reference to the internal cell_list
     pub fn mut_cell_list(&mut self) -> &mut BTreeMap<CellID, i32> {
         &mut self.cell_list
@@ -64,26 +425,454 @@ impl CellList {
         &self.cell_list
     }
 
-    fn gather_cells(storage_level: u64, starting_cell_id: CellID) -> BTreeMap<CellID, i32> {
-        let mut seen = BTreeMap::new();
-        let mut current_stack = vec![starting_cell_id];
-        while let Some(current_neighbor) = current_stack.pop() {
-            if !seen.contains_key(&current_neighbor) {
-                current_stack.append(&mut current_neighbor.all_neighbors(storage_level));
-                seen.insert(current_neighbor, 0);
+    /// returns the storage level of the cells in this list
+    pub fn storage_level(&self) -> u64 {
+        self.storage_level
+    }
+
+    /// Groups neighboring cells with a score of at least `min_score` into clusters (e.g. metros)
+    ///
+    /// Cells are connected if they share an edge or vertex at the storage level. The clusters are
+    /// returned sorted by total score, highest first, and named `cluster_1`, `cluster_2`...
+    pub fn detect_clusters(&self, min_score: i32) -> Vec<Cluster> {
+        let mut seen = BTreeSet::new();
+        let mut clusters = vec![];
+
+        for (cell_id, score) in self.cell_list.iter() {
+            if *score < min_score || seen.contains(cell_id) {
+                continue;
+            }
+
+            let mut cells = vec![];
+            let mut total_score = 0;
+            let mut current_stack = vec![*cell_id];
+            seen.insert(*cell_id);
+            while let Some(current_cell) = current_stack.pop() {
+                total_score += self.cell_list[&current_cell];
+                cells.push(current_cell);
+                for neighbor in current_cell.all_neighbors(self.storage_level) {
+                    let is_dense = self
+                        .cell_list
+                        .get(&neighbor)
+                        .is_some_and(|score| *score >= min_score);
+                    if is_dense && seen.insert(neighbor) {
+                        current_stack.push(neighbor);
+                    }
+                }
             }
+            cells.sort();
+            clusters.push((total_score, cells));
         }
-        seen
+
+        clusters.sort_by_key(|(total_score, _)| std::cmp::Reverse(*total_score));
+        clusters
+            .into_iter()
+            .enumerate()
+            .map(|(index, (score, cells))| Cluster {
+                name: format!("cluster_{}", index + 1),
+                score,
+                cell_union: CellUnion(cells),
+            })
+            .collect()
+    }
+
+    /// enumerates every cell at the storage level in order, walking each face's range of cells
+    fn gather_cells(storage_level: u64) -> BTreeMap<CellID, i32> {
+        (0..6)
+            .flat_map(|face| {
+                let face_cell = CellID::from_face(face);
+                let end = face_cell.child_end_at_level(storage_level);
+                std::iter::successors(
+                    Some(face_cell.child_begin_at_level(storage_level)),
+                    |cell_id| Some(cell_id.next()),
+                )
+                .take_while(move |cell_id| *cell_id != end)
+            })
+            .map(|cell_id| (cell_id, 0))
+            .collect()
+    }
+}
+
+/// `ScoredCellSnapshot` is a scored `CellList` kept up to date incrementally, from a stream of
+/// user events, rather than by scoring every user again. Shards are rebuilt from it with
+/// `GeoshardBuilder::rebuild_from_snapshot`
+#[derive(Clone)]
+pub struct ScoredCellSnapshot {
+    cell_list: CellList,
+}
+
+impl ScoredCellSnapshot {
+    /// Constructs a new `ScoredCellSnapshot` from a scored cell list, e.g. the result of a full
+    /// scoring run from `GeoshardBuilder::snapshot`
+    pub fn new(cell_list: CellList) -> Self {
+        Self { cell_list }
+    }
+
+    /// Adds `delta` to the score of the cell at the storage level holding `cell_id`, which may be
+    /// a finer cell such as a user's leaf cell. Scores don't go below 0
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cell_id` is coarser than the storage level
+    pub fn apply_delta(&mut self, cell_id: CellID, delta: i32) {
+        let storage_level = self.cell_list.storage_level;
+        assert!(
+            cell_id.level() >= storage_level,
+            "cell {} is coarser than the storage level {}",
+            cell_id.to_token(),
+            storage_level
+        );
+        let score = self
+            .cell_list
+            .cell_list
+            .entry(cell_id.parent(storage_level))
+            .or_insert(0);
+        *score = score.saturating_add(delta).max(0);
+    }
+
+    /// moves a user from the cell holding `from` to the cell holding `to`, see `apply_delta`
+    pub fn apply_move(&mut self, from: CellID, to: CellID) {
+        self.apply_delta(from, -1);
+        self.apply_delta(to, 1);
+    }
+
+    /// returns the scored cells
+    pub fn cell_list(&self) -> &CellList {
+        &self.cell_list
+    }
+
+    /// returns the storage level of the cells
+    pub fn storage_level(&self) -> u64 {
+        self.cell_list.storage_level
+    }
+
+    /// returns the total score of every cell
+    pub fn total_score(&self) -> i64 {
+        self.cell_list
+            .cell_list
+            .values()
+            .map(|score| *score as i64)
+            .sum()
+    }
+}
+
+impl From<CellList> for ScoredCellSnapshot {
+    fn from(cell_list: CellList) -> Self {
+        Self::new(cell_list)
+    }
+}
+
+/// Cluster is a connected blob of dense cells found by `CellList::detect_clusters`
+#[derive(Debug, Clone, Serialize)]
+pub struct Cluster {
+    name: String,
+    score: i32,
+    #[serde(rename = "cells", serialize_with = "serialize_tokens")]
+    cell_union: CellUnion,
+}
+
+/// serializes the cells of a cell union as their tokens
+fn serialize_tokens<S: serde::Serializer>(
+    cell_union: &CellUnion,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(cell_union.0.iter().map(|cell_id| cell_id.to_token()))
+}
+
+impl Cluster {
+    /// name of the cluster, numbered by score
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// total score of all the cells in the cluster
+    pub fn score(&self) -> i32 {
+        self.score
+    }
+
+    /// the cells in the cluster at the storage level, in order
+    pub fn cell_union(&self) -> &CellUnion {
+        &self.cell_union
+    }
+
+    /// returns the number of cells in the cluster
+    pub fn cell_count(&self) -> usize {
+        self.cell_union.0.len()
+    }
+
+    /// returns the normalized covering of the cluster, where complete sets of children are
+    /// replaced by their parent
+    pub fn covering(&self) -> CellUnion {
+        let mut covering = self.cell_union.clone();
+        covering.normalize();
+        covering
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::utils::ll;
+    use s2::latlng::LatLng;
+
+    /// the original cell generation, a walk over every cell's neighbors from null island
+    fn gather_cells_by_neighbors(storage_level: u64) -> BTreeMap<CellID, i32> {
+        let starting_cell_id = CellID::from(ll!(0.00000000, 0.00000000)).parent(storage_level);
+        let mut seen = BTreeMap::new();
+        let mut current_stack = vec![starting_cell_id];
+        while let Some(current_neighbor) = current_stack.pop() {
+            if let std::collections::btree_map::Entry::Vacant(entry) = seen.entry(current_neighbor)
+            {
+                current_stack.append(&mut current_neighbor.all_neighbors(storage_level));
+                entry.insert(0);
+            }
+        }
+        seen
+    }
 
     #[test]
     fn test_geoshard_cell_list() {
         let cell_list = CellList::new(8).cell_list;
         assert_eq!(cell_list.len(), 393216);
+        for storage_level in 0..=5 {
+            assert_eq!(
+                CellList::new(storage_level).cell_list,
+                gather_cells_by_neighbors(storage_level)
+            );
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut cell_list = CellList::cached(4);
+        assert_eq!(cell_list.cell_list, CellList::new(4).cell_list);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = index as i32 % 3;
+        }
+        // scores set on a cached list don't leak into the cache
+        assert!(CellList::cached(4)
+            .cell_list
+            .values()
+            .all(|score| *score == 0));
+
+        let mut saved = vec![];
+        cell_list.save(&mut saved).unwrap();
+        let loaded = CellList::load(saved.as_slice()).unwrap();
+        assert_eq!(loaded.storage_level(), 4);
+        assert_eq!(loaded.cell_list, cell_list.cell_list);
+
+        let from_cells = CellList::from_cells(4, loaded.cell_list.keys().copied());
+        assert_eq!(from_cells.cell_list, CellList::new(4).cell_list);
+
+        assert!(CellList::load(&saved[..saved.len() - 1]).is_err());
+        // an empty list at a level past the leaves
+        let mut past_leaves = saved[..12].to_vec();
+        past_leaves.extend(31u64.to_le_bytes());
+        past_leaves.extend(0u64.to_le_bytes());
+        assert!(matches!(
+            CellList::load(past_leaves.as_slice()),
+            Err(error) if error.kind() == io::ErrorKind::InvalidData
+        ));
+        assert!(matches!(
+            CellList::load(&b"not cells"[..]),
+            Err(error) if error.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[test]
+    fn test_scored_cell_snapshot() {
+        let new_york = CellID::from(ll!(-74.0060, 40.7128));
+        let london = CellID::from(ll!(-0.1278, 51.5074));
+        let mut cell_list = CellList::new(4);
+        *cell_list
+            .mut_cell_list()
+            .get_mut(&new_york.parent(4))
+            .unwrap() = 10;
+        let mut snapshot = ScoredCellSnapshot::from(cell_list);
+
+        snapshot.apply_move(new_york, london);
+        snapshot.apply_delta(london.parent(4), 4);
+        assert_eq!(snapshot.cell_list().cell_list()[&new_york.parent(4)], 9);
+        assert_eq!(snapshot.cell_list().cell_list()[&london.parent(4)], 5);
+        assert_eq!(snapshot.total_score(), 14);
+
+        // scores don't go negative
+        snapshot.apply_delta(new_york, -20);
+        assert_eq!(snapshot.cell_list().cell_list()[&new_york.parent(4)], 0);
+        assert_eq!(snapshot.cell_list().cell_list().len(), 1536);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_scored_cell_snapshot_coarse_cell() {
+        ScoredCellSnapshot::new(CellList::sparse(4)).apply_delta(CellID::from_face(0), 1);
+    }
+
+    #[test]
+    fn test_detect_clusters() {
+        let mut cell_list = CellList::new(6);
+        let nyc = CellID::from(ll!(-74.0060, 40.7128)).parent(6);
+        let london = CellID::from(ll!(-0.1278, 51.5074)).parent(6);
+
+        let scores = cell_list.mut_cell_list();
+        *scores.get_mut(&nyc).unwrap() = 100;
+        for neighbor in nyc.all_neighbors(6) {
+            *scores.get_mut(&neighbor).unwrap() = 20;
+        }
+        *scores.get_mut(&london).unwrap() = 50;
+        // below threshold, so it is not part of any cluster
+        *scores.get_mut(&london.all_neighbors(6)[0]).unwrap() = 1;
+
+        let clusters = cell_list.detect_clusters(10);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].name(), "cluster_1");
+        assert_eq!(clusters[0].score(), 100 + 20 * 8);
+        assert_eq!(clusters[0].cell_count(), 9);
+        assert!(clusters[0].cell_union().contains_cellid(&nyc));
+        assert_eq!(clusters[1].score(), 50);
+        assert_eq!(clusters[1].cell_union().0, vec![london]);
+    }
+
+    struct ActiveFakeUser {
+        location: LatLng,
+        last_active: SystemTime,
+    }
+
+    impl User for ActiveFakeUser {
+        fn location(&self) -> &LatLng {
+            &self.location
+        }
+    }
+
+    impl ActiveUser for ActiveFakeUser {
+        fn last_active(&self) -> SystemTime {
+            self.last_active
+        }
+    }
+
+    #[test]
+    fn test_time_decay_scorer() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let day = Duration::from_secs(86_400);
+        let (nyc, london) = (ll!(-74.0060, 40.7128), ll!(-0.1278, 51.5074));
+        let user = |location: &LatLng, age: Duration| ActiveFakeUser {
+            location: location.clone(),
+            last_active: now - age,
+        };
+        let users = vec![
+            user(&nyc, Duration::ZERO),
+            user(&nyc, day),
+            user(&london, day * 2),
+            user(&london, day * 2),
+            user(&london, day * 60),
+            ActiveFakeUser {
+                location: london.clone(),
+                last_active: now + day,
+            },
+        ];
+
+        let scored = TimeDecayScorer::new(day, now)
+            .score_cell_list(CellList::new(6), users.into_iter())
+            .cell_list;
+        assert_eq!(scored[&CellID::from(nyc).parent(6)], 150);
+        assert_eq!(scored[&CellID::from(london).parent(6)], 150);
+        assert_eq!(scored.values().sum::<i32>(), 300);
+    }
+
+    struct MovingFakeUser {
+        trajectory: Vec<(LatLng, SystemTime)>,
+    }
+
+    impl User for MovingFakeUser {
+        fn location(&self) -> &LatLng {
+            &self.trajectory.last().unwrap().0
+        }
+    }
+
+    impl TrajectoryUser for MovingFakeUser {
+        fn trajectory(&self) -> impl Iterator<Item = (&LatLng, SystemTime)> {
+            self.trajectory
+                .iter()
+                .map(|(location, seen)| (location, *seen))
+        }
+    }
+
+    #[test]
+    fn test_trajectory_scorer() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let minute = Duration::from_secs(60);
+        let (home, corridor, work) = (
+            ll!(-74.0060, 40.7128),
+            ll!(-75.1652, 39.9526),
+            ll!(-77.0369, 38.9072),
+        );
+        let users = vec![
+            // 15 minutes at home, 30 along the corridor, and 15 at work until now
+            MovingFakeUser {
+                trajectory: vec![
+                    (home.clone(), now - minute * 60),
+                    (corridor.clone(), now - minute * 45),
+                    (work.clone(), now - minute * 15),
+                ],
+            },
+            // a day old gap at home only counts for the maximum dwell time
+            MovingFakeUser {
+                trajectory: vec![
+                    (home.clone(), now - minute * 1500),
+                    (work.clone(), now - minute * 60),
+                ],
+            },
+            MovingFakeUser {
+                trajectory: vec![(corridor.clone(), now)],
+            },
+        ];
+
+        let scored = TrajectoryScorer::new(now)
+            .score_cell_list(CellList::new(6), users.into_iter())
+            .cell_list;
+        let score = |location: &LatLng| scored[&CellID::from(location).parent(6)];
+        assert_eq!(score(&home), 25 + 50);
+        assert_eq!(score(&corridor), 50 + 100);
+        assert_eq!(score(&work), 25 + 50);
+        assert_eq!(scored.values().sum::<i32>(), 300);
+    }
+
+    /// a user at home and at work
+    struct CommutingFakeUser {
+        home: LatLng,
+        work: LatLng,
+    }
+
+    impl User for CommutingFakeUser {
+        fn location(&self) -> &LatLng {
+            &self.home
+        }
+    }
+
+    impl MultiLocationUser for CommutingFakeUser {
+        fn locations(&self) -> impl Iterator<Item = (&LatLng, f64)> {
+            [(&self.home, 0.6), (&self.work, 0.4)].into_iter()
+        }
+    }
+
+    #[test]
+    fn test_multi_location_scorer() {
+        let (nyc, london) = (ll!(-74.0060, 40.7128), ll!(-0.1278, 51.5074));
+        let users = vec![
+            CommutingFakeUser {
+                home: nyc.clone(),
+                work: london.clone(),
+            },
+            CommutingFakeUser {
+                home: nyc.clone(),
+                work: nyc.clone(),
+            },
+        ];
+        let scored = MultiLocationScorer::new()
+            .score_cell_list(CellList::new(6), users.into_iter())
+            .cell_list;
+        assert_eq!(scored[&CellID::from(&nyc).parent(6)], 160);
+        assert_eq!(scored[&CellID::from(&london).parent(6)], 40);
+        assert_eq!(scored.values().sum::<i32>(), 200);
     }
 }
@@ -0,0 +1,140 @@
+#![deny(missing_docs)]
+//! codegen contains `SqlTemplate`, which generates the SQL to bootstrap a table per shard after
+//! a reshard: the DDL of each shard's table, and a CASE expression routing cell tokens to them
+use s2::cellid::CellID;
+
+use crate::geoshard::GeoshardCollection;
+
+/// `SqlTemplate` names and creates a table per shard. In the table name template, `{shard}` is
+/// replaced by the shard's name, and in the DDL template `{table}` is replaced by the table's
+/// name and `{shard}` by the shard's name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlTemplate {
+    table: String,
+    ddl: String,
+}
+
+impl SqlTemplate {
+    /// Constructs a new `SqlTemplate`, e.g. with the table `users_{shard}` and the DDL
+    /// `CREATE TABLE {table} (id uuid PRIMARY KEY, cell_token text NOT NULL)`
+    pub fn new(table: impl Into<String>, ddl: impl Into<String>) -> Self {
+        Self {
+            table: table.into(),
+            ddl: ddl.into(),
+        }
+    }
+
+    /// returns the name of the shard's table
+    pub fn table_name(&self, shard_name: &str) -> String {
+        self.table.replace("{shard}", shard_name)
+    }
+
+    /// returns the DDL of each shard's table, in map order
+    pub fn ddl(&self, map: &GeoshardCollection) -> Vec<String> {
+        map.shards()
+            .iter()
+            .map(|shard| {
+                self.ddl
+                    .replace("{table}", &self.table_name(shard.name()))
+                    .replace("{shard}", shard.name())
+            })
+            .collect()
+    }
+
+    /// Returns a CASE expression evaluating to the table of the shard holding the cell whose
+    /// token is in `column`, or NULL for cells no shard holds. Each contiguous run of a shard's
+    /// cells is a range of leaf cell tokens, which sort like cell IDs, so it routes tokens of
+    /// cells at any level at or below the storage level
+    pub fn routing_case(&self, map: &GeoshardCollection, column: &str) -> String {
+        let mut ranges: Vec<(CellID, CellID, usize)> = map
+            .shards()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, shard)| {
+                shard
+                    .cell_union()
+                    .0
+                    .iter()
+                    .map(move |cell_id| (cell_id.range_min(), cell_id.range_max(), index))
+            })
+            .collect();
+        ranges.sort();
+        let mut merged: Vec<(CellID, CellID, usize)> = Vec::with_capacity(ranges.len());
+        for (first, last, shard) in ranges {
+            match merged.last_mut() {
+                Some(previous) if previous.2 == shard && previous.1.next() == first => {
+                    previous.1 = last
+                }
+                _ => merged.push((first, last, shard)),
+            }
+        }
+
+        let mut case = String::from("CASE\n");
+        for (first, last, shard) in merged {
+            case.push_str(&format!(
+                "  WHEN {column} BETWEEN {} AND {} THEN {}\n",
+                literal(&first.to_token()),
+                literal(&last.to_token()),
+                literal(&self.table_name(map.shards()[shard].name())),
+            ));
+        }
+        case.push_str("  ELSE NULL\nEND");
+        case
+    }
+}
+
+/// returns the value as a SQL string literal
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        cell_list::CellList,
+        geoshard::{Geoshard, GeoshardCollection},
+    };
+    use s2::cellunion::CellUnion;
+
+    #[test]
+    fn test_sql_template() {
+        let cells: Vec<CellID> = CellList::new(1).cell_list().keys().copied().collect();
+        let map = GeoshardCollection::from_shards(
+            1,
+            vec![
+                Geoshard::new(
+                    "geoshard_user_index_1".to_owned(),
+                    2,
+                    1,
+                    CellUnion(cells[..6].to_vec()),
+                ),
+                Geoshard::new("o'hare".to_owned(), 1, 1, CellUnion(cells[6..].to_vec())),
+            ],
+        );
+        let template = SqlTemplate::new(
+            "users_{shard}",
+            "CREATE TABLE {table} (id uuid PRIMARY KEY, shard text DEFAULT '{shard}')",
+        );
+        assert_eq!(
+            template.ddl(&map)[0],
+            "CREATE TABLE users_geoshard_user_index_1 \
+             (id uuid PRIMARY KEY, shard text DEFAULT 'geoshard_user_index_1')"
+        );
+
+        let case = template.routing_case(&map, "cell_token");
+        let lines: Vec<&str> = case.lines().collect();
+        // each shard holds one contiguous run of cells
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[1],
+            format!(
+                "  WHEN cell_token BETWEEN '{}' AND '{}' THEN 'users_geoshard_user_index_1'",
+                cells[0].range_min().to_token(),
+                cells[5].range_max().to_token()
+            )
+        );
+        assert!(lines[2].ends_with("THEN 'users_o''hare'"));
+        assert_eq!(lines[4], "END");
+    }
+}
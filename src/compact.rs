@@ -0,0 +1,306 @@
+#![deny(missing_docs)]
+//! compact contains a compact binary encoding of shard maps, for shipping large maps (e.g. at
+//! level 12) to clients that only route. Shards own contiguous ranges of the Hilbert curve, so
+//! the map is stored as the run of cells each shard owns along the curve, one varint pair per
+//! run, rather than as every cell's token. The encoding isn't compressed any further: there is
+//! no zstd mode, as the crate has no compression dependency, so callers wanting one compress the
+//! encoded bytes themselves
+use s2::{
+    cellid::{CellID, MAX_LEVEL},
+    cellunion::CellUnion,
+};
+
+use crate::{
+    error::GeoshardError,
+    geoshard::{Geoshard, GeoshardCollection},
+};
+
+/// header of a compact shard map, followed by a format version
+const COMPACT_MAGIC: &[u8; 4] = b"GSMC";
+
+/// version of the compact shard map format
+const COMPACT_VERSION: u8 = 1;
+
+impl GeoshardCollection {
+    /// Encodes the shard names, scores and boundaries. Each run of cells a shard owns along the
+    /// Hilbert curve is stored as its shard and its length in cells of the finest level in the
+    /// map, and cells no shard holds as runs of their own. Per cell scores, labels, the score
+    /// window and any fallback map are left out
+    pub fn to_compact(&self) -> Vec<u8> {
+        let unit_level = self
+            .shards()
+            .iter()
+            .flat_map(|shard| shard.cell_union().0.iter())
+            .map(|cell_id| cell_id.level())
+            .max()
+            .unwrap_or(self.storage_level());
+        let shift = 2 * (MAX_LEVEL - unit_level);
+
+        let mut cells: Vec<(u64, u64, usize)> = self
+            .shards()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, shard)| {
+                shard.cell_union().0.iter().map(move |cell_id| {
+                    (
+                        position(cell_id.range_min()) >> shift,
+                        (position(cell_id.range_max()) >> shift) + 1,
+                        index,
+                    )
+                })
+            })
+            .collect();
+        cells.sort();
+
+        // runs of (shard, length), where the shard count stands for cells no shard holds
+        let gap = self.shards().len();
+        let mut runs: Vec<(usize, u64)> = vec![];
+        let mut cursor = 0;
+        let mut push = |shard: usize, length: u64| match runs.last_mut() {
+            Some((last, last_length)) if *last == shard => *last_length += length,
+            _ => runs.push((shard, length)),
+        };
+        for (start, end, shard) in cells {
+            if start > cursor {
+                push(gap, start - cursor);
+            }
+            push(shard, end - start);
+            cursor = end;
+        }
+        let total = 6 << (2 * unit_level);
+        if cursor < total {
+            push(gap, total - cursor);
+        }
+
+        let mut bytes = COMPACT_MAGIC.to_vec();
+        bytes.extend([
+            COMPACT_VERSION,
+            self.storage_level() as u8,
+            unit_level as u8,
+        ]);
+        write_varint(&mut bytes, self.shards().len() as u64);
+        for shard in self.shards() {
+            write_varint(&mut bytes, shard.name().len() as u64);
+            bytes.extend(shard.name().as_bytes());
+            write_varint(&mut bytes, zigzag(shard.score() as i64));
+        }
+        write_varint(&mut bytes, runs.len() as u64);
+        for (shard, length) in runs {
+            write_varint(&mut bytes, shard as u64);
+            write_varint(&mut bytes, length);
+        }
+        bytes
+    }
+
+    /// Decodes a map encoded by `to_compact`. Each shard holds the fewest cells covering its
+    /// ranges, so coarser cells than the storage level where whole parents belong to a shard.
+    /// Fails with `GeoshardError::InvalidShardMap` if the bytes are not a compact map
+    pub fn from_compact(bytes: &[u8]) -> Result<Self, GeoshardError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != COMPACT_MAGIC {
+            return Err(invalid("not a compact shard map"));
+        }
+        let header = reader.take(3)?;
+        if header[0] != COMPACT_VERSION {
+            return Err(invalid(&format!(
+                "unsupported compact shard map version {}",
+                header[0]
+            )));
+        }
+        let (storage_level, unit_level) = (header[1] as u64, header[2] as u64);
+        if storage_level > MAX_LEVEL || unit_level > MAX_LEVEL {
+            return Err(invalid(&format!(
+                "invalid levels {} and {}",
+                storage_level, unit_level
+            )));
+        }
+
+        let shard_count = reader.varint()? as usize;
+        let mut shards: Vec<(String, i32, Vec<CellID>)> = vec![];
+        for _ in 0..shard_count {
+            let length = reader.varint()? as usize;
+            let name = String::from_utf8(reader.take(length)?.to_vec())
+                .map_err(|_| invalid("shard name is not UTF-8"))?;
+            let score = unzigzag(reader.varint()?) as i32;
+            shards.push((name, score, vec![]));
+        }
+
+        let shift = 2 * (MAX_LEVEL - unit_level);
+        let total = 6u64 << (2 * unit_level);
+        let mut cursor = 0u64;
+        for _ in 0..reader.varint()? {
+            let shard = reader.varint()? as usize;
+            let length = reader.varint()?;
+            let end = cursor
+                .checked_add(length)
+                .filter(|end| *end <= total && length > 0)
+                .ok_or_else(|| invalid("runs go past the last cell"))?;
+            if shard < shards.len() {
+                let range = CellUnion::from_range(leaf(cursor << shift), leaf(end << shift));
+                shards[shard].2.extend(range.0);
+            } else if shard > shards.len() {
+                return Err(invalid(&format!("run of unknown shard {}", shard)));
+            }
+            cursor = end;
+        }
+        if reader.offset != bytes.len() {
+            return Err(invalid("trailing bytes after the runs"));
+        }
+
+        let shards = shards
+            .into_iter()
+            .map(|(name, score, cells)| Geoshard::new(name, score, storage_level, CellUnion(cells)))
+            .collect();
+        Ok(GeoshardCollection::from_shards(storage_level, shards))
+    }
+}
+
+/// returns the position of a leaf cell along the Hilbert curve, across all faces
+fn position(leaf: CellID) -> u64 {
+    leaf.0 >> 1
+}
+
+/// returns the leaf cell at a position along the Hilbert curve
+fn leaf(position: u64) -> CellID {
+    CellID((position << 1) | 1)
+}
+
+/// returns the error for a compact map that can't be decoded
+fn invalid(reason: &str) -> GeoshardError {
+    GeoshardError::InvalidShardMap {
+        reason: format!("invalid compact shard map: {}", reason),
+        shard: None,
+        cell: None,
+    }
+}
+
+/// maps signed values to unsigned ones, small magnitudes to small values
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// reverses `zigzag`
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// writes the value as a LEB128 varint
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// reads a compact shard map
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], GeoshardError> {
+        let bytes = self
+            .offset
+            .checked_add(length)
+            .and_then(|end| self.bytes.get(self.offset..end))
+            .ok_or_else(|| invalid("truncated"))?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, GeoshardError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        cell_list::CellList,
+        geoshard::{GeoshardBuilder, GeoshardSearcher, MissPolicy},
+        testing::{FakeUser, RandCityFactory},
+    };
+
+    #[test]
+    fn test_compact() {
+        let users = FakeUser::seeded(1000, 23, &RandCityFactory::default());
+        let geoshards = GeoshardBuilder::user_count_scorer(8, users.iter(), 10, 20)
+            .with_cached_cells()
+            .build();
+        let compact = geoshards.to_compact();
+        let json = serde_json::to_vec(&geoshards).unwrap();
+        assert!(compact.len() * 100 < json.len());
+
+        let decoded = GeoshardCollection::from_compact(&compact).unwrap();
+        assert_eq!(decoded.storage_level(), 8);
+        assert_eq!(decoded.shards().len(), geoshards.shards().len());
+        for (decoded, shard) in decoded.shards().iter().zip(geoshards.shards()) {
+            assert_eq!(decoded.name(), shard.name());
+            assert_eq!(decoded.score(), shard.score());
+        }
+        let searcher = GeoshardSearcher::from(geoshards);
+        let decoded = GeoshardSearcher::from(decoded);
+        for cell_id in CellList::cached(8).cell_list().keys().step_by(7) {
+            assert_eq!(
+                decoded.get_shard_from_cell_id(cell_id).name(),
+                searcher.get_shard_from_cell_id(cell_id).name()
+            );
+        }
+
+        assert!(GeoshardCollection::from_compact(&compact[..compact.len() - 1]).is_err());
+        assert!(GeoshardCollection::from_compact(b"GSMC").is_err());
+        assert!(GeoshardCollection::from_compact(&json).is_err());
+    }
+
+    #[test]
+    fn test_compact_gaps() {
+        // a map missing most of the globe keeps the gaps unmapped
+        let cells: Vec<CellID> = CellList::new(4)
+            .cell_list()
+            .keys()
+            .copied()
+            .take(10)
+            .collect();
+        let geoshards = GeoshardCollection::from_shards(
+            4,
+            vec![
+                Geoshard::new("a".to_owned(), 3, 4, CellUnion(cells[..4].to_vec())),
+                Geoshard::new("b".to_owned(), -2, 4, CellUnion(cells[6..].to_vec())),
+            ],
+        );
+        let decoded = GeoshardCollection::from_compact(&geoshards.to_compact()).unwrap();
+        assert_eq!(decoded.shards()[1].score(), -2);
+        let searcher = GeoshardSearcher::from(decoded)
+            .with_miss_policy(MissPolicy::Error)
+            .unwrap();
+        assert_eq!(
+            searcher
+                .try_get_shard_from_cell_id(&cells[0])
+                .unwrap()
+                .name(),
+            "a"
+        );
+        assert_eq!(
+            searcher
+                .try_get_shard_from_cell_id(&cells[7])
+                .unwrap()
+                .name(),
+            "b"
+        );
+        assert!(searcher.try_get_shard_from_cell_id(&cells[5]).is_err());
+        assert!(searcher
+            .try_get_shard_from_cell_id(&cells[10 - 1].next())
+            .is_err());
+    }
+}
@@ -0,0 +1,511 @@
+#![deny(missing_docs)]
+//! datagen generates synthetic users for capacity tests of candidate shard maps, following a
+//! `PopulationDistribution`: uniform over the globe, weighted by the population of real cities,
+//! or concentrated on a few Zipfian hotspots. `UserGenerator` is seeded, so the same
+//! configuration always generates the same users, and `UserGenerator::shard_counts` routes them
+//! against a map to show how it would hold up. Enabled by the `datagen` feature
+#[cfg(feature = "searcher")]
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+use rand::{
+    distributions::WeightedIndex, prelude::Distribution, rngs::StdRng, thread_rng, Rng, SeedableRng,
+};
+use s2::latlng::LatLng;
+
+#[cfg(feature = "searcher")]
+use crate::geoshard::GeoshardSearcher;
+use crate::{
+    geoshard::EARTH_RADIUS,
+    users::{IdentifiedUser, User},
+    utils::ll,
+};
+
+/// the largest cities of the world with their coordinates and population, in thousands
+const WORLD_CITIES: &str = include_str!("../data/world_cities.csv");
+
+/// `RandCityFactory` picks random locations out of a list of cities, each with a weight
+/// controlling how often it's picked relative to the others
+#[derive(Debug, Clone)]
+pub struct RandCityFactory {
+    cities: Vec<LatLng>,
+    weights: WeightedIndex<u32>,
+}
+
+impl RandCityFactory {
+    /// Constructs a new `RandCityFactory` where every city is equally likely
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cities` is empty
+    pub fn new(cities: Vec<LatLng>) -> Self {
+        Self::weighted(cities.into_iter().map(|city| (city, 1)).collect())
+    }
+
+    /// Constructs a new `RandCityFactory` where each city is picked in proportion to its weight,
+    /// e.g. its population
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cities` is empty or every weight is 0
+    pub fn weighted(cities: Vec<(LatLng, u32)>) -> Self {
+        let weights = WeightedIndex::new(cities.iter().map(|(_, weight)| *weight))
+            .expect("cities must have at least one positive weight");
+        Self {
+            cities: cities.into_iter().map(|(city, _)| city).collect(),
+            weights,
+        }
+    }
+
+    /// Constructs a `RandCityFactory` of locations at the edges of lat/lng space: on and around
+    /// the antimeridian (including longitudes past ±180), and at and near both poles. Routing and
+    /// radius queries should be as consistent there as anywhere else
+    pub fn edge_cases() -> Self {
+        Self::new(vec![
+            ll!(180.0, 0.0),
+            ll!(-180.0, 0.0),
+            ll!(179.99, 10.0),
+            ll!(-179.99, 10.0),
+            ll!(181.0, 5.0),
+            ll!(-179.0, 5.0),
+            ll!(540.0, -30.0),
+            ll!(0.0, 90.0),
+            ll!(123.0, 90.0),
+            ll!(45.0, 89.9),
+            ll!(0.0, -90.0),
+            ll!(-135.0, -89.9),
+        ])
+    }
+
+    /// Constructs a `RandCityFactory` of the world's largest cities, each picked in proportion to
+    /// its population
+    pub fn world_cities() -> Self {
+        Self::weighted(
+            WORLD_CITIES
+                .lines()
+                .skip(1)
+                .map(|line| {
+                    let fields: Vec<&str> = line.split(',').collect();
+                    let number = |index: usize| fields[index].parse::<f64>().unwrap();
+                    (ll!(number(3), number(2)), number(4) as u32)
+                })
+                .collect(),
+        )
+    }
+
+    /// returns a random city
+    pub fn new_city(&self) -> LatLng {
+        self.new_city_from(&mut thread_rng())
+    }
+
+    /// returns a random city picked with the given RNG, so picks can be reproduced from a seed
+    pub fn new_city_from<R: Rng + ?Sized>(&self, rng: &mut R) -> LatLng {
+        self.cities[self.weights.sample(rng)].clone()
+    }
+
+    /// returns the cities this factory picks from
+    pub fn cities(&self) -> &[LatLng] {
+        &self.cities
+    }
+}
+
+impl Default for RandCityFactory {
+    fn default() -> Self {
+        let cities: Vec<LatLng> = vec![
+            ll!(40.745255, 40.745255),
+            ll!(34.155834, 34.155834),
+            ll!(42.933334, 42.933334),
+            ll!(42.095554, 42.095554),
+            ll!(38.846668, 38.846668),
+            ll!(41.392502, 41.392502),
+            ll!(27.192223, 27.192223),
+            ll!(31.442778, 31.442778),
+            ll!(40.560001, 40.560001),
+            ll!(33.193611, 33.193611),
+            ll!(41.676388, 41.676388),
+            ll!(41.543056, 41.543056),
+            ll!(39.554443, 39.554443),
+            ll!(44.513332, 44.513332),
+            ll!(37.554169, 37.554169),
+            ll!(32.349998, 32.349998),
+            ll!(29.499722, 29.499722),
+            ll!(33.038334, 33.038334),
+            ll!(43.614166, 43.614166),
+            ll!(41.55611, 41.55611),
+            ll!(34.00, 34.00),
+            ll!(26.709723, 26.709723),
+            ll!(38.005001, 38.005001),
+            ll!(35.970554, 35.970554),
+            ll!(25.942122, 25.942122),
+            ll!(33.569443, 33.569443),
+            ll!(39.799999, 39.799999),
+            ll!(34.073334, 34.073334),
+            ll!(40.606388, 40.606388),
+            ll!(30.601389, 30.601389),
+            ll!(38.257778, 38.257778),
+            ll!(37.977222, 37.977222),
+            ll!(42.373611, 42.373611),
+            ll!(32.965557, 32.965557),
+            ll!(37.871666, 37.871666),
+            ll!(38.951561, 38.951561),
+            ll!(33.950001, 33.950001),
+            ll!(30.216667, 30.216667),
+            ll!(42.580276, 42.580276),
+            ll!(36.316666, 36.316666),
+            ll!(37.034946, 37.034946),
+            ll!(40.689167, 40.689167),
+            ll!(33.630554, 33.630554),
+            ll!(39.903057, 39.903057),
+            ll!(25.978889, 25.978889),
+            ll!(35.846111, 35.846111),
+            ll!(34.156113, 34.156113),
+            ll!(41.18639, 41.18639),
+            ll!(40.914745, 40.914745),
+            ll!(42.259445, 42.259445),
+            ll!(41.520557, 41.520557),
+            ll!(33.124722, 33.124722),
+            ll!(39.106667, 39.106667),
+            ll!(42.101391, 42.101391),
+            ll!(37.210388, 37.210388),
+            ll!(33.866669, 33.866669),
+            ll!(26.012501, 26.012501),
+            ll!(38.438332, 38.438332),
+            ll!(33.211666, 33.211666),
+            ll!(37.070831, 37.070831),
+            ll!(43.536388, 43.536388),
+            ll!(45.633331, 45.633331),
+            ll!(42.271389, 42.271389),
+            ll!(30.455, 30.455),
+            ll!(32.492222, 32.492222),
+            ll!(33.466667, 33.466667),
+            ll!(32.361668, 32.361668),
+            ll!(41.763889, 41.763889),
+            ll!(35.199165, 35.199165),
+            ll!(37.661388, 37.661388),
+            ll!(32.907223, 32.907223),
+            ll!(33.669445, 33.669445),
+            ll!(39.710835, 39.710835),
+            ll!(32.705002, 32.705002),
+            ll!(39.099724, 39.099724),
+            ll!(35.1175, 35.1175),
+            ll!(39.791, 39.791),
+            ll!(39.983334, 39.983334),
+            ll!(30.266666, 30.266666),
+            ll!(32.779167, 32.779167),
+            ll!(37.487846, 37.487846),
+            ll!(35.25528, 35.25528),
+            ll!(29.700001, 29.700001),
+            ll!(26.838619, 26.838619),
+            ll!(38.473625, 38.473625),
+            ll!(29.749907, 29.749907),
+            ll!(40.191891, 40.191891),
+            ll!(33.830517, 33.830517),
+            ll!(34.496212, 34.496212),
+            ll!(37.54129, 37.54129),
+            ll!(36.082157, 36.082157),
+            ll!(32.698437, 32.698437),
+            ll!(33.580944, 33.580944),
+            ll!(33.427204, 33.427204),
+            ll!(34.028622, 34.028622),
+            ll!(32.609856, 32.609856),
+            ll!(33.405746, 33.405746),
+            ll!(34.603817, 34.603817),
+            ll!(44.840797, 44.840797),
+            ll!(71.290558, 71.290558),
+        ];
+        Self::new(cities)
+    }
+}
+
+/// `PopulationDistribution` is where a `UserGenerator` places users
+#[derive(Debug, Clone)]
+pub enum PopulationDistribution {
+    /// uniformly over the surface of the globe, oceans included
+    Uniform,
+    /// around cities picked by a `RandCityFactory`, e.g. `RandCityFactory::world_cities` to
+    /// follow real populations
+    Cities(RandCityFactory),
+    /// around hotspots whose share of users follows Zipf's law: the hotspot of rank k (from 1)
+    /// gets users in proportion to 1 / k^exponent
+    Zipf {
+        /// the hotspots, from the most to the least popular
+        hotspots: Vec<LatLng>,
+        /// the exponent of the distribution, e.g. 1.0 for the classic Zipf's law
+        exponent: f64,
+    },
+}
+
+impl PopulationDistribution {
+    /// returns the distribution of users around the world's largest cities, weighted by their
+    /// population
+    pub fn world_cities() -> Self {
+        PopulationDistribution::Cities(RandCityFactory::world_cities())
+    }
+}
+
+/// `SyntheticUser` is a user generated by a `UserGenerator`, identified by the order it was
+/// generated in
+#[derive(Debug, Clone)]
+pub struct SyntheticUser {
+    id: [u8; 8],
+    location: LatLng,
+}
+
+impl PartialEq for SyntheticUser {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.location.lat == other.location.lat
+            && self.location.lng == other.location.lng
+    }
+}
+
+impl SyntheticUser {
+    /// returns the position of the user among the generated users, from 0
+    pub fn index(&self) -> u64 {
+        u64::from_be_bytes(self.id)
+    }
+}
+
+impl User for SyntheticUser {
+    fn location(&self) -> &LatLng {
+        &self.location
+    }
+}
+
+impl User for &SyntheticUser {
+    fn location(&self) -> &LatLng {
+        &self.location
+    }
+}
+
+impl IdentifiedUser for SyntheticUser {
+    fn id(&self) -> &[u8] {
+        &self.id
+    }
+}
+
+/// `UserGenerator` generates users following a `PopulationDistribution`, scattered uniformly
+/// within `spread_km` of the cities or hotspots they're placed around
+#[derive(Debug, Clone)]
+pub struct UserGenerator {
+    distribution: PopulationDistribution,
+    spread_km: f64,
+    seed: u64,
+}
+
+impl UserGenerator {
+    /// Constructs a new `UserGenerator` with the seed 0, scattering users within 25km of their
+    /// city or hotspot
+    pub fn new(distribution: PopulationDistribution) -> Self {
+        Self {
+            distribution,
+            spread_km: 25.0,
+            seed: 0,
+        }
+    }
+
+    /// sets the seed the users are generated from
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// sets how far from their city or hotspot users are scattered, in kilometers. 0 places
+    /// every user exactly on it
+    pub fn with_spread_km(mut self, spread_km: f64) -> Self {
+        self.spread_km = spread_km;
+        self
+    }
+
+    /// returns the first `count` users, generated lazily so large counts don't need to be held
+    /// in memory at once
+    ///
+    /// # Panics
+    ///
+    /// Panics if the distribution is `Zipf` without hotspots, or with an exponent that isn't
+    /// finite
+    pub fn users(&self, count: usize) -> SyntheticUsers<'_> {
+        let hotspot_weights = match &self.distribution {
+            PopulationDistribution::Zipf { hotspots, exponent } => Some(
+                WeightedIndex::new(
+                    (1..=hotspots.len()).map(|rank| 1.0 / (rank as f64).powf(*exponent)),
+                )
+                .expect("a Zipf distribution needs hotspots and a finite exponent"),
+            ),
+            _ => None,
+        };
+        SyntheticUsers {
+            generator: self,
+            rng: StdRng::seed_from_u64(self.seed),
+            hotspot_weights,
+            next: 0,
+            count: count as u64,
+        }
+    }
+
+    /// returns the first `count` users, see `users`
+    pub fn generate(&self, count: usize) -> Vec<SyntheticUser> {
+        self.users(count).collect()
+    }
+
+    /// returns the number of the first `count` users each shard of the searcher's map would
+    /// hold, keyed by shard name. Shards holding none of them are listed with 0
+    #[cfg(feature = "searcher")]
+    pub fn shard_counts(
+        &self,
+        count: usize,
+        searcher: &GeoshardSearcher,
+    ) -> BTreeMap<String, usize> {
+        let mut counts: BTreeMap<String, usize> = searcher
+            .shards()
+            .shards()
+            .iter()
+            .map(|shard| (shard.name().to_owned(), 0))
+            .collect();
+        for user in self.users(count) {
+            *counts
+                .entry(searcher.get_shard_for_user(&user).name().to_owned())
+                .or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// `SyntheticUsers` iterates over the users of a `UserGenerator`, see `UserGenerator::users`
+pub struct SyntheticUsers<'a> {
+    generator: &'a UserGenerator,
+    rng: StdRng,
+    hotspot_weights: Option<WeightedIndex<f64>>,
+    next: u64,
+    count: u64,
+}
+
+impl SyntheticUsers<'_> {
+    /// returns a location uniformly distributed over the globe
+    fn uniform(&mut self) -> LatLng {
+        let lat = (2.0 * self.rng.gen::<f64>() - 1.0).asin().to_degrees();
+        let lng = 360.0 * self.rng.gen::<f64>() - 180.0;
+        ll!(lng, lat)
+    }
+
+    /// returns a location uniformly distributed within the spread of `center`
+    fn scatter(&mut self, center: LatLng) -> LatLng {
+        if self.generator.spread_km <= 0.0 {
+            return center;
+        }
+        let distance =
+            self.generator.spread_km * 1000.0 * self.rng.gen::<f64>().sqrt() / EARTH_RADIUS;
+        let bearing = 2.0 * PI * self.rng.gen::<f64>();
+        let (lat, lng) = (center.lat.rad(), center.lng.rad());
+        let scattered_lat =
+            (lat.sin() * distance.cos() + lat.cos() * distance.sin() * bearing.cos()).asin();
+        let scattered_lng = lng
+            + (bearing.sin() * distance.sin() * lat.cos())
+                .atan2(distance.cos() - lat.sin() * scattered_lat.sin());
+        let lng = (scattered_lng.to_degrees() + 180.0).rem_euclid(360.0) - 180.0;
+        ll!(lng, scattered_lat.to_degrees())
+    }
+}
+
+impl Iterator for SyntheticUsers<'_> {
+    type Item = SyntheticUser;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.count {
+            return None;
+        }
+        let location = match &self.generator.distribution {
+            PopulationDistribution::Uniform => self.uniform(),
+            PopulationDistribution::Cities(cities) => {
+                let city = cities.new_city_from(&mut self.rng);
+                self.scatter(city)
+            }
+            PopulationDistribution::Zipf { hotspots, .. } => {
+                let weights = self.hotspot_weights.as_ref().unwrap();
+                let hotspot = hotspots[weights.sample(&mut self.rng)].clone();
+                self.scatter(hotspot)
+            }
+        };
+        let user = SyntheticUser {
+            id: self.next.to_be_bytes(),
+            location,
+        };
+        self.next += 1;
+        Some(user)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.count - self.next) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::geoshard::GeoshardBuilder;
+
+    #[test]
+    fn test_user_generator() {
+        let world_cities = RandCityFactory::world_cities();
+        assert_eq!(world_cities.cities().len(), 56);
+
+        let generator = UserGenerator::new(PopulationDistribution::world_cities()).with_seed(7);
+        let users = generator.generate(2000);
+        assert_eq!(users, generator.generate(2000));
+        assert_ne!(users, generator.clone().with_seed(8).generate(2000));
+        assert_eq!(users[1999].index(), 1999);
+        // every user is within the spread of a city
+        for user in users.iter().step_by(50) {
+            let nearest = world_cities
+                .cities()
+                .iter()
+                .map(|city| city.distance(user.location()).rad() * EARTH_RADIUS)
+                .fold(f64::MAX, f64::min);
+            assert!(
+                nearest <= 25_000.0 + 1.0,
+                "{} from the nearest city",
+                nearest
+            );
+        }
+
+        // the top hotspot of a Zipf distribution gets about half of the users of 3 hotspots
+        // with exponent 1 (1 / (1 + 1/2 + 1/3))
+        let hotspots = world_cities.cities()[..3].to_vec();
+        let zipf = UserGenerator::new(PopulationDistribution::Zipf {
+            hotspots: hotspots.clone(),
+            exponent: 1.0,
+        })
+        .with_spread_km(0.0);
+        let top = zipf
+            .users(6000)
+            .filter(|user| {
+                user.location().lat == hotspots[0].lat && user.location().lng == hotspots[0].lng
+            })
+            .count();
+        assert!(
+            (3000..3550).contains(&top),
+            "{} users at the top hotspot",
+            top
+        );
+
+        let uniform = UserGenerator::new(PopulationDistribution::Uniform).generate(4000);
+        let northern = uniform
+            .iter()
+            .filter(|user| user.location().lat.deg() > 0.0)
+            .count();
+        assert!((1800..2200).contains(&northern));
+        // a quarter of the globe's area is above 30 degrees north
+        let above_30 = uniform
+            .iter()
+            .filter(|user| user.location().lat.deg() > 30.0)
+            .count();
+        assert!((850..1150).contains(&above_30), "{} above 30N", above_30);
+
+        let shards = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let searcher = GeoshardSearcher::from(shards);
+        let counts = generator.shard_counts(2000, &searcher);
+        assert_eq!(counts.len(), searcher.shards().shards().len());
+        assert_eq!(counts.values().sum::<usize>(), 2000);
+    }
+}
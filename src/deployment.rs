@@ -0,0 +1,83 @@
+#![deny(missing_docs)]
+//! deployment contains startup checks that a shard map is consistent with the infrastructure
+//! actually deployed, e.g. the databases or services backing each shard
+use std::collections::BTreeSet;
+
+use crate::{error::GeoshardError, geoshard::GeoshardCollection};
+
+/// DeploymentCheck is the result of comparing a shard map against the deployed shards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeploymentCheck {
+    orphaned: Vec<String>,
+}
+
+impl DeploymentCheck {
+    /// Confirms every shard in the map has a deployed target with the same name. Fails with
+    /// `GeoshardError::MissingDeployments` if any shard is unbacked, otherwise returns the check,
+    /// which flags deployed targets no shard routes to. Intended to run at boot and in CI
+    /// against infrastructure inventories
+    pub fn validate(
+        map: &GeoshardCollection,
+        deployed_shards: &[String],
+    ) -> Result<Self, GeoshardError> {
+        let deployed: BTreeSet<&str> = deployed_shards.iter().map(String::as_str).collect();
+        let mapped: BTreeSet<&str> = map.shards().iter().map(|shard| shard.name()).collect();
+
+        let missing: Vec<String> = mapped
+            .difference(&deployed)
+            .map(|name| name.to_string())
+            .collect();
+        let orphaned: Vec<String> = deployed
+            .difference(&mapped)
+            .map(|name| name.to_string())
+            .collect();
+
+        if !missing.is_empty() {
+            return Err(GeoshardError::MissingDeployments { missing, orphaned });
+        }
+        Ok(Self { orphaned })
+    }
+
+    /// returns the deployed targets that no shard in the map routes to
+    pub fn orphaned(&self) -> &[String] {
+        &self.orphaned
+    }
+
+    /// returns true if every deployed target is used by the map
+    pub fn is_exact(&self) -> bool {
+        self.orphaned.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::testing::uniform_collection;
+
+    #[test]
+    fn test_validate() {
+        let geoshards = uniform_collection(2, 48);
+        let mut deployed: Vec<String> = geoshards
+            .shards()
+            .iter()
+            .map(|shard| shard.name().to_owned())
+            .collect();
+
+        assert!(DeploymentCheck::validate(&geoshards, &deployed)
+            .unwrap()
+            .is_exact());
+
+        deployed.push("geoshard_user_index_99".to_owned());
+        let check = DeploymentCheck::validate(&geoshards, &deployed).unwrap();
+        assert_eq!(check.orphaned(), ["geoshard_user_index_99".to_owned()]);
+
+        deployed.remove(0);
+        match DeploymentCheck::validate(&geoshards, &deployed) {
+            Err(GeoshardError::MissingDeployments { missing, orphaned }) => {
+                assert_eq!(missing, vec!["geoshard_user_index_1".to_owned()]);
+                assert_eq!(orphaned, vec!["geoshard_user_index_99".to_owned()]);
+            }
+            other => panic!("expected missing deployments, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+#![deny(missing_docs)]
+//! diurnal contains time bucketed shard maps, for load that shifts over the day (e.g. towards
+//! offices in work hours and homes in the evening): a map per window of the day, built from
+//! time stamped activity, and `DiurnalGeoshardSearcher`, which routes with the map of the
+//! window a time falls in
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::GeoshardError,
+    geoshard::{GeoshardCollection, GeoshardSearcher},
+    users::ActiveUser,
+};
+
+/// seconds in a day
+const DAY: u64 = 86_400;
+
+/// `TimeBuckets` splits each day into windows of local time, e.g. 6 windows of 4 hours. When the
+/// width doesn't divide a day, the last window is shorter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TimeBuckets {
+    width: u64,
+    utc_offset: i64,
+}
+
+impl TimeBuckets {
+    /// Constructs a new `TimeBuckets` of windows of `width` (at least a second, and at most a day)
+    /// from midnight UTC
+    pub fn new(width: Duration) -> Self {
+        Self {
+            width: width.as_secs().clamp(1, DAY),
+            utc_offset: 0,
+        }
+    }
+
+    /// sets the offset of local time from UTC, in seconds, so windows start at local midnight
+    pub fn with_utc_offset(mut self, utc_offset: i64) -> Self {
+        self.utc_offset = utc_offset;
+        self
+    }
+
+    /// returns the number of windows in a day
+    pub fn count(&self) -> usize {
+        DAY.div_ceil(self.width) as usize
+    }
+
+    /// returns the width of the windows
+    pub fn width(&self) -> Duration {
+        Duration::from_secs(self.width)
+    }
+
+    /// returns the index of the window `time` falls in
+    pub fn bucket(&self, time: SystemTime) -> usize {
+        let seconds = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(error) => -(error.duration().as_secs() as i64),
+        };
+        let time_of_day = (seconds + self.utc_offset).rem_euclid(DAY as i64) as u64;
+        (time_of_day / self.width) as usize
+    }
+}
+
+/// `TimeBucketedMaps` is a shard map per window of the day, serialized together so they are
+/// stored and rolled out as one
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TimeBucketedMaps {
+    buckets: TimeBuckets,
+    maps: Vec<GeoshardCollection>,
+}
+
+impl TimeBucketedMaps {
+    /// Constructs a new `TimeBucketedMaps` from the map of each window, in order. Fails with
+    /// `GeoshardError::InvalidShardMap` unless there is a map per window
+    pub fn new(buckets: TimeBuckets, maps: Vec<GeoshardCollection>) -> Result<Self, GeoshardError> {
+        let maps = Self { buckets, maps };
+        maps.verify()?;
+        Ok(maps)
+    }
+
+    /// Builds a map per window from time stamped activity, e.g. a user per check-in. The activity
+    /// is split by the window its `last_active` falls in, and `build` builds each window's map
+    /// from its activity (e.g. with `GeoshardBuilder::user_count_scorer(...).try_build()`), with
+    /// the window's index. Every window is built, even without activity
+    pub fn build<T, F>(
+        buckets: TimeBuckets,
+        activity: impl IntoIterator<Item = T>,
+        mut build: F,
+    ) -> Result<Self, GeoshardError>
+    where
+        T: ActiveUser,
+        F: FnMut(usize, std::vec::IntoIter<T>) -> Result<GeoshardCollection, GeoshardError>,
+    {
+        let mut bucketed: Vec<Vec<T>> = (0..buckets.count()).map(|_| vec![]).collect();
+        for user in activity {
+            bucketed[buckets.bucket(user.last_active())].push(user);
+        }
+        let maps = bucketed
+            .into_iter()
+            .enumerate()
+            .map(|(bucket, users)| build(bucket, users.into_iter()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { buckets, maps })
+    }
+
+    /// returns the windows
+    pub fn buckets(&self) -> &TimeBuckets {
+        &self.buckets
+    }
+
+    /// returns the map of each window, in order
+    pub fn maps(&self) -> &[GeoshardCollection] {
+        &self.maps
+    }
+
+    /// checks there is a map per window, e.g. after deserializing
+    pub fn verify(&self) -> Result<(), GeoshardError> {
+        if self.maps.len() != self.buckets.count() {
+            return Err(GeoshardError::InvalidShardMap {
+                reason: format!(
+                    "{} maps for {} time buckets",
+                    self.maps.len(),
+                    self.buckets.count()
+                ),
+                shard: None,
+                cell: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// `DiurnalGeoshardSearcher` routes with the shard map of the window of the day a time falls in
+pub struct DiurnalGeoshardSearcher {
+    buckets: TimeBuckets,
+    searchers: Vec<GeoshardSearcher>,
+}
+
+impl DiurnalGeoshardSearcher {
+    /// returns the searcher of the window `time` falls in
+    pub fn at(&self, time: SystemTime) -> &GeoshardSearcher {
+        &self.searchers[self.buckets.bucket(time)]
+    }
+
+    /// returns the searcher of the current window
+    pub fn now(&self) -> &GeoshardSearcher {
+        self.at(SystemTime::now())
+    }
+
+    /// returns the windows
+    pub fn buckets(&self) -> &TimeBuckets {
+        &self.buckets
+    }
+}
+
+impl TryFrom<TimeBucketedMaps> for DiurnalGeoshardSearcher {
+    type Error = GeoshardError;
+
+    fn try_from(maps: TimeBucketedMaps) -> Result<Self, GeoshardError> {
+        maps.verify()?;
+        Ok(Self {
+            buckets: maps.buckets,
+            searchers: maps.maps.into_iter().map(GeoshardSearcher::from).collect(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use s2::{cellid::CellID, latlng::LatLng};
+
+    use super::*;
+    use crate::{geoshard::GeoshardBuilder, users::User, utils::ll};
+
+    struct CheckIn {
+        location: LatLng,
+        at: SystemTime,
+    }
+
+    impl User for CheckIn {
+        fn location(&self) -> &LatLng {
+            &self.location
+        }
+    }
+
+    impl ActiveUser for CheckIn {
+        fn last_active(&self) -> SystemTime {
+            self.at
+        }
+    }
+
+    #[test]
+    fn test_time_buckets() {
+        let hour = Duration::from_secs(3600);
+        let buckets = TimeBuckets::new(hour * 4);
+        assert_eq!(buckets.count(), 6);
+        assert_eq!(buckets.bucket(UNIX_EPOCH + hour * 9), 2);
+        assert_eq!(buckets.bucket(UNIX_EPOCH + hour * (24 * 3 + 23)), 5);
+        // 9:00 UTC is 4:00 at UTC-5
+        assert_eq!(
+            buckets
+                .with_utc_offset(-5 * 3600)
+                .bucket(UNIX_EPOCH + hour * 9),
+            1
+        );
+        assert_eq!(TimeBuckets::new(hour * 5).count(), 5);
+    }
+
+    #[test]
+    fn test_diurnal_searcher() {
+        let hour = Duration::from_secs(3600);
+        let day = UNIX_EPOCH + hour * 24 * 100;
+        let (office, suburb) = (ll!(-74.0, 40.7), ll!(-87.6, 41.9));
+        let mut activity = vec![];
+        for minute in 0..200u64 {
+            // mornings are busy at the office, evenings in the suburbs
+            activity.push(CheckIn {
+                location: office.clone(),
+                at: day + hour * 8 + Duration::from_secs(minute * 60),
+            });
+            activity.push(CheckIn {
+                location: suburb.clone(),
+                at: day + hour * 20 + Duration::from_secs(minute * 60),
+            });
+        }
+
+        let buckets = TimeBuckets::new(hour * 12);
+        let mut built = vec![];
+        let maps = TimeBucketedMaps::build(buckets, activity, |bucket, users| {
+            built.push(bucket);
+            GeoshardBuilder::user_count_scorer(4, users, 2, 4).try_build()
+        })
+        .unwrap();
+        assert_eq!(built, vec![0, 1]);
+
+        let json = serde_json::to_string(&maps).unwrap();
+        let maps: TimeBucketedMaps = serde_json::from_str(&json).unwrap();
+        let searcher = DiurnalGeoshardSearcher::try_from(maps).unwrap();
+        // the score of the location's cell in the map used at the time
+        let score_at = |time: SystemTime, location: &LatLng| {
+            let shard = searcher.at(time).get_shard_from_location(location);
+            let cell_id = CellID::from(location).parent(4);
+            shard
+                .cell_union()
+                .0
+                .iter()
+                .zip(shard.cell_scores())
+                .find(|(shard_cell_id, _)| **shard_cell_id == cell_id)
+                .map(|(_, score)| *score)
+        };
+        assert_eq!(score_at(day + hour * 9, &office), Some(200));
+        assert_eq!(score_at(day + hour * 9, &suburb), Some(0));
+        assert_eq!(score_at(day + hour * 21, &office), Some(0));
+        assert_eq!(score_at(day + hour * 21, &suburb), Some(200));
+
+        assert!(matches!(
+            TimeBucketedMaps::new(buckets, vec![]),
+            Err(GeoshardError::InvalidShardMap { .. })
+        ));
+    }
+}
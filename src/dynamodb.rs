@@ -0,0 +1,404 @@
+#![deny(missing_docs)]
+//! dynamodb contains the DynamoDB plumbing of a builder job behind the `dynamodb` feature:
+//! `ParallelScan`, a user collection running a parallel segmented scan of a table, and helpers
+//! turning shard names into partition keys. Scan requests go through a `SegmentScanner`, so any
+//! DynamoDB client (such as `aws-sdk-dynamodb`) can be plugged in
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc,
+    },
+    thread,
+};
+
+use s2::latlng::LatLng;
+
+use crate::{users::User, utils::ll};
+
+/// `AttributeValue` is a DynamoDB attribute value, as far as scans need to read them
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    /// a string (`S`)
+    S(String),
+    /// a number (`N`), as DynamoDB sends it
+    N(String),
+    /// any other type of value
+    Other,
+}
+
+/// `Item` is a DynamoDB item, by attribute name
+pub type Item = HashMap<String, AttributeValue>;
+
+/// `ScanPage` is a page of a Scan response
+#[derive(Debug, Clone, Default)]
+pub struct ScanPage {
+    /// the items on the page
+    pub items: Vec<Item>,
+    /// the key to continue the scan from, or `None` on the last page of the segment
+    pub last_evaluated_key: Option<Item>,
+}
+
+/// SegmentScanner is the trait for the client making Scan requests. Implementing this for a
+/// DynamoDB client (setting `Segment`, `TotalSegments` and `ExclusiveStartKey` on a Scan of the
+/// users table) lets `ParallelScan` read the table
+pub trait SegmentScanner: Send + Sync {
+    /// the error of a failed Scan request
+    type Error: Send + 'static;
+
+    /// scans a page of `segment` out of `total_segments`, from `exclusive_start_key` if any
+    fn scan(
+        &self,
+        segment: u32,
+        total_segments: u32,
+        exclusive_start_key: Option<Item>,
+    ) -> Result<ScanPage, Self::Error>;
+}
+
+/// `ScanError` is why a `ParallelScan` stopped
+#[derive(Debug)]
+pub enum ScanError<E> {
+    /// a Scan request of a segment failed
+    Scan {
+        /// the segment scanned
+        segment: u32,
+        /// the client's error
+        error: E,
+    },
+    /// an item has no location, or a location that isn't a number of degrees in range
+    InvalidItem {
+        /// the attribute missing or invalid
+        attribute: String,
+    },
+}
+
+impl<E: fmt::Display> fmt::Display for ScanError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::Scan { segment, error } => {
+                write!(f, "scan of segment {} failed: {}", segment, error)
+            }
+            ScanError::InvalidItem { attribute } => {
+                write!(f, "item has a missing or invalid {} attribute", attribute)
+            }
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> Error for ScanError<E> {}
+
+/// `DynamoUser` is a user read from an item
+#[derive(Debug, Clone)]
+pub struct DynamoUser {
+    location: LatLng,
+    item: Item,
+}
+
+impl DynamoUser {
+    /// returns the item the user was read from
+    pub fn item(&self) -> &Item {
+        &self.item
+    }
+}
+
+impl User for DynamoUser {
+    fn location(&self) -> &LatLng {
+        &self.location
+    }
+}
+
+/// `ParallelScan` scans a table with a thread per segment, yielding a user per item as pages
+/// arrive, in no particular order. Build from it with `GeoshardBuilder::build_from_fallible`,
+/// which stops at the first error. Each segment holds at most a page ahead of the builder, and
+/// stops once the scan is dropped
+pub struct ParallelScan<E> {
+    receiver: Receiver<Result<DynamoUser, ScanError<E>>>,
+}
+
+impl<E: Send + 'static> ParallelScan<E> {
+    /// Starts scanning the table in `total_segments` segments, reading locations from the `lat`
+    /// and `lng` number attributes
+    pub fn new<S>(scanner: Arc<S>, total_segments: u32) -> Self
+    where
+        S: SegmentScanner<Error = E> + 'static,
+    {
+        Self::with_attributes(scanner, total_segments, "lat", "lng")
+    }
+
+    /// `new`, reading locations from the given number attributes
+    pub fn with_attributes<S>(
+        scanner: Arc<S>,
+        total_segments: u32,
+        lat_attribute: &str,
+        lng_attribute: &str,
+    ) -> Self
+    where
+        S: SegmentScanner<Error = E> + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(0);
+        let attributes: Arc<(String, String)> =
+            Arc::new((lat_attribute.to_owned(), lng_attribute.to_owned()));
+        for segment in 0..total_segments.max(1) {
+            let (scanner, sender, attributes) =
+                (scanner.clone(), sender.clone(), attributes.clone());
+            thread::spawn(move || {
+                scan_segment(
+                    scanner.as_ref(),
+                    segment,
+                    total_segments.max(1),
+                    &attributes,
+                    sender,
+                )
+            });
+        }
+        Self { receiver }
+    }
+}
+
+/// scans every page of the segment, until the scan is dropped or fails
+fn scan_segment<S: SegmentScanner>(
+    scanner: &S,
+    segment: u32,
+    total_segments: u32,
+    (lat_attribute, lng_attribute): &(String, String),
+    sender: SyncSender<Result<DynamoUser, ScanError<S::Error>>>,
+) {
+    let mut start_key = None;
+    loop {
+        let page = match scanner.scan(segment, total_segments, start_key) {
+            Ok(page) => page,
+            Err(error) => {
+                let _ = sender.send(Err(ScanError::Scan { segment, error }));
+                return;
+            }
+        };
+        for item in page.items {
+            let user = match (
+                degrees(&item, lat_attribute, 90.0),
+                degrees(&item, lng_attribute, 180.0),
+            ) {
+                (Some(lat), Some(lng)) => Ok(DynamoUser {
+                    location: ll!(lng, lat),
+                    item,
+                }),
+                (None, _) => Err(ScanError::InvalidItem {
+                    attribute: lat_attribute.clone(),
+                }),
+                (_, None) => Err(ScanError::InvalidItem {
+                    attribute: lng_attribute.clone(),
+                }),
+            };
+            let failed = user.is_err();
+            // the scan was dropped, or gets the error and needs nothing more
+            if sender.send(user).is_err() || failed {
+                return;
+            }
+        }
+        start_key = match page.last_evaluated_key {
+            Some(key) => Some(key),
+            None => return,
+        };
+    }
+}
+
+/// returns the number attribute of the item, if it has one within `-max..=max` degrees
+fn degrees(item: &Item, attribute: &str, max: f64) -> Option<f64> {
+    match item.get(attribute)? {
+        AttributeValue::N(value) => value
+            .parse()
+            .ok()
+            .filter(|degrees| (-max..=max).contains(degrees)),
+        _ => None,
+    }
+}
+
+impl<E> Iterator for ParallelScan<E> {
+    type Item = Result<DynamoUser, ScanError<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// returns the prefix of the partition keys of the shard's items, e.g.
+/// `geoshard_user_index_7#`, to query a shard's items with `begins_with`
+pub fn partition_key_prefix(shard_name: &str) -> String {
+    format!("{}#", shard_name)
+}
+
+/// returns the partition key of an item with the given key in the shard, e.g.
+/// `geoshard_user_index_7#user-42`
+pub fn partition_key(shard_name: &str, key: &str) -> String {
+    format!("{}{}", partition_key_prefix(shard_name), key)
+}
+
+/// returns the shard of a partition key made by `partition_key`, if it has one
+pub fn shard_from_partition_key(partition_key: &str) -> Option<&str> {
+    partition_key
+        .split_once('#')
+        .map(|(shard_name, _)| shard_name)
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        error::FallibleBuildError,
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+    };
+
+    /// serves a table from memory, two items a page
+    struct MemoryTable {
+        items: Vec<Item>,
+        failing_segment: Option<u32>,
+    }
+
+    impl SegmentScanner for MemoryTable {
+        type Error = String;
+
+        fn scan(
+            &self,
+            segment: u32,
+            total_segments: u32,
+            exclusive_start_key: Option<Item>,
+        ) -> Result<ScanPage, String> {
+            if self.failing_segment == Some(segment) {
+                return Err("throttled".to_owned());
+            }
+            let start = match exclusive_start_key
+                .as_ref()
+                .and_then(|key| key.get("offset"))
+            {
+                Some(AttributeValue::N(offset)) => offset.parse().unwrap(),
+                _ => 0,
+            };
+            let segment_items: Vec<&Item> = self
+                .items
+                .iter()
+                .skip(segment as usize)
+                .step_by(total_segments as usize)
+                .collect();
+            let end = (start + 2).min(segment_items.len());
+            Ok(ScanPage {
+                items: segment_items[start..end]
+                    .iter()
+                    .map(|item| (*item).clone())
+                    .collect(),
+                last_evaluated_key: (end < segment_items.len()).then(|| {
+                    Item::from([("offset".to_owned(), AttributeValue::N(end.to_string()))])
+                }),
+            })
+        }
+    }
+
+    fn items(users: &[FakeUser]) -> Vec<Item> {
+        users
+            .iter()
+            .map(|user| {
+                Item::from([
+                    (
+                        "lat".to_owned(),
+                        AttributeValue::N(user.location().lat.deg().to_string()),
+                    ),
+                    (
+                        "lng".to_owned(),
+                        AttributeValue::N(user.location().lng.deg().to_string()),
+                    ),
+                ])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parallel_scan() {
+        let users = FakeUser::seeded(1000, 43, &RandCityFactory::default());
+        let table = Arc::new(MemoryTable {
+            items: items(&users),
+            failing_segment: None,
+        });
+        let scan = ParallelScan::new(table, 4);
+        let scanned = GeoshardBuilder::user_count_scorer(4, scan, 4, 8)
+            .build_from_fallible()
+            .unwrap();
+        let built = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        assert_eq!(
+            serde_json::to_string(&scanned).unwrap(),
+            serde_json::to_string(&built).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parallel_scan_errors() {
+        let users = FakeUser::seeded(100, 43, &RandCityFactory::default());
+        let table = Arc::new(MemoryTable {
+            items: items(&users),
+            failing_segment: Some(2),
+        });
+        let error = GeoshardBuilder::user_count_scorer(4, ParallelScan::new(table, 4), 4, 8)
+            .build_from_fallible()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            FallibleBuildError::Users(ScanError::Scan { segment: 2, .. })
+        ));
+
+        let mut items = items(&users);
+        items[50].insert("lng".to_owned(), AttributeValue::S("west".to_owned()));
+        let table = Arc::new(MemoryTable {
+            items,
+            failing_segment: None,
+        });
+        let error = GeoshardBuilder::user_count_scorer(4, ParallelScan::new(table, 4), 4, 8)
+            .build_from_fallible()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "users failed: item has a missing or invalid lng attribute"
+        );
+
+        // locations out of range, or that aren't finite, are invalid too
+        for (attribute, value) in [
+            ("lat", "91"),
+            ("lng", "-180.5"),
+            ("lat", "NaN"),
+            ("lng", "inf"),
+        ] {
+            let mut invalid_items = self::items(&users);
+            invalid_items[50].insert(attribute.to_owned(), AttributeValue::N(value.to_owned()));
+            let table = Arc::new(MemoryTable {
+                items: invalid_items,
+                failing_segment: None,
+            });
+            let error = GeoshardBuilder::user_count_scorer(4, ParallelScan::new(table, 4), 4, 8)
+                .build_from_fallible()
+                .unwrap_err();
+            let invalid = match error {
+                FallibleBuildError::Users(ScanError::InvalidItem { attribute }) => attribute,
+                error => panic!("unexpected error {}", error),
+            };
+            assert_eq!(
+                invalid, attribute,
+                "{}={} wasn't rejected",
+                attribute, value
+            );
+        }
+    }
+
+    #[test]
+    fn test_partition_keys() {
+        assert_eq!(
+            partition_key_prefix("geoshard_user_index_7"),
+            "geoshard_user_index_7#"
+        );
+        let key = partition_key("geoshard_user_index_7", "user#42");
+        assert_eq!(key, "geoshard_user_index_7#user#42");
+        assert_eq!(
+            shard_from_partition_key(&key),
+            Some("geoshard_user_index_7")
+        );
+        assert_eq!(shard_from_partition_key("user-42"), None);
+    }
+}
@@ -0,0 +1,384 @@
+#![deny(missing_docs)]
+//! error contains the errors returned while building and searching geoshards
+use std::fmt;
+
+use serde_derive::Serialize;
+
+#[cfg(feature = "builder")]
+use crate::cell_list::Cluster;
+
+/// GeoshardError is the error type for this crate. Errors carry the token of the cell, the name
+/// of the shard and the map generation involved where there is one (see `cell`, `shard` and
+/// `generation`), and serialize with a `kind` tag, so routing failures can be logged and
+/// aggregated by their identifiers. Some variants only exist with the features that return
+/// them, so matches on it need a wildcard arm
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum GeoshardError {
+    /// Pinned clusters scored more than the largest shard allowed by the shard count constraints
+    #[cfg(feature = "builder")]
+    ClustersTooLarge {
+        /// largest score a shard is allowed to have
+        cap: i32,
+        /// the clusters that could not be pinned
+        clusters: Vec<Cluster>,
+    },
+    /// A single cell scored more than the hard limit on shard score, so no shard can hold it
+    CellOverShardLimit {
+        /// token of the cell
+        cell: String,
+        /// score of the cell
+        score: i32,
+        /// the hard limit on shard score
+        limit: i32,
+    },
+    /// A shard map could not be parsed, or failed verification
+    InvalidShardMap {
+        /// what was wrong with the shard map
+        reason: String,
+        /// name of the offending shard, if the problem is with a single shard
+        shard: Option<String>,
+        /// token of the offending cell, if the problem is with a single cell
+        cell: Option<String>,
+    },
+    /// A shard could not be split or merged
+    InvalidShardOperation {
+        /// why the operation failed
+        reason: String,
+        /// name of the shard the operation failed on
+        shard: Option<String>,
+        /// token of the cell the operation failed on, if any
+        cell: Option<String>,
+    },
+    /// A cell coarser than the map's storage level was looked up, and no shard holds all of it
+    CellLevelMismatch {
+        /// token of the cell
+        cell: String,
+        /// level of the cell
+        level: u64,
+        /// the map's storage level
+        storage_level: u64,
+    },
+    /// No shard in the map holds the cell, e.g. because the map does not cover the whole world
+    UnmappedCell {
+        /// token of the cell
+        cell: String,
+        /// generation of the map that was searched, if known
+        generation: Option<u64>,
+    },
+    /// A pinned region did not contain any cell at the storage level
+    #[cfg(feature = "builder")]
+    EmptyPinnedRegion {
+        /// name of the pinned region
+        name: String,
+    },
+    /// The build was cancelled through its cancellation flag
+    #[cfg(feature = "builder")]
+    Cancelled,
+    /// The builder was configured with a storage level or shard count bounds it can't build with
+    #[cfg(feature = "builder")]
+    InvalidBuilderConfig {
+        /// what was wrong with the configuration
+        reason: String,
+    },
+    /// A shard map generation switch was aborted before the new generation was swapped in
+    SwitchAborted {
+        /// why the switch was aborted
+        reason: String,
+        /// the generation the switch was prepared against
+        generation: u64,
+    },
+    /// A shard map could not be read, e.g. from a map directory being watched
+    MapUnavailable {
+        /// path of the map or directory
+        path: String,
+        /// why it could not be read
+        reason: String,
+    },
+    /// A shard map's signature is missing, or doesn't verify against the map
+    UnverifiedMap {
+        /// path or key of the map
+        path: String,
+        /// why it could not be verified
+        reason: String,
+    },
+    /// A file of users could not be read, e.g. by a CSV or JSONL user loader
+    UserSourceUnavailable {
+        /// path of the file
+        path: String,
+        /// why it could not be read
+        reason: String,
+    },
+    /// A row of a file of users could not be parsed into a user
+    MalformedRow {
+        /// path of the file
+        path: String,
+        /// line of the row, from 1
+        line: usize,
+        /// what was wrong with the row
+        reason: String,
+    },
+    /// A pagination continuation token could not be decoded or resumed
+    InvalidPageToken {
+        /// what was wrong with the token
+        reason: String,
+    },
+    /// A request to the shard lookup service was invalid, e.g. a location out of range
+    InvalidRequest {
+        /// what was wrong with the request
+        reason: String,
+    },
+    /// Shards in the map have no deployed target backing them
+    MissingDeployments {
+        /// shards in the map without a deployed target
+        missing: Vec<String>,
+        /// deployed targets that no shard in the map routes to
+        orphaned: Vec<String>,
+    },
+    /// A scored cell snapshot is at another storage level than the builder
+    #[cfg(feature = "builder")]
+    StorageLevelMismatch {
+        /// the builder's storage level
+        expected: u64,
+        /// the snapshot's storage level
+        found: u64,
+    },
+    /// A shard map could not be published to object storage
+    PublishFailed {
+        /// key of the object that could not be written
+        key: String,
+        /// why it could not be published
+        reason: String,
+    },
+    /// A searcher's lookup table was requested for a storage level too fine to hold it in memory
+    LookupTableTooLarge {
+        /// the map's storage level
+        storage_level: u64,
+        /// the finest storage level lookup tables are built for
+        max_level: u64,
+    },
+    /// A shard fits on no node without going over the node's capacity
+    UnplaceableShard {
+        /// name of the shard
+        shard: String,
+        /// score of the shard
+        score: i32,
+    },
+}
+
+impl fmt::Display for GeoshardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "builder")]
+            GeoshardError::ClustersTooLarge { cap, clusters } => {
+                write!(f, "clusters too large to pin under shard cap {}:", cap)?;
+                for cluster in clusters {
+                    write!(f, " {} (score {})", cluster.name(), cluster.score())?;
+                }
+                Ok(())
+            }
+            GeoshardError::CellOverShardLimit { cell, score, limit } => write!(
+                f,
+                "cell {} scores {}, over the shard score limit of {}",
+                cell, score, limit
+            ),
+            GeoshardError::InvalidShardMap { reason, .. } => {
+                write!(f, "invalid shard map: {}", reason)
+            }
+            GeoshardError::InvalidShardOperation { reason, .. } => {
+                write!(f, "invalid shard operation: {}", reason)
+            }
+            GeoshardError::CellLevelMismatch {
+                cell,
+                level,
+                storage_level,
+            } => write!(
+                f,
+                "cell {} is at level {}, coarser than the storage level {}",
+                cell, level, storage_level
+            ),
+            GeoshardError::UnmappedCell { cell, generation } => {
+                write!(f, "no shard holds cell {}", cell)?;
+                match generation {
+                    Some(generation) => write!(f, " in generation {}", generation),
+                    None => Ok(()),
+                }
+            }
+            #[cfg(feature = "builder")]
+            GeoshardError::EmptyPinnedRegion { name } => {
+                write!(f, "pinned region {} does not contain any cell", name)
+            }
+            #[cfg(feature = "builder")]
+            GeoshardError::Cancelled => write!(f, "build cancelled"),
+            #[cfg(feature = "builder")]
+            GeoshardError::InvalidBuilderConfig { reason } => {
+                write!(f, "invalid builder configuration: {}", reason)
+            }
+            GeoshardError::SwitchAborted { reason, generation } => {
+                write!(
+                    f,
+                    "generation switch from {} aborted: {}",
+                    generation, reason
+                )
+            }
+            GeoshardError::MapUnavailable { path, reason } => {
+                write!(f, "shard map {} unavailable: {}", path, reason)
+            }
+            GeoshardError::PublishFailed { key, reason } => {
+                write!(f, "failed to publish {}: {}", key, reason)
+            }
+            GeoshardError::UnverifiedMap { path, reason } => {
+                write!(f, "shard map {} failed verification: {}", path, reason)
+            }
+            GeoshardError::UserSourceUnavailable { path, reason } => {
+                write!(f, "users {} unavailable: {}", path, reason)
+            }
+            GeoshardError::MalformedRow { path, line, reason } => {
+                write!(f, "malformed row at {}:{}: {}", path, line, reason)
+            }
+            GeoshardError::InvalidPageToken { reason } => {
+                write!(f, "invalid page token: {}", reason)
+            }
+            GeoshardError::InvalidRequest { reason } => write!(f, "invalid request: {}", reason),
+            GeoshardError::MissingDeployments { missing, orphaned } => {
+                write!(f, "shards not deployed: {}", missing.join(", "))?;
+                if !orphaned.is_empty() {
+                    write!(f, "; orphaned deployments: {}", orphaned.join(", "))?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "builder")]
+            GeoshardError::StorageLevelMismatch { expected, found } => write!(
+                f,
+                "snapshot at storage level {} can't build shards at storage level {}",
+                found, expected
+            ),
+            GeoshardError::LookupTableTooLarge {
+                storage_level,
+                max_level,
+            } => write!(
+                f,
+                "no lookup table at storage level {}, finer than level {}",
+                storage_level, max_level
+            ),
+            GeoshardError::UnplaceableShard { shard, score } => {
+                write!(f, "shard {} (score {}) fits on no node", shard, score)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeoshardError {}
+
+impl GeoshardError {
+    /// returns the token of the cell the error is about, if any
+    pub fn cell(&self) -> Option<&str> {
+        match self {
+            GeoshardError::CellOverShardLimit { cell, .. }
+            | GeoshardError::CellLevelMismatch { cell, .. }
+            | GeoshardError::UnmappedCell { cell, .. } => Some(cell),
+            GeoshardError::InvalidShardMap { cell, .. }
+            | GeoshardError::InvalidShardOperation { cell, .. } => cell.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// returns the name of the shard (or pinned region) the error is about, if any
+    pub fn shard(&self) -> Option<&str> {
+        match self {
+            GeoshardError::InvalidShardMap { shard, .. }
+            | GeoshardError::InvalidShardOperation { shard, .. } => shard.as_deref(),
+            GeoshardError::UnplaceableShard { shard, .. } => Some(shard),
+            #[cfg(feature = "builder")]
+            GeoshardError::EmptyPinnedRegion { name } => Some(name),
+            _ => None,
+        }
+    }
+
+    /// returns the shard map generation the error happened in, if known
+    pub fn generation(&self) -> Option<u64> {
+        match self {
+            GeoshardError::UnmappedCell { generation, .. } => *generation,
+            GeoshardError::SwitchAborted { generation, .. } => Some(*generation),
+            _ => None,
+        }
+    }
+
+    /// sets the generation of errors from a lookup in a map of that generation, e.g. the
+    /// generation loaded from a `SharedGeoshardSearcher`
+    pub fn with_generation(mut self, map_generation: u64) -> Self {
+        if let GeoshardError::UnmappedCell { generation, .. } = &mut self {
+            *generation = Some(map_generation);
+        }
+        self
+    }
+}
+
+/// FallibleBuildError is why `GeoshardBuilder::build_from_fallible` failed: either the users
+/// returned an error part way through, or the shards couldn't be built from their scores
+#[cfg(feature = "builder")]
+#[derive(Debug)]
+pub enum FallibleBuildError<E> {
+    /// The users returned an error, which stopped scoring
+    Users(E),
+    /// The builder's configuration was invalid, or the shards couldn't satisfy its constraints
+    Build(GeoshardError),
+}
+
+#[cfg(feature = "builder")]
+impl<E> From<GeoshardError> for FallibleBuildError<E> {
+    fn from(error: GeoshardError) -> Self {
+        FallibleBuildError::Build(error)
+    }
+}
+
+#[cfg(feature = "builder")]
+impl<E: fmt::Display> fmt::Display for FallibleBuildError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FallibleBuildError::Users(error) => write!(f, "users failed: {}", error),
+            FallibleBuildError::Build(error) => error.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "builder")]
+impl<E: fmt::Debug + fmt::Display> std::error::Error for FallibleBuildError<E> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_context() {
+        let error = GeoshardError::UnmappedCell {
+            cell: "89c25".to_owned(),
+            generation: None,
+        }
+        .with_generation(3);
+        assert_eq!(error.cell(), Some("89c25"));
+        assert_eq!(error.shard(), None);
+        assert_eq!(error.generation(), Some(3));
+        assert_eq!(
+            error.to_string(),
+            "no shard holds cell 89c25 in generation 3"
+        );
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"kind":"unmapped_cell","cell":"89c25","generation":3}"#
+        );
+
+        let error = GeoshardError::InvalidShardOperation {
+            reason: "unknown shard geoshard_user_index_9".to_owned(),
+            shard: Some("geoshard_user_index_9".to_owned()),
+            cell: None,
+        };
+        assert_eq!(error.shard(), Some("geoshard_user_index_9"));
+        assert_eq!(error.cell(), None);
+        assert_eq!(
+            serde_json::to_value(&error).unwrap()["kind"],
+            "invalid_shard_operation"
+        );
+    }
+}
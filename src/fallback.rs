@@ -0,0 +1,204 @@
+#![deny(missing_docs)]
+//! fallback derives tiny, coarse shard maps that can be shipped alongside the main shard map,
+//! so a searcher can keep routing (in degraded mode) when the main map fails to load or verify
+#[cfg(feature = "builder")]
+use std::collections::BTreeMap;
+
+use s2::cellid::CellID;
+
+#[cfg(feature = "searcher")]
+use crate::geoshard::{GeoshardSearcher, UncheckedGeoshardCollection};
+#[cfg(feature = "builder")]
+use crate::{cell_list::CellList, geoshard::Partitioner};
+use crate::{error::GeoshardError, geoshard::GeoshardCollection};
+
+impl GeoshardCollection {
+    /// Derives a coarse map at `level` (e.g. level 2, with 6 to 24 shards) from this collection's
+    /// cell scores. The coarse map covers every cell at that level, so it can route any location
+    #[cfg(feature = "builder")]
+    pub fn coarse_fallback(
+        &self,
+        level: u64,
+        min_shard_count: i32,
+        max_shard_count: i32,
+    ) -> GeoshardCollection {
+        let mut scored_cells: BTreeMap<CellID, i32> = CellList::new(level).cell_list().clone();
+        for shard in self.shards() {
+            for (cell_id, score) in shard.cell_union().0.iter().zip(shard.cell_scores()) {
+                *scored_cells.entry(cell_id.parent(level)).or_insert(0) += score;
+            }
+        }
+        Partitioner::new(level, min_shard_count, max_shard_count).balance(
+            &scored_cells,
+            min_shard_count,
+            max_shard_count,
+        )
+    }
+
+    /// embeds the given fallback map in this collection, so it is serialized alongside it
+    pub fn with_fallback(mut self, fallback: GeoshardCollection) -> Self {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// returns the embedded fallback map, if any
+    pub fn fallback(&self) -> Option<&GeoshardCollection> {
+        self.fallback.as_deref()
+    }
+
+    /// Checks the collection is usable for routing: it must have shards, every shard must be at
+    /// the collection's storage level, and every cell must be valid. Cells may be coarser than the
+    /// storage level (covering the gaps of sparse builds) or finer (hot cells split by overflow
+    /// splitting)
+    pub fn verify(&self) -> Result<(), GeoshardError> {
+        let invalid = |reason: String, shard: Option<&str>, cell: Option<&CellID>| {
+            Err(GeoshardError::InvalidShardMap {
+                reason,
+                shard: shard.map(str::to_owned),
+                cell: cell.map(CellID::to_token),
+            })
+        };
+        if self.shards().is_empty() {
+            return invalid("shard map has no shards".to_owned(), None, None);
+        }
+        for shard in self.shards() {
+            if shard.storage_level() != self.storage_level() {
+                return invalid(
+                    format!(
+                        "shard {} is at level {}, expected {}",
+                        shard.name(),
+                        shard.storage_level(),
+                        self.storage_level()
+                    ),
+                    Some(shard.name()),
+                    None,
+                );
+            }
+            if let Some(cell_id) = shard
+                .cell_union()
+                .0
+                .iter()
+                .find(|cell_id| !cell_id.is_valid())
+            {
+                return invalid(
+                    format!("shard {} has invalid cell {}", shard.name(), cell_id.0),
+                    Some(shard.name()),
+                    Some(cell_id),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "searcher")]
+impl GeoshardSearcher {
+    /// Loads a searcher from a JSON shard map. If the map fails to parse or verify, the searcher
+    /// runs in degraded mode on the map's embedded fallback or, if that is unavailable too, on the
+    /// given `fallback` (e.g. a coarse map compiled into the binary). The reason is reported by
+    /// `status()`. Errors only if there is no usable map at all
+    pub fn load_or_degrade(
+        json_shards: &str,
+        fallback: Option<GeoshardCollection>,
+    ) -> Result<Self, GeoshardError> {
+        // parsed unchecked, so a map at the wrong level still yields its embedded fallback
+        let (error, embedded_fallback) =
+            match serde_json::from_str::<UncheckedGeoshardCollection>(json_shards) {
+                Ok(mut unchecked) => {
+                    let embedded_fallback = unchecked.fallback.take();
+                    match GeoshardCollection::try_from(unchecked)
+                        .and_then(|shards| shards.verify().map(|_| shards))
+                    {
+                        Ok(mut shards) => {
+                            shards.fallback = embedded_fallback;
+                            return Ok(GeoshardSearcher::from(shards));
+                        }
+                        Err(error) => (error, embedded_fallback),
+                    }
+                }
+                Err(error) => (
+                    GeoshardError::InvalidShardMap {
+                        reason: error.to_string(),
+                        shard: None,
+                        cell: None,
+                    },
+                    None,
+                ),
+            };
+
+        match embedded_fallback
+            .map(|fallback| *fallback)
+            .into_iter()
+            .chain(fallback)
+            .find(|fallback| fallback.verify().is_ok())
+        {
+            Some(fallback) => Ok(GeoshardSearcher::degraded(fallback, error.to_string())),
+            None => Err(error),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{geoshard::SearcherStatus, utils::ll};
+
+    fn scored_collection() -> GeoshardCollection {
+        let mut cell_list = CellList::new(4);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 5) as i32;
+        }
+        GeoshardCollection::new(100, cell_list.cell_list(), 4)
+    }
+
+    #[test]
+    fn test_coarse_fallback() {
+        let geoshards = scored_collection();
+        let fallback = geoshards.coarse_fallback(2, 6, 24);
+        assert_eq!(fallback.storage_level(), 2);
+        assert!(fallback.shards().len() >= 6 && fallback.shards().len() <= 25);
+        assert!(fallback.verify().is_ok());
+
+        let total = |collection: &GeoshardCollection| -> i32 {
+            collection
+                .shards()
+                .iter()
+                .flat_map(|shard| shard.cell_scores())
+                .sum()
+        };
+        assert_eq!(total(&fallback), total(&geoshards));
+    }
+
+    #[test]
+    fn test_load_or_degrade() {
+        let geoshards = scored_collection();
+        let fallback = geoshards.coarse_fallback(2, 6, 24);
+        let location = ll!(-74.0060, 40.7128);
+
+        let json = serde_json::to_string(&geoshards.with_fallback(fallback)).unwrap();
+        let searcher = GeoshardSearcher::load_or_degrade(&json, None).unwrap();
+        assert_eq!(searcher.status(), &SearcherStatus::Healthy);
+        assert_eq!(searcher.shards().storage_level(), 4);
+
+        // a map at the wrong level fails verification and serves the embedded fallback
+        let broken_json = json.replacen("\"storage_level\":4", "\"storage_level\":5", 1);
+        let unbacked_json = serde_json::to_string(&scored_collection())
+            .unwrap()
+            .replacen("\"storage_level\":4", "\"storage_level\":5", 1);
+        let error = GeoshardSearcher::load_or_degrade(&unbacked_json, None).unwrap_err();
+        assert_eq!(error.shard(), Some("geoshard_user_index_1"));
+        let searcher = GeoshardSearcher::load_or_degrade(&broken_json, None).unwrap();
+        assert!(searcher.is_degraded());
+        assert_eq!(searcher.shards().storage_level(), 2);
+        assert!(searcher
+            .get_shard_from_location(&location)
+            .cell_union()
+            .contains_cellid(&searcher.get_cell_id_from_location(&location)));
+
+        // unparseable maps fall back to the provided map
+        let provided = scored_collection().coarse_fallback(2, 6, 24);
+        let searcher = GeoshardSearcher::load_or_degrade("{", Some(provided)).unwrap();
+        assert!(matches!(searcher.status(), SearcherStatus::Degraded { .. }));
+        assert!(GeoshardSearcher::load_or_degrade("{", None).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+#![deny(missing_docs)]
+//! ffi exposes shard lookups to C behind the `ffi` feature, so gateways in other languages can
+//! route against the same shard maps. The declarations are in `include/geoshard.h`, and the
+//! library can be built with `cargo rustc --release --features ffi --crate-type cdylib` (or
+//! `staticlib`)
+use std::{
+    collections::BTreeMap,
+    ffi::{c_char, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{
+    geoshard::{GeoshardCollection, GeoshardSearcher},
+    utils::ll,
+};
+
+/// `GeoshardHandle` is an opaque handle to a loaded shard map, along with the names of its
+/// shards as C strings so lookups don't allocate
+pub struct GeoshardHandle {
+    searcher: GeoshardSearcher,
+    names: BTreeMap<String, CString>,
+}
+
+/// Loads a JSON shard map (as `GeoshardCollection` serializes it) from `len` bytes at `bytes`.
+/// Returns a handle to pass to `geoshard_lookup` and release with `geoshard_free`, or null if
+/// the map doesn't parse or verify
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn geoshard_load(bytes: *const u8, len: usize) -> *mut GeoshardHandle {
+    if bytes.is_null() {
+        return ptr::null_mut();
+    }
+    let bytes = slice::from_raw_parts(bytes, len);
+    let handle = panic::catch_unwind(|| {
+        let shards: GeoshardCollection = serde_json::from_slice(bytes).ok()?;
+        shards.verify().ok()?;
+        let names = shards
+            .shards()
+            .iter()
+            .map(|shard| Some((shard.name().to_owned(), CString::new(shard.name()).ok()?)))
+            .collect::<Option<_>>()?;
+        Some(GeoshardHandle {
+            searcher: GeoshardSearcher::from(shards),
+            names,
+        })
+    });
+    match handle {
+        Ok(Some(handle)) => Box::into_raw(Box::new(handle)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Returns the name of the shard holding the location, in degrees, as a NUL terminated string
+/// owned by the handle (valid until it is freed). Returns null if the handle is null, the
+/// location is out of range or no shard holds it and the miss policy is `MissPolicy::Error`
+///
+/// # Safety
+///
+/// `handle` must be null or a handle returned by `geoshard_load` that has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn geoshard_lookup(
+    handle: *const GeoshardHandle,
+    lat: f64,
+    lng: f64,
+) -> *const c_char {
+    let Some(handle) = handle.as_ref() else {
+        return ptr::null();
+    };
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return ptr::null();
+    }
+    let name = panic::catch_unwind(AssertUnwindSafe(|| {
+        let shard = handle
+            .searcher
+            .try_get_shard_from_location(&ll!(lng, lat))
+            .ok()?;
+        handle.names.get(shard.name())
+    }));
+    match name {
+        Ok(Some(name)) => name.as_ptr(),
+        _ => ptr::null(),
+    }
+}
+
+/// Releases a handle returned by `geoshard_load`. Null handles are ignored
+///
+/// # Safety
+///
+/// `handle` must be null or a handle returned by `geoshard_load` that has not been freed, and
+/// no name returned for it may be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn geoshard_free(handle: *mut GeoshardHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use std::ffi::CStr;
+
+    use super::*;
+    use crate::testing::uniform_collection;
+
+    #[test]
+    fn test_ffi() {
+        let shards = uniform_collection(4, 64);
+        let json = serde_json::to_vec(&shards).unwrap();
+        let expected = GeoshardSearcher::from(shards)
+            .get_shard_from_location(&ll!(-74.0060, 40.7128))
+            .name()
+            .to_owned();
+
+        unsafe {
+            let handle = geoshard_load(json.as_ptr(), json.len());
+            assert!(!handle.is_null());
+            let name = geoshard_lookup(handle, 40.7128, -74.0060);
+            assert_eq!(CStr::from_ptr(name).to_str().unwrap(), expected);
+            assert!(geoshard_lookup(handle, 95.0, 0.0).is_null());
+            assert!(geoshard_lookup(ptr::null(), 40.7128, -74.0060).is_null());
+            geoshard_free(handle);
+            geoshard_free(ptr::null_mut());
+
+            let broken = b"{";
+            assert!(geoshard_load(broken.as_ptr(), broken.len()).is_null());
+            assert!(geoshard_load(ptr::null(), 0).is_null());
+        }
+    }
+}
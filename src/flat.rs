@@ -0,0 +1,316 @@
+#![deny(missing_docs)]
+//! flat contains a flat, sorted on disk layout of shard maps, and `FlatGeoshardSearcher`, which
+//! looks shards up in it in place. Loading only checks the header, so clients embedding a map
+//! (e.g. with `include_bytes!` in a mobile SDK) can route without parsing or allocating it
+//!
+//! The layout is little endian: the magic `GSFL`, then the version, storage level, shard count
+//! and range count as `u32`s, the ranges as (first leaf, last leaf, shard) `u64`s sorted by
+//! their first leaf, and the shard names as a table of (offset, length) `u32`s into the UTF-8
+//! names that end the file
+use std::{fs, io::Write, path::Path};
+
+use s2::{cellid::CellID, latlng::LatLng};
+
+use crate::{
+    error::GeoshardError,
+    geoshard::{GeoshardCollection, GeoshardSearcher},
+    lookup::{self, CellRange, RangeTable},
+};
+
+/// header of a flat shard map, followed by a format version
+const FLAT_MAGIC: &[u8; 4] = b"GSFL";
+
+/// version of the flat shard map format
+const FLAT_VERSION: u32 = 1;
+
+/// bytes in the header
+const HEADER_SIZE: usize = 20;
+
+/// bytes in a range
+const RANGE_SIZE: usize = 24;
+
+/// bytes in an entry of the name table
+const NAME_SIZE: usize = 8;
+
+impl GeoshardCollection {
+    /// Returns the map as sorted ranges of leaf cells, for lookups without `std`, see the lookup
+    /// module. Contiguous cells of a shard are a single range
+    ///
+    /// # Panics
+    ///
+    /// Panics if shards hold the same cell, which `verify` reports
+    pub fn range_table(&self) -> RangeTable {
+        let ranges = self
+            .shards()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, shard)| {
+                shard.cell_union().0.iter().map(move |cell_id| CellRange {
+                    first: cell_id.range_min().0,
+                    last: cell_id.range_max().0,
+                    shard: index as u32,
+                })
+            })
+            .collect();
+        RangeTable::new(ranges).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Writes the map in the flat layout read by `FlatGeoshardSearcher`. Contiguous cells of a
+    /// shard are written as a single range. Only the names and boundaries of the shards are
+    /// written
+    pub fn write_flat<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let table = self.range_table();
+        let merged = table.ranges();
+
+        writer.write_all(FLAT_MAGIC)?;
+        for word in [
+            FLAT_VERSION,
+            self.storage_level() as u32,
+            self.shards().len() as u32,
+            merged.len() as u32,
+        ] {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        for range in merged {
+            writer.write_all(&range.first.to_le_bytes())?;
+            writer.write_all(&range.last.to_le_bytes())?;
+            writer.write_all(&(range.shard as u64).to_le_bytes())?;
+        }
+        let mut offset = 0u32;
+        for shard in self.shards() {
+            writer.write_all(&offset.to_le_bytes())?;
+            writer.write_all(&(shard.name().len() as u32).to_le_bytes())?;
+            offset += shard.name().len() as u32;
+        }
+        for shard in self.shards() {
+            writer.write_all(shard.name().as_bytes())?;
+        }
+        writer.flush()
+    }
+}
+
+/// `FlatGeoshardSearcher` looks up shards in a map in the flat layout written by
+/// `GeoshardCollection::write_flat`, binary searching the ranges in place. The map can be held
+/// in any bytes, such as a `Vec<u8>` read from a file or a `&'static [u8]` embedded in the binary
+#[derive(Debug, Clone)]
+pub struct FlatGeoshardSearcher<Bytes = Vec<u8>> {
+    bytes: Bytes,
+    storage_level: u64,
+    shard_count: usize,
+    range_count: usize,
+}
+
+impl FlatGeoshardSearcher<Vec<u8>> {
+    /// Reads a flat map from the file at `path` into a single buffer. Fails with
+    /// `GeoshardError::MapUnavailable` if the file can't be read, and
+    /// `GeoshardError::InvalidShardMap` if it isn't a flat map
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, GeoshardError> {
+        let bytes = fs::read(path.as_ref()).map_err(|error| GeoshardError::MapUnavailable {
+            path: path.as_ref().display().to_string(),
+            reason: error.to_string(),
+        })?;
+        Self::from_bytes(bytes)
+    }
+}
+
+impl<Bytes: AsRef<[u8]>> FlatGeoshardSearcher<Bytes> {
+    /// Constructs a searcher over a flat map. Only the header and the size of the map are
+    /// checked, so this takes the same time for any map. Fails with
+    /// `GeoshardError::InvalidShardMap` if the bytes aren't a flat map
+    pub fn from_bytes(bytes: Bytes) -> Result<Self, GeoshardError> {
+        let data = bytes.as_ref();
+        if data.len() < HEADER_SIZE || &data[..4] != FLAT_MAGIC {
+            return Err(invalid("not a flat shard map"));
+        }
+        let word = |index: usize| read_u32(data, 4 + 4 * index) as usize;
+        if word(0) != FLAT_VERSION as usize {
+            return Err(invalid(&format!(
+                "unsupported flat shard map version {}",
+                word(0)
+            )));
+        }
+        let (storage_level, shard_count, range_count) = (word(1) as u64, word(2), word(3));
+        let size = data.len();
+        let table_end = range_count
+            .checked_mul(RANGE_SIZE)
+            .zip(shard_count.checked_mul(NAME_SIZE))
+            .and_then(|(ranges, names)| (HEADER_SIZE + ranges).checked_add(names))
+            .filter(|end| *end <= size)
+            .ok_or_else(|| invalid("truncated"))?;
+        let searcher = Self {
+            bytes,
+            storage_level,
+            shard_count,
+            range_count,
+        };
+        // names are checked when looked up, but the last one must end within the map
+        if shard_count > 0 {
+            let (offset, length) = searcher.name_entry(shard_count - 1);
+            if table_end + offset + length > size {
+                return Err(invalid("truncated"));
+            }
+        }
+        Ok(searcher)
+    }
+
+    /// returns the storage level of the map
+    pub fn storage_level(&self) -> u64 {
+        self.storage_level
+    }
+
+    /// returns the number of shards in the map
+    pub fn shard_count(&self) -> usize {
+        self.shard_count
+    }
+
+    /// returns the name of the shard at `index`, in the order of the map's shards
+    pub fn shard_name(&self, index: usize) -> Option<&str> {
+        if index >= self.shard_count {
+            return None;
+        }
+        let (offset, length) = self.name_entry(index);
+        let start = self.names_start() + self.shard_count * NAME_SIZE + offset;
+        let name = self.bytes.as_ref().get(start..start + length)?;
+        std::str::from_utf8(name).ok()
+    }
+
+    /// returns the name of the shard holding the location, or `None` if no shard does
+    pub fn shard_for_location(&self, location: &LatLng) -> Option<&str> {
+        self.shard_for_cell_id(&CellID::from(location))
+    }
+
+    /// returns the name of the shard holding the cell, which may be at any level at or below
+    /// the storage level, or `None` if no shard does
+    pub fn shard_for_cell_id(&self, cell_id: &CellID) -> Option<&str> {
+        let shard = lookup::find_shard(self.range_count, |index| self.range(index), cell_id.0)?;
+        self.shard_name(shard as usize)
+    }
+
+    /// returns the range at `index`
+    fn range(&self, index: usize) -> CellRange {
+        let start = HEADER_SIZE + index * RANGE_SIZE;
+        let data = self.bytes.as_ref();
+        CellRange {
+            first: read_u64(data, start),
+            last: read_u64(data, start + 8),
+            shard: read_u64(data, start + 16) as u32,
+        }
+    }
+
+    /// returns where the name table starts
+    fn names_start(&self) -> usize {
+        HEADER_SIZE + self.range_count * RANGE_SIZE
+    }
+
+    /// returns the offset and length of the name of the shard at `index`
+    fn name_entry(&self, index: usize) -> (usize, usize) {
+        let start = self.names_start() + index * NAME_SIZE;
+        let data = self.bytes.as_ref();
+        (
+            read_u32(data, start) as usize,
+            read_u32(data, start + 4) as usize,
+        )
+    }
+}
+
+impl GeoshardSearcher {
+    /// Loads a flat shard map (see `GeoshardCollection::write_flat`) for lookups without parsing
+    /// it, see `FlatGeoshardSearcher::open`. The whole file is read into a single buffer, not
+    /// memory mapped, so loading takes time and memory in proportion to the map's size. To
+    /// route from a map that is already in memory (mapped by the caller or embedded with
+    /// `include_bytes!`) without copying it, use `FlatGeoshardSearcher::from_bytes`
+    pub fn from_flat_file<P: AsRef<Path>>(path: P) -> Result<FlatGeoshardSearcher, GeoshardError> {
+        FlatGeoshardSearcher::open(path)
+    }
+}
+
+/// returns the error for bytes that aren't a flat map
+fn invalid(reason: &str) -> GeoshardError {
+    GeoshardError::InvalidShardMap {
+        reason: format!("invalid flat shard map: {}", reason),
+        shard: None,
+        cell: None,
+    }
+}
+
+fn read_u32(data: &[u8], start: usize) -> u32 {
+    u32::from_le_bytes(data[start..start + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], start: usize) -> u64 {
+    u64::from_le_bytes(data[start..start + 8].try_into().unwrap())
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        cell_list::CellList,
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+        utils::ll,
+    };
+
+    #[test]
+    fn test_flat_geoshard_searcher() {
+        let users = FakeUser::seeded(1000, 29, &RandCityFactory::default());
+        let geoshards = GeoshardBuilder::user_count_scorer(6, users.iter(), 10, 20)
+            .with_cached_cells()
+            .build();
+        let mut bytes = vec![];
+        geoshards.write_flat(&mut bytes).unwrap();
+        let path = std::env::temp_dir().join("geoshard_test_flat.bin");
+        fs::write(&path, &bytes).unwrap();
+
+        let flat = GeoshardSearcher::from_flat_file(&path).unwrap();
+        let embedded = FlatGeoshardSearcher::from_bytes(bytes.as_slice()).unwrap();
+        let searcher = GeoshardSearcher::from(geoshards);
+        assert_eq!(flat.storage_level(), 6);
+        assert_eq!(flat.shard_count(), searcher.shards().shards().len());
+        for cell_id in CellList::cached(6).cell_list().keys().step_by(3) {
+            let expected = searcher.get_shard_from_cell_id(cell_id).name();
+            assert_eq!(flat.shard_for_cell_id(cell_id), Some(expected));
+            assert_eq!(
+                embedded.shard_for_cell_id(&cell_id.child_begin()),
+                Some(expected)
+            );
+        }
+        let new_york = ll!(-74.0060, 40.7128);
+        assert_eq!(
+            flat.shard_for_location(&new_york),
+            Some(searcher.get_shard_from_location(&new_york).name())
+        );
+        // a cell coarser than the storage level spans several shards
+        assert_eq!(flat.shard_for_cell_id(&CellID::from_face(0)), None);
+        fs::remove_file(&path).unwrap();
+
+        assert!(FlatGeoshardSearcher::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(FlatGeoshardSearcher::from_bytes(&b"GSFL"[..]).is_err());
+        assert!(matches!(
+            GeoshardSearcher::from_flat_file(&path),
+            Err(GeoshardError::MapUnavailable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_flat_gaps() {
+        let cells: Vec<CellID> = CellList::new(2).cell_list().keys().copied().collect();
+        let geoshards = GeoshardCollection::from_shards(
+            2,
+            vec![crate::geoshard::Geoshard::new(
+                "only".to_owned(),
+                1,
+                2,
+                s2::cellunion::CellUnion(cells[3..5].to_vec()),
+            )],
+        );
+        let mut bytes = vec![];
+        geoshards.write_flat(&mut bytes).unwrap();
+        let flat = FlatGeoshardSearcher::from_bytes(bytes).unwrap();
+        assert_eq!(flat.shard_for_cell_id(&cells[3]), Some("only"));
+        assert_eq!(flat.shard_for_cell_id(&cells[4]), Some("only"));
+        assert_eq!(flat.shard_for_cell_id(&cells[2]), None);
+        assert_eq!(flat.shard_for_cell_id(&cells[5]), None);
+        assert_eq!(flat.shard_name(1), None);
+    }
+}
@@ -0,0 +1,231 @@
+#![deny(missing_docs)]
+//! generation contains `SharedGeoshardSearcher`, a searcher shared between threads that can be
+//! swapped for a new shard map generation atomically, and the `GenerationSwitcher` which swaps
+//! generations in two phases with hooks to warm the new generation and drain the old one
+use std::sync::{Arc, RwLock};
+
+use crate::{error::GeoshardError, geoshard::GeoshardSearcher};
+
+/// `SharedGeoshardSearcher` is a cheaply cloneable handle to the current generation of the
+/// searcher. Readers `load` the current generation and keep using it for the whole request,
+/// even if it is swapped in the meantime
+#[derive(Debug, Clone)]
+pub struct SharedGeoshardSearcher {
+    current: Arc<RwLock<(u64, Arc<GeoshardSearcher>)>>,
+}
+
+impl SharedGeoshardSearcher {
+    /// Constructs a new `SharedGeoshardSearcher` serving the searcher as generation 0
+    pub fn new(searcher: GeoshardSearcher) -> Self {
+        Self {
+            current: Arc::new(RwLock::new((0, Arc::new(searcher)))),
+        }
+    }
+
+    /// returns the current generation of the searcher
+    pub fn load(&self) -> Arc<GeoshardSearcher> {
+        self.current.read().unwrap().1.clone()
+    }
+
+    /// returns the number of the current generation along with it
+    pub fn load_generation(&self) -> (u64, Arc<GeoshardSearcher>) {
+        let current = self.current.read().unwrap();
+        (current.0, current.1.clone())
+    }
+
+    /// returns the number of the current generation, incremented by every swap
+    pub fn generation(&self) -> u64 {
+        self.current.read().unwrap().0
+    }
+
+    /// swaps in the searcher as the next generation, returning the previous one
+    pub fn swap(&self, searcher: GeoshardSearcher) -> Arc<GeoshardSearcher> {
+        self.swap_if(Arc::new(searcher), None)
+            .expect("unconditional swaps always succeed")
+    }
+
+    /// swaps in the searcher if the current generation is `expected_generation` (or always, if
+    /// it is `None`), returning the previous generation
+    fn swap_if(
+        &self,
+        searcher: Arc<GeoshardSearcher>,
+        expected_generation: Option<u64>,
+    ) -> Option<Arc<GeoshardSearcher>> {
+        let mut current = self.current.write().unwrap();
+        if expected_generation.is_some_and(|expected| expected != current.0) {
+            return None;
+        }
+        let generation = current.0 + 1;
+        let (_, previous) = std::mem::replace(&mut *current, (generation, searcher));
+        Some(previous)
+    }
+}
+
+impl From<Arc<GeoshardSearcher>> for SharedGeoshardSearcher {
+    fn from(searcher: Arc<GeoshardSearcher>) -> Self {
+        Self {
+            current: Arc::new(RwLock::new((0, searcher))),
+        }
+    }
+}
+
+/// SwitchHooks is the trait for the service specific work around a generation switch
+pub trait SwitchHooks {
+    /// called with the next generation before it is swapped in, e.g. to warm caches or open
+    /// connections to new shards. Returning an error aborts the switch
+    fn before_switch(&self, _next: &GeoshardSearcher) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// called with the previous generation after the swap, along with the shards that are not in
+    /// the new generation, e.g. to drain connections to them
+    fn after_switch(&self, _previous: &GeoshardSearcher, _removed_shards: &[String]) {}
+}
+
+impl SwitchHooks for () {}
+
+/// `GenerationSwitcher` switches a `SharedGeoshardSearcher` to new generations in two phases:
+/// `prepare` runs the `before_switch` hook, and the returned `PreparedSwitch` is then committed
+/// (swapping atomically and running `after_switch`) or aborted
+pub struct GenerationSwitcher<Hooks> {
+    shared: SharedGeoshardSearcher,
+    hooks: Hooks,
+}
+
+impl<Hooks: SwitchHooks> GenerationSwitcher<Hooks> {
+    /// Constructs a new `GenerationSwitcher` for the shared searcher
+    pub fn new(shared: SharedGeoshardSearcher, hooks: Hooks) -> Self {
+        Self { shared, hooks }
+    }
+
+    /// returns the shared searcher being switched
+    pub fn shared(&self) -> &SharedGeoshardSearcher {
+        &self.shared
+    }
+
+    /// prepares a switch to the next generation, running the `before_switch` hook. Fails with
+    /// `GeoshardError::SwitchAborted` if the hook fails
+    pub fn prepare(
+        &self,
+        next: GeoshardSearcher,
+    ) -> Result<PreparedSwitch<'_, Hooks>, GeoshardError> {
+        let generation = self.shared.generation();
+        self.hooks
+            .before_switch(&next)
+            .map_err(|reason| GeoshardError::SwitchAborted { reason, generation })?;
+        Ok(PreparedSwitch {
+            switcher: self,
+            next: Arc::new(next),
+            generation,
+        })
+    }
+}
+
+/// `PreparedSwitch` is a generation switch that is ready to be committed or aborted
+pub struct PreparedSwitch<'a, Hooks> {
+    switcher: &'a GenerationSwitcher<Hooks>,
+    next: Arc<GeoshardSearcher>,
+    generation: u64,
+}
+
+impl<Hooks: SwitchHooks> PreparedSwitch<'_, Hooks> {
+    /// returns the generation that was prepared
+    pub fn next(&self) -> &GeoshardSearcher {
+        &self.next
+    }
+
+    /// Swaps the prepared generation in, then runs the `after_switch` hook with the previous one,
+    /// returning the new generation number. Fails with `GeoshardError::SwitchAborted`, without
+    /// swapping, if another switch was committed since this one was prepared
+    pub fn commit(self) -> Result<u64, GeoshardError> {
+        let shared = &self.switcher.shared;
+        let previous = shared
+            .swap_if(self.next.clone(), Some(self.generation))
+            .ok_or_else(|| GeoshardError::SwitchAborted {
+                reason: format!(
+                    "generation {} was replaced while the switch was prepared",
+                    self.generation
+                ),
+                generation: self.generation,
+            })?;
+
+        let removed_shards: Vec<String> = previous
+            .shards()
+            .shards()
+            .iter()
+            .map(|shard| shard.name())
+            .filter(|name| {
+                !self
+                    .next
+                    .shards()
+                    .shards()
+                    .iter()
+                    .any(|shard| shard.name() == *name)
+            })
+            .map(str::to_owned)
+            .collect();
+        self.switcher.hooks.after_switch(&previous, &removed_shards);
+        Ok(self.generation + 1)
+    }
+
+    /// abandons the prepared switch, leaving the current generation in place
+    pub fn abort(self) {}
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::testing::uniform_collection;
+
+    #[derive(Default)]
+    struct RecordingHooks {
+        removed: Mutex<Vec<String>>,
+    }
+
+    impl SwitchHooks for RecordingHooks {
+        fn before_switch(&self, next: &GeoshardSearcher) -> Result<(), String> {
+            match next.shards().shards().len() {
+                0 => Err("no shards".to_owned()),
+                _ => Ok(()),
+            }
+        }
+
+        fn after_switch(&self, _previous: &GeoshardSearcher, removed_shards: &[String]) {
+            self.removed
+                .lock()
+                .unwrap()
+                .extend_from_slice(removed_shards);
+        }
+    }
+
+    fn searcher(container_size: i32) -> GeoshardSearcher {
+        GeoshardSearcher::from(uniform_collection(2, container_size))
+    }
+
+    #[test]
+    fn test_generation_switcher() {
+        let shared = SharedGeoshardSearcher::new(searcher(24));
+        let switcher = GenerationSwitcher::new(shared.clone(), RecordingHooks::default());
+        let reader = shared.load();
+
+        let prepared = switcher.prepare(searcher(48)).unwrap();
+        let stale = switcher.prepare(searcher(96)).unwrap();
+        assert_eq!(prepared.commit().unwrap(), 1);
+        assert_eq!(shared.generation(), 1);
+        assert_eq!(shared.load().shards().shards().len(), 2);
+        // readers keep the generation they loaded
+        assert_eq!(reader.shards().shards().len(), 4);
+        assert_eq!(
+            *switcher.hooks.removed.lock().unwrap(),
+            vec!["geoshard_user_index_3", "geoshard_user_index_4"]
+        );
+
+        assert!(matches!(
+            stale.commit(),
+            Err(GeoshardError::SwitchAborted { .. })
+        ));
+        assert_eq!(shared.generation(), 1);
+    }
+}
@@ -0,0 +1,274 @@
+#![deny(missing_docs)]
+//! geocoding contains the `PlaceNamer` trait, which turns cells into human readable place
+//! names, and the logic used to label shards and attribute their score to places with them
+use std::collections::BTreeMap;
+
+use s2::{cellid::CellID, latlng::LatLng};
+use serde_derive::{Deserialize, Serialize};
+
+#[cfg(feature = "offline-geocoding")]
+use crate::{geoshard::EARTH_RADIUS, utils::ll};
+use crate::{
+    geoshard::{Geoshard, GeoshardCollection},
+    polygon::Polygon,
+};
+
+/// coarse centroids of countries and the states/provinces of the largest countries
+#[cfg(feature = "offline-geocoding")]
+const ADMIN1_CENTROIDS: &str = include_str!("../data/admin1_centroids.csv");
+
+/// PlaceNamer is the trait for a reverse geocoding provider. Implementing this allows
+/// shards to be labeled with the places they cover, e.g. "NYC + New England"
+pub trait PlaceNamer {
+    /// returns the name of the place the cell is in, if it is known
+    fn place_name(&self, cell_id: &CellID) -> Option<String>;
+}
+
+/// PolygonPlaceNamer names cells after the first of the provided polygons (e.g. country
+/// boundaries) containing the cell's center
+pub struct PolygonPlaceNamer {
+    regions: Vec<(String, Polygon)>,
+}
+
+impl PolygonPlaceNamer {
+    /// Constructs a new `PolygonPlaceNamer` from named polygons
+    pub fn new(regions: Vec<(String, Polygon)>) -> Self {
+        Self { regions }
+    }
+}
+
+impl PlaceNamer for PolygonPlaceNamer {
+    fn place_name(&self, cell_id: &CellID) -> Option<String> {
+        let center = LatLng::from(cell_id);
+        self.regions
+            .iter()
+            .find(|(_, polygon)| polygon.contains(&center))
+            .map(|(name, _)| name.clone())
+    }
+}
+
+/// `RegionReport` attributes the score of a collection to the places (countries, regions...)
+/// named by a `PlaceNamer`, overall and per shard. A shard lists every place it has a cell in,
+/// even if those cells have no score, so it shows every jurisdiction the shard touches
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RegionReport {
+    /// total score per place
+    pub by_region: BTreeMap<String, i64>,
+    /// score per place within each shard, keyed by shard name
+    pub by_shard: BTreeMap<String, BTreeMap<String, i64>>,
+    /// total score of the cells the `PlaceNamer` could not name
+    pub unattributed: i64,
+}
+
+impl RegionReport {
+    /// returns the places the given shard has cells in
+    pub fn regions_for_shard(&self, shard_name: &str) -> Vec<&str> {
+        self.by_shard
+            .get(shard_name)
+            .map(|regions| regions.keys().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl GeoshardCollection {
+    /// Builds a `RegionReport` attributing the score of every cell to the place it is in.
+    /// This names every cell in the collection, which can be slow for high storage levels
+    pub fn region_report<Namer: PlaceNamer>(&self, place_namer: &Namer) -> RegionReport {
+        let mut report = RegionReport::default();
+        for shard in self.shards() {
+            let shard_regions = report.by_shard.entry(shard.name().to_owned()).or_default();
+            for (index, cell_id) in shard.cell_union().0.iter().enumerate() {
+                let score = shard.cell_scores().get(index).copied().unwrap_or(0) as i64;
+                match place_namer.place_name(cell_id) {
+                    Some(place_name) => {
+                        *shard_regions.entry(place_name.clone()).or_insert(0) += score;
+                        *report.by_region.entry(place_name).or_insert(0) += score;
+                    }
+                    None => report.unattributed += score,
+                }
+            }
+        }
+        report
+    }
+}
+
+/// OfflinePlaceNamer names cells after the nearest centroid in a bundled, coarse dataset of
+/// countries and first level administrative divisions (states, provinces) without making any
+/// external calls. Places are named by their division when there is one (e.g. "New York"),
+/// otherwise by their country
+#[cfg(feature = "offline-geocoding")]
+pub struct OfflinePlaceNamer {
+    places: Vec<(String, LatLng)>,
+    max_distance_km: f64,
+}
+
+#[cfg(feature = "offline-geocoding")]
+impl OfflinePlaceNamer {
+    /// Constructs a new `OfflinePlaceNamer` where cells further than `max_distance_km` from
+    /// every centroid (such as the open ocean) are left unnamed
+    pub fn new(max_distance_km: f64) -> Self {
+        let places = ADMIN1_CENTROIDS
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut columns = line.split(',');
+                let admin1 = columns.next()?;
+                let country = columns.next()?;
+                let lat: f64 = columns.next()?.parse().ok()?;
+                let lng: f64 = columns.next()?.parse().ok()?;
+                let name = if admin1.is_empty() { country } else { admin1 };
+                Some((name.to_owned(), ll!(lng, lat)))
+            })
+            .collect();
+        Self {
+            places,
+            max_distance_km,
+        }
+    }
+}
+
+#[cfg(feature = "offline-geocoding")]
+impl Default for OfflinePlaceNamer {
+    fn default() -> Self {
+        Self::new(1500.0)
+    }
+}
+
+#[cfg(feature = "offline-geocoding")]
+impl PlaceNamer for OfflinePlaceNamer {
+    fn place_name(&self, cell_id: &CellID) -> Option<String> {
+        let center = LatLng::from(cell_id);
+        let (name, distance) = self
+            .places
+            .iter()
+            .map(|(name, centroid)| (name, center.distance(centroid).rad() * EARTH_RADIUS))
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        if distance / 1000.0 > self.max_distance_km {
+            return None;
+        }
+        Some(name.clone())
+    }
+}
+
+/// builds a label from the names of the places holding most of the shard's score.
+/// Shards without any score are labeled by the places covering the most cells instead
+pub(crate) fn label<Namer: PlaceNamer>(
+    place_namer: &Namer,
+    shard: &Geoshard,
+    max_places: usize,
+) -> Option<String> {
+    let cells = &shard.cell_union().0;
+    let cell_scores = shard.cell_scores();
+    let has_scores = cell_scores.len() == cells.len() && cell_scores.iter().any(|score| *score > 0);
+
+    let mut place_scores: BTreeMap<String, i64> = BTreeMap::new();
+    for (index, cell_id) in cells.iter().enumerate() {
+        let weight = if has_scores {
+            cell_scores[index] as i64
+        } else {
+            1
+        };
+        if weight <= 0 {
+            continue;
+        }
+        if let Some(place_name) = place_namer.place_name(cell_id) {
+            *place_scores.entry(place_name).or_insert(0) += weight;
+        }
+    }
+
+    let mut places: Vec<(String, i64)> = place_scores.into_iter().collect();
+    places.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    let names: Vec<String> = places
+        .into_iter()
+        .take(max_places)
+        .map(|(name, _)| name)
+        .collect();
+
+    if names.is_empty() {
+        None
+    } else {
+        Some(names.join(" + "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use s2::cellunion::CellUnion;
+
+    struct FaceNamer;
+
+    impl PlaceNamer for FaceNamer {
+        fn place_name(&self, cell_id: &CellID) -> Option<String> {
+            match cell_id.face() {
+                5 => None,
+                face => Some(format!("face {}", face)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_label() {
+        let cells: Vec<CellID> = (0..6).map(CellID::from_face).collect();
+        let shard = Geoshard::new("geoshard".to_owned(), 16, 0, CellUnion(cells.clone()))
+            .with_cell_scores(vec![1, 0, 10, 0, 5, 100]);
+        assert_eq!(
+            label(&FaceNamer, &shard, 2),
+            Some("face 2 + face 4".to_owned())
+        );
+
+        let unscored = Geoshard::new("geoshard".to_owned(), 0, 0, CellUnion(cells[5..].to_vec()));
+        assert_eq!(label(&FaceNamer, &unscored, 2), None);
+    }
+
+    #[test]
+    fn test_region_report() {
+        use crate::{testing::uniform_collection, utils::ll};
+
+        let western = Polygon::new(&[
+            ll!(-180.0, 90.0),
+            ll!(0.0, 90.0),
+            ll!(0.0, -90.0),
+            ll!(-180.0, -90.0),
+        ]);
+        let place_namer = PolygonPlaceNamer::new(vec![("West".to_owned(), western)]);
+
+        let geoshards = uniform_collection(2, 48);
+        let report = geoshards.region_report(&place_namer);
+
+        let west = report.by_region["West"];
+        assert!(west > 0);
+        assert_eq!(west + report.unattributed, 96);
+        let shard_total: i64 = report
+            .by_shard
+            .values()
+            .flat_map(|regions| regions.values())
+            .sum();
+        assert_eq!(shard_total, west);
+        for shard in geoshards.shards() {
+            let touches_west = shard
+                .cell_union()
+                .0
+                .iter()
+                .any(|cell_id| LatLng::from(cell_id).lng.deg() < 0.0);
+            assert_eq!(
+                report.regions_for_shard(shard.name()) == vec!["West"],
+                touches_west
+            );
+        }
+    }
+
+    #[cfg(feature = "offline-geocoding")]
+    #[test]
+    fn test_offline_place_namer() {
+        use crate::utils::ll;
+
+        let place_namer = OfflinePlaceNamer::default();
+        let albany = CellID::from(ll!(-73.7562, 42.6526)).parent(10);
+        assert_eq!(place_namer.place_name(&albany), Some("New York".to_owned()));
+        let lyon = CellID::from(ll!(4.8357, 45.7640)).parent(10);
+        assert_eq!(place_namer.place_name(&lyon), Some("France".to_owned()));
+        let south_pacific = CellID::from(ll!(-130.0, -45.0)).parent(10);
+        assert_eq!(place_namer.place_name(&south_pacific), None);
+    }
+}
@@ -0,0 +1,236 @@
+#![deny(missing_docs)]
+//! geofence contains named regions (polygons and caps) registered on a `GeoshardSearcher`, so
+//! per region rules such as feature flags can be enforced alongside routing. Regions are covered
+//! with cells at the storage level like shards are, so looking up the geofences of a location
+//! only tests the regions covering its cell
+use std::collections::BTreeMap;
+
+use s2::{
+    cap::Cap, cell::Cell, cellid::CellID, latlng::LatLng, point::Point, region::RegionCoverer, s1,
+};
+
+use crate::{
+    geoshard::{Geoshard, GeoshardSearcher, EARTH_RADIUS},
+    polygon::Polygon,
+};
+
+/// `GeofenceRegion` is the area of a geofence
+#[derive(Debug, Clone)]
+pub enum GeofenceRegion {
+    /// the area inside a polygon
+    Polygon(Polygon),
+    /// the area within `radius` meters of `center`
+    Cap {
+        /// center of the cap
+        center: LatLng,
+        /// radius of the cap in meters
+        radius: f64,
+    },
+}
+
+impl GeofenceRegion {
+    /// returns true if the location is inside the region
+    pub fn contains(&self, location: &LatLng) -> bool {
+        match self {
+            GeofenceRegion::Polygon(polygon) => polygon.contains(location),
+            GeofenceRegion::Cap { center, radius } => {
+                cap(center, *radius).contains_point(&Point::from(location))
+            }
+        }
+    }
+
+    /// returns the cells at `level` overlapping the region, possibly with some just outside it
+    fn covering(&self, level: u64) -> Vec<CellID> {
+        let coverer = RegionCoverer {
+            min_level: level as u8,
+            max_level: level as u8,
+            level_mod: 0,
+            max_cells: 0,
+        };
+        match self {
+            GeofenceRegion::Polygon(polygon) => coverer
+                .covering(&polygon.bound())
+                .0
+                .into_iter()
+                .filter(|cell_id| polygon.intersects_rect(&Cell::from(*cell_id).rect_bound()))
+                .collect(),
+            GeofenceRegion::Cap { center, radius } => coverer.covering(&cap(center, *radius)).0,
+        }
+    }
+}
+
+/// returns the cap within `radius` meters of `center`
+fn cap(center: &LatLng, radius: f64) -> Cap {
+    Cap::from_center_angle(&Point::from(center), &s1::Rad(radius / EARTH_RADIUS).into())
+}
+
+/// `Geofence` is a named region with its covering at the storage level
+#[derive(Debug, Clone)]
+struct Geofence {
+    name: String,
+    region: GeofenceRegion,
+    covering: Vec<CellID>,
+}
+
+/// `Geofences` are the geofences registered on a searcher, indexed by the cells covering them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Geofences {
+    geofences: Vec<Geofence>,
+    index: BTreeMap<CellID, Vec<usize>>,
+}
+
+impl Geofences {
+    /// adds the geofence, replacing any geofence with the same name
+    fn insert(&mut self, name: String, region: GeofenceRegion, level: u64) {
+        let covering = region.covering(level);
+        let geofence = Geofence {
+            name,
+            region,
+            covering,
+        };
+        match self
+            .geofences
+            .iter()
+            .position(|registered| registered.name == geofence.name)
+        {
+            Some(index) => self.geofences[index] = geofence,
+            None => self.geofences.push(geofence),
+        }
+        self.index.clear();
+        for (index, geofence) in self.geofences.iter().enumerate() {
+            for cell_id in geofence.covering.iter() {
+                self.index.entry(*cell_id).or_default().push(index);
+            }
+        }
+    }
+}
+
+impl GeoshardSearcher {
+    /// Registers a geofence, replacing any geofence with the same name. The region is covered
+    /// with cells at the storage level, so large regions on fine maps take more memory
+    pub fn with_geofence<S: Into<String>>(mut self, name: S, region: GeofenceRegion) -> Self {
+        let level = self.shards().storage_level();
+        self.geofences.insert(name.into(), region, level);
+        self
+    }
+
+    /// returns the names of the geofences containing the location, in registration order
+    pub fn geofences_for_location(&self, location: &LatLng) -> Vec<&str> {
+        let cell_id = CellID::from(location).parent(self.shards().storage_level());
+        self.geofences
+            .index
+            .get(&cell_id)
+            .into_iter()
+            .flatten()
+            .map(|index| &self.geofences.geofences[*index])
+            .filter(|geofence| geofence.region.contains(location))
+            .map(|geofence| geofence.name.as_str())
+            .collect()
+    }
+
+    /// returns the shards holding part of the geofence, in map order, or `None` if no geofence
+    /// is registered with that name. Shards holding cells just outside the region may be included
+    pub fn shards_for_geofence(&self, name: &str) -> Option<Vec<&Geoshard>> {
+        let geofence = self
+            .geofences
+            .geofences
+            .iter()
+            .find(|geofence| geofence.name == name)?;
+        let mut touched = vec![false; self.shards().shards().len()];
+        for cell_id in geofence.covering.iter() {
+            for shard in self.get_shards_from_cell_id(cell_id) {
+                if let Some(index) = self
+                    .shards()
+                    .shards()
+                    .iter()
+                    .position(|candidate| std::ptr::eq(candidate, shard))
+                {
+                    touched[index] = true;
+                }
+            }
+        }
+        Some(
+            self.shards()
+                .shards()
+                .iter()
+                .zip(touched)
+                .filter(|(_, touched)| *touched)
+                .map(|(shard, _)| shard)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+        utils::ll,
+    };
+
+    #[test]
+    fn test_geofences() {
+        let users = FakeUser::seeded(1000, 31, &RandCityFactory::default());
+        // rough outline of Colorado
+        let colorado = Polygon::new(&[
+            ll!(-109.05, 41.0),
+            ll!(-102.05, 41.0),
+            ll!(-102.05, 37.0),
+            ll!(-109.05, 37.0),
+        ]);
+        let searcher = GeoshardSearcher::from(
+            GeoshardBuilder::user_count_scorer(6, users.iter(), 10, 20).build(),
+        )
+        .with_geofence("colorado", GeofenceRegion::Polygon(colorado))
+        .with_geofence(
+            "denver",
+            GeofenceRegion::Cap {
+                center: ll!(-104.99, 39.74),
+                radius: 50_000.0,
+            },
+        );
+
+        assert_eq!(
+            searcher.geofences_for_location(&ll!(-104.99, 39.74)),
+            vec!["colorado", "denver"]
+        );
+        assert_eq!(
+            searcher.geofences_for_location(&ll!(-105.27, 40.01)),
+            vec!["colorado", "denver"]
+        );
+        assert_eq!(
+            searcher.geofences_for_location(&ll!(-106.82, 39.19)),
+            vec!["colorado"]
+        );
+        assert!(searcher
+            .geofences_for_location(&ll!(-111.89, 40.76))
+            .is_empty());
+
+        // every location in the geofence routes to one of its shards
+        let shards = searcher.shards_for_geofence("colorado").unwrap();
+        for (lng, lat) in [(-108.5, 37.3), (-102.3, 40.8), (-105.0, 39.0)] {
+            let shard = searcher.get_shard_from_location(&ll!(lng, lat));
+            assert!(shards
+                .iter()
+                .any(|geofenced| geofenced.name() == shard.name()));
+        }
+        let denver = searcher.shards_for_geofence("denver").unwrap();
+        assert!(denver.len() <= shards.len());
+        assert!(searcher.shards_for_geofence("utah").is_none());
+
+        // registering a name again replaces the geofence
+        let searcher = searcher.with_geofence(
+            "denver",
+            GeofenceRegion::Cap {
+                center: ll!(-104.99, 39.74),
+                radius: 1_000.0,
+            },
+        );
+        assert_eq!(
+            searcher.geofences_for_location(&ll!(-105.27, 40.01)),
+            vec!["colorado"]
+        );
+    }
+}
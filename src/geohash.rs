@@ -0,0 +1,135 @@
+#![deny(missing_docs)]
+//! geohash contains helpers to express shards as geohash prefixes, so systems keyed
+//! on geohash (such as Redis geo sets) can route to shards without using S2
+use std::collections::{BTreeMap, BTreeSet};
+
+use s2::{latlng::LatLng, rect::Rect};
+
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// encodes the given location as a geohash with `precision` characters. Longitudes outside
+/// [-180, 180] are wrapped around the antimeridian first
+pub fn encode(location: &LatLng, precision: usize) -> String {
+    let location = location.normalized();
+    let (lat_bits, lng_bits) = bits(precision);
+    from_indexes(
+        index(location.lat.deg(), 90.0, lat_bits),
+        index(location.lng.deg(), 180.0, lng_bits),
+        precision,
+    )
+}
+
+/// returns the geohashes with `precision` characters that intersect the given rect
+pub(crate) fn covering(rect: &Rect, precision: usize) -> BTreeSet<String> {
+    let (lat_bits, lng_bits) = bits(precision);
+    let lat_range = index(rect.lat.lo.to_degrees(), 90.0, lat_bits)
+        ..=index(rect.lat.hi.to_degrees(), 90.0, lat_bits);
+
+    let (lng_lo, lng_hi) = (rect.lng.lo.to_degrees(), rect.lng.hi.to_degrees());
+    // an inverted longitude interval wraps around the antimeridian
+    let lng_ranges = if lng_lo > lng_hi {
+        vec![(lng_lo, 180.0), (-180.0, lng_hi)]
+    } else {
+        vec![(lng_lo, lng_hi)]
+    };
+
+    let mut geohashes = BTreeSet::new();
+    for lat_index in lat_range {
+        for (lo, hi) in lng_ranges.iter() {
+            for lng_index in index(*lo, 180.0, lng_bits)..=index(*hi, 180.0, lng_bits) {
+                geohashes.insert(from_indexes(lat_index, lng_index, precision));
+            }
+        }
+    }
+    geohashes
+}
+
+/// replaces every complete set of 32 sibling geohashes with their parent prefix
+pub(crate) fn compact(mut geohashes: BTreeSet<String>) -> BTreeSet<String> {
+    loop {
+        let mut children: BTreeMap<String, usize> = BTreeMap::new();
+        for geohash in geohashes.iter().filter(|geohash| geohash.len() > 1) {
+            *children
+                .entry(geohash[..geohash.len() - 1].to_owned())
+                .or_insert(0) += 1;
+        }
+
+        let parents: Vec<String> = children
+            .into_iter()
+            .filter(|(_, count)| *count == BASE32.len())
+            .map(|(parent, _)| parent)
+            .collect();
+        if parents.is_empty() {
+            return geohashes;
+        }
+
+        for parent in parents {
+            for character in BASE32.iter() {
+                geohashes.remove(&format!("{}{}", parent, *character as char));
+            }
+            geohashes.insert(parent);
+        }
+    }
+}
+
+/// returns the number of latitude and longitude bits in a geohash of `precision` characters
+fn bits(precision: usize) -> (u32, u32) {
+    let total = 5 * precision as u32;
+    (total / 2, total - total / 2)
+}
+
+/// returns the index of `value` in [-`max`, `max`] divided into 2^`bits` equal parts
+fn index(value: f64, max: f64, bits: u32) -> u64 {
+    let cells = 1u64 << bits;
+    let index = ((value + max) / (2.0 * max) * cells as f64).floor();
+    (index.max(0.0) as u64).min(cells - 1)
+}
+
+/// interleaves the latitude and longitude indexes (longitude first) into base32 characters
+fn from_indexes(lat_index: u64, lng_index: u64, precision: usize) -> String {
+    let (lat_bits, lng_bits) = bits(precision);
+    let mut geohash = String::with_capacity(precision);
+    let mut character = 0;
+    for bit in 0..5 * precision as u32 {
+        let value = if bit % 2 == 0 {
+            (lng_index >> (lng_bits - 1 - bit / 2)) & 1
+        } else {
+            (lat_index >> (lat_bits - 1 - bit / 2)) & 1
+        };
+        character = (character << 1) | value as usize;
+        if bit % 5 == 4 {
+            geohash.push(BASE32[character] as char);
+            character = 0;
+        }
+    }
+    geohash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::ll;
+
+    #[test]
+    fn test_geohash() {
+        assert_eq!(encode(&ll!(10.40744, 57.64911), 11), "u4pruydqqvj");
+        assert_eq!(encode(&ll!(-74.0060, 40.7128), 5), "dr5re");
+        assert_eq!(encode(&ll!(181.0, 10.0), 5), encode(&ll!(-179.0, 10.0), 5));
+
+        let rect = Rect::from(ll!(-74.0060, 40.7128));
+        assert_eq!(
+            covering(&rect, 5).into_iter().collect::<Vec<String>>(),
+            vec!["dr5re"]
+        );
+
+        let children: BTreeSet<String> = BASE32
+            .iter()
+            .map(|character| format!("dr5r{}", *character as char))
+            .chain(std::iter::once("dr5q0".to_owned()))
+            .collect();
+        assert_eq!(
+            compact(children).into_iter().collect::<Vec<String>>(),
+            vec!["dr5q0", "dr5r"]
+        );
+    }
+}
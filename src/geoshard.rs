@@ -8,23 +8,38 @@
 //! # Examples
 //!
 //! ```rust
-//! use location_based_sharding::geoshard::{GeoshardBuilder, GeoshardSearcher};
-//! #[cfg(test)]
-//! use location_based_sharding::geoshard::test::FakeUser;
+//! #[cfg(all(feature = "builder", feature = "test-util"))]
+//! use location_based_sharding::{
+//!     geoshard::{GeoshardBuilder, GeoshardSearcher},
+//!     testing::FakeUser,
+//! };
 //!
-//! #[cfg(test)]
-//! let geoshards = GeoshardBuilder::user_count_scorer(8, Box::new(vec![].into_iter()), 40, 100).build();
-//! #[cfg(test)]
+//! #[cfg(all(feature = "builder", feature = "test-util"))]
+//! let geoshards = GeoshardBuilder::user_count_scorer(8, Box::new(Vec::<FakeUser>::new().into_iter()), 40, 100).build();
+//! #[cfg(all(feature = "builder", feature = "test-util"))]
 //! let shard_searcher = GeoshardSearcher::from(geoshards);
 //! // let shard_user_is_in = shard_searcher.get_shard_user(some_user);
 //! ```
 
-use std::collections::BTreeMap;
-
-use s2::{
-    cap::Cap, cellid::CellID, cellunion::CellUnion, latlng::LatLng, point::Point,
-    region::RegionCoverer, s1,
+#[cfg(feature = "builder")]
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "builder")]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "searcher")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "builder")]
+use std::time::Instant;
+use std::{
+    fmt,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+
+#[cfg(feature = "builder")]
+use s2::cellid::MAX_LEVEL;
+#[cfg(feature = "searcher")]
+use s2::{cap::Cap, point::Point, region::RegionCoverer, s1};
+use s2::{cell::Cell, cellid::CellID, cellunion::CellUnion, latlng::LatLng};
 use serde::{
     de::{MapAccess, Visitor},
     ser::SerializeStruct,
@@ -32,12 +47,38 @@ use serde::{
 };
 use serde_derive::{Deserialize, Serialize};
 
+#[cfg(all(feature = "tracing", feature = "searcher"))]
+use crate::trace;
+#[cfg(feature = "searcher")]
+use crate::{
+    cache::LruCache,
+    geofence::Geofences,
+    lookup::RangeTable,
+    polygon::Polygon,
+    users::{IdentifiedUser, LocationSelector, MultiLocationUser, User},
+    utils::{ll, stable_hash},
+};
+#[cfg(feature = "builder")]
 use crate::{
-    cell_list::{CellList, CellScorer, UserCountScorer},
-    users::User,
+    cell_list::{
+        CellList, CellScorer, Cluster, PreScoredCells, ScoredCellSnapshot, UserCountScorer,
+    },
+    error::FallibleBuildError,
+    geocoding::{self, PlaceNamer},
+    migration::ReshardComparison,
+    preset::Preset,
+    report::{BuildReport, SamplingEstimate, ScorerComparison},
+    strategy::{OptimalContiguous, PartitionStrategy},
+    users::{FallibleUsers, SampledUsers},
 };
+use crate::{error::GeoshardError, geohash};
 
-const EARTH_RADIUS: f64 = 6.37e6f64;
+pub(crate) const EARTH_RADIUS: f64 = 6.37e6f64;
+
+/// finest storage level `GeoshardSearcher::with_lookup_table` builds tables for, whose table
+/// takes 400MB
+#[cfg(feature = "searcher")]
+pub const MAX_LOOKUP_TABLE_LEVEL: u64 = 12;
 
 /// The `GeoshardBuilder<Scorer>` type. This used to generate and score shards baed on provided Scorer.
 /// Generating Shards can potentially be an expensive operation, which is why the builder pattern is
@@ -52,20 +93,932 @@ const EARTH_RADIUS: f64 = 6.37e6f64;
 /// ```rust
 /// use location_based_sharding::geoshard::GeoshardBuilder;
 ///
-/// #[cfg(test)]
-/// use location_based_sharding::geoshard::test::FakeUser;
+/// #[cfg(feature = "test-util")]
+/// use location_based_sharding::testing::FakeUser;
 ///
-/// #[cfg(test)]
+/// #[cfg(feature = "test-util")]
 /// let geoshards = GeoshardBuilder::user_count_scorer(4, Box::new(vec![FakeUser::new()].into_iter()), 40, 100).build();
 /// ```
+#[cfg(feature = "builder")]
 pub struct GeoshardBuilder<Scorer, UserCollection> {
-    storage_level: u64,
     users: UserCollection,
     cell_scorer: Scorer,
+    partitioner: Partitioner,
+}
+
+/// `PinnedRegion` is a region whose cells must map to a dedicated shard regardless of the
+/// optimizer, e.g. a country that legally requires its data to be isolated
+#[cfg(feature = "builder")]
+#[derive(Debug, Clone)]
+pub enum PinnedRegion {
+    /// every cell at the storage level that intersects the cell union
+    Cells(CellUnion),
+    /// every cell at the storage level whose center is inside the polygon
+    Polygon(Polygon),
+}
+
+#[cfg(feature = "builder")]
+impl PinnedRegion {
+    /// returns true if the cell belongs to the region
+    fn contains(&self, cell_id: &CellID) -> bool {
+        match self {
+            PinnedRegion::Cells(cell_union) => cell_union.intersects_cellid(cell_id),
+            PinnedRegion::Polygon(polygon) => polygon.contains(&LatLng::from(cell_id)),
+        }
+    }
+}
+
+#[cfg(feature = "builder")]
+impl From<CellUnion> for PinnedRegion {
+    fn from(mut cell_union: CellUnion) -> Self {
+        cell_union.normalize();
+        PinnedRegion::Cells(cell_union)
+    }
+}
+
+#[cfg(feature = "builder")]
+impl From<Polygon> for PinnedRegion {
+    fn from(polygon: Polygon) -> Self {
+        PinnedRegion::Polygon(polygon)
+    }
+}
+
+/// `BuildPhase` is a phase of building shards, reported to `GeoshardBuilder::with_progress`
+#[cfg(feature = "builder")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildPhase {
+    /// generating every cell at the storage level, counted in cells. Not reported for sparse
+    /// cell lists, which start empty
+    CellGeneration,
+    /// scoring the cells, counted in users. The total is the users' size hint, if they have one
+    Scoring,
+    /// trying every container size for the lowest standard deviation, counted in sizes tried.
+    /// This runs once per set of cells balanced (e.g. again after pinning clusters)
+    ContainerSearch,
+}
+
+/// `ProgressCallback` is called with the phase, the work done and the total work, if known
+#[cfg(feature = "builder")]
+type ProgressCallback = Arc<dyn Fn(BuildPhase, u64, Option<u64>) + Send + Sync>;
+
+/// `ProgressUsers` wraps the users of a `GeoshardBuilder` with progress reporting, reporting the
+/// users scored every `PROGRESS_INTERVAL` users, and the final count once they run out
+#[cfg(feature = "builder")]
+pub struct ProgressUsers<UserCollection> {
+    users: UserCollection,
+    done: u64,
+    total: Option<u64>,
+    finished: bool,
+    progress: ProgressCallback,
+}
+
+/// number of users scored between `BuildPhase::Scoring` progress reports
+#[cfg(feature = "builder")]
+const PROGRESS_INTERVAL: u64 = 10_000;
+
+#[cfg(feature = "builder")]
+impl<UserCollection: Iterator> Iterator for ProgressUsers<UserCollection> {
+    type Item = UserCollection::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.users.next() {
+            Some(user) => {
+                self.done += 1;
+                if self.done.is_multiple_of(PROGRESS_INTERVAL) {
+                    (self.progress)(BuildPhase::Scoring, self.done, self.total);
+                }
+                Some(user)
+            }
+            None => {
+                if !self.finished {
+                    self.finished = true;
+                    (self.progress)(BuildPhase::Scoring, self.done, Some(self.done));
+                }
+                None
+            }
+        }
+    }
+}
+
+/// `OverflowCounts` is the number of users in each cell at the overflow splitting level
+#[cfg(feature = "builder")]
+type OverflowCounts = Arc<Mutex<BTreeMap<CellID, u32>>>;
+
+/// `OverflowUsers` wraps the users of a `GeoshardBuilder` for `with_overflow_splitting`, counting
+/// the users in each cell at the overflow level as they are scored
+#[cfg(feature = "builder")]
+pub struct OverflowUsers<UserCollection> {
+    users: UserCollection,
+    level: u64,
+    counts: BTreeMap<CellID, u32>,
+    shared_counts: OverflowCounts,
+}
+
+#[cfg(feature = "builder")]
+impl<UserCollection> Iterator for OverflowUsers<UserCollection>
+where
+    UserCollection: Iterator,
+    UserCollection::Item: User,
+{
+    type Item = UserCollection::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let user = self.users.next()?;
+        let cell_id = CellID::from(user.location()).parent(self.level);
+        *self.counts.entry(cell_id).or_insert(0) += 1;
+        Some(user)
+    }
+}
+
+#[cfg(feature = "builder")]
+impl<UserCollection> Drop for OverflowUsers<UserCollection> {
+    // the counts are handed to the partitioner once scoring is done with the users
+    fn drop(&mut self) {
+        let mut shared_counts = self.shared_counts.lock().unwrap();
+        for (cell_id, count) in std::mem::take(&mut self.counts) {
+            *shared_counts.entry(cell_id).or_insert(0) += count;
+        }
+    }
+}
+
+/// `PartitionObjective` is what the partitioner balances across shards
+#[cfg(feature = "builder")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PartitionObjective {
+    /// balance the total score of each shard (the default)
+    #[default]
+    Score,
+    /// balance the number of cells in each shard, for when per cell overhead (indexes, files)
+    /// costs more than user load
+    CellCount,
+    /// balance a blend of both, where `score_weight` (between 0 and 1) is the weight of the
+    /// score and the rest is the weight of the cell count
+    Blend {
+        /// weight of the score in the blend, between 0 and 1
+        score_weight: f64,
+    },
+}
+
+#[cfg(feature = "builder")]
+impl PartitionObjective {
+    /// returns the weight of every cell under this objective, or `None` when it is the score.
+    /// Every cell's share of the cell count is worth the mean cell score, and the weights are
+    /// scaled so they add up to at least the number of cells
+    fn weights(&self, scored_cells: &BTreeMap<CellID, i32>) -> Option<BTreeMap<CellID, i32>> {
+        let score_weight = match self {
+            PartitionObjective::Score => return None,
+            PartitionObjective::CellCount => 0.0,
+            PartitionObjective::Blend { score_weight } => score_weight.clamp(0.0, 1.0),
+        };
+        let cell_count = scored_cells.len() as f64;
+        let total_score = scored_cells
+            .values()
+            .map(|score| *score as f64)
+            .sum::<f64>();
+        if total_score <= 0.0 {
+            return Some(scored_cells.keys().map(|cell_id| (*cell_id, 1)).collect());
+        }
+        let mean_score = total_score / cell_count;
+        let scale = total_score.max(cell_count) / total_score;
+        Some(
+            scored_cells
+                .iter()
+                .map(|(cell_id, score)| {
+                    let weight = score_weight * *score as f64 + (1.0 - score_weight) * mean_score;
+                    (*cell_id, (weight * scale).round() as i32)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// `Partitioner` holds the builder configuration used to turn a scored `CellList` into shards
+#[cfg(feature = "builder")]
+pub(crate) struct Partitioner {
+    storage_level: u64,
+    min_shard_count: i32,
+    max_shard_count: i32,
+    cluster_pin_score: Option<i32>,
+    max_shard_score: Option<i32>,
+    min_shard_score: Option<i32>,
+    pinned_regions: Vec<(String, PinnedRegion)>,
+    residency_zones: Vec<(String, PinnedRegion)>,
+    score_window: Option<ScoreWindow>,
+    objective: PartitionObjective,
+    max_shard_extent: Option<f64>,
+    preset: Option<Preset>,
+    progress: Option<ProgressCallback>,
+    cancellation: Option<Arc<AtomicBool>>,
+    deadline: Option<Instant>,
+    overflow: Option<(u64, OverflowCounts)>,
+    cached_cells: bool,
+    sparse_cells: bool,
+    strategy: Option<Arc<dyn PartitionStrategy + Send + Sync>>,
+    /// the rate and seed users are sampled at, and the number of users sampled
+    sampling: Option<(f64, u64, Arc<AtomicU64>)>,
+}
+
+#[cfg(feature = "builder")]
+impl Partitioner {
+    pub(crate) fn new(storage_level: u64, min_shard_count: i32, max_shard_count: i32) -> Self {
+        Self {
+            storage_level,
+            min_shard_count,
+            max_shard_count,
+            cluster_pin_score: None,
+            max_shard_score: None,
+            min_shard_score: None,
+            pinned_regions: vec![],
+            residency_zones: vec![],
+            score_window: None,
+            objective: PartitionObjective::Score,
+            max_shard_extent: None,
+            preset: None,
+            progress: None,
+            cancellation: None,
+            deadline: None,
+            overflow: None,
+            cached_cells: false,
+            sparse_cells: false,
+            strategy: None,
+            sampling: None,
+        }
+    }
+
+    /// returns the container sizes to try. With a deadline, sizes are tried coarse to fine
+    /// (the middle size, then the quarters, and so on) so the search covers the whole range
+    /// evenly whenever it is cut short, otherwise they are tried in order
+    fn container_sizes(&self, min_size: i32, max_size: i32) -> Vec<i32> {
+        if self.deadline.is_none() || max_size <= min_size {
+            return (min_size..=max_size).collect();
+        }
+        let mut sizes = vec![];
+        let mut tried = BTreeSet::new();
+        let mut stride = ((max_size - min_size) as u32).next_power_of_two() as i32;
+        while stride > 0 {
+            let mut size = min_size + stride / 2;
+            while size <= max_size {
+                if tried.insert(size) {
+                    sizes.push(size);
+                }
+                size += stride;
+            }
+            stride /= 2;
+        }
+        sizes
+    }
+
+    /// returns true if the build was cancelled through the cancellation flag
+    fn cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(|cancellation| cancellation.load(Ordering::Relaxed))
+    }
+
+    /// returns `GeoshardError::Cancelled` if the build was cancelled
+    fn check_cancelled(&self) -> Result<(), GeoshardError> {
+        if self.cancelled() {
+            return Err(GeoshardError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// reports progress to the progress callback, if there is one
+    fn report_progress(&self, phase: BuildPhase, done: u64, total: Option<u64>) {
+        if let Some(progress) = &self.progress {
+            progress(phase, done, total);
+        }
+    }
+
+    /// generates the cells to score at the storage level
+    fn cell_list(&self) -> CellList {
+        if self.sparse_cells {
+            return CellList::sparse(self.storage_level);
+        }
+        let total = 6u64 << (2 * self.storage_level);
+        #[cfg(feature = "tracing")]
+        let mut span = trace::Span::new("cell_generation");
+        #[cfg(feature = "tracing")]
+        span.record("storage_level", self.storage_level);
+        self.report_progress(BuildPhase::CellGeneration, 0, Some(total));
+        let cell_list = if self.cached_cells {
+            CellList::cached(self.storage_level)
+        } else {
+            CellList::new(self.storage_level)
+        };
+        self.report_progress(BuildPhase::CellGeneration, total, Some(total));
+        #[cfg(feature = "tracing")]
+        span.record("cells", cell_list.cell_list().len());
+        cell_list
+    }
+
+    /// checks the configuration, scores `users` with `cell_scorer` and builds shards from the
+    /// scores, traced as a `build` span. `scored` is called once the users are scored, and a
+    /// failure stops the build before partitioning. Returns the scored cells with the shards
+    fn build<Scorer, Users, T, E>(
+        &self,
+        cell_scorer: &Scorer,
+        users: Users,
+        scored: impl FnOnce() -> Result<(), E>,
+    ) -> Result<(GeoshardCollection, CellList), E>
+    where
+        Scorer: CellScorer<Users>,
+        Users: Iterator<Item = T>,
+        T: User,
+        E: From<GeoshardError>,
+    {
+        check_config(
+            self.storage_level,
+            self.min_shard_count,
+            self.max_shard_count,
+        )?;
+        self.check_sampling()?;
+        #[cfg(feature = "tracing")]
+        let mut span = trace::Span::new("build");
+        #[cfg(feature = "tracing")]
+        {
+            span.record("storage_level", self.storage_level);
+            span.record("min_shard_count", self.min_shard_count);
+            span.record("max_shard_count", self.max_shard_count);
+        }
+        let cell_list = self.cell_list();
+        // Calculate the score for each S2 cell based off of the provided Cell Scorer
+        #[cfg(feature = "tracing")]
+        let mut scoring = trace::Span::new("score_cell_list");
+        let cell_list = self.scale_sampled(cell_scorer.score_cell_list(cell_list, users));
+        #[cfg(feature = "tracing")]
+        {
+            scoring.record("storage_level", self.storage_level);
+            scoring.record(
+                "scored_cells",
+                cell_list
+                    .cell_list()
+                    .values()
+                    .filter(|score| **score != 0)
+                    .count(),
+            );
+            drop(scoring);
+        }
+        scored()?;
+        let geoshards = self.partition(&cell_list)?;
+        #[cfg(feature = "tracing")]
+        {
+            span.record("shard_count", geoshards.shards().len());
+            span.record("standard_deviation", geoshards.standard_deviation());
+        }
+        Ok((geoshards, cell_list))
+    }
+
+    /// reports the configuration of the partitioner, and a summary of the shards it built
+    fn report(&self, geoshards: &GeoshardCollection) -> BuildReport {
+        BuildReport {
+            preset: self.preset,
+            storage_level: self.storage_level,
+            min_shard_count: self.min_shard_count,
+            max_shard_count: self.max_shard_count,
+            objective: self.objective,
+            cluster_pin_score: self.cluster_pin_score,
+            max_shard_score: self.max_shard_score,
+            min_shard_score: self.min_shard_score,
+            max_shard_extent: self.max_shard_extent,
+            pinned_regions: self
+                .pinned_regions
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect(),
+            residency_zones: self
+                .residency_zones
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect(),
+            shard_count: geoshards.shards.len(),
+            total_score: geoshards
+                .shards
+                .iter()
+                .map(|shard| shard.cell_score as i64)
+                .sum(),
+            standard_deviation: geoshards.standard_deviation(),
+            sampling: self.sampling.as_ref().map(|(rate, seed, sampled)| {
+                SamplingEstimate::new(
+                    *rate,
+                    *seed,
+                    sampled.load(Ordering::Relaxed),
+                    geoshards
+                        .shards
+                        .iter()
+                        .map(|shard| shard.cell_score as i64)
+                        .min()
+                        .unwrap_or(0),
+                )
+            }),
+        }
+    }
+
+    /// fails with `GeoshardError::InvalidBuilderConfig` if the sampling rate isn't in (0, 1]
+    fn check_sampling(&self) -> Result<(), GeoshardError> {
+        match &self.sampling {
+            Some((rate, _, _)) if !(*rate > 0.0 && *rate <= 1.0) => {
+                Err(GeoshardError::InvalidBuilderConfig {
+                    reason: format!("sampling rate {} is not in (0, 1]", rate),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// scales the scores of cells scored from a sample of the users up by the sampling rate, so
+    /// they estimate the scores of every user
+    fn scale_sampled(&self, mut cell_list: CellList) -> CellList {
+        if let Some((rate, _, _)) = &self.sampling {
+            for score in cell_list.mut_cell_list().values_mut() {
+                *score = (*score as f64 / rate).round() as i32;
+            }
+        }
+        cell_list
+    }
+
+    /// partitions the cells, stamping the collection with the score window if there is one
+    fn partition(&self, cell_list: &CellList) -> Result<GeoshardCollection, GeoshardError> {
+        check_config(
+            self.storage_level,
+            self.min_shard_count,
+            self.max_shard_count,
+        )?;
+        self.check_sampling()?;
+        self.check_cancelled()?;
+        let mut geoshards = self.partition_cells(cell_list)?;
+        // the container search stops early when cancelled, so its shards can't be used
+        self.check_cancelled()?;
+        if cell_list.is_sparse() {
+            geoshards.fill_gaps();
+        }
+        geoshards.score_window = self.score_window;
+        Ok(geoshards)
+    }
+
+    /// carves out any pinned cells into their own shards, then balances the remaining cells
+    fn partition_cells(&self, cell_list: &CellList) -> Result<GeoshardCollection, GeoshardError> {
+        let scored_cells = cell_list.cell_list();
+        if let Some(limit) = self.max_shard_score {
+            // hot cells that can be split across shards are checked once split
+            let split_cells = self.split_hot_cells(scored_cells, limit);
+            if let Some((cell_id, score)) = split_cells
+                .as_ref()
+                .unwrap_or(scored_cells)
+                .iter()
+                .find(|(_, score)| **score > limit)
+            {
+                return Err(GeoshardError::CellOverShardLimit {
+                    cell: cell_id.to_token(),
+                    score: *score,
+                    limit,
+                });
+            }
+        }
+
+        let region_shards = self.region_shards(scored_cells)?;
+        let cluster_shards = if region_shards.is_empty() {
+            self.pinned_shards(cell_list)?
+        } else {
+            let mut unpinned = cell_list.clone();
+            for shard in region_shards.iter() {
+                for cell_id in shard.cell_union().0.iter() {
+                    unpinned.mut_cell_list().remove(cell_id);
+                }
+            }
+            self.pinned_shards(&unpinned)?
+        };
+        if region_shards.is_empty() && cluster_shards.is_empty() {
+            return Ok(self.balance(scored_cells, self.min_shard_count, self.max_shard_count));
+        }
+
+        let mut remaining_cells = scored_cells.clone();
+        for shard in region_shards.iter().chain(cluster_shards.iter()) {
+            for cell_id in shard.cell_union().0.iter() {
+                remaining_cells.remove(cell_id);
+            }
+        }
+
+        let pinned_count = (region_shards.len() + cluster_shards.len()) as i32;
+        let mut geoshards = self.balance(
+            &remaining_cells,
+            (self.min_shard_count - pinned_count).max(1),
+            (self.max_shard_count - pinned_count).max(1),
+        );
+        for shard in cluster_shards {
+            let name = format!("geoshard_user_index_{}", geoshards.shards.len() + 1);
+            geoshards.shards.push(Geoshard { name, ..shard });
+        }
+        // pinned regions keep the name they were declared with
+        geoshards.shards.extend(region_shards);
+        Ok(geoshards)
+    }
+
+    /// returns a shard for each pinned region. A cell in several regions belongs to the first
+    /// region declared
+    fn region_shards(
+        &self,
+        scored_cells: &BTreeMap<CellID, i32>,
+    ) -> Result<Vec<Geoshard>, GeoshardError> {
+        let mut claimed = BTreeSet::new();
+        let mut shards = vec![];
+        for (name, region) in self.pinned_regions.iter() {
+            let (cells, cell_scores): (Vec<CellID>, Vec<i32>) = scored_cells
+                .iter()
+                .filter(|(cell_id, _)| !claimed.contains(*cell_id) && region.contains(cell_id))
+                .map(|(cell_id, score)| (*cell_id, *score))
+                .unzip();
+            if cells.is_empty() {
+                return Err(GeoshardError::EmptyPinnedRegion { name: name.clone() });
+            }
+            claimed.extend(cells.iter().copied());
+            shards.push(
+                Geoshard::new(
+                    name.clone(),
+                    cell_scores.iter().sum(),
+                    self.storage_level,
+                    CellUnion(cells),
+                )
+                .with_cell_scores(cell_scores),
+            );
+        }
+        Ok(shards)
+    }
+
+    /// detects clusters when `pin_clusters` is set, and returns a shard for each of them
+    fn pinned_shards(&self, cell_list: &CellList) -> Result<Vec<Geoshard>, GeoshardError> {
+        let min_score = match self.cluster_pin_score {
+            Some(min_score) => min_score,
+            None => return Ok(vec![]),
+        };
+
+        let scored_cells = cell_list.cell_list();
+        let total_load = scored_cells.values().sum::<i32>();
+        let cap = self
+            .max_shard_score
+            .map_or(total_load / self.min_shard_count, |limit| {
+                limit.min(total_load / self.min_shard_count)
+            });
+
+        let (clusters, oversized): (Vec<Cluster>, Vec<Cluster>) = cell_list
+            .detect_clusters(min_score)
+            .into_iter()
+            .partition(|cluster| cluster.score() <= cap);
+        if !oversized.is_empty() {
+            return Err(GeoshardError::ClustersTooLarge {
+                cap,
+                clusters: oversized,
+            });
+        }
+
+        Ok(clusters
+            .into_iter()
+            .map(|cluster| {
+                let cell_scores = cluster
+                    .cell_union()
+                    .0
+                    .iter()
+                    .map(|cell_id| scored_cells[cell_id])
+                    .collect();
+                Geoshard::new(
+                    cluster.name().to_owned(),
+                    cluster.score(),
+                    self.storage_level,
+                    cluster.cell_union().clone(),
+                )
+                .with_cell_scores(cell_scores)
+            })
+            .collect())
+    }
+
+    /// Splits the cells scoring more than `limit` into their children, recursively, down to the
+    /// overflow level. Each cell's score is split between its children by the number of users
+    /// counted in each, so cells without counted users (or at the overflow level) are kept
+    /// whole. Returns `None` without overflow splitting, or when no cell is over the limit
+    fn split_hot_cells(
+        &self,
+        scored_cells: &BTreeMap<CellID, i32>,
+        limit: i32,
+    ) -> Option<BTreeMap<CellID, i32>> {
+        let (level, counts) = self.overflow.as_ref()?;
+        if scored_cells.values().all(|score| *score <= limit) {
+            return None;
+        }
+        let counts = counts.lock().unwrap();
+        let users_in = |cell_id: &CellID| -> u64 {
+            counts
+                .range(cell_id.range_min()..=cell_id.range_max())
+                .map(|(_, count)| *count as u64)
+                .sum()
+        };
+
+        let mut split_cells = BTreeMap::new();
+        let mut hot_cells = vec![];
+        for (cell_id, score) in scored_cells.iter() {
+            if *score > limit {
+                hot_cells.push((*cell_id, *score));
+            } else {
+                split_cells.insert(*cell_id, *score);
+            }
+        }
+        while let Some((cell_id, score)) = hot_cells.pop() {
+            let users = users_in(&cell_id);
+            if score <= limit || cell_id.level() >= *level || users == 0 {
+                split_cells.insert(cell_id, score);
+                continue;
+            }
+            let children = cell_id.children();
+            let child_users: Vec<u64> = children.iter().map(users_in).collect();
+            let mut child_scores: Vec<i32> = child_users
+                .iter()
+                .map(|child_users| (score as i64 * *child_users as i64 / users as i64) as i32)
+                .collect();
+            // the rounding remainder goes to the busiest child
+            let busiest = (0..4).max_by_key(|index| child_users[*index]).unwrap();
+            child_scores[busiest] += score - child_scores.iter().sum::<i32>();
+            hot_cells.extend(children.into_iter().zip(child_scores));
+        }
+        Some(split_cells)
+    }
+
+    /// returns the residency zone of each cell, in order, or `None` without residency zones. A
+    /// cell belongs to the first zone containing it, and cells outside every zone to none
+    fn zones(&self, scored_cells: &BTreeMap<CellID, i32>) -> Option<Vec<Option<usize>>> {
+        if self.residency_zones.is_empty() {
+            return None;
+        }
+        Some(
+            scored_cells
+                .keys()
+                .map(|cell_id| {
+                    self.residency_zones
+                        .iter()
+                        .position(|(_, zone)| zone.contains(cell_id))
+                })
+                .collect(),
+        )
+    }
+
+    /// generate shards for every possible shard count and find the one with the lowest standard
+    /// deviation between them.
+    pub(crate) fn balance(
+        &self,
+        scored_cells: &BTreeMap<CellID, i32>,
+        min_shard_count: i32,
+        max_shard_count: i32,
+    ) -> GeoshardCollection {
+        // Split the cells too hot for the largest shard allowed, with overflow splitting
+        let total_score: i32 = scored_cells.values().sum();
+        let limit = self
+            .max_shard_score
+            .map_or(total_score / min_shard_count, |limit| {
+                limit.min(total_score / min_shard_count)
+            });
+        let split_cells = self.split_hot_cells(scored_cells, limit);
+        let scored_cells = split_cells.as_ref().unwrap_or(scored_cells);
+
+        // Balance on the objective's weights, then put the real scores back on the best shards
+        let weighted_cells = self.objective.weights(scored_cells);
+        let real_scores = scored_cells;
+        let scored_cells = weighted_cells.as_ref().unwrap_or(scored_cells);
+
+        let zones = self.zones(scored_cells);
+        let zones = zones.as_deref();
+
+        if let Some(strategy) = &self.strategy {
+            let scores: Vec<i32> = scored_cells.values().copied().collect();
+            let runs = strategy.partition(
+                &scores,
+                min_shard_count.max(1) as usize,
+                max_shard_count.max(1) as usize,
+            );
+            let mut shards = GeoshardCollection::from_runs(scored_cells, &runs, self.storage_level);
+            if let Some(zones) = zones {
+                shards.split_at_zones(zones);
+            }
+            if let Some(floor) = self.min_shard_score {
+                shards.coalesce(floor, self.max_shard_score, zones);
+            }
+            if weighted_cells.is_some() {
+                shards.rescore(real_scores);
+            }
+            return shards;
+        }
+
+        // Get the total load in all the cells
+        let total_load = scored_cells.iter().fold(0, |sum, i| sum + i.1);
+
+        // Calculate the max_shard size and min_shard size based on shard count constraints
+        let mut max_size = total_load / min_shard_count;
+        let mut min_size = total_load / max_shard_count;
+
+        // A hard score limit wins over the shard count constraints, even if it creates more shards
+        if let Some(limit) = self.max_shard_score {
+            max_size = max_size.min(limit);
+            min_size = min_size.min(max_size);
+        }
+
+        let mut best_shards: Option<GeoshardCollection> = None;
+        let mut min_standard_deviation = f64::MAX;
+        let sizes = Some((max_size - min_size + 1).max(0) as u64);
+        self.report_progress(BuildPhase::ContainerSearch, 0, sizes);
+        let container_sizes = self.container_sizes(min_size, max_size);
+        #[cfg(feature = "tracing")]
+        let mut span = trace::Span::new("balance");
+        #[cfg(feature = "tracing")]
+        {
+            span.record("storage_level", self.storage_level);
+            span.record("cells", scored_cells.len());
+            span.record("candidates", container_sizes.len());
+        }
+        #[cfg(feature = "tracing")]
+        let mut tried_sizes = 0u64;
+
+        // Try every possible shard size and return the one that has the lowest standard deviation
+        for (tried, container_size) in container_sizes.into_iter().enumerate() {
+            if self.cancelled() {
+                break;
+            }
+            // past the deadline, keep the best shards found so far (but always try one size)
+            if tried > 0
+                && self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                break;
+            }
+            let mut shards = GeoshardCollection::pack(
+                container_size,
+                scored_cells,
+                self.storage_level,
+                self.max_shard_extent,
+                zones,
+            );
+            if let Some(floor) = self.min_shard_score {
+                shards.coalesce(floor, self.max_shard_score, zones);
+            }
+            let standard_deviation = shards.standard_deviation();
+            if standard_deviation < min_standard_deviation {
+                #[cfg(feature = "tracing")]
+                trace::event("container_size_improved", || {
+                    vec![
+                        ("container_size", container_size.into()),
+                        ("shard_count", shards.shards().len().into()),
+                        ("standard_deviation", standard_deviation.into()),
+                    ]
+                });
+                min_standard_deviation = standard_deviation;
+                best_shards = Some(shards);
+            }
+            self.report_progress(BuildPhase::ContainerSearch, tried as u64 + 1, sizes);
+            #[cfg(feature = "tracing")]
+            {
+                tried_sizes += 1;
+            }
+        }
+
+        let mut best_shards = best_shards
+            .unwrap_or_else(|| GeoshardCollection::from_shards(self.storage_level, vec![]));
+        if weighted_cells.is_some() {
+            best_shards.rescore(real_scores);
+        }
+        #[cfg(feature = "tracing")]
+        {
+            span.record("tried", tried_sizes);
+            span.record("shard_count", best_shards.shards().len());
+            span.record("standard_deviation", best_shards.standard_deviation());
+        }
+        best_shards
+    }
+}
+
+/// checks the storage level is an S2 level and the shard count bounds are a non empty range of
+/// positive counts, so a misconfigured builder fails before scoring
+#[cfg(feature = "builder")]
+fn check_config(
+    storage_level: u64,
     min_shard_count: i32,
     max_shard_count: i32,
+) -> Result<(), GeoshardError> {
+    let reason = if storage_level > MAX_LEVEL {
+        format!(
+            "storage level {} is over the maximum S2 level of {}",
+            storage_level, MAX_LEVEL
+        )
+    } else if min_shard_count < 1 {
+        format!("min_shard_count {} is less than 1", min_shard_count)
+    } else if min_shard_count > max_shard_count {
+        format!(
+            "min_shard_count {} is greater than max_shard_count {}",
+            min_shard_count, max_shard_count
+        )
+    } else {
+        return Ok(());
+    };
+    Err(GeoshardError::InvalidBuilderConfig { reason })
+}
+
+/// `Unset` marks a stage of a `StagedGeoshardBuilder` that hasn't been configured yet
+#[cfg(feature = "builder")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unset;
+
+/// `StagedGeoshardBuilder` configures a `GeoshardBuilder` a stage at a time: the users, the
+/// scorer and then the shard count bounds, which check the configuration and return the
+/// builder. `bounds` only exists once the users and scorer are set, so forgetting either is a
+/// compile time error rather than a failed build
+///
+/// # Examples
+///
+/// ```rust
+/// use location_based_sharding::{cell_list::UserCountScorer, geoshard::GeoshardBuilder};
+///
+/// #[cfg(feature = "test-util")]
+/// use location_based_sharding::testing::FakeUser;
+///
+/// #[cfg(feature = "test-util")]
+/// let geoshards = GeoshardBuilder::staged(4)
+///     .users(vec![FakeUser::new()].into_iter())
+///     .scorer(UserCountScorer)
+///     .bounds(40, 100)
+///     .unwrap()
+///     .build();
+/// ```
+#[cfg(feature = "builder")]
+pub struct StagedGeoshardBuilder<Scorer, UserCollection> {
+    storage_level: u64,
+    users: UserCollection,
+    cell_scorer: Scorer,
+}
+
+#[cfg(feature = "builder")]
+impl GeoshardBuilder<Unset, Unset> {
+    /// Starts a `StagedGeoshardBuilder` storing cells at `storage_level`
+    pub fn staged(storage_level: u64) -> StagedGeoshardBuilder<Unset, Unset> {
+        StagedGeoshardBuilder {
+            storage_level,
+            users: Unset,
+            cell_scorer: Unset,
+        }
+    }
+}
+
+#[cfg(feature = "builder")]
+impl<Scorer> StagedGeoshardBuilder<Scorer, Unset> {
+    /// sets the users to score
+    pub fn users<UserCollection>(
+        self,
+        users: UserCollection,
+    ) -> StagedGeoshardBuilder<Scorer, UserCollection> {
+        StagedGeoshardBuilder {
+            storage_level: self.storage_level,
+            users,
+            cell_scorer: self.cell_scorer,
+        }
+    }
+}
+
+#[cfg(feature = "builder")]
+impl<UserCollection> StagedGeoshardBuilder<Unset, UserCollection> {
+    /// sets the scorer of the cells
+    pub fn scorer<Scorer>(
+        self,
+        cell_scorer: Scorer,
+    ) -> StagedGeoshardBuilder<Scorer, UserCollection> {
+        StagedGeoshardBuilder {
+            storage_level: self.storage_level,
+            users: self.users,
+            cell_scorer,
+        }
+    }
+}
+
+#[cfg(feature = "builder")]
+impl<Scorer, UserCollection> StagedGeoshardBuilder<Scorer, UserCollection>
+where
+    Scorer: CellScorer<UserCollection>,
+    UserCollection: Iterator,
+{
+    /// Sets the shard count bounds, returning the configured `GeoshardBuilder`. Fails with
+    /// `GeoshardError::InvalidBuilderConfig` if the storage level is over the maximum S2 level,
+    /// `min_shard_count` is less than 1 or `min_shard_count` is greater than `max_shard_count`
+    pub fn bounds(
+        self,
+        min_shard_count: i32,
+        max_shard_count: i32,
+    ) -> Result<GeoshardBuilder<Scorer, UserCollection>, GeoshardError> {
+        check_config(self.storage_level, min_shard_count, max_shard_count)?;
+        Ok(GeoshardBuilder::new(
+            self.storage_level,
+            self.users,
+            self.cell_scorer,
+            min_shard_count,
+            max_shard_count,
+        ))
+    }
 }
 
+#[cfg(feature = "builder")]
 impl<Scorer, UserCollection> GeoshardBuilder<Scorer, UserCollection> {
     /// Constructs a new Builder for Geoshards
     ///
@@ -90,10 +1043,10 @@ impl<Scorer, UserCollection> GeoshardBuilder<Scorer, UserCollection> {
     /// ```rust
     /// use location_based_sharding::{cell_list::UserCountScorer, geoshard::GeoshardBuilder};
     ///
-    /// #[cfg(test)]
-    /// use location_based_sharding::geoshard::test::FakeUser;
+    /// #[cfg(feature = "test-util")]
+    /// use location_based_sharding::testing::FakeUser;
     ///
-    /// #[cfg(test)]
+    /// #[cfg(feature = "test-util")]
     /// let geoshards = GeoshardBuilder::new(4, Box::new(vec![FakeUser::new()].into_iter()), UserCountScorer, 40, 100).build();
     /// ```
     pub fn new(
@@ -104,53 +1057,434 @@ impl<Scorer, UserCollection> GeoshardBuilder<Scorer, UserCollection> {
         max_shard_count: i32,
     ) -> Self {
         Self {
-            storage_level,
             cell_scorer,
             users,
-            min_shard_count,
-            max_shard_count,
+            partitioner: Partitioner::new(storage_level, min_shard_count, max_shard_count),
         }
     }
 
-    /// `build` will actually build the S2 CellList from the given storage level, score each cell, and
-    /// then generate shards for every possible shard count and find the one with the lowest standard
+    /// `pin_clusters` makes sure every cluster of neighboring cells scoring at least `min_score`
+    /// (see `CellList::detect_clusters`) lands entirely within one shard, by giving each cluster a
+    /// shard of its own before balancing the remaining cells. Clusters that score more than the
+    /// largest allowed shard (total score / `min_shard_count`) can't be pinned and are reported as a
+    /// `GeoshardError::ClustersTooLarge` from `try_build`
+    pub fn pin_clusters(mut self, min_score: i32) -> Self {
+        self.partitioner.cluster_pin_score = Some(min_score);
+        self
+    }
+
+    /// `with_max_shard_score` sets a hard limit on the score of every shard, such as the document
+    /// cap of a storage node. Ranges of cells over the limit are split into more shards, even if
+    /// that goes over `max_shard_count` or worsens the standard deviation. A single cell scoring
+    /// over the limit can't be split and is reported as a `GeoshardError::CellOverShardLimit`
+    /// from `try_build`
+    pub fn with_max_shard_score(mut self, limit: i32) -> Self {
+        self.partitioner.max_shard_score = Some(limit);
+        self
+    }
+
+    /// `pin_region` gives every cell in the region a dedicated shard named `name`, regardless of
+    /// the optimizer, e.g. for a country that legally requires data isolation. Pinned regions are
+    /// carved out before clusters are pinned and the remaining cells are balanced. A region
+    /// without any cells at the storage level is reported as a `GeoshardError::EmptyPinnedRegion`
+    /// from `try_build`
+    pub fn pin_region(mut self, name: impl Into<String>, region: impl Into<PinnedRegion>) -> Self {
+        self.partitioner
+            .pinned_regions
+            .push((name.into(), region.into()));
+        self
+    }
+
+    /// `with_residency_zone` tags the cells in the region (e.g. a country or the EU) with the
+    /// zone `name`, and no shard is built holding cells of two zones, or of a zone and cells
+    /// outside every zone, e.g. for data residency rules. A cell in several zones belongs to the
+    /// first zone declared. Unlike pinned regions, zones are balanced into as many shards as
+    /// their score needs, so shards are cut where the cell order crosses a zone's border, which
+    /// can build more shards than `max_shard_count`
+    pub fn with_residency_zone(
+        mut self,
+        name: impl Into<String>,
+        region: impl Into<PinnedRegion>,
+    ) -> Self {
+        self.partitioner
+            .residency_zones
+            .push((name.into(), region.into()));
+        self
+    }
+
+    /// `with_min_shard_score` coalesces neighboring shards (adjacent along the cell order) while
+    /// their combined score is under `floor`, so sparse regions such as oceans and deserts collapse
+    /// into a few large shards while dense regions stay fine grained. Shards are never coalesced
+    /// over the `with_max_shard_score` limit
+    pub fn with_min_shard_score(mut self, floor: i32) -> Self {
+        self.partitioner.min_shard_score = Some(floor);
+        self
+    }
+
+    /// `with_max_shard_extent` limits how far apart the cells of a shard can be, so cross shard
+    /// queries for local features stay bounded: every cell's center must be within
+    /// `max_extent` meters of the center of the shard's first cell (in cell order). Shards are cut
+    /// early when the limit is reached, even if they are under the load target
+    pub fn with_max_shard_extent(mut self, max_extent: f64) -> Self {
+        self.partitioner.max_shard_extent = Some(max_extent);
+        self
+    }
+
+    /// `with_objective` sets what is balanced across shards: the score (the default), the number
+    /// of cells, or a blend of both. Shards still report their real scores, while
+    /// `with_max_shard_score` and `with_min_shard_score` apply to the objective's weights
+    pub fn with_objective(mut self, objective: PartitionObjective) -> Self {
+        self.partitioner.objective = objective;
+        self
+    }
+
+    /// `with_score_window` stamps the built collection with the time range of the users' score
+    /// data, so searchers can warn when serving a map built from stale data
+    pub fn with_score_window(mut self, score_window: ScoreWindow) -> Self {
+        self.partitioner.score_window = Some(score_window);
+        self
+    }
+
+    /// `with_progress` calls `progress` with the phase, the work done and the total work (if known)
+    /// as the cells are generated, scored and partitioned, e.g. to log the progress of large
+    /// builds. Scoring progress is reported every 10,000 users, see `BuildPhase`
+    pub fn with_progress<F>(
+        self,
+        progress: F,
+    ) -> GeoshardBuilder<Scorer, ProgressUsers<UserCollection>>
+    where
+        F: Fn(BuildPhase, u64, Option<u64>) + Send + Sync + 'static,
+        UserCollection: Iterator,
+    {
+        let progress: ProgressCallback = Arc::new(progress);
+        let mut partitioner = self.partitioner;
+        partitioner.progress = Some(progress.clone());
+        GeoshardBuilder {
+            users: ProgressUsers {
+                total: self.users.size_hint().1.map(|total| total as u64),
+                users: self.users,
+                done: 0,
+                finished: false,
+                progress,
+            },
+            cell_scorer: self.cell_scorer,
+            partitioner,
+        }
+    }
+
+    /// `with_overflow_splitting` splits cells that score more than the largest shard allowed
+    /// (total score / `min_shard_count`, or the `max_shard_score`) into their children, down to
+    /// `max_level` at most, so a single hot cell (e.g. downtown Manhattan) can be spread across
+    /// several shards instead of becoming one oversized shard. Users are counted per cell at
+    /// `max_level` while scoring, so memory grows with the number of distinct cells users are in
+    /// at that level, and each hot cell's score is split between its children by user count.
+    /// Cells pinned by clusters or regions are not split
+    pub fn with_overflow_splitting(
+        self,
+        max_level: u64,
+    ) -> GeoshardBuilder<Scorer, OverflowUsers<UserCollection>> {
+        let counts = OverflowCounts::default();
+        let mut partitioner = self.partitioner;
+        partitioner.overflow = Some((max_level, counts.clone()));
+        GeoshardBuilder {
+            users: OverflowUsers {
+                users: self.users,
+                level: max_level,
+                counts: BTreeMap::new(),
+                shared_counts: counts,
+            },
+            cell_scorer: self.cell_scorer,
+            partitioner,
+        }
+    }
+
+    /// `with_partition_strategy` splits the cells into shards with the given strategy, such as
+    /// `OptimalContiguous`, instead of the default container size sweep. The strategy is given
+    /// the shard count constraints, and shards under the `min_shard_score` are still coalesced,
+    /// but deadlines and the `max_shard_extent` only apply to the default sweep
+    pub fn with_partition_strategy<Strategy>(mut self, strategy: Strategy) -> Self
+    where
+        Strategy: PartitionStrategy + Send + Sync + 'static,
+    {
+        self.partitioner.strategy = Some(Arc::new(strategy));
+        self
+    }
+
+    /// `with_exact_shard_count` builds exactly `shard_count` shards (as long as there are as many
+    /// cells), e.g. to match a number of database instances, rather than searching the min/max
+    /// range. The cells are split into contiguous shards minimizing the score of the heaviest
+    /// shard with `OptimalContiguous`. Pinned shards count towards the total, and a
+    /// `min_shard_score` can still coalesce shards below it. Counts under 1 fail the build with
+    /// `GeoshardError::InvalidBuilderConfig`
+    pub fn with_exact_shard_count(mut self, shard_count: i32) -> Self {
+        self.partitioner.min_shard_count = shard_count;
+        self.partitioner.max_shard_count = shard_count;
+        self.with_partition_strategy(OptimalContiguous::default())
+    }
+
+    /// `with_cached_cells` reuses the cells generated by earlier builds at the same storage level
+    /// in this process instead of generating them again, see `CellList::cached`
+    pub fn with_cached_cells(mut self) -> Self {
+        self.partitioner.cached_cells = true;
+        self
+    }
+
+    /// `with_sparse_cells` scores into a sparse `CellList`, holding only the cells users are in
+    /// rather than every cell at the storage level, see `CellList::sparse`. The unscored cells
+    /// between shards are then covered by range, with cells as coarse as fit in each gap, so the
+    /// shards can route any location. Pinned regions and clusters only hold scored cells, so
+    /// locations in their unscored cells are routed to a neighbouring shard
+    pub fn with_sparse_cells(mut self) -> Self {
+        self.partitioner.sparse_cells = true;
+        self
+    }
+
+    /// `with_cancellation` lets the build be cancelled by setting `cancellation` to true from
+    /// another thread, e.g. when a deploy is rolled back. Cancellation is checked after scoring
+    /// and before every container size tried, and `try_build` then returns a
+    /// `GeoshardError::Cancelled`. Scoring itself runs until the users run out
+    pub fn with_cancellation(mut self, cancellation: Arc<AtomicBool>) -> Self {
+        self.partitioner.cancellation = Some(cancellation);
+        self
+    }
+
+    /// `with_user_filter` only scores the users `predicate` returns true for, e.g. to skip banned
+    /// users, drop duplicate IDs or score a 10% sample. Users are filtered as they are scored, so
+    /// the collection is never held in memory. Filters can be chained, each seeing the users kept
+    /// by the previous ones
+    pub fn with_user_filter<P>(
+        self,
+        predicate: P,
+    ) -> GeoshardBuilder<Scorer, std::iter::Filter<UserCollection, P>>
+    where
+        UserCollection: Iterator,
+        P: FnMut(&UserCollection::Item) -> bool,
+    {
+        GeoshardBuilder {
+            users: self.users.filter(predicate),
+            cell_scorer: self.cell_scorer,
+            partitioner: self.partitioner,
+        }
+    }
+
+    /// `with_sampling` scores a random sample of the users, each kept with probability `rate`
+    /// (in (0, 1]), and scales the scores up by the rate, so a good shard map of a huge user
+    /// collection can be built without scoring every user. The sample is drawn as the users are
+    /// scored, from a generator seeded with `seed`, so builds with the same seed are
+    /// reproducible. `try_build_with_report` reports the error the sampling may introduce, see
+    /// `SamplingEstimate`. Overflow splitting counts the sampled users only
+    pub fn with_sampling(
+        mut self,
+        rate: f64,
+        seed: u64,
+    ) -> GeoshardBuilder<Scorer, SampledUsers<UserCollection>> {
+        let users = SampledUsers::new(self.users, rate, seed);
+        self.partitioner.sampling = Some((rate, seed, users.sampled()));
+        GeoshardBuilder {
+            users,
+            cell_scorer: self.cell_scorer,
+            partitioner: self.partitioner,
+        }
+    }
+
+    /// `build` will actually build the S2 CellList from the given storage level, score each cell, and
+    /// then generate shards for every possible shard count and find the one with the lowest standard
     /// deviation between them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the shards can't satisfy the builder's constraints, see `try_build`
     pub fn build<T>(self) -> GeoshardCollection
     where
         Scorer: CellScorer<UserCollection>,
         UserCollection: Iterator<Item = T>,
         T: User,
     {
-        // Calculate the score for each S2 cell based off of the provided Cell Scorer
-        let cell_list = self
-            .cell_scorer
-            .score_cell_list(CellList::new(self.storage_level), self.users);
-        let scored_cells = cell_list.cell_list();
+        self.try_build().unwrap_or_else(|error| panic!("{}", error))
+    }
 
-        // Get the total load in all the cells
-        let total_load = scored_cells.iter().fold(0, |sum, i| sum + i.1);
+    /// `try_build_with_report` is `try_build`, also returning a `BuildReport` of the configuration
+    /// used (including any preset) and a summary of the shards built
+    pub fn try_build_with_report<T>(
+        self,
+    ) -> Result<(GeoshardCollection, BuildReport), GeoshardError>
+    where
+        Scorer: CellScorer<UserCollection>,
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        let (geoshards, _) = self
+            .partitioner
+            .build(&self.cell_scorer, self.users, || Ok(()))?;
+        let report = self.partitioner.report(&geoshards);
+        Ok((geoshards, report))
+    }
 
-        // Calculate the max_shard size and min_shard size based on shard count constraints
-        let max_size = total_load / self.min_shard_count;
-        let min_size = total_load / self.max_shard_count;
+    /// `build_and_compare` is `try_build` as a dry run against the `current` production map,
+    /// also returning a `ReshardComparison` of what rolling the new map out would move, costed
+    /// with the new scores. Fails with `GeoshardError::InvalidShardMap` if the current map is at
+    /// another storage level
+    pub fn build_and_compare<T>(
+        self,
+        current: &GeoshardCollection,
+    ) -> Result<(GeoshardCollection, ReshardComparison), GeoshardError>
+    where
+        Scorer: CellScorer<UserCollection>,
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        let (geoshards, cell_list) =
+            self.partitioner
+                .build(&self.cell_scorer, self.users, || Ok(()))?;
+        let comparison = ReshardComparison::new(current, &geoshards, cell_list.cell_list())?;
+        Ok((geoshards, comparison))
+    }
 
-        let mut best_shards: Option<GeoshardCollection> = None;
-        let mut min_standard_deviation = f64::MAX;
+    /// `compare_scorers` scores the same users with the builder's scorer and with `other`, and
+    /// builds shards from both, to evaluate switching scorers (e.g. from user counts to
+    /// `TimeDecayScorer`) without running two production builds. The comparison reports the cells
+    /// scored differently, and how the shards the builder's scorer would build score under
+    /// `other`. The users are iterated once per scorer
+    pub fn compare_scorers<Other, T>(self, other: Other) -> Result<ScorerComparison, GeoshardError>
+    where
+        Scorer: CellScorer<UserCollection>,
+        Other: CellScorer<UserCollection>,
+        UserCollection: Iterator<Item = T> + Clone,
+        T: User,
+    {
+        let cells_a = self.partitioner.scale_sampled(
+            self.cell_scorer
+                .score_cell_list(self.partitioner.cell_list(), self.users.clone()),
+        );
+        let cells_b = self
+            .partitioner
+            .scale_sampled(other.score_cell_list(self.partitioner.cell_list(), self.users));
+        let shards_a = self.partitioner.partition(&cells_a)?;
+        let shards_b = self.partitioner.partition(&cells_b)?;
+        Ok(ScorerComparison::new(
+            cells_a.cell_list(),
+            cells_b.cell_list(),
+            &shards_a,
+            shards_b.shards().len(),
+        ))
+    }
 
-        // Try every possible shard size and return the one that has the lowest standard deviation
-        for container_size in min_size..=max_size {
-            let shards = GeoshardCollection::new(container_size, scored_cells, self.storage_level);
-            let standard_deviation = shards.standard_deviation();
-            if standard_deviation < min_standard_deviation {
-                min_standard_deviation = standard_deviation;
-                best_shards = Some(shards);
-            }
+    /// `snapshot` scores the users without building shards, returning the scores as a
+    /// `ScoredCellSnapshot` to keep up to date from user events and rebuild from with
+    /// `rebuild_from_snapshot`
+    pub fn snapshot<T>(self) -> ScoredCellSnapshot
+    where
+        Scorer: CellScorer<UserCollection>,
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        ScoredCellSnapshot::new(
+            self.partitioner.scale_sampled(
+                self.cell_scorer
+                    .score_cell_list(self.partitioner.cell_list(), self.users),
+            ),
+        )
+    }
+
+    /// `rebuild_from_snapshot` is `try_build` from the scores in `snapshot`, skipping the scorer
+    /// and the builder's users entirely, so small changes in the users don't mean scoring all of
+    /// them again. Overflow splitting needs the users' locations and doesn't apply. Fails with
+    /// `GeoshardError::StorageLevelMismatch` if the snapshot is at another storage level
+    pub fn rebuild_from_snapshot(
+        self,
+        snapshot: &ScoredCellSnapshot,
+    ) -> Result<GeoshardCollection, GeoshardError> {
+        if snapshot.storage_level() != self.partitioner.storage_level {
+            return Err(GeoshardError::StorageLevelMismatch {
+                expected: self.partitioner.storage_level,
+                found: snapshot.storage_level(),
+            });
         }
+        self.partitioner.partition(snapshot.cell_list())
+    }
+
+    /// `build_with_deadline` is `try_build` for a time budget: once `budget` has passed since the
+    /// call, the container size search stops and the best shards found so far are returned, along
+    /// with their standard deviation. Sizes are tried coarse to fine, so a short budget still
+    /// samples the whole range. At least one size is always tried, even when the budget is spent
+    /// scoring
+    pub fn build_with_deadline<T>(
+        mut self,
+        budget: Duration,
+    ) -> Result<(GeoshardCollection, f64), GeoshardError>
+    where
+        Scorer: CellScorer<UserCollection>,
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        self.partitioner.deadline = Some(Instant::now() + budget);
+        let geoshards = self.try_build()?;
+        let standard_deviation = geoshards.standard_deviation();
+        Ok((geoshards, standard_deviation))
+    }
+
+    /// `try_build` is `build`, returning an error instead of panicking when the shards can't satisfy
+    /// the builder's constraints (such as pinned clusters that are too large). An invalid storage
+    /// level or shard count bounds fail with `GeoshardError::InvalidBuilderConfig` before scoring
+    pub fn try_build<T>(self) -> Result<GeoshardCollection, GeoshardError>
+    where
+        Scorer: CellScorer<UserCollection>,
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        let (geoshards, _) = self
+            .partitioner
+            .build(&self.cell_scorer, self.users, || Ok(()))?;
+        Ok(geoshards)
+    }
 
-        best_shards.unwrap()
+    /// `build_from_fallible` is `try_build` for user collections that can fail part way through,
+    /// such as a paginated database scan. Scoring stops at the first `Err` from the users, which is
+    /// returned as `FallibleBuildError::Users` instead of a `GeoshardCollection`, and errors
+    /// building the shards are returned as `FallibleBuildError::Build`
+    pub fn build_from_fallible<T, E>(self) -> Result<GeoshardCollection, FallibleBuildError<E>>
+    where
+        Scorer: CellScorer<FallibleUsers<UserCollection, E>>,
+        UserCollection: Iterator<Item = Result<T, E>>,
+        T: User,
+    {
+        let users = FallibleUsers::new(self.users);
+        let error = users.error();
+        let (geoshards, _) = self.partitioner.build(&self.cell_scorer, users, || {
+            match error.borrow_mut().take() {
+                Some(error) => Err(FallibleBuildError::Users(error)),
+                None => Ok(()),
+            }
+        })?;
+        Ok(geoshards)
+    }
+}
+
+#[cfg(feature = "builder")]
+impl GeoshardBuilder<PreScoredCells, std::iter::Empty<LatLng>> {
+    /// Create a `GeoshardBuilder<PreScoredCells>` balancing cells already scored elsewhere (e.g.
+    /// per cell load from an analytics warehouse), without any users to iterate. The storage
+    /// level is the level of the coarsest cell, and finer cells add their score to their parent
+    /// at that level. Cells missing from `cells` score 0
+    pub fn from_scored_cells(
+        cells: BTreeMap<CellID, i32>,
+        min_shard_count: i32,
+        max_shard_count: i32,
+    ) -> Self {
+        let cell_scorer = PreScoredCells::new(cells);
+        Self {
+            users: std::iter::empty(),
+            partitioner: Partitioner::new(
+                cell_scorer.storage_level(),
+                min_shard_count,
+                max_shard_count,
+            ),
+            cell_scorer,
+        }
     }
 }
 
+#[cfg(feature = "builder")]
 impl<UserCollection> GeoshardBuilder<UserCountScorer, UserCollection> {
     /// Create a `GeoshardBuilder<UserCountScorer>` where a cells given scorer is defaultly set
     /// to score based off UserCount in that area
@@ -161,13 +1495,28 @@ impl<UserCollection> GeoshardBuilder<UserCountScorer, UserCollection> {
         max_shard_count: i32,
     ) -> Self {
         Self {
-            storage_level,
             users,
             cell_scorer: UserCountScorer,
-            max_shard_count,
-            min_shard_count,
+            partitioner: Partitioner::new(storage_level, min_shard_count, max_shard_count),
         }
     }
+
+    /// Create a `GeoshardBuilder<UserCountScorer>` configured by the preset for a common workload
+    /// (storage level, shard count range and constraints). The configuration can be tuned further
+    /// with the other builder methods, and is reported by `try_build_with_report`
+    pub fn preset(preset: Preset, users: UserCollection) -> Self {
+        let (min_shard_count, max_shard_count) = preset.shard_count_range();
+        let mut builder = Self::user_count_scorer(
+            preset.storage_level(),
+            users,
+            min_shard_count,
+            max_shard_count,
+        )
+        .with_objective(preset.objective());
+        builder.partitioner.max_shard_extent = preset.max_shard_extent();
+        builder.partitioner.preset = Some(preset);
+        builder
+    }
 }
 
 /// `Geoshard` represents one shard...each shard contains a variable amount of cells
@@ -177,6 +1526,8 @@ pub struct Geoshard {
     storage_level: u64,
     cell_score: i32,
     cell_union: CellUnion,
+    cell_scores: Vec<i32>,
+    label: Option<String>,
 }
 
 impl Serialize for Geoshard {
@@ -197,6 +1548,8 @@ impl Serialize for Geoshard {
                 .collect::<Vec<String>>(),
         )?;
         state.serialize_field("cell_score", &self.cell_score)?;
+        state.serialize_field("cell_scores", &self.cell_scores)?;
+        state.serialize_field("label", &self.label)?;
         state.end()
     }
 }
@@ -211,6 +1564,8 @@ impl<'de> Deserialize<'de> for Geoshard {
             StorageLevel,
             Cells,
             CellScore,
+            CellScores,
+            Label,
         }
 
         impl<'de> Deserialize<'de> for Field {
@@ -224,7 +1579,9 @@ impl<'de> Deserialize<'de> for Geoshard {
                     type Value = Field;
 
                     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                        formatter.write_str("`name` or `storage_level` or `cells` or `cell_score`")
+                        formatter.write_str(
+                            "`name` or `storage_level` or `cells` or `cell_score` or `cell_scores` or `label`",
+                        )
                     }
 
                     fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -236,6 +1593,8 @@ impl<'de> Deserialize<'de> for Geoshard {
                             "storage_level" => Ok(Field::StorageLevel),
                             "cells" => Ok(Field::Cells),
                             "cell_score" => Ok(Field::CellScore),
+                            "cell_scores" => Ok(Field::CellScores),
+                            "label" => Ok(Field::Label),
                             _ => Err(serde::de::Error::unknown_field(value, FIELDS)),
                         }
                     }
@@ -268,6 +1627,8 @@ impl<'de> Deserialize<'de> for Geoshard {
                 let cell_score = seq
                     .next_element()?
                     .ok_or_else(|| serde::de::Error::invalid_length(4, &self))?;
+                let cell_scores = seq.next_element()?.unwrap_or_default();
+                let label = seq.next_element()?.flatten();
 
                 Ok(Geoshard::new(
                     name,
@@ -279,7 +1640,9 @@ impl<'de> Deserialize<'de> for Geoshard {
                             .map(|token| CellID::from_token(&token))
                             .collect(),
                     ),
-                ))
+                )
+                .with_cell_scores(cell_scores)
+                .with_label(label))
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
@@ -290,6 +1653,8 @@ impl<'de> Deserialize<'de> for Geoshard {
                 let mut storage_level = None;
                 let mut cells = None;
                 let mut cell_score = None;
+                let mut cell_scores = None;
+                let mut label = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Name => {
@@ -322,6 +1687,18 @@ impl<'de> Deserialize<'de> for Geoshard {
                             }
                             cell_score = Some(map.next_value()?);
                         }
+                        Field::CellScores => {
+                            if cell_scores.is_some() {
+                                return Err(serde::de::Error::duplicate_field("cell_scores"));
+                            }
+                            cell_scores = Some(map.next_value()?);
+                        }
+                        Field::Label => {
+                            if label.is_some() {
+                                return Err(serde::de::Error::duplicate_field("label"));
+                            }
+                            label = Some(map.next_value()?);
+                        }
                     }
                 }
                 let name = name.ok_or_else(|| serde::de::Error::missing_field("name"))?;
@@ -330,17 +1707,23 @@ impl<'de> Deserialize<'de> for Geoshard {
                     cell_score.ok_or_else(|| serde::de::Error::missing_field("cell_score"))?;
                 let storage_level = storage_level
                     .ok_or_else(|| serde::de::Error::missing_field("storage_level"))?;
-                Ok(Geoshard::new(
-                    name,
-                    cell_score,
-                    storage_level,
-                    CellUnion(cells),
-                ))
+                Ok(
+                    Geoshard::new(name, cell_score, storage_level, CellUnion(cells))
+                        .with_cell_scores(cell_scores.unwrap_or_default())
+                        .with_label(label.flatten()),
+                )
             }
         }
 
-        const FIELDS: &'static [&'static str] =
-            &["name", "storage_level", "start", "end", "cell_score"];
+        const FIELDS: &[&str] = &[
+            "name",
+            "storage_level",
+            "start",
+            "end",
+            "cell_score",
+            "cell_scores",
+            "label",
+        ];
         deserializer.deserialize_struct("Geoshard", FIELDS, GeoshardVisitor)
     }
 }
@@ -353,27 +1736,80 @@ impl Geoshard {
             storage_level,
             cell_score,
             cell_union,
+            cell_scores: vec![],
+            label: None,
         }
     }
 
+    /// sets the human readable label of this shard, such as "New York + New Jersey"
+    pub fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// sets the per cell scores of this shard, in the same order as the cells in the `cell_union`
+    pub fn with_cell_scores(mut self, cell_scores: Vec<i32>) -> Self {
+        self.cell_scores = cell_scores;
+        self
+    }
+
     /// name returns the name of the shard
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    /// label returns the human readable label of the shard, if it has been labeled
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// score returns the total score of the cells in this shard
+    pub fn score(&self) -> i32 {
+        self.cell_score
+    }
+
+    /// returns this shard's share of the total score of its map, e.g. of
+    /// `GeoshardCollection::total_score`, from 0 to 1. A map without score has a share of 0
+    pub fn score_fraction_of_total(&self, total_score: i64) -> f64 {
+        match total_score {
+            0 => 0.0,
+            total_score => self.cell_score as f64 / total_score as f64,
+        }
+    }
+
+    /// returns the approximate area covered by this shard's cells, in square kilometers
+    pub fn approx_area_km2(&self) -> f64 {
+        let earth_radius_km = EARTH_RADIUS / 1000.0;
+        self.cell_union
+            .0
+            .iter()
+            .map(|cell_id| Cell::from(cell_id).approx_area())
+            .sum::<f64>()
+            * earth_radius_km
+            * earth_radius_km
+    }
+
     /// cell_count returns the cell_count for this geoshard
     pub fn cell_count(&self) -> usize {
         self.cell_union.0.len()
     }
 
-    /// returns the starting cell
+    /// Returns the starting cell
+    ///
+    /// # Panics
+    ///
+    /// Panics if the shard has no cells, which built and verified shards always have
     pub fn start(&self) -> &CellID {
-        &self.cell_union.0[0]
+        self.cell_union.0.first().expect("the shard has no cells")
     }
 
-    /// returns the end cell
+    /// Returns the end cell
+    ///
+    /// # Panics
+    ///
+    /// Panics if the shard has no cells, which built and verified shards always have
     pub fn end(&self) -> &CellID {
-        &self.cell_union.0.last().unwrap()
+        self.cell_union.0.last().expect("the shard has no cells")
     }
 
     /// Returns a cell union from this shard
@@ -381,20 +1817,200 @@ impl Geoshard {
         &self.cell_union
     }
 
+    /// Returns the geohash prefixes of at most `precision` characters covering this shard's cells
+    ///
+    /// Geohashes along the shard's boundary also overlap its neighboring shards, and complete sets of
+    /// sibling geohashes are collapsed into their shorter parent prefix
+    pub fn geohash_covering(&self, precision: usize) -> Vec<String> {
+        let geohashes = self
+            .cell_union
+            .0
+            .iter()
+            .flat_map(|cell_id| geohash::covering(&Cell::from(cell_id).rect_bound(), precision))
+            .collect();
+        geohash::compact(geohashes).into_iter().collect()
+    }
+
     /// returns the stroage level of the cells in this shard
     pub fn storage_level(&self) -> u64 {
         self.storage_level
     }
+
+    /// returns the score of each cell in this shard, in the same order as the `cell_union`
+    /// this is empty for shards deserialized from maps that did not record cell scores
+    pub fn cell_scores(&self) -> &[i32] {
+        &self.cell_scores
+    }
+
+    /// returns the `n` highest scored cells in this shard
+    pub fn hottest_cells(&self, n: usize) -> Vec<HotCell> {
+        let mut hot_cells = self.hot_cells().collect::<Vec<HotCell>>();
+        hot_cells.sort_by_key(|hot_cell| std::cmp::Reverse(hot_cell.score));
+        hot_cells.truncate(n);
+        hot_cells
+    }
+
+    fn hot_cells(&self) -> impl Iterator<Item = HotCell> + '_ {
+        self.cell_union
+            .0
+            .iter()
+            .zip(self.cell_scores.iter())
+            .map(|(cell_id, score)| HotCell::new(&self.name, *cell_id, *score))
+    }
+}
+
+impl fmt::Display for Geoshard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(label) = &self.label {
+            write!(f, " ({})", label)?;
+        }
+        write!(
+            f,
+            ": score {}, {} cells",
+            self.cell_score,
+            self.cell_count()
+        )
+    }
+}
+
+/// `HotCell` is a scored cell reported by `hottest_cells`, along with the shard it belongs to
+#[derive(Debug, Clone)]
+pub struct HotCell {
+    /// name of the shard the cell is in
+    pub shard: String,
+    /// the S2 cell
+    pub cell_id: CellID,
+    /// the S2 token for the cell
+    pub token: String,
+    /// the lat/lng center of the cell
+    pub center: LatLng,
+    /// the score given to the cell by the scorer
+    pub score: i32,
+}
+
+impl HotCell {
+    fn new(shard: &str, cell_id: CellID, score: i32) -> Self {
+        Self {
+            shard: shard.to_owned(),
+            cell_id,
+            token: cell_id.to_token(),
+            center: LatLng::from(cell_id),
+            score,
+        }
+    }
 }
 
-/// `GeoshardCollection` is the collection of shards generated by by the builder
+/// `GeoshardCollection` is the collection of shards generated by by the builder. Deserializing
+/// fails unless every shard is at the collection's storage level
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(try_from = "UncheckedGeoshardCollection")]
 pub struct GeoshardCollection {
     storage_level: u64,
     shards: Vec<Geoshard>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) fallback: Option<Box<GeoshardCollection>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    score_window: Option<ScoreWindow>,
+    #[serde(skip)]
+    neighbors: OnceLock<Vec<Vec<usize>>>,
+}
+
+/// `UncheckedGeoshardCollection` is a deserialized `GeoshardCollection` whose shards' storage
+/// levels haven't been checked against the collection's
+#[derive(Deserialize)]
+pub(crate) struct UncheckedGeoshardCollection {
+    storage_level: u64,
+    shards: Vec<Geoshard>,
+    #[serde(default)]
+    pub(crate) fallback: Option<Box<GeoshardCollection>>,
+    #[serde(default)]
+    score_window: Option<ScoreWindow>,
+}
+
+impl TryFrom<UncheckedGeoshardCollection> for GeoshardCollection {
+    type Error = GeoshardError;
+
+    fn try_from(unchecked: UncheckedGeoshardCollection) -> Result<Self, GeoshardError> {
+        if let Some(shard) = unchecked
+            .shards
+            .iter()
+            .find(|shard| shard.storage_level() != unchecked.storage_level)
+        {
+            return Err(GeoshardError::InvalidShardMap {
+                reason: format!(
+                    "shard {} is at level {}, expected {}",
+                    shard.name(),
+                    shard.storage_level(),
+                    unchecked.storage_level
+                ),
+                shard: Some(shard.name().to_owned()),
+                cell: None,
+            });
+        }
+        let mut collection = Self::from_shards(unchecked.storage_level, unchecked.shards);
+        collection.fallback = unchecked.fallback;
+        collection.score_window = unchecked.score_window;
+        Ok(collection)
+    }
+}
+
+/// `ScoreWindow` is the time range of the score data a shard map was built from, in seconds
+/// since the unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScoreWindow {
+    /// when the oldest score data was collected
+    pub start: u64,
+    /// when the newest score data was collected
+    pub end: u64,
+}
+
+impl ScoreWindow {
+    /// Constructs a new `ScoreWindow`. Times before the unix epoch are clamped to it
+    pub fn new(start: SystemTime, end: SystemTime) -> Self {
+        Self {
+            start: unix_seconds(start),
+            end: unix_seconds(end),
+        }
+    }
+}
+
+/// returns the whole seconds from the unix epoch to `time`, or 0 if it is before the epoch
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
 }
 
 impl GeoshardCollection {
+    /// constructs a collection from shards that were already built
+    pub(crate) fn from_shards(storage_level: u64, shards: Vec<Geoshard>) -> Self {
+        Self {
+            storage_level,
+            shards,
+            fallback: None,
+            score_window: None,
+            neighbors: OnceLock::new(),
+        }
+    }
+
+    /// stamps the collection with the time range of the score data it was built from
+    pub fn with_score_window(mut self, score_window: ScoreWindow) -> Self {
+        self.score_window = Some(score_window);
+        self
+    }
+
+    /// returns the time range of the score data the collection was built from, if stamped
+    pub fn score_window(&self) -> Option<&ScoreWindow> {
+        self.score_window.as_ref()
+    }
+
+    /// returns how old the newest score data the collection was built from is at `now`, or
+    /// `None` if the collection was not stamped with a score window
+    pub fn staleness(&self, now: SystemTime) -> Option<Duration> {
+        let end = UNIX_EPOCH + Duration::from_secs(self.score_window?.end);
+        Some(now.duration_since(end).unwrap_or_default())
+    }
+
     /// returns shards in this collection
     pub fn shards(&self) -> &Vec<Geoshard> {
         &self.shards
@@ -404,6 +2020,112 @@ impl GeoshardCollection {
     pub fn storage_level(&self) -> u64 {
         self.storage_level
     }
+
+    /// labels every shard with the names of the places holding most of its score, as named by
+    /// the `place_namer`, e.g. "New York + New England". At most `max_places` names are used per label
+    #[cfg(feature = "builder")]
+    pub fn label_shards<Namer: PlaceNamer>(&mut self, place_namer: &Namer, max_places: usize) {
+        for shard in self.shards.iter_mut() {
+            shard.label = geocoding::label(place_namer, shard, max_places);
+        }
+    }
+
+    /// returns the `n` highest scored cells across every shard in this collection
+    pub fn hottest_cells(&self, n: usize) -> Vec<HotCell> {
+        let mut hot_cells = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.hot_cells())
+            .collect::<Vec<HotCell>>();
+        hot_cells.sort_by_key(|hot_cell| std::cmp::Reverse(hot_cell.score));
+        hot_cells.truncate(n);
+        hot_cells
+    }
+
+    /// Returns the shards sharing a cell border with the shard named `shard_name`, in collection
+    /// order, or `None` if there is no such shard. The neighbor graph is built once, on the first
+    /// call, and kept until the shards are split or merged
+    pub fn neighbors(&self, shard_name: &str) -> Option<Vec<&Geoshard>> {
+        let index = self
+            .shards
+            .iter()
+            .position(|shard| shard.name == shard_name)?;
+        let neighbors = self.neighbors.get_or_init(|| self.neighbor_graph());
+        Some(
+            neighbors[index]
+                .iter()
+                .map(|neighbor| &self.shards[*neighbor])
+                .collect(),
+        )
+    }
+
+    /// returns the positions of the shards adjacent to each shard, found by looking up the cells
+    /// across each edge of every cell
+    fn neighbor_graph(&self) -> Vec<Vec<usize>> {
+        let mut ranges: Vec<(CellID, CellID, usize)> = self
+            .shards
+            .iter()
+            .enumerate()
+            .flat_map(|(index, shard)| {
+                shard
+                    .cell_union
+                    .0
+                    .iter()
+                    .map(move |cell_id| (cell_id.range_min(), cell_id.range_max(), index))
+            })
+            .collect();
+        ranges.sort();
+
+        let mut graph = vec![vec![]; self.shards.len()];
+        for (index, shard) in self.shards.iter().enumerate() {
+            for cell_id in shard.cell_union.0.iter() {
+                for neighbor in cell_id.edge_neighbors() {
+                    // the cells overlapping the neighbor, whether they are coarser or finer
+                    let start = ranges
+                        .partition_point(|(_, range_max, _)| *range_max < neighbor.range_min());
+                    for (range_min, _, other) in ranges[start..].iter() {
+                        if *range_min > neighbor.range_max() {
+                            break;
+                        }
+                        if *other != index {
+                            graph[index].push(*other);
+                        }
+                    }
+                }
+            }
+        }
+        for neighbors in graph.iter_mut() {
+            neighbors.sort_unstable();
+            neighbors.dedup();
+        }
+        graph
+    }
+
+    /// returns the total score of every shard
+    pub fn total_score(&self) -> i64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.cell_score as i64)
+            .sum()
+    }
+
+    /// Calculates the standard deviation between shards
+    pub fn standard_deviation(&self) -> f64 {
+        let mean: f64 = self
+            .shards
+            .iter()
+            .fold(0.0, |sum, x| sum + x.cell_score as f64)
+            / self.shards.len() as f64;
+
+        let varience: f64 = self
+            .shards
+            .iter()
+            .map(|x| (x.cell_score as f64 - mean) * (x.cell_score as f64 - mean))
+            .sum::<f64>()
+            / self.shards.len() as f64;
+
+        varience.sqrt()
+    }
 }
 
 // impl TryFrom<&str> for GeoshardCollection {
@@ -413,6 +2135,7 @@ impl GeoshardCollection {
 //     }
 // }
 
+#[cfg(feature = "builder")]
 impl GeoshardCollection {
     /// Constructs a new `GeoshardCollection`
     ///
@@ -423,420 +2146,2887 @@ impl GeoshardCollection {
         scored_cells: &BTreeMap<CellID, i32>,
         storage_level: u64,
     ) -> Self {
-        let mut current_cell_count = 0;
+        #[cfg(feature = "tracing")]
+        let mut span = trace::Span::new("geoshard_collection_new");
+        let geoshards = Self::pack(container_size, scored_cells, storage_level, None, None);
+        #[cfg(feature = "tracing")]
+        {
+            span.record("storage_level", storage_level);
+            span.record("container_size", container_size);
+            span.record("shard_count", geoshards.shards().len());
+        }
+        geoshards
+    }
+
+    /// packs the cells, in order, into shards scoring at most `container_size`. With a
+    /// `max_extent`, a shard is also cut early once a cell's center is more than `max_extent`
+    /// meters from the center of the shard's first cell. With `zones` (the zone of each cell, in
+    /// order), a shard is also cut where the zone changes
+    fn pack(
+        container_size: i32,
+        scored_cells: &BTreeMap<CellID, i32>,
+        storage_level: u64,
+        max_extent: Option<f64>,
+        zones: Option<&[Option<usize>]>,
+    ) -> Self {
         let mut current_score = 0;
-        let mut cells = vec![];
+        let mut cells: Vec<CellID> = vec![];
+        let mut cell_scores = vec![];
+        let mut shards = Vec::new();
+
+        // every shard closed holds at least one cell, and every cell lands in exactly one shard
+        let close_shard = |shards: &mut Vec<Geoshard>, cells, cell_scores, score| {
+            let shard = Geoshard::new(
+                format!("geoshard_user_index_{}", shards.len() + 1),
+                score,
+                storage_level,
+                CellUnion(cells),
+            )
+            .with_cell_scores(cell_scores);
+            shards.push(shard);
+        };
+
+        let mut zone = None;
+        for (index, (cell_id, cell_score)) in scored_cells.iter().enumerate() {
+            let cell_zone = zones.map(|zones| zones[index]);
+            let crosses_zone = !cells.is_empty() && cell_zone != zone;
+            zone = cell_zone;
+            let too_wide = match (max_extent, cells.first()) {
+                (Some(max_extent), Some(first_cell)) => {
+                    LatLng::from(first_cell)
+                        .distance(&LatLng::from(cell_id))
+                        .rad()
+                        * EARTH_RADIUS
+                        > max_extent
+                }
+                _ => false,
+            };
+            // a cell over the container size on its own starts a shard rather than leaving an
+            // empty one behind
+            if !cells.is_empty()
+                && (cell_score + current_score > container_size || too_wide || crosses_zone)
+            {
+                close_shard(
+                    &mut shards,
+                    std::mem::take(&mut cells),
+                    std::mem::take(&mut cell_scores),
+                    current_score,
+                );
+                current_score = 0;
+            }
+            cells.push(*cell_id);
+            cell_scores.push(*cell_score);
+            current_score += cell_score;
+        }
+        if !cells.is_empty() {
+            close_shard(&mut shards, cells, cell_scores, current_score);
+        }
+
+        Self {
+            shards,
+            storage_level,
+            fallback: None,
+            score_window: None,
+            neighbors: OnceLock::new(),
+        }
+    }
+
+    /// splits the cells, in order, into shards of the given numbers of cells
+    fn from_runs(scored_cells: &BTreeMap<CellID, i32>, runs: &[usize], storage_level: u64) -> Self {
+        let mut cells = scored_cells.iter();
+        let shards = runs
+            .iter()
+            .enumerate()
+            .map(|(index, run)| {
+                let (cell_ids, cell_scores): (Vec<CellID>, Vec<i32>) = cells
+                    .by_ref()
+                    .take(*run)
+                    .map(|(cell_id, score)| (*cell_id, *score))
+                    .unzip();
+                Geoshard::new(
+                    format!("geoshard_user_index_{}", index + 1),
+                    cell_scores.iter().sum(),
+                    storage_level,
+                    CellUnion(cell_ids),
+                )
+                .with_cell_scores(cell_scores)
+            })
+            .collect();
+        Self::from_shards(storage_level, shards)
+    }
+
+    /// Covers the cells no shard holds, as left by a sparse cell list. Each gap between two held
+    /// cells (in cell ID order) is covered by the fewest cells that fit it, which join the shard
+    /// of the cell before the gap with a score of 0. The gap before the first held cell joins its
+    /// shard. Without any shards, a single shard covers the six faces
+    fn fill_gaps(&mut self) {
+        let mut held: Vec<(CellID, usize)> = self
+            .shards
+            .iter()
+            .enumerate()
+            .flat_map(|(index, shard)| {
+                shard
+                    .cell_union
+                    .0
+                    .iter()
+                    .map(move |cell_id| (*cell_id, index))
+            })
+            .collect();
+        held.sort();
+        let (first, last) = match (held.first(), held.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => {
+                let faces = CellUnion((0..6).map(CellID::from_face).collect());
+                self.shards = vec![Geoshard::new(
+                    "geoshard_user_index_1".to_owned(),
+                    0,
+                    self.storage_level,
+                    faces,
+                )
+                .with_cell_scores(vec![0; 6])];
+                return;
+            }
+        };
+
+        let mut gaps = vec![vec![]; self.shards.len()];
+        let begin = CellID::from_face(0).child_begin_at_level(MAX_LEVEL);
+        let end = CellID::from_face(5).child_end_at_level(MAX_LEVEL);
+        gaps[first.1].extend(CellUnion::from_range(begin, first.0.range_min()).0);
+        for pair in held.windows(2) {
+            let ((previous, index), (next, _)) = (pair[0], pair[1]);
+            gaps[index]
+                .extend(CellUnion::from_range(previous.range_max().next(), next.range_min()).0);
+        }
+        gaps[last.1].extend(CellUnion::from_range(last.0.range_max().next(), end).0);
+
+        for (shard, gap) in self.shards.iter_mut().zip(gaps) {
+            if gap.is_empty() {
+                continue;
+            }
+            let mut cells: Vec<(CellID, i32)> = shard
+                .cell_union
+                .0
+                .iter()
+                .copied()
+                .zip(shard.cell_scores.iter().copied())
+                .chain(gap.into_iter().map(|cell_id| (cell_id, 0)))
+                .collect();
+            cells.sort();
+            let (cell_ids, cell_scores) = cells.into_iter().unzip();
+            shard.cell_union = CellUnion(cell_ids);
+            shard.cell_scores = cell_scores;
+        }
+    }
+
+    /// merges consecutive shards while their combined score is under `floor`, and at most
+    /// `limit` if there is one, then renames the shards so they stay numbered in order. With
+    /// `zones` (the zone of each cell held, in order), shards of different zones aren't merged
+    pub(crate) fn coalesce(
+        &mut self,
+        floor: i32,
+        limit: Option<i32>,
+        zones: Option<&[Option<usize>]>,
+    ) {
+        let mut shards: Vec<Geoshard> = Vec::with_capacity(self.shards.len());
+        let (mut offset, mut previous_zone) = (0, None);
+        for shard in self.shards.drain(..) {
+            let zone = zones.map(|zones| zones[offset]);
+            offset += shard.cell_union.0.len();
+            let same_zone = std::mem::replace(&mut previous_zone, zone) == zone;
+            let fits = |previous: &Geoshard| {
+                let combined = previous.cell_score + shard.cell_score;
+                combined < floor && limit.is_none_or(|limit| combined <= limit)
+            };
+            match shards.last_mut() {
+                Some(previous) if same_zone && fits(previous) => {
+                    previous.cell_score += shard.cell_score;
+                    previous.cell_union.0.extend(shard.cell_union.0);
+                    previous.cell_scores.extend(shard.cell_scores);
+                }
+                _ => shards.push(shard),
+            }
+        }
+        self.shards = shards;
+        self.renumber();
+    }
+
+    /// splits shards holding cells of several zones (the zone of each cell held, in order) where
+    /// the zone changes, then renames the shards so they stay numbered in order
+    fn split_at_zones(&mut self, zones: &[Option<usize>]) {
+        let mut shards: Vec<Geoshard> = Vec::with_capacity(self.shards.len());
+        let mut zones = zones.iter();
+        for shard in self.shards.drain(..) {
+            let mut split: Vec<Geoshard> = vec![];
+            let mut zone = None;
+            for (cell_id, score) in shard.cell_union.0.iter().zip(shard.cell_scores.iter()) {
+                let cell_zone = zones.next().copied().flatten();
+                match split.last_mut() {
+                    Some(last) if cell_zone == zone => {
+                        last.cell_union.0.push(*cell_id);
+                        last.cell_scores.push(*score);
+                        last.cell_score += score;
+                    }
+                    _ => split.push(
+                        Geoshard::new(
+                            shard.name.clone(),
+                            *score,
+                            shard.storage_level,
+                            CellUnion(vec![*cell_id]),
+                        )
+                        .with_cell_scores(vec![*score]),
+                    ),
+                }
+                zone = cell_zone;
+            }
+            shards.extend(split);
+        }
+        self.shards = shards;
+        self.renumber();
+    }
+
+    /// Splits the shard named `shard_name` in two without rebuilding the collection: cells
+    /// before `at_cell` stay in the shard, and `at_cell` and the cells after it move to a new
+    /// shard right after it. The shard needs per cell scores to split its score. Shards are
+    /// renumbered afterwards, while custom names (such as pinned regions) are kept
+    pub fn split_shard(&mut self, shard_name: &str, at_cell: &CellID) -> Result<(), GeoshardError> {
+        let index = self.shard_index(shard_name)?;
+        let shard = &mut self.shards[index];
+        let invalid = |reason: String, cell: Option<String>| {
+            Err(GeoshardError::InvalidShardOperation {
+                reason,
+                shard: Some(shard_name.to_owned()),
+                cell,
+            })
+        };
+        if shard.cell_scores.len() != shard.cell_union.0.len() {
+            return invalid(format!("shard {} has no per cell scores", shard_name), None);
+        }
+        let split_at = match shard
+            .cell_union
+            .0
+            .iter()
+            .position(|cell_id| cell_id == at_cell)
+        {
+            Some(0) | None => {
+                return invalid(
+                    format!(
+                        "cell {} is not a split point of shard {}",
+                        at_cell.to_token(),
+                        shard_name
+                    ),
+                    Some(at_cell.to_token()),
+                )
+            }
+            Some(split_at) => split_at,
+        };
+
+        let cells = shard.cell_union.0.split_off(split_at);
+        let cell_scores = shard.cell_scores.split_off(split_at);
+        let score = cell_scores.iter().sum::<i32>();
+        shard.cell_score -= score;
+        let new_shard = Geoshard::new(
+            format!("geoshard_user_index_{}", index + 2),
+            score,
+            shard.storage_level,
+            CellUnion(cells),
+        )
+        .with_cell_scores(cell_scores);
+        self.shards.insert(index + 1, new_shard);
+        self.renumber();
+        Ok(())
+    }
+
+    /// Merges the shard named `b` into the shard named `a` without rebuilding the collection,
+    /// summing their scores. The merged shard keeps `a`'s place, and shards are renumbered
+    /// afterwards, while custom names (such as pinned regions) are kept. Only shards adjacent in
+    /// cell order can be merged, so the merged shard's `start` to `end` range holds no other
+    /// shard's cells
+    pub fn merge_shards(&mut self, a: &str, b: &str) -> Result<(), GeoshardError> {
+        if a == b {
+            return Err(GeoshardError::InvalidShardOperation {
+                reason: format!("can't merge shard {} with itself", a),
+                shard: Some(a.to_owned()),
+                cell: None,
+            });
+        }
+        let (shard_a, shard_b) = (
+            &self.shards[self.shard_index(a)?],
+            &self.shards[self.shard_index(b)?],
+        );
+        let first = shard_a.start().min(shard_b.start());
+        let last = shard_a.end().max(shard_b.end());
+        let between = self
+            .shards
+            .iter()
+            .filter(|shard| shard.name != a && shard.name != b)
+            .find(|shard| {
+                let cells = &shard.cell_union.0;
+                cells
+                    .get(cells.partition_point(|cell_id| cell_id < first))
+                    .is_some_and(|cell_id| cell_id <= last)
+            });
+        if let Some(between) = between {
+            return Err(GeoshardError::InvalidShardOperation {
+                reason: format!(
+                    "can't merge shards {} and {}, shard {} is between them",
+                    a, b, between.name
+                ),
+                shard: Some(b.to_owned()),
+                cell: None,
+            });
+        }
+        let merged = self.shards.remove(self.shard_index(b)?);
+        let index = self.shard_index(a)?;
+        let shard = &mut self.shards[index];
+
+        let has_scores = shard.cell_scores.len() == shard.cell_union.0.len()
+            && merged.cell_scores.len() == merged.cell_union.0.len();
+        let mut cells: Vec<(CellID, i32)> = shard
+            .cell_union
+            .0
+            .iter()
+            .copied()
+            .zip(
+                shard
+                    .cell_scores
+                    .iter()
+                    .copied()
+                    .chain(std::iter::repeat(0)),
+            )
+            .chain(
+                merged.cell_union.0.iter().copied().zip(
+                    merged
+                        .cell_scores
+                        .iter()
+                        .copied()
+                        .chain(std::iter::repeat(0)),
+                ),
+            )
+            .collect();
+        cells.sort();
+
+        shard.cell_score += merged.cell_score;
+        shard.cell_union = CellUnion(cells.iter().map(|(cell_id, _)| *cell_id).collect());
+        shard.cell_scores = if has_scores {
+            cells.iter().map(|(_, score)| *score).collect()
+        } else {
+            vec![]
+        };
+        shard.label = None;
+        self.renumber();
+        Ok(())
+    }
+
+    /// replaces the score of every shard and cell with the score in `scored_cells`
+    fn rescore(&mut self, scored_cells: &BTreeMap<CellID, i32>) {
+        for shard in self.shards.iter_mut() {
+            shard.cell_scores = shard
+                .cell_union
+                .0
+                .iter()
+                .map(|cell_id| scored_cells.get(cell_id).copied().unwrap_or(0))
+                .collect();
+            shard.cell_score = shard.cell_scores.iter().sum();
+        }
+    }
+
+    /// returns the position of the shard with the given name
+    fn shard_index(&self, shard_name: &str) -> Result<usize, GeoshardError> {
+        self.shards
+            .iter()
+            .position(|shard| shard.name == shard_name)
+            .ok_or_else(|| GeoshardError::InvalidShardOperation {
+                reason: format!("unknown shard {}", shard_name),
+                shard: Some(shard_name.to_owned()),
+                cell: None,
+            })
+    }
+
+    /// renames the generated shards so they stay numbered in order, keeping custom names
+    fn renumber(&mut self) {
+        // the shards have changed, so their neighbors have to be found again
+        self.neighbors = OnceLock::new();
+        let generated = self
+            .shards
+            .iter_mut()
+            .filter(|shard| shard.name.starts_with("geoshard_user_index_"));
+        for (index, shard) in generated.enumerate() {
+            shard.name = format!("geoshard_user_index_{}", index + 1);
+        }
+    }
+}
+
+/// `GeoshardSearcher` actual contains logic to find a users given shard, given a user
+#[cfg(feature = "searcher")]
+#[derive(Debug)]
+pub struct GeoshardSearcher {
+    storage_level: u64,
+    shards: GeoshardCollection,
+    status: SearcherStatus,
+    staleness_threshold: Option<Duration>,
+    lookup_table: Option<Vec<u32>>,
+    covering_cache: Option<Mutex<CoveringCache>>,
+    covering_config: CoveringConfig,
+    pub(crate) geofences: Geofences,
+    miss_policy: MissPolicy,
+    /// the map's ranges, built on the first miss routed by `MissPolicy::NearestByRange`
+    miss_ranges: OnceLock<RangeTable>,
+}
+
+/// `MissPolicy` is what the shard lookups (such as `get_shard_from_location` and
+/// `try_get_shard_from_location`) do with cells no shard holds, e.g. on a map that doesn't cover
+/// the whole world or a lookup at another level than the map's
+#[cfg(feature = "searcher")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MissPolicy {
+    /// fail with `GeoshardError::UnmappedCell`, for services that would rather fail than
+    /// misroute. The `try_` lookups return the error, and the others panic with it
+    Error,
+    /// the shard holding the nearest cell in cell order, which is usually nearby. The first miss
+    /// builds the map's `range_table`, which later misses binary search
+    NearestByRange,
+    /// the named shard, e.g. a catch-all shard
+    Default(String),
+    /// the map's last shard
+    #[default]
+    LastShard,
+}
+
+/// `CoveringConfig` sets the parameters of the S2 region coverer used for radius queries.
+/// The default covers with cells at the storage level only. Allowing coarser cells (a lower
+/// `min_level`) and capping `max_cells` gives smaller cell filters for large radius queries, at
+/// the cost of covering more area than the radius and so fanning out to more shards
+#[cfg(feature = "searcher")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CoveringConfig {
+    /// coarsest level of the covering cells, the storage level when `None`
+    pub min_level: Option<u8>,
+    /// finest level of the covering cells, the storage level when `None`
+    pub max_level: Option<u8>,
+    /// only levels `min_level + k * level_mod` are used when over 1
+    pub level_mod: u8,
+    /// the maximum number of cells in a covering, a soft limit (0 lets the coverer decide)
+    pub max_cells: usize,
+}
+
+/// `CoveringCache` caches coverings keyed by quantized latitude, longitude and radius
+#[cfg(feature = "searcher")]
+type CoveringCache = LruCache<(i64, i64, u32), Arc<[CellID]>>;
+
+/// `StalenessWarning` reports that a searcher is serving a map built from score data older
+/// than its staleness threshold, e.g. because a rebuild job stopped running
+#[cfg(feature = "searcher")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StalenessWarning {
+    /// age of the newest score data the map was built from
+    pub staleness: Duration,
+    /// the configured staleness threshold
+    pub threshold: Duration,
+}
+
+#[cfg(feature = "searcher")]
+impl fmt::Display for StalenessWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "shard map scores are {}s old, over the {}s staleness threshold",
+            self.staleness.as_secs(),
+            self.threshold.as_secs()
+        )
+    }
+}
+
+/// `SearcherStatus` reports whether a `GeoshardSearcher` is serving the map it was asked to load
+#[cfg(feature = "searcher")]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum SearcherStatus {
+    /// serving the requested shard map
+    Healthy,
+    /// the requested shard map failed to load or verify, so a coarse fallback map is being served
+    Degraded {
+        /// why the requested shard map was not used
+        reason: String,
+    },
+}
+
+#[cfg(feature = "searcher")]
+impl GeoshardSearcher {
+    /// returns a searcher serving the `fallback` map in degraded mode
+    pub(crate) fn degraded(fallback: GeoshardCollection, reason: String) -> Self {
+        Self {
+            status: SearcherStatus::Degraded { reason },
+            ..Self::from(fallback)
+        }
+    }
+
+    /// returns whether this searcher is serving the map it was asked to load, or a fallback
+    pub fn status(&self) -> &SearcherStatus {
+        &self.status
+    }
+
+    /// returns true when this searcher is serving a fallback map
+    pub fn is_degraded(&self) -> bool {
+        self.status != SearcherStatus::Healthy
+    }
+
+    /// sets what lookups do with cells no shard holds, routing them to the last shard by
+    /// default. Fails with `GeoshardError::InvalidShardMap` if a default shard isn't in the map
+    pub fn with_miss_policy(mut self, miss_policy: MissPolicy) -> Result<Self, GeoshardError> {
+        if let MissPolicy::Default(name) = &miss_policy {
+            if !self.shards.shards.iter().any(|shard| shard.name() == name) {
+                return Err(GeoshardError::InvalidShardMap {
+                    reason: "no such default shard".to_owned(),
+                    shard: Some(name.clone()),
+                    cell: None,
+                });
+            }
+        }
+        self.miss_policy = miss_policy;
+        Ok(self)
+    }
+
+    /// returns what lookups do with cells no shard holds
+    pub fn miss_policy(&self) -> &MissPolicy {
+        &self.miss_policy
+    }
+
+    /// returns the shard the miss policy routes the cell to, or `GeoshardError::UnmappedCell` if
+    /// the miss policy is `MissPolicy::Error` or the map has no shards
+    ///
+    /// # Panics
+    ///
+    /// Panics on a `MissPolicy::NearestByRange` miss if shards hold the same cell, which
+    /// `GeoshardCollection::verify` reports
+    fn fallback_shard(&self, cell_id: &CellID) -> Result<&Geoshard, GeoshardError> {
+        let shards = &self.shards.shards;
+        let fallback = match &self.miss_policy {
+            MissPolicy::Error => None,
+            MissPolicy::LastShard => shards.last(),
+            MissPolicy::NearestByRange => self
+                .miss_ranges
+                .get_or_init(|| self.shards.range_table())
+                .nearest_shard(cell_id.0)
+                .and_then(|index| shards.get(index as usize)),
+            MissPolicy::Default(name) => shards.iter().find(|shard| shard.name() == name),
+        };
+        fallback.ok_or_else(|| GeoshardError::UnmappedCell {
+            cell: cell_id.to_token(),
+            generation: None,
+        })
+    }
+
+    /// sets the age of score data over which `staleness_warning` warns
+    pub fn with_staleness_threshold(mut self, threshold: Duration) -> Self {
+        self.staleness_threshold = Some(threshold);
+        self
+    }
+
+    /// returns a warning if a staleness threshold is set and the map being served was built from
+    /// score data older than it at `now`. Maps without a score window never warn
+    pub fn staleness_warning(&self, now: SystemTime) -> Option<StalenessWarning> {
+        let threshold = self.staleness_threshold?;
+        let staleness = self.shards.staleness(now)?;
+        (staleness > threshold).then_some(StalenessWarning {
+            staleness,
+            threshold,
+        })
+    }
+
+    /// return shards
+    pub fn shards(&self) -> &GeoshardCollection {
+        &self.shards
+    }
+
+    /// returns shard for given user
+    pub fn get_shard_for_user<T>(&self, user: T) -> &Geoshard
+    where
+        T: User,
+    {
+        let location = user.location();
+        self.get_shard_from_location(location)
+    }
+
+    /// returns the shard for the user's location chosen by `selector`, e.g. their home or their
+    /// work location, or `None` if the user has no such location
+    pub fn get_shard_for_user_at<T>(
+        &self,
+        user: &T,
+        selector: LocationSelector,
+    ) -> Option<&Geoshard>
+    where
+        T: MultiLocationUser,
+    {
+        selector
+            .select(user)
+            .map(|location| self.get_shard_from_location(location))
+    }
+
+    /// Returns the shard for every user, in order. Large batches are split across the available
+    /// cores with scoped threads, and consecutive users in the same cell reuse the previous lookup,
+    /// so batches sorted by location (e.g. by cell) are the cheapest to look up
+    pub fn get_shards_for_users_batch<T>(&self, users: &[T]) -> Vec<&Geoshard>
+    where
+        T: User + Sync,
+    {
+        const MIN_CHUNK_SIZE: usize = 10_000;
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk_size = users.len().div_ceil(threads).max(MIN_CHUNK_SIZE);
+        if users.len() <= chunk_size {
+            return self.lookup_chunk(users);
+        }
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = users
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || self.lookup_chunk(chunk)))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("batch lookup thread panicked"))
+                .collect()
+        })
+    }
+
+    /// looks up the users' shards, reusing the lookup of the previous user when in the same cell
+    fn lookup_chunk<T: User>(&self, users: &[T]) -> Vec<&Geoshard> {
+        let mut previous: Option<(CellID, &Geoshard)> = None;
+        users
+            .iter()
+            .map(|user| {
+                let cell_id = self.get_cell_id_from_location(user.location());
+                match previous {
+                    Some((previous_cell, geoshard)) if previous_cell == cell_id => geoshard,
+                    _ => match self.find_shard_from_cell_id(&cell_id) {
+                        Ok(geoshard) => {
+                            previous = Some((cell_id, geoshard));
+                            geoshard
+                        }
+                        // the cell is split across shards, so its users are looked up one by one
+                        Err(_) => self.get_shard_from_location(user.location()),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// returns the shard for the given location along with a replica index in `0..replica_count`
+    /// for the user. The replica is picked by rendezvous hashing on the shard name and `user_id`, so
+    /// repeated reads for a user stick to the same replica (and only move when the replica count or
+    /// the user's shard changes)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `replica_count` is 0
+    pub fn replica_for(
+        &self,
+        location: &LatLng,
+        user_id: impl AsRef<[u8]>,
+        replica_count: u32,
+    ) -> (&Geoshard, u32) {
+        assert!(replica_count > 0, "replica_count must be at least 1");
+        let shard = self.get_shard_from_location(location);
+        let replica = (0..replica_count)
+            .max_by_key(|replica| rendezvous_weight(shard.name(), user_id.as_ref(), *replica))
+            .unwrap();
+        (shard, replica)
+    }
+
+    /// Returns the user's shard, breaking ties near shard boundaries by the user's ID. If other
+    /// shards are within `margin` (as `get_shards_from_radius` takes it) of the user, the shard is
+    /// picked among them by rendezvous hashing on the shard names and ID, so users on a boundary
+    /// don't flap between shards when it shifts slightly: a user only moves if their shard leaves
+    /// the margin, or a shard joining it outranks theirs. Users further than `margin` from any
+    /// boundary get the shard their location is in
+    pub fn get_shard_for_identified_user<U: IdentifiedUser>(
+        &self,
+        user: &U,
+        margin: u32,
+    ) -> &Geoshard {
+        let mut candidates = self.get_shards_from_radius(user.location(), margin);
+        candidates.push(self.get_shard_from_location(user.location()));
+        candidates
+            .into_iter()
+            .max_by_key(|shard| (rendezvous_weight(shard.name(), user.id(), 0), shard.name()))
+            .unwrap()
+    }
+
+    /// Returns the shard the user is assigned to among the named shards, by rendezvous hashing on
+    /// the shard names and `user_id`, or `None` if none of the names are in the map. This spreads
+    /// users deterministically across several shards, e.g. the users of a single cell too busy
+    /// for one shard, and only the users of a removed shard move when the names change
+    pub fn rendezvous_shard(
+        &self,
+        user_id: impl AsRef<[u8]>,
+        shard_names: &[&str],
+    ) -> Option<&Geoshard> {
+        self.shards
+            .shards
+            .iter()
+            .filter(|shard| shard_names.contains(&shard.name()))
+            .max_by_key(|shard| rendezvous_weight(shard.name(), user_id.as_ref(), 0))
+    }
+
+    /// returns the given `CellID` for given location
+    pub fn get_cell_id_from_location(&self, location: &LatLng) -> CellID {
+        CellID::from(location).parent(self.storage_level)
+    }
+
+    /// Returns shard from given location. Locations no shard holds are routed by the miss policy,
+    /// see `with_miss_policy`
+    ///
+    /// # Panics
+    ///
+    /// Panics if no shard holds the location and the map has no shards or the miss policy is
+    /// `MissPolicy::Error`, see `try_get_shard_from_location`
+    pub fn get_shard_from_location(&self, location: &LatLng) -> &Geoshard {
+        #[cfg(feature = "tracing")]
+        let mut span = trace::Span::new("get_shard_from_location");
+        let geoshard = self
+            .try_get_shard_from_location(location)
+            .unwrap_or_else(|error| panic!("{}", error));
+        #[cfg(feature = "tracing")]
+        span.record("shard", geoshard.name());
+        geoshard
+    }
+
+    /// returns the shard holding the location. Locations no shard holds are routed by the miss
+    /// policy, failing with `GeoshardError::UnmappedCell` if the map has no shards or the miss
+    /// policy is `MissPolicy::Error`. Locations in a cell split across shards (see
+    /// `GeoshardBuilder::with_overflow_splitting`) are found by their leaf cell
+    pub fn try_get_shard_from_location(
+        &self,
+        location: &LatLng,
+    ) -> Result<&Geoshard, GeoshardError> {
+        self.find_shard_from_cell_id(&self.get_cell_id_from_location(location))
+            .or_else(|error| {
+                let leaf = CellID::from(location);
+                self.find_shard_from_cell_id(&leaf)
+                    .or_else(|_| self.fallback_shard(&leaf))
+                    .map_err(|_| error)
+            })
+    }
+
+    /// Precomputes a dense table from every cell at the storage level to its shard, so
+    /// `get_shard_from_cell_id` (and the location lookups built on it) take constant time for
+    /// cells at the storage level. The table holds 6 * 4^storage_level entries of 4 bytes,
+    /// e.g. 1.5MB at level 8, 25MB at level 10 and 400MB at level 12. Fails with
+    /// `GeoshardError::LookupTableTooLarge` for storage levels above `MAX_LOOKUP_TABLE_LEVEL`
+    pub fn with_lookup_table(mut self) -> Result<Self, GeoshardError> {
+        let mut lookup_table = lookup_table(self.storage_level)?;
+        for (index, geoshard) in self.shards.shards.iter().enumerate() {
+            // cells coarser than the storage level (such as the gaps of a sparse build) fill
+            // every position they cover
+            for cell_id in geoshard.cell_union().0.iter() {
+                if cell_id.level() <= self.storage_level {
+                    let start =
+                        self.cell_position(&cell_id.child_begin_at_level(self.storage_level));
+                    let len = 1 << (2 * (self.storage_level - cell_id.level()));
+                    lookup_table[start..start + len].fill(index as u32);
+                }
+            }
+        }
+        self.lookup_table = Some(lookup_table);
+        Ok(self)
+    }
+
+    /// returns the position of a cell at the storage level among every cell at that level
+    fn cell_position(&self, cell_id: &CellID) -> usize {
+        (cell_id.0 >> (61 - 2 * self.storage_level)) as usize
+    }
+
+    /// Returns a shard for given cell ID. Cells no shard holds are routed by the miss policy, see
+    /// `with_miss_policy`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell is coarser than the storage level and no shard holds all of it, or if no
+    /// shard holds the cell and the map has no shards or the miss policy is `MissPolicy::Error`,
+    /// see `try_get_shard_from_cell_id`
+    pub fn get_shard_from_cell_id(&self, cell_id: &CellID) -> &Geoshard {
+        #[cfg(feature = "tracing")]
+        let mut span = trace::Span::new("get_shard_from_cell_id");
+        let geoshard = self
+            .try_get_shard_from_cell_id(cell_id)
+            .unwrap_or_else(|error| panic!("{}", error));
+        #[cfg(feature = "tracing")]
+        span.record("shard", geoshard.name());
+        geoshard
+    }
+
+    /// returns the shard holding the cell. Cells no shard holds are routed by the miss policy,
+    /// failing with `GeoshardError::UnmappedCell` if the map has no shards or the miss policy is
+    /// `MissPolicy::Error`. Cells finer than the storage level (such as leaf cells) are found by
+    /// their parent at the storage level, unless it is split across shards. Cells coarser than
+    /// the storage level that no shard holds all of return `GeoshardError::CellLevelMismatch`,
+    /// see `get_shards_from_cell_id` for the shards intersecting them
+    pub fn try_get_shard_from_cell_id(&self, cell_id: &CellID) -> Result<&Geoshard, GeoshardError> {
+        match self.find_shard_from_cell_id(cell_id) {
+            Err(GeoshardError::UnmappedCell { .. }) => self.fallback_shard(cell_id),
+            found => found,
+        }
+    }
+
+    /// returns the shard holding the cell, or `GeoshardError::UnmappedCell` if no shard does,
+    /// regardless of the miss policy
+    fn find_shard_from_cell_id(&self, cell_id: &CellID) -> Result<&Geoshard, GeoshardError> {
+        if let Some(lookup_table) = &self.lookup_table {
+            if cell_id.level() >= self.storage_level {
+                let position = self.cell_position(&cell_id.parent(self.storage_level));
+                if let Some(geoshard) = self.shards.shards.get(lookup_table[position] as usize) {
+                    return Ok(geoshard);
+                }
+            }
+        }
+        self.shards
+            .shards
+            .iter()
+            .find(|geoshard| geoshard.cell_union().contains_cellid(cell_id))
+            .ok_or_else(|| match cell_id.level() < self.storage_level {
+                true => GeoshardError::CellLevelMismatch {
+                    cell: cell_id.to_token(),
+                    level: cell_id.level(),
+                    storage_level: self.storage_level,
+                },
+                false => GeoshardError::UnmappedCell {
+                    cell: cell_id.to_token(),
+                    generation: None,
+                },
+            })
+    }
+
+    /// returns the shards within `radius` meters of a location
+    pub fn get_shards_from_radius(&self, location: &LatLng, radius: u32) -> Vec<&Geoshard> {
+        #[cfg(feature = "tracing")]
+        let mut span = trace::Span::new("get_shards_from_radius");
+        let geoshards: Vec<&Geoshard> = self
+            .cell_ids_from_radius(location, radius)
+            .into_iter()
+            .flat_map(|cell_id| self.get_shards_from_cell_id(&cell_id))
+            .collect();
+        #[cfg(feature = "tracing")]
+        {
+            span.record("radius", radius as u64);
+            span.record("shard_count", geoshards.len());
+        }
+        geoshards
+    }
+
+    /// Returns the shards holding part of a cell at any level, for systems indexing at other S2
+    /// levels than the map. Cells at or finer than the storage level are in a single shard, found
+    /// as by `get_shard_from_cell_id`, while coarser cells return every shard intersecting them,
+    /// in map order
+    ///
+    /// # Panics
+    ///
+    /// Panics if a cell at or finer than the storage level is in no shard and the miss policy
+    /// can't route it, see `get_shard_from_cell_id`
+    pub fn get_shards_from_cell_id(&self, cell_id: &CellID) -> Vec<&Geoshard> {
+        if cell_id.level() >= self.storage_level {
+            return vec![self.get_shard_from_cell_id(cell_id)];
+        }
+        self.shards
+            .shards
+            .iter()
+            .filter(|geoshard| geoshard.cell_union().intersects_cellid(cell_id))
+            .collect()
+    }
+
+    /// sets the parameters used to cover radius queries, see `CoveringConfig`
+    pub fn with_covering_config(mut self, covering_config: CoveringConfig) -> Self {
+        self.covering_config = covering_config;
+        self
+    }
+
+    /// returns the region coverer for radius queries, as set by the covering config
+    fn region_coverer(&self) -> RegionCoverer {
+        let storage_level = self.storage_level as u8;
+        RegionCoverer {
+            min_level: self.covering_config.min_level.unwrap_or(storage_level),
+            max_level: self.covering_config.max_level.unwrap_or(storage_level),
+            level_mod: self.covering_config.level_mod,
+            max_cells: self.covering_config.max_cells,
+        }
+    }
+
+    /// returns every shard with a cell within `tolerance` meters of the location, starting with
+    /// the location's own shard, so users straddling a boundary can be published to the shards on
+    /// both sides of it. Distances are measured to cells at the storage level, so shards up to a
+    /// cell's width further away can be included
+    pub fn shards_near_location(&self, location: &LatLng, tolerance: f64) -> Vec<&Geoshard> {
+        let mut shards = vec![self.get_shard_from_location(location)];
+        for cell_id in self.covering(location, tolerance) {
+            for shard in self.get_shards_from_cell_id(&cell_id) {
+                if !shards.iter().any(|near| near.name() == shard.name()) {
+                    shards.push(shard);
+                }
+            }
+        }
+        shards
+    }
+
+    /// Returns the shards to fan a radius query out to, each with the cells (at the storage
+    /// level, unless set otherwise by the `CoveringConfig`) within `radius` meters of the location
+    /// that the shard holds, so backends can filter on them. Shards are in the order their first
+    /// cell was found
+    pub fn cells_by_shard_from_radius(
+        &self,
+        location: &LatLng,
+        radius: f64,
+    ) -> Vec<(&Geoshard, Vec<CellID>)> {
+        self.cells_by_shard(self.covering(location, radius))
+    }
+
+    /// `cells_by_shard_from_radius` for the cells intersecting the polygon, e.g. a delivery zone.
+    /// Cells are kept if their bounding rectangle intersects the polygon, so cells just outside
+    /// its edges can be included
+    pub fn cells_by_shard_from_polygon(&self, polygon: &Polygon) -> Vec<(&Geoshard, Vec<CellID>)> {
+        let covering = self
+            .region_coverer()
+            .covering(&polygon.bound())
+            .0
+            .into_iter()
+            .filter(|cell_id| polygon.intersects_rect(&Cell::from(cell_id).rect_bound()));
+        self.cells_by_shard(covering)
+    }
+
+    /// groups the cells of a query region by the shards holding them. Coarse cells spanning
+    /// several shards are clipped to the cells of each shard within them (merging complete sets
+    /// of siblings into their parent), so every token only matches documents of its own shard
+    fn cells_by_shard(
+        &self,
+        covering: impl IntoIterator<Item = CellID>,
+    ) -> Vec<(&Geoshard, Vec<CellID>)> {
+        let mut fan_out: Vec<(&Geoshard, Vec<CellID>)> = vec![];
+        for cell_id in covering {
+            let shards = self.get_shards_from_cell_id(&cell_id);
+            let spans_shards = shards.len() > 1;
+            for shard in shards {
+                let clipped = match spans_shards {
+                    true => {
+                        let mut clipped = CellUnion(
+                            shard
+                                .cell_union()
+                                .0
+                                .iter()
+                                .filter_map(|shard_cell| match shard_cell.contains(&cell_id) {
+                                    true => Some(cell_id),
+                                    false => cell_id.contains(shard_cell).then_some(*shard_cell),
+                                })
+                                .collect(),
+                        );
+                        clipped.normalize();
+                        clipped.0
+                    }
+                    false => vec![cell_id],
+                };
+                match fan_out
+                    .iter_mut()
+                    .find(|(fanned_out, _)| fanned_out.name() == shard.name())
+                {
+                    Some((_, cells)) => cells.extend(clipped),
+                    None => fan_out.push((shard, clipped)),
+                }
+            }
+        }
+        fan_out
+    }
+
+    /// returns the cells within `radius` meters of the location, as set by the covering config
+    fn covering(&self, location: &LatLng, radius: f64) -> Vec<CellID> {
+        let cap = Cap::from_center_angle(
+            &Point::from(location),
+            &s1::Rad(radius / EARTH_RADIUS).into(),
+        );
+        self.region_coverer().covering(&cap).0
+    }
+
+    /// Gives all the CellIDs in a given radius in meters
+    pub fn cell_ids_from_radius(&self, location: &LatLng, radius: u32) -> Vec<CellID> {
+        self.covering(location, radius as f64)
+    }
+
+    /// keeps the coverings of the `capacity` most recently used (location, radius) pairs for
+    /// `cell_ids_from_radius_cached`
+    pub fn with_covering_cache(mut self, capacity: usize) -> Self {
+        self.covering_cache = Some(Mutex::new(LruCache::new(capacity)));
+        self
+    }
+
+    /// `cell_ids_from_radius` for hot, repeated queries (such as city centers), with `radius` in
+    /// meters. The location is quantized to 1e-4 degrees (about 11 meters) and coverings are cached per quantized location
+    /// and radius, see `with_covering_cache`. Without a cache, the covering is computed every call.
+    /// Longitudes are wrapped around the antimeridian, and every longitude at a pole shares a key
+    pub fn cell_ids_from_radius_cached(&self, location: &LatLng, radius: u32) -> Arc<[CellID]> {
+        const QUANTUM: f64 = 1e-4;
+        const HALF_TURN: i64 = (180.0 / QUANTUM) as i64;
+        let lat = (location.lat.deg() / QUANTUM).round() as i64;
+        let lng = if lat.abs() >= HALF_TURN / 2 {
+            0
+        } else {
+            ((location.lng.deg() / QUANTUM).round() as i64 + HALF_TURN).rem_euclid(2 * HALF_TURN)
+                - HALF_TURN
+        };
+        let key = (lat, lng, radius);
+        let quantized = ll!(key.1 as f64 * QUANTUM, key.0 as f64 * QUANTUM);
+
+        let cache = match &self.covering_cache {
+            Some(cache) => cache,
+            None => return self.cell_ids_from_radius(&quantized, radius).into(),
+        };
+        if let Some(cell_ids) = cache.lock().unwrap().get(&key) {
+            return cell_ids;
+        }
+        let cell_ids: Arc<[CellID]> = self.cell_ids_from_radius(&quantized, radius).into();
+        cache.lock().unwrap().insert(key, cell_ids.clone());
+        cell_ids
+    }
+}
+
+#[cfg(feature = "searcher")]
+impl From<GeoshardCollection> for GeoshardSearcher {
+    fn from(shards: GeoshardCollection) -> Self {
+        let storage_level = shards.storage_level;
+        Self {
+            storage_level,
+            shards,
+            status: SearcherStatus::Healthy,
+            staleness_threshold: None,
+            lookup_table: None,
+            covering_cache: None,
+            covering_config: CoveringConfig::default(),
+            geofences: Geofences::default(),
+            miss_policy: MissPolicy::default(),
+            miss_ranges: OnceLock::new(),
+        }
+    }
+}
+
+/// `SearcherSnapshot` is the serialized form of a `GeoshardSearcher`: its map and configuration,
+/// with the lookup table (if any) as runs of consecutive positions routed to the same shard, as
+/// `(first position, length, shard index)`. Covering caches are serialized by capacity, and come
+/// back empty. Geofences aren't serialized, and are registered again after deserializing
+#[cfg(feature = "searcher")]
+#[derive(Deserialize, Serialize)]
+struct SearcherSnapshot<Shards, Status, Policy> {
+    shards: Shards,
+    status: Status,
+    staleness_threshold: Option<Duration>,
+    covering_config: CoveringConfig,
+    covering_cache_capacity: Option<usize>,
+    miss_policy: Policy,
+    lookup_runs: Option<Vec<(usize, usize, u32)>>,
+}
+
+/// Serializes the searcher ready to serve, including its lookup table, see `SearcherSnapshot`
+#[cfg(feature = "searcher")]
+impl Serialize for GeoshardSearcher {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let lookup_runs = self.lookup_table.as_ref().map(|lookup_table| {
+            let mut runs: Vec<(usize, usize, u32)> = vec![];
+            for (position, shard) in lookup_table.iter().enumerate() {
+                match runs.last_mut() {
+                    Some((_, length, run_shard)) if run_shard == shard => *length += 1,
+                    _ => runs.push((position, 1, *shard)),
+                }
+            }
+            runs.retain(|(_, _, shard)| *shard != u32::MAX);
+            runs
+        });
+        SearcherSnapshot {
+            shards: &self.shards,
+            status: &self.status,
+            staleness_threshold: self.staleness_threshold,
+            covering_config: self.covering_config,
+            covering_cache_capacity: self
+                .covering_cache
+                .as_ref()
+                .map(|cache| cache.lock().unwrap().capacity()),
+            miss_policy: &self.miss_policy,
+            lookup_runs,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Deserializes a searcher serialized with its lookup table, without rebuilding the table
+#[cfg(feature = "searcher")]
+impl<'de> Deserialize<'de> for GeoshardSearcher {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot: SearcherSnapshot<GeoshardCollection, SearcherStatus, MissPolicy> =
+            SearcherSnapshot::deserialize(deserializer)?;
+        let mut searcher = Self {
+            status: snapshot.status,
+            staleness_threshold: snapshot.staleness_threshold,
+            covering_config: snapshot.covering_config,
+            covering_cache: snapshot
+                .covering_cache_capacity
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            miss_policy: snapshot.miss_policy,
+            ..Self::from(snapshot.shards)
+        };
+        if let Some(runs) = snapshot.lookup_runs {
+            let mut lookup_table =
+                lookup_table(searcher.storage_level).map_err(serde::de::Error::custom)?;
+            for (first, length, shard) in runs {
+                let positions = lookup_table
+                    .get_mut(first..first.saturating_add(length))
+                    .filter(|_| (shard as usize) < searcher.shards.shards.len())
+                    .ok_or_else(|| {
+                        serde::de::Error::custom(format!(
+                            "lookup run of shard {} at {} is out of range",
+                            shard, first
+                        ))
+                    })?;
+                positions.fill(shard);
+            }
+            searcher.lookup_table = Some(lookup_table);
+        }
+        Ok(searcher)
+    }
+}
+
+/// returns an empty lookup table for cells at the storage level, see
+/// `GeoshardSearcher::with_lookup_table`
+#[cfg(feature = "searcher")]
+fn lookup_table(storage_level: u64) -> Result<Vec<u32>, GeoshardError> {
+    if storage_level > MAX_LOOKUP_TABLE_LEVEL {
+        return Err(GeoshardError::LookupTableTooLarge {
+            storage_level,
+            max_level: MAX_LOOKUP_TABLE_LEVEL,
+        });
+    }
+    Ok(vec![u32::MAX; 6 << (2 * storage_level)])
+}
+
+/// returns the rendezvous hashing weight of the shard (or one of its replicas) for the user
+#[cfg(feature = "searcher")]
+fn rendezvous_weight(shard_name: &str, user_id: &[u8], replica: u32) -> u64 {
+    stable_hash(&[shard_name.as_bytes(), user_id, &replica.to_be_bytes()])
+}
+
+/// Test helpers shared with the other modules in this crate
+#[cfg(all(test, feature = "builder"))]
+pub mod test {
+
+    use super::*;
+    use crate::{
+        testing::{uniform_collection, FakeUser, RandCityFactory},
+        utils::ll,
+    };
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use s2::cellid::CellID;
+
+    macro_rules! shard {
+        ($cell_score:expr) => {
+            Geoshard::new("fake-shard".to_owned(), $cell_score, 0, CellUnion(vec![]))
+        };
+    }
+
+    /// RandomCellScore scores cells with random ocean/city like loads, generated from `seed`
+    pub struct RandomCellScore {
+        /// seed for the RNG, so the same seed always produces the same scores
+        pub seed: u64,
+    }
+
+    #[test]
+    fn test_shard_search() {
+        let geoshards =
+            GeoshardBuilder::user_count_scorer(4, Box::new([FakeUser::new()].iter()), 40, 100)
+                .build();
+        let geoshard_searcher = GeoshardSearcher::from(geoshards);
+
+        let geoshard = geoshard_searcher.get_shard_from_location(&ll!(34.181061, -103.345177));
+
+        let cell_id = geoshard_searcher.get_cell_id_from_location(&ll!(34.181061, -103.345177));
+
+        assert!(geoshard.cell_union().contains_cellid(&cell_id));
+    }
+
+    #[test]
+    fn test_shard_radius_search() {
+        let geoshard = GeoshardBuilder::new(
+            4,
+            Box::new([FakeUser::new()].iter()),
+            RandomCellScore { seed: 1 },
+            40,
+            100,
+        )
+        .build();
+        let geoshards = GeoshardSearcher::from(geoshard);
+        let geoshards = geoshards.get_shards_from_radius(&ll!(34.181061, -103.345177), 200);
+        assert_eq!(geoshards.len(), 1);
+    }
+
+    #[test]
+    fn test_generate_shards() {
+        let geoshard = GeoshardBuilder::new(
+            4,
+            Box::new([FakeUser::new()].iter()),
+            RandomCellScore { seed: 1 },
+            40,
+            100,
+        )
+        .build();
+
+        let shards = geoshard.shards;
+
+        if (shards.len() as i32) > 100 || (shards.len() as i32) < 40 {
+            panic!("Shard len out of range: {}", shards.len());
+        }
+    }
+
+    impl<UserCollection> CellScorer<UserCollection> for RandomCellScore {
+        fn score_cell_list<T>(&self, mut cell_list: CellList, _users: UserCollection) -> CellList {
+            let mock_values = cell_list.mut_cell_list();
+            let mut rng = StdRng::seed_from_u64(self.seed);
+
+            // Ocean
+            for _ in 0..=1000 {
+                let rand_lat = rng.gen_range(0.000000..2000.000000);
+                let rand_long = rng.gen_range(0.000000..2000.000000);
+
+                let cell_id = CellID::from(ll!(rand_lat, rand_long));
+                let rand_load_count = rng.gen_range(0..5);
+                mock_values.insert(cell_id, rand_load_count);
+            }
+
+            // Small Cities
+            for _ in 0..=100 {
+                let rand_lat = rng.gen_range(0.000000..2000.000000);
+                let rand_long = rng.gen_range(0.000000..2000.000000);
+
+                let cell_id = CellID::from(ll!(rand_lat, rand_long));
+                let rand_load_count = rng.gen_range(10..100);
+                mock_values.insert(cell_id, rand_load_count);
+            }
+
+            // Medium Cities
+            for _ in 0..=50 {
+                let rand_lat = rng.gen_range(0.000000..2000.000000);
+                let rand_long = rng.gen_range(0.000000..2000.000000);
+
+                let cell_id = CellID::from(ll!(rand_lat, rand_long));
+                let rand_load_count = rng.gen_range(100..500);
+                mock_values.insert(cell_id, rand_load_count);
+            }
+
+            // Big Cities
+            for _ in 0..=10 {
+                let rand_lat = rng.gen_range(0.000000..2000.000000);
+                let rand_long = rng.gen_range(0.000000..2000.000000);
+
+                let cell_id = CellID::from(ll!(rand_lat, rand_long));
+                let rand_load_count = rng.gen_range(1000..2000);
+                mock_values.insert(cell_id, rand_load_count);
+            }
+
+            cell_list
+        }
+    }
+
+    #[test]
+    fn test_build_from_fallible() {
+        let users = [FakeUser::new(), FakeUser::new()];
+        let geoshards =
+            GeoshardBuilder::user_count_scorer(4, users.iter().map(Ok::<&FakeUser, String>), 1, 2)
+                .build_from_fallible();
+        assert!(geoshards.is_ok());
+
+        let failing_users = users
+            .iter()
+            .map(Ok)
+            .chain(std::iter::once(Err("page 3 timed out".to_owned())));
+        let geoshards =
+            GeoshardBuilder::user_count_scorer(4, failing_users, 1, 2).build_from_fallible();
+        assert!(matches!(
+            geoshards.unwrap_err(),
+            FallibleBuildError::Users(error) if error == "page 3 timed out"
+        ));
+
+        let geoshards =
+            GeoshardBuilder::user_count_scorer(4, users.iter().map(Ok::<&FakeUser, String>), 3, 2)
+                .build_from_fallible();
+        assert!(matches!(
+            geoshards.unwrap_err(),
+            FallibleBuildError::Build(GeoshardError::InvalidBuilderConfig { .. })
+        ));
+
+        let cancellation = Arc::new(AtomicBool::new(true));
+        let geoshards =
+            GeoshardBuilder::user_count_scorer(4, users.iter().map(Ok::<&FakeUser, String>), 1, 2)
+                .with_cancellation(cancellation)
+                .build_from_fallible();
+        assert!(matches!(
+            geoshards.unwrap_err(),
+            FallibleBuildError::Build(GeoshardError::Cancelled)
+        ));
+    }
+
+    fn clustered_cell_list() -> (CellList, CellID) {
+        let mut cell_list = CellList::new(4);
+        let nyc = CellID::from(ll!(-74.0060, 40.7128)).parent(4);
+        let scores = cell_list.mut_cell_list();
+        for score in scores.values_mut().step_by(10) {
+            *score = 1;
+        }
+        *scores.get_mut(&nyc).unwrap() = 10;
+        for neighbor in nyc.all_neighbors(4) {
+            *scores.get_mut(&neighbor).unwrap() = 5;
+        }
+        (cell_list, nyc)
+    }
+
+    #[test]
+    fn test_pin_clusters() {
+        let (cell_list, nyc) = clustered_cell_list();
+        let mut partitioner = Partitioner::new(4, 4, 8);
+        partitioner.cluster_pin_score = Some(5);
+
+        let geoshards = partitioner.partition(&cell_list).unwrap();
+        let searcher = GeoshardSearcher::from(geoshards);
+        let nyc_shard = searcher.get_shard_from_cell_id(&nyc);
+        assert_eq!(nyc_shard.cell_score, 50);
+        for neighbor in nyc.all_neighbors(4) {
+            assert_eq!(
+                searcher.get_shard_from_cell_id(&neighbor).name(),
+                nyc_shard.name()
+            );
+        }
+        assert_eq!(
+            searcher
+                .shards()
+                .shards()
+                .iter()
+                .map(|shard| shard.cell_count())
+                .sum::<usize>(),
+            cell_list.cell_list().len()
+        );
+
+        partitioner.min_shard_count = 20;
+        partitioner.max_shard_count = 20;
+        match partitioner.partition(&cell_list) {
+            Err(GeoshardError::ClustersTooLarge { clusters, .. }) => {
+                assert_eq!(clusters.len(), 1);
+                assert_eq!(clusters[0].score(), 50);
+            }
+            other => panic!("expected ClustersTooLarge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pin_region() {
+        let (cell_list, nyc) = clustered_cell_list();
+        let us_east = Polygon::new(&[
+            ll!(-80.0, 45.0),
+            ll!(-70.0, 45.0),
+            ll!(-70.0, 35.0),
+            ll!(-80.0, 35.0),
+        ]);
+        let mut partitioner = Partitioner::new(4, 4, 8);
+        partitioner.cluster_pin_score = Some(5);
+        partitioner.pinned_regions = vec![
+            ("us_east".to_owned(), PinnedRegion::from(us_east)),
+            ("nyc".to_owned(), PinnedRegion::from(CellUnion(vec![nyc]))),
+        ];
+
+        // nyc is inside us_east, which was declared first
+        match partitioner.partition(&cell_list) {
+            Err(GeoshardError::EmptyPinnedRegion { name }) => assert_eq!(name, "nyc"),
+            other => panic!("expected EmptyPinnedRegion, got {:?}", other),
+        }
+
+        partitioner.pinned_regions.pop();
+        let geoshards = partitioner.partition(&cell_list).unwrap();
+        let region_shard = geoshards.shards().last().unwrap();
+        assert_eq!(region_shard.name(), "us_east");
+        assert!(region_shard.cell_union().0.contains(&nyc));
+
+        let mut cells: Vec<CellID> = geoshards
+            .shards()
+            .iter()
+            .flat_map(|shard| shard.cell_union().0.iter().copied())
+            .collect();
+        cells.sort();
+        assert!(cells.iter().eq(cell_list.cell_list().keys()));
+    }
+
+    #[test]
+    fn test_residency_zones() {
+        let users = FakeUser::seeded(2000, 37, &RandCityFactory::default());
+        // the test cities run along lat == lng, so these zones split them in three
+        let south = Polygon::new(&[
+            ll!(20.0, 20.0),
+            ll!(33.0, 20.0),
+            ll!(33.0, 33.0),
+            ll!(20.0, 33.0),
+        ]);
+        let north = Polygon::new(&[
+            ll!(38.0, 38.0),
+            ll!(50.0, 38.0),
+            ll!(50.0, 50.0),
+            ll!(38.0, 50.0),
+        ]);
+        let zone_of = |shard: &Geoshard| -> Vec<Option<bool>> {
+            let mut zones: Vec<Option<bool>> = shard
+                .cell_union()
+                .0
+                .iter()
+                .map(|cell_id| {
+                    let center = LatLng::from(cell_id);
+                    match (south.contains(&center), north.contains(&center)) {
+                        (true, _) => Some(true),
+                        (_, true) => Some(false),
+                        _ => None,
+                    }
+                })
+                .collect();
+            zones.dedup();
+            zones
+        };
+
+        let build = |builder: GeoshardBuilder<_, _>| {
+            builder
+                .with_residency_zone("south", south.clone())
+                .with_residency_zone("north", north.clone())
+                .build()
+        };
+        let unconstrained = GeoshardBuilder::user_count_scorer(5, users.iter(), 4, 6).build();
+        assert!(unconstrained
+            .shards()
+            .iter()
+            .any(|shard| zone_of(shard).len() > 1));
+
+        let greedy = build(GeoshardBuilder::user_count_scorer(5, users.iter(), 4, 6));
+        let coalesced = build(
+            GeoshardBuilder::user_count_scorer(5, users.iter(), 4, 6).with_min_shard_score(1000),
+        );
+        let exact = build(
+            GeoshardBuilder::user_count_scorer(5, users.iter(), 4, 6).with_exact_shard_count(4),
+        );
+        for geoshards in [&greedy, &coalesced, &exact] {
+            for shard in geoshards.shards() {
+                assert_eq!(zone_of(shard).len(), 1, "{} spans zones", shard.name());
+            }
+            let total: i32 = geoshards.shards().iter().map(|shard| shard.score()).sum();
+            assert_eq!(total, 2000);
+        }
+        let (_, report) = GeoshardBuilder::user_count_scorer(5, users.iter(), 4, 6)
+            .with_residency_zone("south", south.clone())
+            .try_build_with_report()
+            .unwrap();
+        assert_eq!(report.residency_zones, vec!["south".to_owned()]);
+    }
+
+    #[test]
+    fn test_lookup_table() {
+        let (cell_list, _) = clustered_cell_list();
+        let mut partitioner = Partitioner::new(4, 4, 8);
+        partitioner.cluster_pin_score = Some(5);
+        let searcher = GeoshardSearcher::from(partitioner.partition(&cell_list).unwrap());
+        let json = serde_json::to_string(searcher.shards()).unwrap();
+        let with_table =
+            GeoshardSearcher::from(serde_json::from_str::<GeoshardCollection>(&json).unwrap())
+                .with_lookup_table()
+                .unwrap();
+
+        for cell_id in cell_list.cell_list().keys() {
+            assert_eq!(
+                with_table.get_shard_from_cell_id(cell_id).name(),
+                searcher.get_shard_from_cell_id(cell_id).name()
+            );
+        }
+        // leaf cells are found by their parent, and coarser cells are refused
+        for cell_id in cell_list.cell_list().keys().take(50) {
+            let leaf = cell_id.child_begin_at_level(30);
+            assert_eq!(
+                with_table.get_shard_from_cell_id(&leaf).name(),
+                searcher.get_shard_from_cell_id(cell_id).name()
+            );
+            assert_eq!(
+                searcher.get_shard_from_cell_id(&leaf).name(),
+                searcher.get_shard_from_cell_id(cell_id).name()
+            );
+        }
+        // tables finer than MAX_LOOKUP_TABLE_LEVEL are refused rather than allocated
+        let fine = CellID::from(ll!(-74.0060, 40.7128)).parent(16);
+        let fine = GeoshardCollection::from_shards(
+            16,
+            vec![Geoshard::new(
+                "fine".to_owned(),
+                1,
+                16,
+                CellUnion(vec![fine]),
+            )],
+        );
+        assert!(matches!(
+            GeoshardSearcher::from(fine).with_lookup_table(),
+            Err(GeoshardError::LookupTableTooLarge {
+                storage_level: 16,
+                max_level: MAX_LOOKUP_TABLE_LEVEL
+            })
+        ));
+
+        let coarse = cell_list.cell_list().keys().next().unwrap().parent(2);
+        for searcher in [&with_table, &searcher] {
+            let error = searcher.try_get_shard_from_cell_id(&coarse).unwrap_err();
+            assert!(matches!(
+                error,
+                GeoshardError::CellLevelMismatch {
+                    level: 2,
+                    storage_level: 4,
+                    ..
+                }
+            ));
+            assert_eq!(error.cell(), Some(coarse.to_token().as_str()));
+        }
+
+        // coarser cells are answered with every shard intersecting them
+        let expected: Vec<&str> = searcher
+            .shards()
+            .shards()
+            .iter()
+            .filter(|shard| {
+                shard
+                    .cell_union()
+                    .0
+                    .iter()
+                    .any(|cell_id| cell_id.parent(2) == coarse)
+            })
+            .map(Geoshard::name)
+            .collect();
+        assert!(!expected.is_empty());
+        for searcher in [&with_table, &searcher] {
+            let shards: Vec<&str> = searcher
+                .get_shards_from_cell_id(&coarse)
+                .into_iter()
+                .map(Geoshard::name)
+                .collect();
+            assert_eq!(shards, expected);
+        }
+        let leaf = coarse.child_begin_at_level(30);
+        assert_eq!(
+            searcher
+                .get_shards_from_cell_id(&leaf)
+                .iter()
+                .map(|shard| shard.name())
+                .collect::<Vec<_>>(),
+            vec![searcher.get_shard_from_cell_id(&leaf).name()]
+        );
+
+        // maps with shards at another level than their own don't deserialize
+        let mismatched = json.replacen("\"storage_level\":4", "\"storage_level\":5", 1);
+        assert!(serde_json::from_str::<GeoshardCollection>(&mismatched)
+            .unwrap_err()
+            .to_string()
+            .contains("expected 5"));
+
+        // a map without the first shard has no shard for its cells
+        let mut partial = serde_json::from_str::<GeoshardCollection>(&json).unwrap();
+        let first = partial.shards.remove(0);
+        let partial = GeoshardSearcher::from(partial)
+            .with_lookup_table()
+            .unwrap()
+            .with_miss_policy(MissPolicy::Error)
+            .unwrap();
+        let error = partial
+            .try_get_shard_from_cell_id(first.start())
+            .unwrap_err()
+            .with_generation(2);
+        assert_eq!(error.cell(), Some(first.start().to_token().as_str()));
+        assert_eq!(error.generation(), Some(2));
+    }
+
+    #[test]
+    fn test_serialize_searcher() {
+        let (cell_list, _) = clustered_cell_list();
+        let searcher =
+            GeoshardSearcher::from(Partitioner::new(4, 4, 8).partition(&cell_list).unwrap())
+                .with_lookup_table()
+                .unwrap()
+                .with_covering_cache(16)
+                .with_miss_policy(MissPolicy::NearestByRange)
+                .unwrap();
+
+        let json = serde_json::to_string(&searcher).unwrap();
+        let loaded: GeoshardSearcher = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.lookup_table, searcher.lookup_table);
+        assert_eq!(loaded.miss_policy(), &MissPolicy::NearestByRange);
+        assert_eq!(
+            loaded
+                .covering_cache
+                .as_ref()
+                .map(|cache| cache.lock().unwrap().capacity()),
+            Some(16)
+        );
+        for cell_id in cell_list.cell_list().keys() {
+            assert_eq!(
+                loaded.get_shard_from_cell_id(cell_id).name(),
+                searcher.get_shard_from_cell_id(cell_id).name()
+            );
+        }
+
+        let without_table = GeoshardSearcher::from(
+            serde_json::from_str::<GeoshardCollection>(
+                &serde_json::to_string(searcher.shards()).unwrap(),
+            )
+            .unwrap(),
+        );
+        let loaded: GeoshardSearcher =
+            serde_json::from_str(&serde_json::to_string(&without_table).unwrap()).unwrap();
+        assert!(loaded.lookup_table.is_none());
+
+        let corrupt = json.replacen("\"lookup_runs\":[[", "\"lookup_runs\":[[99999999,1,0],[", 1);
+        assert!(serde_json::from_str::<GeoshardSearcher>(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_staged_builder() {
+        let users = FakeUser::seeded(1000, 23, &RandCityFactory::default());
+        let staged = GeoshardBuilder::staged(4)
+            .users(users.iter())
+            .scorer(UserCountScorer)
+            .bounds(4, 8)
+            .unwrap()
+            .build();
+        let built = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        assert_eq!(
+            serde_json::to_string(&staged).unwrap(),
+            serde_json::to_string(&built).unwrap()
+        );
+
+        // the scorer can be set before the users
+        let reason = |error: GeoshardError| match error {
+            GeoshardError::InvalidBuilderConfig { reason } => reason,
+            error => panic!("unexpected error {}", error),
+        };
+        let error = GeoshardBuilder::staged(4)
+            .scorer(UserCountScorer)
+            .users(users.iter())
+            .bounds(8, 4)
+            .err()
+            .unwrap();
+        assert_eq!(
+            reason(error),
+            "min_shard_count 8 is greater than max_shard_count 4"
+        );
+        let error = GeoshardBuilder::staged(31)
+            .users(users.iter())
+            .scorer(UserCountScorer)
+            .bounds(4, 8)
+            .err()
+            .unwrap();
+        assert!(reason(error).starts_with("storage level 31"));
+
+        let error = GeoshardBuilder::user_count_scorer(4, users.iter(), 0, 8)
+            .try_build()
+            .unwrap_err();
+        assert_eq!(reason(error), "min_shard_count 0 is less than 1");
+    }
+
+    #[test]
+    fn test_user_filter() {
+        let users = FakeUser::seeded(2000, 47, &RandCityFactory::default());
+        let banned = |user: &&FakeUser| user.name.starts_with(['a', 'b', 'c']);
+        let sampled = |user: &&FakeUser| stable_hash(&[user.id()]) % 10 < 3;
+        let mut seen = std::collections::HashSet::new();
+        // duplicates of the first 500 users are dropped by their ID
+        let filtered =
+            GeoshardBuilder::user_count_scorer(4, users.iter().chain(&users[..500]), 4, 8)
+                .with_user_filter(|user| !banned(user))
+                .with_user_filter(move |user| seen.insert(user.id().to_vec()))
+                .with_user_filter(sampled)
+                .build();
+
+        let kept: Vec<&FakeUser> = users
+            .iter()
+            .filter(|user| !banned(user) && sampled(user))
+            .collect();
+        assert!(kept.len() < 800);
+        let expected = GeoshardBuilder::user_count_scorer(4, kept.into_iter(), 4, 8).build();
+        assert_eq!(
+            serde_json::to_string(&filtered).unwrap(),
+            serde_json::to_string(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sampling() {
+        let users = FakeUser::seeded(20000, 53, &RandCityFactory::default());
+        let exact = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .with_cached_cells()
+            .build();
+        let (sampled, report) = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .with_cached_cells()
+            .with_sampling(0.25, 7)
+            .try_build_with_report()
+            .unwrap();
+        let estimate = report.sampling.unwrap();
+        assert_eq!((estimate.rate, estimate.seed), (0.25, 7));
+        assert!((4500..5500).contains(&estimate.sampled_users));
+        assert!(estimate.estimated_users.abs_diff(20000) < 2000);
+        assert!(estimate.max_relative_error > 0.0 && estimate.max_relative_error < 0.5);
+        // the scores are scaled back up to estimate every user
+        assert!(sampled.total_score().abs_diff(exact.total_score()) < 2000);
+
+        // the same seed samples the same users
+        let again = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .with_cached_cells()
+            .with_sampling(0.25, 7)
+            .build();
+        assert_eq!(
+            serde_json::to_string(&sampled).unwrap(),
+            serde_json::to_string(&again).unwrap()
+        );
+        // sampling everyone is an exact build
+        let everyone = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .with_cached_cells()
+            .with_sampling(1.0, 7)
+            .build();
+        assert_eq!(
+            serde_json::to_string(&everyone).unwrap(),
+            serde_json::to_string(&exact).unwrap()
+        );
+        assert!(matches!(
+            GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+                .with_sampling(0.0, 7)
+                .try_build(),
+            Err(GeoshardError::InvalidBuilderConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn test_miss_policy() {
+        let (cell_list, _) = clustered_cell_list();
+        let json = serde_json::to_string(&Partitioner::new(4, 4, 8).partition(&cell_list).unwrap())
+            .unwrap();
+        let partial = || {
+            let mut partial = serde_json::from_str::<GeoshardCollection>(&json).unwrap();
+            let first = partial.shards.remove(0);
+            (GeoshardSearcher::from(partial), first)
+        };
+
+        // the cells of the missing first shard come before the second shard's
+        let (searcher, first) = partial();
+        let (second, last) = (
+            searcher.shards().shards()[0].name().to_owned(),
+            searcher.shards().shards().last().unwrap().name().to_owned(),
+        );
+        assert_eq!(searcher.miss_policy(), &MissPolicy::LastShard);
+        assert_eq!(searcher.get_shard_from_cell_id(first.start()).name(), last);
+        // the try_ lookups, which the services call, route misses the same way
+        assert_eq!(
+            searcher
+                .try_get_shard_from_cell_id(first.start())
+                .unwrap()
+                .name(),
+            last
+        );
+
+        let searcher = searcher
+            .with_miss_policy(MissPolicy::NearestByRange)
+            .unwrap();
+        assert_eq!(searcher.get_shard_from_cell_id(first.end()).name(), second);
+        let location = LatLng::from(first.end());
+        assert_eq!(searcher.get_shard_from_location(&location).name(), second);
+        assert_eq!(
+            searcher
+                .try_get_shard_from_location(&location)
+                .unwrap()
+                .name(),
+            second
+        );
+
+        let searcher = searcher
+            .with_miss_policy(MissPolicy::Default(second.clone()))
+            .unwrap();
+        assert_eq!(
+            searcher.get_shard_from_cell_id(first.start()).name(),
+            second
+        );
+        assert_eq!(
+            searcher
+                .try_get_shard_from_cell_id(first.start())
+                .unwrap()
+                .name(),
+            second
+        );
+        assert!(matches!(
+            searcher.with_miss_policy(MissPolicy::Default(first.name().to_owned())),
+            Err(GeoshardError::InvalidShardMap { shard: Some(_), .. })
+        ));
+
+        let (searcher, first) = partial();
+        let searcher = searcher.with_miss_policy(MissPolicy::Error).unwrap();
+        assert!(matches!(
+            searcher.try_get_shard_from_cell_id(first.start()),
+            Err(GeoshardError::UnmappedCell { .. })
+        ));
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            searcher
+                .get_shard_from_cell_id(first.start())
+                .name()
+                .to_owned()
+        }))
+        .unwrap_err();
+        assert_eq!(
+            panic.downcast_ref::<String>().unwrap(),
+            &format!("no shard holds cell {}", first.start().to_token())
+        );
+        // cells the map holds are routed as before
+        let second = &searcher.shards().shards()[0];
+        assert_eq!(
+            searcher.get_shard_from_cell_id(second.start()).name(),
+            second.name()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "no shard holds cell")]
+    fn test_get_shard_from_empty_map() {
+        let searcher = GeoshardSearcher::from(GeoshardCollection::from_shards(4, vec![]));
+        searcher.get_shard_from_location(&ll!(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_get_shards_for_users_batch() {
+        let (cell_list, _) = clustered_cell_list();
+        let searcher =
+            GeoshardSearcher::from(Partitioner::new(4, 4, 8).partition(&cell_list).unwrap());
+        let users = FakeUser::seeded(25_000, 7, &RandCityFactory::default());
+
+        let batch = searcher.get_shards_for_users_batch(&users);
+        assert_eq!(batch.len(), users.len());
+        for (user, geoshard) in users.iter().zip(batch) {
+            assert_eq!(geoshard.name(), searcher.get_shard_for_user(user).name());
+        }
+    }
+
+    #[test]
+    fn test_get_shard_for_user_at() {
+        struct CommutingUser {
+            home: LatLng,
+            work: LatLng,
+        }
+        impl User for CommutingUser {
+            fn location(&self) -> &LatLng {
+                &self.home
+            }
+        }
+        impl MultiLocationUser for CommutingUser {
+            fn locations(&self) -> impl Iterator<Item = (&LatLng, f64)> {
+                [(&self.home, 0.4), (&self.work, 0.6)].into_iter()
+            }
+        }
+
+        let (cell_list, _) = clustered_cell_list();
+        let searcher =
+            GeoshardSearcher::from(Partitioner::new(4, 4, 8).partition(&cell_list).unwrap());
+        let (home, work) = (ll!(-180.0, -80.0), ll!(179.0, 80.0));
+        let (home_shard, work_shard) = (
+            searcher.get_shard_from_location(&home).name(),
+            searcher.get_shard_from_location(&work).name(),
+        );
+        assert_ne!(home_shard, work_shard);
+
+        let user = CommutingUser { home, work };
+        let shard_at = |selector| {
+            searcher
+                .get_shard_for_user_at(&user, selector)
+                .map(|shard| shard.name())
+        };
+        assert_eq!(shard_at(LocationSelector::Primary), Some(home_shard));
+        assert_eq!(shard_at(LocationSelector::Heaviest), Some(work_shard));
+        assert_eq!(shard_at(LocationSelector::Nth(0)), Some(home_shard));
+        assert_eq!(shard_at(LocationSelector::Nth(2)), None);
+    }
+
+    #[test]
+    fn test_covering_cache() {
+        let (cell_list, _) = clustered_cell_list();
+        let searcher =
+            GeoshardSearcher::from(Partitioner::new(4, 4, 8).partition(&cell_list).unwrap())
+                .with_covering_cache(2);
+        let nyc = ll!(-74.0060, 40.7128);
+
+        let cell_ids = searcher.cell_ids_from_radius_cached(&nyc, 5);
+        assert_eq!(cell_ids.to_vec(), searcher.cell_ids_from_radius(&nyc, 5));
+        let cached = searcher.cell_ids_from_radius_cached(&ll!(-74.00601, 40.71281), 5);
+        assert!(Arc::ptr_eq(&cell_ids, &cached));
+        searcher.cell_ids_from_radius_cached(&nyc, 10);
+        searcher.cell_ids_from_radius_cached(&nyc, 20);
+        // cached coverings are in meters too: 2,000 km around New York reaches Chicago
+        let chicago = CellID::from(ll!(-87.6298, 41.8781));
+        assert!(!cell_ids.iter().any(|cell_id| cell_id.contains(&chicago)));
+        assert!(searcher
+            .cell_ids_from_radius_cached(&nyc, 2_000_000)
+            .iter()
+            .any(|cell_id| cell_id.contains(&chicago)));
+
+        assert!(!Arc::ptr_eq(
+            &cell_ids,
+            &searcher.cell_ids_from_radius_cached(&nyc, 5)
+        ));
+    }
+
+    #[test]
+    fn test_covering_config() {
+        let (cell_list, _) = clustered_cell_list();
+        let geoshards = Partitioner::new(4, 4, 8).partition(&cell_list).unwrap();
+        let json = serde_json::to_string(&geoshards).unwrap();
+        let searcher = GeoshardSearcher::from(geoshards);
+        let coarse =
+            GeoshardSearcher::from(serde_json::from_str::<GeoshardCollection>(&json).unwrap())
+                .with_covering_config(CoveringConfig {
+                    min_level: Some(1),
+                    max_level: Some(4),
+                    level_mod: 1,
+                    max_cells: 4,
+                });
+        let nyc = ll!(-74.0060, 40.7128);
+
+        let fine_cells = searcher.cells_by_shard_from_radius(&nyc, 2_000_000.0);
+        let coarse_cells = coarse.cells_by_shard_from_radius(&nyc, 2_000_000.0);
+        let cell_count = |fan_out: &Vec<(&Geoshard, Vec<CellID>)>| {
+            fan_out.iter().map(|(_, cells)| cells.len()).sum::<usize>()
+        };
+        assert!(cell_count(&coarse_cells) < cell_count(&fine_cells));
+        assert!(coarse_cells.len() >= fine_cells.len());
+        for (shard, _) in fine_cells {
+            assert!(coarse_cells
+                .iter()
+                .any(|(coarse_shard, _)| coarse_shard.name() == shard.name()));
+        }
+    }
+
+    #[test]
+    fn test_shards_near_location() {
+        let searcher = GeoshardSearcher::from(uniform_collection(4, 64));
+        let shards = searcher.shards().shards();
+
+        // the first cell of a shard borders the last cell of the previous shard
+        let boundary = LatLng::from(shards[1].start());
+        let near = searcher.shards_near_location(&boundary, 1.0);
+        assert_eq!(near.len(), 1);
+        assert_eq!(near[0].name(), shards[1].name());
+
+        let near = searcher.shards_near_location(&boundary, 1_000_000.0);
+        assert_eq!(near[0].name(), shards[1].name());
+        assert!(near.iter().any(|shard| shard.name() == shards[0].name()));
+    }
+
+    #[test]
+    fn test_antimeridian_and_poles() {
+        let mut cell_list = CellList::new(5);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 13) as i32;
+        }
+        let searcher =
+            GeoshardSearcher::from(GeoshardCollection::new(500, cell_list.cell_list(), 5))
+                .with_covering_cache(16);
+        let radius = 500_000.0;
+
+        for location in RandCityFactory::edge_cases().cities() {
+            // every shard with a cell center in the radius is fanned out to, wrapping or not
+            let cap = Cap::from_center_angle(
+                &Point::from(location),
+                &s1::Rad(radius / EARTH_RADIUS).into(),
+            );
+            let expected: BTreeSet<&str> = cell_list
+                .cell_list()
+                .keys()
+                .filter(|cell_id| cap.contains_point(&Point::from(*cell_id)))
+                .map(|cell_id| searcher.get_shard_from_cell_id(cell_id).name())
+                .collect();
+            let fan_out: BTreeSet<&str> = searcher
+                .cells_by_shard_from_radius(location, radius)
+                .into_iter()
+                .map(|(shard, _)| shard.name())
+                .collect();
+            assert!(!expected.is_empty());
+            assert!(fan_out.is_superset(&expected), "{:?}", location);
+            assert!(fan_out.contains(searcher.get_shard_from_location(location).name()));
+        }
+
+        // the same place is routed and cached the same way on either side of the wrap
+        let same_places = [
+            (ll!(180.0, 0.0), ll!(-180.0, 0.0)),
+            (ll!(181.0, 5.0), ll!(-179.0, 5.0)),
+            (ll!(0.0, 90.0), ll!(123.0, 90.0)),
+        ];
+        for (a, b) in same_places.iter() {
+            assert_eq!(
+                searcher.get_shard_from_location(a).name(),
+                searcher.get_shard_from_location(b).name()
+            );
+            assert!(Arc::ptr_eq(
+                &searcher.cell_ids_from_radius_cached(a, 500),
+                &searcher.cell_ids_from_radius_cached(b, 500)
+            ));
+        }
+    }
 
-        let mut shards = Vec::new();
-        let mut geoshard_count = 1;
+    #[test]
+    fn test_geohash_covering() {
+        let geoshards = uniform_collection(2, 2);
+        assert_eq!(geoshards.shards().len(), 48);
+        let searcher = GeoshardSearcher::from(geoshards);
 
-        for (cell_id, cell_score) in scored_cells.iter() {
-            if cell_score + current_score > container_size {
-                let shard = Geoshard::new(
-                    format!("geoshard_user_index_{}", geoshard_count),
-                    current_score,
-                    cell_id.level(),
-                    CellUnion(cells),
-                );
+        let location = ll!(-74.0060, 40.7128);
+        let geohash = geohash::encode(&location, 3);
+        let covering = searcher
+            .get_shard_from_location(&location)
+            .geohash_covering(3);
+        assert!(covering.iter().any(|prefix| geohash.starts_with(prefix)));
+    }
 
-                assert_eq!(shard.cell_union().0.len(), current_cell_count);
-                cells = vec![];
-                shards.push(shard);
-                current_cell_count = 0;
-                current_score = 0;
-                geoshard_count += 1;
+    #[test]
+    fn test_label_shards() {
+        struct HemisphereNamer;
+        impl PlaceNamer for HemisphereNamer {
+            fn place_name(&self, cell_id: &CellID) -> Option<String> {
+                match LatLng::from(cell_id).lat.deg() >= 0.0 {
+                    true => Some("North".to_owned()),
+                    false => Some("South".to_owned()),
+                }
             }
-            cells.push(*cell_id);
-            current_cell_count += 1;
-            current_score += cell_score;
         }
 
-        if cells.len() != 0 {
-            let shard = Geoshard::new(
-                format!("geoshard_user_index_{}", geoshard_count),
-                current_score,
-                storage_level,
-                CellUnion(cells),
-            );
+        let mut geoshards = uniform_collection(2, 96);
+        geoshards.label_shards(&HemisphereNamer, 2);
 
-            shards.push(shard);
+        let shard = &geoshards.shards()[0];
+        assert_eq!(shard.label(), Some("North + South"));
+        assert_eq!(
+            shard.to_string(),
+            "geoshard_user_index_1 (North + South): score 96, 96 cells"
+        );
+
+        let json = serde_json::to_string(&geoshards).unwrap();
+        let parsed: GeoshardCollection = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.shards()[0].label(), Some("North + South"));
+    }
+
+    #[test]
+    fn test_replica_for() {
+        let searcher = GeoshardSearcher::from(uniform_collection(2, 2));
+        let location = ll!(-74.0060, 40.7128);
+
+        let (shard, replica) = searcher.replica_for(&location, "user-1", 3);
+        assert_eq!(
+            shard.name(),
+            searcher.get_shard_from_location(&location).name()
+        );
+        assert!(replica < 3);
+        assert_eq!(searcher.replica_for(&location, "user-1", 3).1, replica);
+
+        let replicas: std::collections::BTreeSet<u32> = (0..100)
+            .map(|user| {
+                searcher
+                    .replica_for(&location, format!("user-{}", user), 3)
+                    .1
+            })
+            .collect();
+        assert_eq!(replicas.len(), 3);
+
+        // growing the replica count only moves users onto the new replica
+        for user in 0..100 {
+            let user_id = format!("user-{}", user);
+            let (_, before) = searcher.replica_for(&location, &user_id, 3);
+            let (_, after) = searcher.replica_for(&location, &user_id, 4);
+            assert!(after == before || after == 3);
         }
+    }
 
-        Self {
-            shards,
-            storage_level,
+    #[test]
+    fn test_identified_users() {
+        let searcher = GeoshardSearcher::from(uniform_collection(4, 64));
+        let users = FakeUser::seeded(200, 5, &RandCityFactory::default());
+
+        let mut moved = 0;
+        for user in users.iter() {
+            let own_shard = searcher.get_shard_from_location(user.location());
+            assert_eq!(
+                searcher.get_shard_for_identified_user(&user, 0).name(),
+                own_shard.name()
+            );
+            let shard = searcher.get_shard_for_identified_user(&user, 50_000_000);
+            assert_eq!(
+                searcher
+                    .get_shard_for_identified_user(&user, 50_000_000)
+                    .name(),
+                shard.name()
+            );
+            let mut candidates = searcher.get_shards_from_radius(user.location(), 50_000_000);
+            candidates.push(own_shard);
+            assert!(candidates
+                .iter()
+                .any(|candidate| candidate.name() == shard.name()));
+            if shard.name() != own_shard.name() {
+                moved += 1;
+            }
+        }
+        assert!(moved > 0);
+
+        // spreading users across two shards only moves the users of a removed shard
+        let names = ["geoshard_user_index_1", "geoshard_user_index_2"];
+        let mut spread = BTreeMap::new();
+        for user in users.iter() {
+            let shard = searcher.rendezvous_shard(user.id(), &names).unwrap();
+            *spread.entry(shard.name()).or_insert(0) += 1;
+            if shard.name() == names[0] {
+                assert_eq!(
+                    searcher
+                        .rendezvous_shard(user.id(), &names[..1])
+                        .unwrap()
+                        .name(),
+                    names[0]
+                );
+            }
         }
+        assert_eq!(spread.len(), 2);
+        assert!(searcher.rendezvous_shard("user", &["nowhere"]).is_none());
     }
 
-    /// Calculates the standard deviation between shards
-    pub fn standard_deviation(&self) -> f64 {
-        let mean: f64 = self
-            .shards
+    #[test]
+    fn test_max_shard_score() {
+        let mut cell_list = CellList::new(4);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 10) as i32;
+        }
+        let mut partitioner = Partitioner::new(4, 2, 4);
+        let unlimited = partitioner.partition(&cell_list).unwrap();
+        assert!(unlimited
+            .shards()
             .iter()
-            .fold(0.0, |sum, x| sum + x.cell_score as f64)
-            / self.shards.len() as f64;
+            .any(|shard| shard.cell_score > 1000));
 
-        let varience: f64 = self
-            .shards
+        partitioner.max_shard_score = Some(1000);
+        let limited = partitioner.partition(&cell_list).unwrap();
+        assert!(limited.shards().len() > 4);
+        assert!(limited
+            .shards()
             .iter()
-            .map(|x| (x.cell_score as f64 - mean) * (x.cell_score as f64 - mean))
-            .sum::<f64>()
-            / self.shards.len() as f64;
+            .all(|shard| shard.cell_score <= 1000));
 
-        varience.sqrt()
+        partitioner.max_shard_score = Some(5);
+        match partitioner.partition(&cell_list) {
+            Err(GeoshardError::CellOverShardLimit { score, limit, .. }) => {
+                assert_eq!((score, limit), (6, 5));
+            }
+            other => panic!("expected CellOverShardLimit, got {:?}", other),
+        }
     }
-}
 
-/// `GeoshardSearcher` actual contains logic to find a users given shard, given a user
-#[derive(Debug)]
-pub struct GeoshardSearcher {
-    storage_level: u64,
-    shards: GeoshardCollection,
-}
+    #[test]
+    fn test_min_shard_score() {
+        // sparse cells with a few hot spots, split into a shard per cell
+        let mut cell_list = CellList::new(3);
+        let cell_count = cell_list.cell_list().len();
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = if index % 50 == 1 { 30 } else { 1 };
+        }
 
-impl GeoshardSearcher {
-    /// return shards
-    pub fn shards(&self) -> &GeoshardCollection {
-        &self.shards
-    }
+        let geoshards = GeoshardCollection::new(1, cell_list.cell_list(), 3);
+        let mut coalesced = GeoshardCollection::new(1, cell_list.cell_list(), 3);
+        coalesced.coalesce(10, None, None);
 
-    /// returns shard for given user
-    pub fn get_shard_for_user<T>(&self, user: T) -> &Geoshard
-    where
-        T: User,
-    {
-        let location = user.location();
-        self.get_shard_from_location(location)
-    }
+        assert_eq!(geoshards.shards().len(), cell_count);
+        assert!(coalesced.shards().len() < cell_count / 5);
+        for pair in coalesced.shards().windows(2) {
+            assert!(pair[0].cell_score + pair[1].cell_score >= 10);
+        }
+        assert_eq!(
+            coalesced
+                .shards()
+                .iter()
+                .map(Geoshard::cell_count)
+                .sum::<usize>(),
+            cell_count
+        );
+        assert_eq!(coalesced.shards()[1].name(), "geoshard_user_index_2");
 
-    /// returns the given `CellID` for given location
-    pub fn get_cell_id_from_location(&self, location: &LatLng) -> CellID {
-        CellID::from(location).parent(self.storage_level)
-    }
+        // a floor over the limit coalesces up to the limit only
+        let mut limited = GeoshardCollection::new(1, cell_list.cell_list(), 3);
+        limited.coalesce(100, Some(35), None);
+        assert!(limited.shards().len() < cell_count / 5);
+        assert!(limited.shards().iter().all(|shard| shard.cell_score <= 35));
+        assert!(limited.shards().iter().any(|shard| shard.cell_score > 10));
 
-    /// returns shard from given location
-    pub fn get_shard_from_location(&self, location: &LatLng) -> &Geoshard {
-        self.get_shard_from_cell_id(&self.get_cell_id_from_location(location))
+        let mut partitioner = Partitioner::new(3, 2, cell_count as i32);
+        partitioner.min_shard_score = Some(100);
+        partitioner.max_shard_score = Some(40);
+        let partitioned = partitioner.partition(&cell_list).unwrap();
+        assert!(partitioned
+            .shards()
+            .iter()
+            .all(|shard| shard.cell_score <= 40));
     }
 
-    /// returns a shard for given cell ID
-    pub fn get_shard_from_cell_id(&self, cell_id: &CellID) -> &Geoshard {
-        for geoshard in self.shards.shards.iter() {
-            if geoshard.cell_union().contains_cellid(cell_id) {
-                return geoshard;
-            }
+    #[test]
+    fn test_split_and_merge_shards() {
+        let mut cell_list = CellList::new(3);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 4) as i32;
+        }
+        let mut geoshards = GeoshardCollection::new(100, cell_list.cell_list(), 3);
+        let shard_count = geoshards.shards().len();
+        let total_score: i32 = geoshards.shards().iter().map(|shard| shard.score()).sum();
+
+        let hot_shard = &geoshards.shards()[1];
+        let at_cell = hot_shard.cell_union().0[10];
+        let hot_cell_count = hot_shard.cell_count();
+        geoshards
+            .split_shard("geoshard_user_index_2", &at_cell)
+            .unwrap();
+        assert_eq!(geoshards.shards().len(), shard_count + 1);
+        assert_eq!(geoshards.shards()[1].cell_count(), 10);
+        assert_eq!(geoshards.shards()[2].cell_count(), hot_cell_count - 10);
+        assert_eq!(geoshards.shards()[2].start(), &at_cell);
+        assert_eq!(
+            geoshards.shards()[1].score(),
+            geoshards.shards()[1].cell_scores().iter().sum::<i32>()
+        );
+        for (index, shard) in geoshards.shards().iter().enumerate() {
+            assert_eq!(shard.name(), format!("geoshard_user_index_{}", index + 1));
         }
-        self.shards.shards.last().unwrap()
+        assert!(geoshards
+            .split_shard("geoshard_user_index_2", &at_cell)
+            .is_err());
+
+        geoshards
+            .merge_shards("geoshard_user_index_2", "geoshard_user_index_3")
+            .unwrap();
+        assert_eq!(geoshards.shards().len(), shard_count);
+        assert_eq!(geoshards.shards()[1].cell_count(), hot_cell_count);
+        assert_eq!(
+            geoshards
+                .shards()
+                .iter()
+                .map(|shard| shard.score())
+                .sum::<i32>(),
+            total_score
+        );
+        assert!(geoshards
+            .merge_shards("geoshard_user_index_1", "geoshard_user_index_1")
+            .is_err());
+        // the second shard is between the first and third
+        assert!(matches!(
+            geoshards.merge_shards("geoshard_user_index_1", "geoshard_user_index_3"),
+            Err(GeoshardError::InvalidShardOperation { reason, .. })
+                if reason.ends_with("shard geoshard_user_index_2 is between them")
+        ));
+        assert_eq!(geoshards.shards().len(), shard_count);
+        assert!(geoshards
+            .merge_shards("geoshard_user_index_1", "missing")
+            .is_err());
     }
 
-    /// returns the given shard in a location and radius
-    pub fn get_shards_from_radius(&self, location: &LatLng, radius: u32) -> Vec<&Geoshard> {
-        self.cell_ids_from_radius(location, radius)
-            .into_iter()
-            .map(|cell_id| self.get_shard_from_cell_id(&cell_id))
-            .collect()
+    #[test]
+    fn test_partition_objective() {
+        let (cell_list, _) = clustered_cell_list();
+        let total_score: i32 = cell_list.cell_list().values().sum();
+        let cell_count_spread = |objective| {
+            let mut partitioner = Partitioner::new(4, 4, 8);
+            partitioner.objective = objective;
+            let geoshards = partitioner.partition(&cell_list).unwrap();
+            assert_eq!(
+                geoshards
+                    .shards()
+                    .iter()
+                    .map(|shard| shard.score())
+                    .sum::<i32>(),
+                total_score
+            );
+            let counts: Vec<usize> = geoshards
+                .shards()
+                .iter()
+                .map(|shard| shard.cell_count())
+                .collect();
+            counts.iter().max().unwrap() - counts.iter().min().unwrap()
+        };
+
+        let by_score = cell_count_spread(PartitionObjective::Score);
+        let by_cell_count = cell_count_spread(PartitionObjective::CellCount);
+        let blended = cell_count_spread(PartitionObjective::Blend { score_weight: 0.5 });
+        assert!(by_cell_count < by_score);
+        assert!(by_cell_count <= blended);
     }
 
-    /// Gives all the CellIDs in a given radius in miles
-    pub fn cell_ids_from_radius(&self, location: &LatLng, radius: u32) -> Vec<CellID> {
-        let center_point = Point::from(location);
+    #[test]
+    fn test_max_shard_extent() {
+        let (cell_list, _) = clustered_cell_list();
+        let max_extent = 3_000_000.0;
+        let mut partitioner = Partitioner::new(4, 4, 8);
+        let unbounded = partitioner.partition(&cell_list).unwrap();
+        partitioner.max_shard_extent = Some(max_extent);
+        let bounded = partitioner.partition(&cell_list).unwrap();
+
+        let extent = |shard: &Geoshard| {
+            let first = LatLng::from(shard.start());
+            shard
+                .cell_union()
+                .0
+                .iter()
+                .map(|cell_id| first.distance(&LatLng::from(cell_id)).rad() * EARTH_RADIUS)
+                .fold(0.0, f64::max)
+        };
+        assert!(unbounded
+            .shards()
+            .iter()
+            .any(|shard| extent(shard) > max_extent));
+        assert!(bounded
+            .shards()
+            .iter()
+            .all(|shard| extent(shard) <= max_extent));
+        assert!(bounded.shards().len() > unbounded.shards().len());
+    }
+
+    #[test]
+    fn test_preset() {
+        let users = FakeUser::seeded(500, 3, &RandCityFactory::default());
+        let (geoshards, report) = GeoshardBuilder::preset(Preset::Gaming, users.iter())
+            .try_build_with_report()
+            .unwrap();
 
-        let center_angle = s1::Deg(radius as f64 / EARTH_RADIUS).into();
+        assert_eq!(report.preset, Some(Preset::Gaming));
+        assert_eq!(report.storage_level, Preset::Gaming.storage_level());
+        assert_eq!(
+            (report.min_shard_count, report.max_shard_count),
+            Preset::Gaming.shard_count_range()
+        );
+        assert_eq!(report.max_shard_extent, Preset::Gaming.max_shard_extent());
+        assert_eq!(report.shard_count, geoshards.shards().len());
+        assert_eq!(report.total_score, 500);
+        assert_eq!(geoshards.storage_level(), 6);
+    }
 
-        let cap = Cap::from_center_angle(&center_point, &center_angle);
+    #[test]
+    fn test_with_progress() {
+        let users = FakeUser::seeded(25_000, 3, &RandCityFactory::default());
+        let reports = Arc::new(Mutex::new(vec![]));
+        let recorder = reports.clone();
+        GeoshardBuilder::user_count_scorer(3, users.iter(), 4, 8)
+            .with_progress(move |phase, done, total| {
+                recorder.lock().unwrap().push((phase, done, total))
+            })
+            .build();
 
-        let region_cover = RegionCoverer {
-            max_level: self.storage_level as u8,
-            min_level: self.storage_level as u8,
-            level_mod: 0,
-            max_cells: 0,
+        let reports = reports.lock().unwrap();
+        let of_phase = |phase: BuildPhase| -> Vec<(u64, Option<u64>)> {
+            reports
+                .iter()
+                .filter(|report| report.0 == phase)
+                .map(|report| (report.1, report.2))
+                .collect()
         };
-        region_cover.covering(&cap).0
+        assert_eq!(
+            of_phase(BuildPhase::CellGeneration),
+            vec![(0, Some(384)), (384, Some(384))]
+        );
+        assert_eq!(
+            of_phase(BuildPhase::Scoring),
+            vec![
+                (10_000, Some(25_000)),
+                (20_000, Some(25_000)),
+                (25_000, Some(25_000))
+            ]
+        );
+        let search = of_phase(BuildPhase::ContainerSearch);
+        assert_eq!(search[0].0, 0);
+        assert_eq!(search.last().unwrap().0, search[0].1.unwrap());
+        assert_eq!(reports[0].0, BuildPhase::CellGeneration);
+        assert_eq!(reports.last().unwrap().0, BuildPhase::ContainerSearch);
     }
-}
 
-impl From<GeoshardCollection> for GeoshardSearcher {
-    fn from(shards: GeoshardCollection) -> Self {
-        let storage_level = shards.storage_level;
-        Self {
-            storage_level,
-            shards,
-        }
+    #[test]
+    fn test_with_cancellation() {
+        let users = FakeUser::seeded(100, 3, &RandCityFactory::default());
+        let cancellation = Arc::new(AtomicBool::new(false));
+        let build = || {
+            GeoshardBuilder::user_count_scorer(3, users.iter(), 4, 8)
+                .with_cancellation(cancellation.clone())
+                .try_build()
+        };
+        assert!(build().is_ok());
+
+        cancellation.store(true, Ordering::Relaxed);
+        assert!(matches!(build(), Err(GeoshardError::Cancelled)));
+
+        // cancelling part way through the container search
+        cancellation.store(false, Ordering::Relaxed);
+        let cancel = cancellation.clone();
+        let result = GeoshardBuilder::user_count_scorer(3, users.iter(), 4, 8)
+            .with_cancellation(cancellation.clone())
+            .with_progress(move |phase, done, _| {
+                if phase == BuildPhase::ContainerSearch && done == 2 {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            })
+            .try_build();
+        assert!(matches!(result, Err(GeoshardError::Cancelled)));
     }
-}
 
-#[cfg(test)]
-pub mod test {
+    #[test]
+    fn test_build_with_deadline() {
+        let users = FakeUser::seeded(1000, 3, &RandCityFactory::default());
+        let builder = || GeoshardBuilder::user_count_scorer(3, users.iter(), 4, 8);
+        let optimal = builder().build();
 
-    use super::*;
-    use crate::utils::ll;
+        let (geoshards, standard_deviation) = builder()
+            .build_with_deadline(Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(standard_deviation, optimal.standard_deviation());
+        assert_eq!(geoshards.shards().len(), optimal.shards().len());
 
-    use rand::Rng;
+        // with no time at all, the middle container size is still tried
+        let (geoshards, standard_deviation) =
+            builder().build_with_deadline(Duration::ZERO).unwrap();
+        assert_eq!(standard_deviation, geoshards.standard_deviation());
+        assert!(standard_deviation >= optimal.standard_deviation());
+        let total_score: i32 = geoshards.shards().iter().map(Geoshard::score).sum();
+        assert_eq!(total_score, 1000);
+    }
 
-    use lazy_static::lazy_static;
-    use rand::{distributions::Alphanumeric, prelude::SliceRandom, thread_rng};
-    use s2::cellid::CellID;
+    #[test]
+    fn test_collection_covers_every_cell() {
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let storage_level = rng.gen_range(1..=3);
+            let mut cell_list = CellList::new(storage_level);
+            for score in cell_list.mut_cell_list().values_mut() {
+                *score = match rng.gen_range(0..10) {
+                    0..=3 => 0,
+                    4..=8 => rng.gen_range(1..20),
+                    _ => rng.gen_range(100..1000),
+                };
+            }
+            let container_size = rng.gen_range(1..500);
+            let max_extent = rng.gen_bool(0.3).then(|| rng.gen_range(1e5..5e6));
+            let geoshards = GeoshardCollection::pack(
+                container_size,
+                cell_list.cell_list(),
+                storage_level,
+                max_extent,
+                None,
+            );
 
-    struct RandCityFactory {
-        cities: Vec<LatLng>,
-    }
-    impl RandCityFactory {
-        fn new_city(&self) -> LatLng {
-            let mut rng = rand::thread_rng();
-            self.cities.choose(&mut rng).unwrap().clone()
-        }
-
-        fn cities(&self) -> &Vec<LatLng> {
-            &self.cities
-        }
-    }
-
-    impl Default for RandCityFactory {
-        fn default() -> Self {
-            let cities: Vec<LatLng> = vec![
-                ll!(40.745255, 40.745255),
-                ll!(34.155834, 34.155834),
-                ll!(42.933334, 42.933334),
-                ll!(42.095554, 42.095554),
-                ll!(38.846668, 38.846668),
-                ll!(41.392502, 41.392502),
-                ll!(27.192223, 27.192223),
-                ll!(31.442778, 31.442778),
-                ll!(40.560001, 40.560001),
-                ll!(33.193611, 33.193611),
-                ll!(41.676388, 41.676388),
-                ll!(41.543056, 41.543056),
-                ll!(39.554443, 39.554443),
-                ll!(44.513332, 44.513332),
-                ll!(37.554169, 37.554169),
-                ll!(32.349998, 32.349998),
-                ll!(29.499722, 29.499722),
-                ll!(33.038334, 33.038334),
-                ll!(43.614166, 43.614166),
-                ll!(41.55611, 41.55611),
-                ll!(34.00, 34.00),
-                ll!(26.709723, 26.709723),
-                ll!(38.005001, 38.005001),
-                ll!(35.970554, 35.970554),
-                ll!(25.942122, 25.942122),
-                ll!(33.569443, 33.569443),
-                ll!(39.799999, 39.799999),
-                ll!(34.073334, 34.073334),
-                ll!(40.606388, 40.606388),
-                ll!(30.601389, 30.601389),
-                ll!(38.257778, 38.257778),
-                ll!(37.977222, 37.977222),
-                ll!(42.373611, 42.373611),
-                ll!(32.965557, 32.965557),
-                ll!(37.871666, 37.871666),
-                ll!(38.951561, 38.951561),
-                ll!(33.950001, 33.950001),
-                ll!(30.216667, 30.216667),
-                ll!(42.580276, 42.580276),
-                ll!(36.316666, 36.316666),
-                ll!(37.034946, 37.034946),
-                ll!(40.689167, 40.689167),
-                ll!(33.630554, 33.630554),
-                ll!(39.903057, 39.903057),
-                ll!(25.978889, 25.978889),
-                ll!(35.846111, 35.846111),
-                ll!(34.156113, 34.156113),
-                ll!(41.18639, 41.18639),
-                ll!(40.914745, 40.914745),
-                ll!(42.259445, 42.259445),
-                ll!(41.520557, 41.520557),
-                ll!(33.124722, 33.124722),
-                ll!(39.106667, 39.106667),
-                ll!(42.101391, 42.101391),
-                ll!(37.210388, 37.210388),
-                ll!(33.866669, 33.866669),
-                ll!(26.012501, 26.012501),
-                ll!(38.438332, 38.438332),
-                ll!(33.211666, 33.211666),
-                ll!(37.070831, 37.070831),
-                ll!(43.536388, 43.536388),
-                ll!(45.633331, 45.633331),
-                ll!(42.271389, 42.271389),
-                ll!(30.455, 30.455),
-                ll!(32.492222, 32.492222),
-                ll!(33.466667, 33.466667),
-                ll!(32.361668, 32.361668),
-                ll!(41.763889, 41.763889),
-                ll!(35.199165, 35.199165),
-                ll!(37.661388, 37.661388),
-                ll!(32.907223, 32.907223),
-                ll!(33.669445, 33.669445),
-                ll!(39.710835, 39.710835),
-                ll!(32.705002, 32.705002),
-                ll!(39.099724, 39.099724),
-                ll!(35.1175, 35.1175),
-                ll!(39.791, 39.791),
-                ll!(39.983334, 39.983334),
-                ll!(30.266666, 30.266666),
-                ll!(32.779167, 32.779167),
-                ll!(37.487846, 37.487846),
-                ll!(35.25528, 35.25528),
-                ll!(29.700001, 29.700001),
-                ll!(26.838619, 26.838619),
-                ll!(38.473625, 38.473625),
-                ll!(29.749907, 29.749907),
-                ll!(40.191891, 40.191891),
-                ll!(33.830517, 33.830517),
-                ll!(34.496212, 34.496212),
-                ll!(37.54129, 37.54129),
-                ll!(36.082157, 36.082157),
-                ll!(32.698437, 32.698437),
-                ll!(33.580944, 33.580944),
-                ll!(33.427204, 33.427204),
-                ll!(34.028622, 34.028622),
-                ll!(32.609856, 32.609856),
-                ll!(33.405746, 33.405746),
-                ll!(34.603817, 34.603817),
-                ll!(44.840797, 44.840797),
-                ll!(71.290558, 71.290558),
-            ];
-            Self { cities }
-        }
-    }
-
-    lazy_static! {
-        static ref RANDOM_CITY_FACTORY: RandCityFactory = RandCityFactory::default();
-    }
-
-    #[derive(Clone)]
-    pub struct FakeUser {
-        pub name: String,
-        location: LatLng,
-    }
-
-    impl PartialEq for FakeUser {
-        fn eq(&self, other: &Self) -> bool {
-            other.name == self.name
-        }
-    }
-
-    impl FakeUser {
-        pub fn new() -> Self {
-            let name: String = thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(30)
-                .map(char::from)
-                .collect();
-            Self {
-                name,
-                location: RANDOM_CITY_FACTORY.new_city(),
+            let mut cells = vec![];
+            for (index, shard) in geoshards.shards().iter().enumerate() {
+                assert!(shard.cell_count() > 0, "seed {}", seed);
+                assert_eq!(shard.storage_level(), storage_level);
+                assert_eq!(shard.name(), format!("geoshard_user_index_{}", index + 1));
+                assert_eq!(shard.score(), shard.cell_scores().iter().sum::<i32>());
+                assert!(shard.score() <= container_size || shard.cell_count() == 1);
+                cells.extend(shard.cell_union().0.iter().copied());
             }
+            assert_eq!(
+                cells,
+                cell_list.cell_list().keys().copied().collect::<Vec<_>>(),
+                "seed {}",
+                seed
+            );
+            assert!(geoshards.verify().is_ok());
         }
     }
 
-    impl User for &FakeUser {
-        fn location(&self) -> &LatLng {
-            &self.location
+    #[test]
+    fn test_overflow_splitting() {
+        // 400 users spread over Manhattan, all in one level 4 cell
+        let mut users: Vec<FakeUser> = (0..400)
+            .map(|index| {
+                let (x, y) = ((index % 20) as f64, (index / 20) as f64);
+                FakeUser::at(ll!(-74.05 + x * 0.005, 40.70 + y * 0.005))
+            })
+            .collect();
+        users.extend(FakeUser::seeded(300, 9, &RandCityFactory::default()));
+        let limit = 700 / 4;
+
+        let whole = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        assert!(whole.shards().iter().any(|shard| shard.score() >= 400));
+
+        let geoshards = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .with_overflow_splitting(12)
+            .build();
+        assert!(geoshards.verify().is_ok());
+        assert!(geoshards
+            .shards()
+            .iter()
+            .all(|shard| shard.score() <= limit));
+        let total_score: i32 = geoshards.shards().iter().map(Geoshard::score).sum();
+        assert_eq!(total_score, 700);
+
+        let searcher = GeoshardSearcher::from(geoshards);
+        let batch = searcher.get_shards_for_users_batch(&users);
+        let mut manhattan_shards = BTreeSet::new();
+        for (index, (user, batch_shard)) in users.iter().zip(batch).enumerate() {
+            let shard = searcher
+                .try_get_shard_from_location(user.location())
+                .unwrap();
+            assert_eq!(shard.name(), batch_shard.name());
+            if index < 400 {
+                manhattan_shards.insert(shard.name());
+            }
         }
+        assert!(manhattan_shards.len() > 2);
     }
 
-    macro_rules! shard {
-        ($cell_score:expr) => {
-            Geoshard::new("fake-shard".to_owned(), $cell_score, 0, CellUnion(vec![]));
+    #[test]
+    fn test_sparse_cells() {
+        let users = FakeUser::seeded(1000, 3, &RandCityFactory::default());
+        let dense = GeoshardBuilder::user_count_scorer(6, users.iter(), 4, 8).build();
+        let sparse = || {
+            GeoshardBuilder::user_count_scorer(6, users.iter(), 4, 8)
+                .with_sparse_cells()
+                .build()
         };
+        let (searcher, sparse) = (GeoshardSearcher::from(sparse()), sparse());
+        assert!(sparse.verify().is_ok());
+        assert_eq!(sparse.shards().len(), dense.shards().len());
+        let total_score: i32 = sparse.shards().iter().map(Geoshard::score).sum();
+        assert_eq!(total_score, 1000);
+        let cell_count: usize = sparse.shards().iter().map(Geoshard::cell_count).sum();
+        assert!(cell_count < CellList::new(6).cell_list().len() / 10);
+
+        // the gaps are covered, so every cell is routed, and the same way by the lookup table
+        let lookup_searcher = GeoshardSearcher::from(sparse).with_lookup_table().unwrap();
+        for cell_id in CellList::new(6).cell_list().keys() {
+            let shard = searcher.try_get_shard_from_cell_id(cell_id).unwrap();
+            assert_eq!(
+                lookup_searcher
+                    .try_get_shard_from_cell_id(cell_id)
+                    .unwrap()
+                    .name(),
+                shard.name()
+            );
+        }
+        // with no users, one shard covers the globe
+        let empty = GeoshardBuilder::user_count_scorer(6, Vec::<FakeUser>::new().iter(), 4, 8)
+            .with_sparse_cells()
+            .build();
+        assert_eq!(empty.shards().len(), 1);
+        assert_eq!(empty.shards()[0].cell_count(), 6);
     }
 
-    pub struct RandomCellScore;
+    #[test]
+    fn test_partition_strategy() {
+        let users = FakeUser::seeded(2000, 7, &RandCityFactory::default());
+        let greedy = GeoshardBuilder::user_count_scorer(4, users.iter(), 6, 6).build();
+        let optimal = GeoshardBuilder::user_count_scorer(4, users.iter(), 6, 6)
+            .with_partition_strategy(OptimalContiguous::default())
+            .build();
+        assert!(optimal.verify().is_ok());
+        assert_eq!(optimal.shards().len(), 6);
+        let max_score = |shards: &GeoshardCollection| {
+            shards.shards().iter().map(Geoshard::score).max().unwrap()
+        };
+        assert!(max_score(&optimal) <= max_score(&greedy));
+        let total_score: i32 = optimal.shards().iter().map(Geoshard::score).sum();
+        assert_eq!(total_score, 2000);
+        let cell_count: usize = optimal.shards().iter().map(Geoshard::cell_count).sum();
+        assert_eq!(cell_count, CellList::new(4).cell_list().len());
+    }
 
     #[test]
-    fn test_shard_search() {
-        let geoshards =
-            GeoshardBuilder::user_count_scorer(4, Box::new(vec![FakeUser::new()].iter()), 40, 100)
+    fn test_exact_shard_count() {
+        let users = FakeUser::seeded(1000, 11, &RandCityFactory::default());
+        for shard_count in [1, 7, 24, 40] {
+            let shards = GeoshardBuilder::user_count_scorer(4, users.iter(), 2, 100)
+                .with_exact_shard_count(shard_count)
                 .build();
-        let geoshard_searcher = GeoshardSearcher::from(geoshards);
+            assert!(shards.verify().is_ok());
+            assert_eq!(shards.shards().len(), shard_count as usize);
+            let total_score: i32 = shards.shards().iter().map(Geoshard::score).sum();
+            assert_eq!(total_score, 1000);
+        }
+        for shard_count in [0, -3] {
+            assert!(matches!(
+                GeoshardBuilder::user_count_scorer(4, users.iter(), 2, 100)
+                    .with_exact_shard_count(shard_count)
+                    .try_build(),
+                Err(GeoshardError::InvalidBuilderConfig { .. })
+            ));
+        }
+    }
 
-        let geoshard = geoshard_searcher.get_shard_from_location(&ll!(34.181061, -103.345177));
+    #[test]
+    fn test_neighbors() {
+        let mut shards = uniform_collection(4, 64);
+        assert!(shards.neighbors("nowhere").is_none());
 
-        let cell_id = geoshard_searcher.get_cell_id_from_location(&ll!(34.181061, -103.345177));
+        // every cell is at the storage level, so adjacency can be checked cell by cell
+        let shard_of = |shards: &GeoshardCollection, cell_id: &CellID| {
+            shards
+                .shards()
+                .iter()
+                .position(|shard| shard.cell_union().contains_cellid(cell_id))
+                .unwrap()
+        };
+        for (index, shard) in shards.shards().iter().enumerate() {
+            let mut expected: Vec<usize> = shard
+                .cell_union()
+                .0
+                .iter()
+                .flat_map(|cell_id| cell_id.edge_neighbors())
+                .map(|neighbor| shard_of(&shards, &neighbor))
+                .filter(|other| *other != index)
+                .collect();
+            expected.sort();
+            expected.dedup();
+            let neighbors: Vec<usize> = shards
+                .neighbors(shard.name())
+                .unwrap()
+                .iter()
+                .map(|neighbor| shard_of(&shards, &neighbor.cell_union().0[0]))
+                .collect();
+            assert_eq!(neighbors, expected);
+            assert!(!neighbors.is_empty());
+        }
 
-        assert!(geoshard.cell_union().contains_cellid(&cell_id));
+        // splitting a shard makes its two halves neighbors
+        let at_cell = shards.shards()[0].cell_union().0[8];
+        shards
+            .split_shard("geoshard_user_index_1", &at_cell)
+            .unwrap();
+        let neighbors = shards.neighbors("geoshard_user_index_1").unwrap();
+        assert!(neighbors
+            .iter()
+            .any(|neighbor| neighbor.name() == "geoshard_user_index_2"));
     }
 
     #[test]
-    fn test_shard_radius_search() {
-        let geoshard = GeoshardBuilder::new(
-            4,
-            Box::new(vec![FakeUser::new()].iter()),
-            RandomCellScore,
-            40,
-            100,
-        )
-        .build();
-        let geoshards = GeoshardSearcher::from(geoshard);
-        let geoshards = geoshards.get_shards_from_radius(&ll!(34.181061, -103.345177), 200);
-        assert_eq!(geoshards.len(), 1);
+    fn test_build_and_compare() {
+        let users = FakeUser::seeded(1000, 11, &RandCityFactory::default());
+        let current = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let (same, comparison) = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .build_and_compare(&current)
+            .unwrap();
+        assert_eq!(same.shards().len(), current.shards().len());
+        assert_eq!(comparison.boundary_changes(), 0);
+        assert_eq!(comparison.total_score, 1000);
+        assert!(comparison
+            .to_string()
+            .starts_with("this reshard moves 0.0% of users across 0 shard boundary changes"));
+
+        let more_users = FakeUser::seeded(2000, 12, &RandCityFactory::default());
+        let (new, comparison) = GeoshardBuilder::user_count_scorer(4, more_users.iter(), 9, 12)
+            .build_and_compare(&current)
+            .unwrap();
+        assert_eq!(comparison.new_shard_count, new.shards().len());
+        assert!(comparison.boundary_changes() > 0);
+        assert!(comparison.moved_fraction() > 0.0 && comparison.moved_fraction() <= 1.0);
+        assert_eq!(comparison.total_score, 2000);
+
+        assert!(matches!(
+            GeoshardBuilder::user_count_scorer(5, users.iter(), 4, 8).build_and_compare(&current),
+            Err(GeoshardError::InvalidShardMap { .. })
+        ));
+        // the configuration is checked before any cells are generated
+        assert!(matches!(
+            GeoshardBuilder::user_count_scorer(31, users.iter(), 4, 8).build_and_compare(&current),
+            Err(GeoshardError::InvalidBuilderConfig { .. })
+        ));
+        assert!(matches!(
+            GeoshardBuilder::user_count_scorer(31, users.iter(), 4, 8).try_build_with_report(),
+            Err(GeoshardError::InvalidBuilderConfig { .. })
+        ));
     }
 
     #[test]
-    fn test_generate_shards() {
-        let geoshard = GeoshardBuilder::new(
-            4,
-            Box::new(vec![FakeUser::new()].iter()),
-            RandomCellScore,
-            40,
-            100,
-        )
-        .build();
+    fn test_compare_scorers() {
+        /// counts users north of the 37th parallel twice
+        struct NorthernScorer;
+        impl<UserCollection> CellScorer<UserCollection> for NorthernScorer {
+            fn score_cell_list<T>(&self, mut cell_list: CellList, users: UserCollection) -> CellList
+            where
+                UserCollection: Iterator<Item = T>,
+                T: User,
+            {
+                let storage_level = cell_list.storage_level();
+                for user in users {
+                    let weight = if user.location().lat.deg() > 37.0 {
+                        2
+                    } else {
+                        1
+                    };
+                    let cell_id = CellID::from(user.location()).parent(storage_level);
+                    *cell_list.mut_cell_list().entry(cell_id).or_insert(0) += weight;
+                }
+                cell_list
+            }
+        }
 
-        let shards = geoshard.shards;
+        let users = FakeUser::seeded(1000, 5, &RandCityFactory::default());
+        let same = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .compare_scorers(UserCountScorer)
+            .unwrap();
+        assert!(same.cells.is_empty());
+        assert_eq!(same.max_shard_divergence(), 0.0);
+        assert_eq!(same.shard_count_a, same.shard_count_b);
 
-        if (shards.len() as i32) > 100 || (shards.len() as i32) < 40 {
-            panic!("Shard len out of range: {}", shards.len());
+        let comparison = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .compare_scorers(NorthernScorer)
+            .unwrap();
+        assert_eq!(comparison.total_score_a, 1000);
+        assert!(comparison.total_score_b > 1000);
+        assert!(!comparison.cells.is_empty());
+        assert!(comparison
+            .cells
+            .iter()
+            .all(|cell| cell.score_b > cell.score_a));
+        assert!(comparison.max_shard_divergence() > 0.0);
+        let score_b: i64 = comparison.shards.iter().map(|shard| shard.score_b).sum();
+        assert_eq!(score_b, comparison.total_score_b);
+        assert_eq!(
+            comparison.shards[0].share_divergence.abs(),
+            comparison.max_shard_divergence()
+        );
+    }
+
+    #[test]
+    fn test_rebuild_from_snapshot() {
+        let users = FakeUser::seeded(1000, 13, &RandCityFactory::default());
+        let mut snapshot = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).snapshot();
+        assert_eq!(snapshot.total_score(), 1000);
+
+        let rebuilt = GeoshardBuilder::user_count_scorer(4, Vec::<FakeUser>::new().iter(), 4, 8)
+            .rebuild_from_snapshot(&snapshot)
+            .unwrap();
+        let built = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        assert_eq!(
+            serde_json::to_string(&rebuilt).unwrap(),
+            serde_json::to_string(&built).unwrap()
+        );
+
+        // new users are added without scoring the others again
+        let new_users = FakeUser::seeded(10, 14, &RandCityFactory::default());
+        for user in new_users.iter() {
+            snapshot.apply_delta(CellID::from(user.location()), 1);
         }
+        let all_users: Vec<&FakeUser> = users.iter().chain(new_users.iter()).collect();
+        let rebuilt = GeoshardBuilder::user_count_scorer(4, Vec::<FakeUser>::new().iter(), 4, 8)
+            .rebuild_from_snapshot(&snapshot)
+            .unwrap();
+        let built = GeoshardBuilder::user_count_scorer(4, all_users.into_iter(), 4, 8).build();
+        assert_eq!(
+            serde_json::to_string(&rebuilt).unwrap(),
+            serde_json::to_string(&built).unwrap()
+        );
+
+        assert!(matches!(
+            GeoshardBuilder::user_count_scorer(6, Vec::<FakeUser>::new().iter(), 4, 8)
+                .rebuild_from_snapshot(&snapshot),
+            Err(GeoshardError::StorageLevelMismatch {
+                expected: 6,
+                found: 4
+            })
+        ));
     }
 
-    impl<UserCollection> CellScorer<UserCollection> for RandomCellScore {
-        fn score_cell_list<T>(&self, mut cell_list: CellList, _users: UserCollection) -> CellList {
-            let mock_values = cell_list.mut_cell_list();
-            let mut rng = rand::thread_rng();
+    #[test]
+    fn test_from_scored_cells() {
+        let users = FakeUser::seeded(1000, 41, &RandCityFactory::default());
+        let snapshot = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).snapshot();
+        // only the cells with users, some of them reported at a finer level
+        let cells: BTreeMap<CellID, i32> = snapshot
+            .cell_list()
+            .cell_list()
+            .iter()
+            .filter(|(_, score)| **score > 0)
+            .enumerate()
+            .map(|(index, (cell_id, score))| match index % 2 {
+                0 => (cell_id.child_begin_at_level(7), *score),
+                _ => (*cell_id, *score),
+            })
+            .collect();
 
-            // Ocean
-            for _ in 0..=1000 {
-                let rand_lat = rng.gen_range(0.000000..2000.000000);
-                let rand_long = rng.gen_range(0.000000..2000.000000);
+        let built = GeoshardBuilder::from_scored_cells(cells, 4, 8).build();
+        assert_eq!(built.storage_level(), 4);
+        let from_users = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        assert_eq!(
+            serde_json::to_string(&built).unwrap(),
+            serde_json::to_string(&from_users).unwrap()
+        );
+    }
 
-                let cell_id = CellID::from(ll!(rand_lat, rand_long));
-                let rand_load_count = rng.gen_range(0..5);
-                mock_values.insert(cell_id, rand_load_count);
-            }
+    #[test]
+    fn test_container_sizes() {
+        let mut partitioner = Partitioner::new(3, 4, 8);
+        assert_eq!(partitioner.container_sizes(3, 6), vec![3, 4, 5, 6]);
+        partitioner.deadline = Some(Instant::now());
+        let sizes = partitioner.container_sizes(10, 18);
+        assert_eq!(&sizes[..3], &[14, 12, 16]);
+        let mut sorted = sizes.clone();
+        sorted.sort();
+        assert_eq!(sorted, (10..=18).collect::<Vec<i32>>());
+    }
 
-            // Small Cities
-            for _ in 0..=100 {
-                let rand_lat = rng.gen_range(0.000000..2000.000000);
-                let rand_long = rng.gen_range(0.000000..2000.000000);
+    #[test]
+    fn test_staleness() {
+        let (cell_list, _) = clustered_cell_list();
+        let built_at = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut partitioner = Partitioner::new(4, 4, 8);
+        partitioner.score_window = Some(ScoreWindow::new(built_at - day, built_at));
+        let geoshards = partitioner.partition(&cell_list).unwrap();
 
-                let cell_id = CellID::from(ll!(rand_lat, rand_long));
-                let rand_load_count = rng.gen_range(10..100);
-                mock_values.insert(cell_id, rand_load_count);
-            }
+        let json = serde_json::to_string(&geoshards).unwrap();
+        let geoshards: GeoshardCollection = serde_json::from_str(&json).unwrap();
+        assert_eq!(geoshards.score_window().unwrap().end, 1_600_000_000);
+        assert_eq!(geoshards.staleness(built_at + day), Some(day));
+        assert_eq!(geoshards.staleness(built_at - day), Some(Duration::ZERO));
 
-            // Medium Cities
-            for _ in 0..=50 {
-                let rand_lat = rng.gen_range(0.000000..2000.000000);
-                let rand_long = rng.gen_range(0.000000..2000.000000);
+        let searcher = GeoshardSearcher::from(geoshards).with_staleness_threshold(day * 7);
+        assert_eq!(searcher.staleness_warning(built_at + day), None);
+        let warning = searcher.staleness_warning(built_at + day * 8).unwrap();
+        assert_eq!(warning.staleness, day * 8);
+    }
 
-                let cell_id = CellID::from(ll!(rand_lat, rand_long));
-                let rand_load_count = rng.gen_range(100..500);
-                mock_values.insert(cell_id, rand_load_count);
-            }
+    #[test]
+    fn test_hottest_cells() {
+        let mut scored_cells = CellList::new(2).cell_list().clone();
+        let cell_ids: Vec<CellID> = scored_cells.keys().cloned().collect();
+        *scored_cells.get_mut(&cell_ids[3]).unwrap() = 50;
+        *scored_cells.get_mut(&cell_ids[40]).unwrap() = 30;
+        *scored_cells.get_mut(&cell_ids[90]).unwrap() = 10;
 
-            // Big Cities
-            for _ in 0..=10 {
-                let rand_lat = rng.gen_range(0.000000..2000.000000);
-                let rand_long = rng.gen_range(0.000000..2000.000000);
+        let geoshards = GeoshardCollection::new(35, &scored_cells, 2);
+        let hottest = geoshards.hottest_cells(2);
+        assert_eq!(hottest.len(), 2);
+        assert_eq!(hottest[0].score, 50);
+        assert_eq!(hottest[0].token, cell_ids[3].to_token());
+        assert_eq!(hottest[1].score, 30);
+        assert_eq!(CellID::from(&hottest[1].center).parent(2), cell_ids[40]);
 
-                let cell_id = CellID::from(ll!(rand_lat, rand_long));
-                let rand_load_count = rng.gen_range(1000..2000);
-                mock_values.insert(cell_id, rand_load_count);
-            }
+        let searcher = GeoshardSearcher::from(geoshards);
+        let shard = searcher.get_shard_from_cell_id(&cell_ids[90]);
+        assert_eq!(shard.hottest_cells(1)[0].score, 10);
+        assert_eq!(shard.hottest_cells(1)[0].shard, shard.name());
+    }
 
-            cell_list
-        }
+    #[test]
+    fn test_shard_stats() {
+        let users = FakeUser::seeded(1000, 29, &RandCityFactory::default());
+        let geoshards = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let total_score = geoshards.total_score();
+        assert_eq!(total_score, 1000);
+        let shards = geoshards.shards();
+        assert_eq!(
+            shards.iter().map(|shard| shard.score() as i64).sum::<i64>(),
+            total_score
+        );
+        let fractions: f64 = shards
+            .iter()
+            .map(|shard| shard.score_fraction_of_total(total_score))
+            .sum();
+        assert!((fractions - 1.0).abs() < 1e-9);
+        assert_eq!(shards[0].score_fraction_of_total(0), 0.0);
+
+        // the shards cover the earth, about 4 pi r^2
+        let area: f64 = shards.iter().map(Geoshard::approx_area_km2).sum();
+        let earth_area = 4.0 * std::f64::consts::PI * 6370.0 * 6370.0;
+        assert!((area - earth_area).abs() / earth_area < 0.01);
+        assert!(shards.iter().all(|shard| shard.approx_area_km2() > 0.0));
     }
 
     #[test]
@@ -867,6 +5057,9 @@ pub mod test {
         let geoshard_collection = GeoshardCollection {
             shards,
             storage_level: 4,
+            fallback: None,
+            score_window: None,
+            neighbors: OnceLock::new(),
         };
 
         let standard_dev = geoshard_collection.standard_deviation();
@@ -0,0 +1,351 @@
+#![deny(missing_docs)]
+//! http serves the shard lookup service over HTTP behind the `http` feature, so the crate can
+//! run as a routing sidecar on its own. `HttpRouter` maps requests to responses, and `serve`
+//! runs it on a plain blocking HTTP/1.1 listener
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    error::GeoshardError,
+    generation::SharedGeoshardSearcher,
+    server::{LookupRequest, RadiusLookupRequest, ShardLookupService},
+};
+
+/// `HttpResponse` is the status, content type and body of a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    /// HTTP status code
+    pub status: u16,
+    /// value of the `Content-Type` header
+    pub content_type: &'static str,
+    /// the response body
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// responds with the body serialized as JSON
+    fn json<T: Serialize>(status: u16, body: &T) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(body).unwrap_or_default(),
+        }
+    }
+
+    /// responds with the error, with a status matching its kind
+    fn error(error: &GeoshardError) -> Self {
+        let status = match error {
+            GeoshardError::InvalidRequest { .. } => 400,
+            GeoshardError::UnmappedCell { .. } => 404,
+            _ => 500,
+        };
+        Self::json(status, error)
+    }
+}
+
+/// `HttpRouter` serves the shard lookup service over HTTP:
+///
+/// - `GET /shard?lat=&lng=`, the shard holding the location
+/// - `GET /shards/radius?lat=&lng=&radius=`, the shards within `radius` meters of the location
+/// - `GET /shards`, the whole shard map as JSON
+/// - `GET /metrics`, request counters and the current map, in the Prometheus text format
+///
+/// Lookups respond with the JSON of the `server` module's responses, and errors with the JSON of
+/// the `GeoshardError`
+#[derive(Debug)]
+pub struct HttpRouter {
+    service: ShardLookupService,
+    searcher: SharedGeoshardSearcher,
+    requests: BTreeMap<&'static str, AtomicU64>,
+    errors: AtomicU64,
+}
+
+impl HttpRouter {
+    /// Constructs a new `HttpRouter` answering from the searcher. Pass a
+    /// `SharedGeoshardSearcher` to keep serving new generations as they are swapped in
+    pub fn new(searcher: impl Into<SharedGeoshardSearcher>) -> Self {
+        let searcher = searcher.into();
+        Self {
+            service: ShardLookupService::new(searcher.clone()),
+            searcher,
+            requests: ["/shard", "/shards/radius", "/shards", "/metrics"]
+                .into_iter()
+                .map(|route| (route, AtomicU64::new(0)))
+                .collect(),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    /// responds to a request for `target` (the path and query string)
+    pub fn handle(&self, method: &str, target: &str) -> HttpResponse {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let Some(requests) = self.requests.get(path) else {
+            return self.not_found(path);
+        };
+        if method != "GET" {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            return HttpResponse {
+                status: 405,
+                content_type: "text/plain",
+                body: format!("{} is not allowed", method),
+            };
+        }
+        requests.fetch_add(1, Ordering::Relaxed);
+
+        let params = Params::parse(query);
+        let response = match path {
+            "/shard" => params.location().and_then(|(lat, lng)| {
+                let response = self.service.lookup(&LookupRequest { lat, lng })?;
+                Ok(HttpResponse::json(200, &response))
+            }),
+            "/shards/radius" => params.location().and_then(|(lat, lng)| {
+                let radius = params.get("radius")?;
+                let response =
+                    self.service
+                        .radius_lookup(&RadiusLookupRequest { lat, lng, radius })?;
+                Ok(HttpResponse::json(200, &response))
+            }),
+            "/shards" => {
+                let searcher = self.searcher.load();
+                Ok(HttpResponse::json(200, searcher.shards()))
+            }
+            _ => Ok(self.metrics()),
+        };
+        response.unwrap_or_else(|error| {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            HttpResponse::error(&error)
+        })
+    }
+
+    /// responds to a path without a route
+    fn not_found(&self, path: &str) -> HttpResponse {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        HttpResponse {
+            status: 404,
+            content_type: "text/plain",
+            body: format!("no route for {}", path),
+        }
+    }
+
+    /// renders the request counters and current map in the Prometheus text format
+    fn metrics(&self) -> HttpResponse {
+        let (generation, searcher) = self.searcher.load_generation();
+        let mut body = String::from("# TYPE geoshard_http_requests_total counter\n");
+        for (route, count) in self.requests.iter() {
+            body.push_str(&format!(
+                "geoshard_http_requests_total{{route=\"{}\"}} {}\n",
+                route,
+                count.load(Ordering::Relaxed)
+            ));
+        }
+        body.push_str(&format!(
+            "# TYPE geoshard_http_errors_total counter\ngeoshard_http_errors_total {}\n",
+            self.errors.load(Ordering::Relaxed)
+        ));
+        body.push_str(&format!(
+            "# TYPE geoshard_map_generation gauge\ngeoshard_map_generation {}\n",
+            generation
+        ));
+        body.push_str(&format!(
+            "# TYPE geoshard_map_shards gauge\ngeoshard_map_shards {}\n",
+            searcher.shards().shards().len()
+        ));
+        body.push_str(&format!(
+            "# TYPE geoshard_map_degraded gauge\ngeoshard_map_degraded {}\n",
+            searcher.is_degraded() as u8
+        ));
+        HttpResponse {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+}
+
+/// the parameters of a query string
+struct Params<'a>(BTreeMap<&'a str, &'a str>);
+
+impl<'a> Params<'a> {
+    fn parse(query: &'a str) -> Self {
+        Params(
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .collect(),
+        )
+    }
+
+    /// returns the parsed value of the named parameter
+    fn get<T: std::str::FromStr>(&self, name: &str) -> Result<T, GeoshardError> {
+        let value = self
+            .0
+            .get(name)
+            .ok_or_else(|| GeoshardError::InvalidRequest {
+                reason: format!("missing parameter {}", name),
+            })?;
+        value.parse().map_err(|_| GeoshardError::InvalidRequest {
+            reason: format!("invalid parameter {}={}", name, value),
+        })
+    }
+
+    /// returns the `lat` and `lng` parameters
+    fn location(&self) -> Result<(f64, f64), GeoshardError> {
+        Ok((self.get("lat")?, self.get("lng")?))
+    }
+}
+
+/// how long `serve` waits on a connection's reads and writes before dropping it, so idle or
+/// stalled clients don't hold their threads
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the router on the listener, one thread per connection, until accepting a connection
+/// fails. Each connection serves a single request, and request bodies are ignored. Connections
+/// are dropped after `CONNECTION_TIMEOUT` without progress
+pub fn serve(router: Arc<HttpRouter>, listener: TcpListener) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept()?;
+        let router = router.clone();
+        thread::spawn(move || {
+            // the client may hang up part way through, which only concerns this connection
+            let _ = respond(&router, stream);
+        });
+    }
+}
+
+/// reads a request from the stream and writes the router's response to it
+fn respond(router: &HttpRouter, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => router.handle(method, target),
+        _ => HttpResponse {
+            status: 400,
+            content_type: "text/plain",
+            body: "malformed request".to_owned(),
+        },
+    };
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use std::io::Read;
+
+    use super::*;
+    use crate::{geoshard::GeoshardSearcher, testing::uniform_collection};
+
+    fn searcher() -> Arc<GeoshardSearcher> {
+        Arc::new(GeoshardSearcher::from(uniform_collection(4, 64)))
+    }
+
+    #[test]
+    fn test_http_router() {
+        let router = HttpRouter::new(searcher());
+
+        let response = router.handle("GET", "/shard?lat=40.7128&lng=-74.0060");
+        assert_eq!(response.status, 200);
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert!(body["shard"]
+            .as_str()
+            .unwrap()
+            .starts_with("geoshard_user_index_"));
+        assert_eq!(body["generation"], 0);
+
+        let new_york = body["shard"].as_str().unwrap().to_owned();
+        let response = router.handle("GET", "/shard?lat=51.5074&lng=-0.1278");
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        let london = body["shard"].as_str().unwrap().to_owned();
+        assert_ne!(london, new_york);
+        let response = router.handle("GET", "/shards/radius?lat=40.7128&lng=-74.0060&radius=5000");
+        assert_eq!(response.status, 200);
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        let shards: Vec<&str> = body["shards"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|shard| shard.as_str().unwrap())
+            .collect();
+        assert!(shards.contains(&new_york.as_str()));
+        assert!(!shards.contains(&london.as_str()));
+        let response = router.handle(
+            "GET",
+            "/shards/radius?lat=40.7128&lng=-74.0060&radius=20000000",
+        );
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert!(body["shards"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::Value::from(london)));
+        assert_eq!(router.handle("GET", "/shards").status, 200);
+
+        let response = router.handle("GET", "/shard?lat=91&lng=0");
+        assert_eq!(response.status, 400);
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(body["kind"], "invalid_request");
+        assert_eq!(router.handle("GET", "/shard?lat=40").status, 400);
+        assert_eq!(router.handle("POST", "/shards").status, 405);
+        assert_eq!(router.handle("GET", "/nowhere").status, 404);
+
+        let metrics = router.handle("GET", "/metrics").body;
+        assert!(metrics.contains("geoshard_http_requests_total{route=\"/shard\"} 4\n"));
+        assert!(metrics.contains("geoshard_http_errors_total 4\n"));
+        assert!(metrics.contains("geoshard_map_shards 24\n"));
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = Arc::new(HttpRouter::new(searcher()));
+        thread::spawn(move || serve(router, listener));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET /shard?lat=51.5074&lng=-0.1278 HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\"generation\":0"));
+    }
+}
@@ -1,8 +1,105 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+extern crate alloc;
+
+#[cfg(feature = "builder")]
+pub mod anonymize;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "searcher")]
+mod cache;
+#[cfg(feature = "builder")]
 pub mod cell_list;
+#[cfg(feature = "builder")]
+pub mod codegen;
+#[cfg(feature = "std")]
+pub mod compact;
+#[cfg(any(all(test, feature = "std"), feature = "datagen"))]
+pub mod datagen;
+#[cfg(feature = "std")]
+pub mod deployment;
+#[cfg(feature = "searcher")]
+pub mod diurnal;
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod fallback;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "searcher")]
+pub mod flat;
+#[cfg(feature = "searcher")]
+pub mod generation;
+#[cfg(feature = "builder")]
+pub mod geocoding;
+#[cfg(feature = "searcher")]
+pub mod geofence;
+#[cfg(feature = "std")]
+pub mod geohash;
+#[cfg(feature = "std")]
 pub mod geoshard;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod lookup;
+#[cfg(feature = "builder")]
+pub mod migration;
+#[cfg(feature = "std")]
+pub mod pagination;
+#[cfg(feature = "builder")]
+pub mod partitioning;
+#[cfg(feature = "builder")]
+pub mod placement;
+#[cfg(feature = "std")]
+pub mod point;
+#[cfg(feature = "std")]
+pub mod polygon;
+#[cfg(feature = "builder")]
+pub mod preset;
+#[cfg(feature = "publish")]
+pub mod publish;
+#[cfg(feature = "searcher")]
+pub mod query;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "builder")]
+pub mod report;
+#[cfg(feature = "builder")]
+pub mod reshard;
+#[cfg(feature = "searcher")]
+pub mod router;
+#[cfg(feature = "builder")]
+pub mod scaling;
+#[cfg(feature = "searcher")]
+pub mod scatter;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "searcher")]
+pub mod shadow;
+#[cfg(feature = "signing")]
+pub mod signing;
+#[cfg(feature = "builder")]
+pub mod simulation;
+#[cfg(feature = "builder")]
+pub mod strategy;
+#[cfg(feature = "searcher")]
+pub mod subscriber;
+#[cfg(feature = "std")]
+pub mod tenant;
+#[cfg(any(all(test, feature = "std"), feature = "test-util"))]
+pub mod testing;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(feature = "builder")]
+pub mod tracking;
+#[cfg(feature = "std")]
 pub mod users;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+#[cfg(feature = "std")]
 pub mod utils {
+    #[cfg(any(test, feature = "searcher", feature = "test-util"))]
     macro_rules! ll {
         ($lng:expr, $lat:expr) => {
             s2::latlng::LatLng {
@@ -12,14 +109,34 @@ pub mod utils {
         };
     }
 
+    #[cfg(any(test, feature = "searcher", feature = "test-util"))]
     pub(crate) use ll;
+
+    /// hashes the given parts with FNV-1a followed by a splitmix64 finalizer. Unlike `std`'s
+    /// hashers, the result is stable across processes, platforms and Rust versions
+    #[cfg(feature = "searcher")]
+    pub(crate) fn stable_hash(parts: &[&[u8]]) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for part in parts {
+            for byte in part.iter().chain(std::iter::once(&0xff)) {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^ (hash >> 31)
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "builder"))]
 mod test {
     use std::{collections::HashMap, fs::File, io::Write};
 
-    use crate::geoshard::{test::FakeUser, GeoshardBuilder, GeoshardCollection, GeoshardSearcher};
+    use crate::{
+        geoshard::{GeoshardBuilder, GeoshardCollection, GeoshardSearcher},
+        testing::FakeUser,
+    };
 
     #[test]
     fn test_geoshard_searcher() {
@@ -51,7 +168,7 @@ mod test {
         let json_shards = serde_json::to_string(shards).unwrap();
         let mut shard_file = File::create("shard.json").expect("could not create shard file");
         shard_file
-            .write_all(&json_shards.as_bytes())
+            .write_all(json_shards.as_bytes())
             .expect("could not write json shards");
 
         let parsed_shards: GeoshardCollection = serde_json::from_str(&json_shards).unwrap();
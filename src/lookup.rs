@@ -0,0 +1,259 @@
+#![deny(missing_docs)]
+#![deny(
+    clippy::std_instead_of_core,
+    clippy::std_instead_of_alloc,
+    clippy::alloc_instead_of_core
+)]
+//! lookup contains the core of shard lookups, sorted ranges of leaf cells searched by binary
+//! search, using only `core` and `alloc` so it builds without `std` (the lints above keep it
+//! that way): with `default-features = false` the crate is `no_std` and holds only this
+//! module. Embedded gateways can route with a `RangeTable` and the raw 64 bit IDs of S2
+//! cells, computed by the client or with any S2 implementation, as converting locations to cells
+//! needs floating point functions `core` doesn't have. `GeoshardCollection::range_table` builds
+//! the table of a map, and `FlatGeoshardSearcher` searches the same ranges in place
+use alloc::vec::Vec;
+use core::fmt;
+
+/// level of leaf cells, the finest level of S2 cells
+const MAX_LEVEL: u64 = 30;
+
+/// returns the level of the cell with the raw ID `cell_id`, from 0 for faces to 30 for leaves
+pub fn cell_level(cell_id: u64) -> u64 {
+    MAX_LEVEL - cell_id.trailing_zeros() as u64 / 2
+}
+
+/// returns the raw ID of the ancestor of the cell at `level`, which must be at or above the
+/// cell's level
+pub fn cell_parent(cell_id: u64, level: u64) -> u64 {
+    let lsb = 1u64 << (2 * (MAX_LEVEL - level));
+    (cell_id & lsb.wrapping_neg()) | lsb
+}
+
+/// returns the first and last leaf cells within the cell, as raw IDs
+pub fn leaf_range(cell_id: u64) -> (u64, u64) {
+    let lsb = cell_id & cell_id.wrapping_neg();
+    (cell_id - (lsb - 1), cell_id + (lsb - 1))
+}
+
+/// `CellRange` routes the leaf cells from `first` to `last`, inclusive, to the shard at index
+/// `shard`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CellRange {
+    /// raw ID of the first leaf cell in the range
+    pub first: u64,
+    /// raw ID of the last leaf cell in the range
+    pub last: u64,
+    /// index of the shard holding the range
+    pub shard: u32,
+}
+
+/// `OverlappingRanges` is the error for ranges routing the same leaf cell to several shards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverlappingRanges {
+    /// raw ID of the first leaf cell in both ranges
+    pub leaf: u64,
+}
+
+impl fmt::Display for OverlappingRanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ranges overlap at leaf cell {:016x}", self.leaf)
+    }
+}
+
+/// Returns the shard of the cell with the raw ID `cell_id` among `range_count` ranges sorted by
+/// their first leaf, read with `range`, or `None` if no single range holds the whole cell. This
+/// is the search of both `RangeTable` and the in place lookups of flat maps
+pub fn find_shard<F>(range_count: usize, range: F, cell_id: u64) -> Option<u32>
+where
+    F: Fn(usize) -> CellRange,
+{
+    let (first, last) = leaf_range(cell_id);
+    // the first range starting after the cell's first leaf, so the range before it is the
+    // candidate
+    let (mut low, mut high) = (0, range_count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if range(middle).first <= first {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    let candidate = range(low.checked_sub(1)?);
+    (last <= candidate.last).then_some(candidate.shard)
+}
+
+/// `RangeTable` routes cells to shards with sorted, non overlapping ranges of leaf cells.
+/// Contiguous ranges of the same shard are merged, so lookups take logarithmic time in the
+/// number of shard boundaries rather than cells
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangeTable {
+    ranges: Vec<CellRange>,
+}
+
+impl RangeTable {
+    /// Constructs a new `RangeTable` from ranges in any order. Fails with `OverlappingRanges` if
+    /// two ranges share a leaf cell
+    pub fn new(mut ranges: Vec<CellRange>) -> Result<Self, OverlappingRanges> {
+        ranges.sort_unstable();
+        let mut merged: Vec<CellRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(previous) if previous.last >= range.first => {
+                    return Err(OverlappingRanges { leaf: range.first })
+                }
+                Some(previous)
+                    if previous.shard == range.shard && previous.last + 2 == range.first =>
+                {
+                    previous.last = range.last
+                }
+                _ => merged.push(range),
+            }
+        }
+        Ok(Self { ranges: merged })
+    }
+
+    /// returns the ranges, sorted by their first leaf
+    pub fn ranges(&self) -> &[CellRange] {
+        &self.ranges
+    }
+
+    /// returns the index of the shard holding the cell with the raw ID `cell_id`, at any level,
+    /// or `None` if no shard holds all of it
+    pub fn shard_for_cell(&self, cell_id: u64) -> Option<u32> {
+        find_shard(self.ranges.len(), |index| self.ranges[index], cell_id)
+    }
+
+    /// returns the indexes of the shards holding part of the cell with the raw ID `cell_id`, in
+    /// leaf order and each once per contiguous range, e.g. for cells coarser than the map's
+    pub fn shards_for_cell(&self, cell_id: u64) -> impl Iterator<Item = u32> + '_ {
+        let (first, last) = leaf_range(cell_id);
+        let start = self.ranges.partition_point(|range| range.last < first);
+        self.ranges[start..]
+            .iter()
+            .take_while(move |range| range.first <= last)
+            .map(|range| range.shard)
+    }
+
+    /// returns the index of the shard holding the range nearest the cell with the raw ID
+    /// `cell_id` in leaf order, or `None` if there are no ranges, e.g. to route cells no shard
+    /// holds
+    pub fn nearest_shard(&self, cell_id: u64) -> Option<u32> {
+        let after = self.ranges.partition_point(|range| range.last < cell_id);
+        let distance = |range: &CellRange| match cell_id {
+            id if id < range.first => range.first - id,
+            id if id > range.last => id - range.last,
+            _ => 0,
+        };
+        self.ranges[after.saturating_sub(1)..self.ranges.len().min(after + 1)]
+            .iter()
+            .min_by_key(|range| distance(range))
+            .map(|range| range.shard)
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use s2::cellid::CellID;
+
+    use super::*;
+    use crate::{
+        geoshard::GeoshardSearcher,
+        testing::{FakeUser, RandCityFactory},
+        users::User,
+        utils::ll,
+    };
+
+    #[test]
+    fn test_range_table() {
+        let cell_id = CellID::from(ll!(-74.0060, 40.7128));
+        for level in [0, 7, 30] {
+            let parent = cell_id.parent(level);
+            assert_eq!(cell_level(parent.0), level);
+            assert_eq!(cell_parent(cell_id.0, level), parent.0);
+            assert_eq!(
+                leaf_range(parent.0),
+                (parent.range_min().0, parent.range_max().0)
+            );
+        }
+
+        let users = FakeUser::seeded(1000, 37, &RandCityFactory::default());
+        let shards = crate::geoshard::GeoshardBuilder::user_count_scorer(6, users.iter(), 10, 20)
+            .with_cached_cells()
+            .build();
+        let table = shards.range_table();
+        let cell_count: usize = shards.shards().iter().map(|shard| shard.cell_count()).sum();
+        assert!(table.ranges().len() < cell_count);
+        let searcher = GeoshardSearcher::from(shards);
+        let names: Vec<&str> = searcher
+            .shards()
+            .shards()
+            .iter()
+            .map(|shard| shard.name())
+            .collect();
+        for user in users.iter().step_by(7) {
+            let cell_id = CellID::from(user.location());
+            let shard = table.shard_for_cell(cell_id.0).unwrap();
+            assert_eq!(
+                names[shard as usize],
+                searcher.get_shard_from_cell_id(&cell_id).name()
+            );
+        }
+
+        // a face spans several shards, which are all listed
+        let face = (0..6)
+            .map(CellID::from_face)
+            .find(|face| searcher.get_shards_from_cell_id(face).len() > 1)
+            .unwrap();
+        assert_eq!(table.shard_for_cell(face.0), None);
+        let mut spanning: Vec<&str> = table
+            .shards_for_cell(face.0)
+            .map(|shard| names[shard as usize])
+            .collect();
+        spanning.sort();
+        spanning.dedup();
+        let mut expected: Vec<&str> = searcher
+            .get_shards_from_cell_id(&face)
+            .iter()
+            .map(|shard| shard.name())
+            .collect();
+        expected.sort();
+        assert_eq!(spanning, expected);
+
+        let overlapping = vec![
+            CellRange {
+                first: 1,
+                last: 9,
+                shard: 0,
+            },
+            CellRange {
+                first: 5,
+                last: 11,
+                shard: 1,
+            },
+        ];
+        assert_eq!(
+            RangeTable::new(overlapping),
+            Err(OverlappingRanges { leaf: 5 })
+        );
+
+        let gapped = RangeTable::new(vec![
+            CellRange {
+                first: 11,
+                last: 19,
+                shard: 0,
+            },
+            CellRange {
+                first: 41,
+                last: 49,
+                shard: 1,
+            },
+        ])
+        .unwrap();
+        assert_eq!(gapped.shard_for_cell(25), None);
+        for (cell_id, nearest) in [(1, 0), (15, 0), (25, 0), (35, 1), (45, 1), (99, 1)] {
+            assert_eq!(gapped.nearest_shard(cell_id), Some(nearest));
+        }
+        assert_eq!(RangeTable::default().nearest_shard(25), None);
+    }
+}
@@ -0,0 +1,235 @@
+#![deny(missing_docs)]
+//! migration estimates the cost of moving from one shard map to another, and plans the
+//! moves as contiguous ranges of cells, so rebuilds that move too much data can be rejected
+use std::{collections::BTreeMap, fmt};
+
+use s2::cellid::CellID;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{error::GeoshardError, geoshard::GeoshardCollection};
+
+/// `CellMove` moves a contiguous range of cells, from `start_token` to `end_token` inclusive,
+/// from one shard to another
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CellMove {
+    /// name of the shard the cells are in, in the old map
+    pub from: String,
+    /// name of the shard the cells are in, in the new map
+    pub to: String,
+    /// token of the first cell in the range
+    pub start_token: String,
+    /// token of the last cell in the range
+    pub end_token: String,
+    /// number of cells in the range
+    pub cell_count: usize,
+    /// total score of the cells in the range, i.e. the users/documents to move
+    pub score: i64,
+}
+
+/// `MigrationPlan` lists the cell ranges that change shard between two shard maps, in cell order
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MigrationPlan {
+    moves: Vec<CellMove>,
+}
+
+impl MigrationPlan {
+    /// Plans the migration from the `old` to the `new` map, costing each moved cell with its
+    /// score in `cell_scores` (cells without a score cost nothing). Cells that are not in the old
+    /// map have nothing to move. Both maps must have the same storage level
+    pub fn new(
+        old: &GeoshardCollection,
+        new: &GeoshardCollection,
+        cell_scores: &BTreeMap<CellID, i32>,
+    ) -> Result<Self, GeoshardError> {
+        if old.storage_level() != new.storage_level() {
+            return Err(GeoshardError::InvalidShardMap {
+                reason: format!(
+                    "can't migrate from level {} to level {}",
+                    old.storage_level(),
+                    new.storage_level()
+                ),
+                shard: None,
+                cell: None,
+            });
+        }
+
+        let old_shards: BTreeMap<CellID, &str> = old
+            .shards()
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .cell_union()
+                    .0
+                    .iter()
+                    .map(move |cell_id| (*cell_id, shard.name()))
+            })
+            .collect();
+        let new_shards: BTreeMap<CellID, &str> = new
+            .shards()
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .cell_union()
+                    .0
+                    .iter()
+                    .map(move |cell_id| (*cell_id, shard.name()))
+            })
+            .collect();
+
+        let mut moves: Vec<CellMove> = vec![];
+        let mut previous: Option<CellID> = None;
+        for (cell_id, to) in new_shards {
+            let from = match old_shards.get(&cell_id) {
+                Some(from) if *from != to => *from,
+                _ => continue,
+            };
+            let score = cell_scores.get(&cell_id).copied().unwrap_or(0) as i64;
+            match moves.last_mut() {
+                Some(last)
+                    if last.from == from
+                        && last.to == to
+                        && previous.is_some_and(|previous| previous.next() == cell_id) =>
+                {
+                    last.end_token = cell_id.to_token();
+                    last.cell_count += 1;
+                    last.score += score;
+                }
+                _ => moves.push(CellMove {
+                    from: from.to_owned(),
+                    to: to.to_owned(),
+                    start_token: cell_id.to_token(),
+                    end_token: cell_id.to_token(),
+                    cell_count: 1,
+                    score,
+                }),
+            }
+            previous = Some(cell_id);
+        }
+        Ok(Self { moves })
+    }
+
+    /// returns the moves, in cell order
+    pub fn moves(&self) -> &[CellMove] {
+        &self.moves
+    }
+
+    /// returns the total score of the cells that change shard
+    pub fn cost(&self) -> i64 {
+        self.moves.iter().map(|cell_move| cell_move.score).sum()
+    }
+
+    /// returns the number of cells that change shard
+    pub fn moved_cells(&self) -> usize {
+        self.moves
+            .iter()
+            .map(|cell_move| cell_move.cell_count)
+            .sum()
+    }
+
+    /// returns true if the migration moves at most `budget` score
+    pub fn within_budget(&self, budget: i64) -> bool {
+        self.cost() <= budget
+    }
+}
+
+/// `ReshardComparison` summarizes what rolling out a new map in place of the current one would
+/// cost, to review before approving a reshard. It displays as a one line summary, e.g. `this
+/// reshard moves 12.3% of users across 14 shard boundary changes (8 -> 9 shards)`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReshardComparison {
+    /// the moves from the current map to the new one
+    pub plan: MigrationPlan,
+    /// total score of the new map's cells, i.e. all the users/documents
+    pub total_score: i64,
+    /// number of shards in the current map
+    pub current_shard_count: usize,
+    /// number of shards in the new map
+    pub new_shard_count: usize,
+    /// standard deviation between the current map's shards' scores
+    pub current_standard_deviation: f64,
+    /// standard deviation between the new map's shards' scores
+    pub new_standard_deviation: f64,
+}
+
+impl ReshardComparison {
+    /// Compares the `current` map with the `new` one, costing moves with `cell_scores`. Both maps
+    /// must have the same storage level
+    pub fn new(
+        current: &GeoshardCollection,
+        new: &GeoshardCollection,
+        cell_scores: &BTreeMap<CellID, i32>,
+    ) -> Result<Self, GeoshardError> {
+        Ok(Self {
+            plan: MigrationPlan::new(current, new, cell_scores)?,
+            total_score: cell_scores.values().map(|score| *score as i64).sum(),
+            current_shard_count: current.shards().len(),
+            new_shard_count: new.shards().len(),
+            current_standard_deviation: current.standard_deviation(),
+            new_standard_deviation: new.standard_deviation(),
+        })
+    }
+
+    /// returns the fraction of the total score that changes shard, from 0 to 1
+    pub fn moved_fraction(&self) -> f64 {
+        match self.total_score {
+            0 => 0.0,
+            total_score => self.plan.cost() as f64 / total_score as f64,
+        }
+    }
+
+    /// returns the number of contiguous ranges of cells that change shard
+    pub fn boundary_changes(&self) -> usize {
+        self.plan.moves().len()
+    }
+}
+
+impl fmt::Display for ReshardComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "this reshard moves {:.1}% of users across {} shard boundary changes ({} -> {} shards)",
+            self.moved_fraction() * 100.0,
+            self.boundary_changes(),
+            self.current_shard_count,
+            self.new_shard_count
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cell_list::CellList, geoshard::GeoshardSearcher};
+
+    #[test]
+    fn test_migration_plan() {
+        let mut cell_list = CellList::new(3);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 5) as i32;
+        }
+        let old = GeoshardCollection::new(100, cell_list.cell_list(), 3);
+        let new = GeoshardCollection::new(120, cell_list.cell_list(), 3);
+
+        let unchanged = MigrationPlan::new(&old, &old, cell_list.cell_list()).unwrap();
+        assert!(unchanged.moves().is_empty());
+        assert_eq!(unchanged.cost(), 0);
+
+        let plan = MigrationPlan::new(&old, &new, cell_list.cell_list()).unwrap();
+        assert!(!plan.moves().is_empty());
+        let old = GeoshardSearcher::from(old);
+        let mut expected_cost = 0;
+        let mut expected_cells = 0;
+        for shard in new.shards() {
+            for cell_id in shard.cell_union().0.iter() {
+                if old.get_shard_from_cell_id(cell_id).name() != shard.name() {
+                    expected_cost += cell_list.cell_list()[cell_id] as i64;
+                    expected_cells += 1;
+                }
+            }
+        }
+        assert_eq!(plan.cost(), expected_cost);
+        assert_eq!(plan.moved_cells(), expected_cells);
+        assert!(plan.within_budget(expected_cost));
+        assert!(!plan.within_budget(expected_cost - 1));
+    }
+}
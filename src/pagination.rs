@@ -0,0 +1,182 @@
+#![deny(missing_docs)]
+//! pagination encodes where a scan across several shards stopped (such as over the shards from
+//! `get_shards_from_radius`) into an opaque continuation token, so the next page of the scan can
+//! pick up where the last one left off
+use s2::cellid::CellID;
+
+use crate::{error::GeoshardError, geoshard::Geoshard};
+
+/// version of the continuation token format
+const PAGE_TOKEN_VERSION: &str = "1";
+
+/// `PageToken` is the position of a multi-shard scan: the shard being scanned, the last cell
+/// read from it, and how many results of that cell were already returned. Tokens are opaque to
+/// clients but not signed, so a tampered token can only resume the scan somewhere else
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageToken {
+    shard: String,
+    cell: Option<CellID>,
+    offset: u64,
+}
+
+impl PageToken {
+    /// Constructs a new `PageToken` resuming at `offset` results into `cell` of `shard`. Without
+    /// a cell, the scan resumes from the start of the shard
+    pub fn new(shard: impl Into<String>, cell: Option<CellID>, offset: u64) -> Self {
+        Self {
+            shard: shard.into(),
+            cell,
+            offset,
+        }
+    }
+
+    /// returns the name of the shard the scan stopped in
+    pub fn shard(&self) -> &str {
+        &self.shard
+    }
+
+    /// returns the last cell read, if any
+    pub fn cell(&self) -> Option<CellID> {
+        self.cell
+    }
+
+    /// returns the number of results of the last cell already returned
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// encodes the token as an opaque, URL safe string
+    pub fn encode(&self) -> String {
+        let cell = self
+            .cell
+            .map(|cell_id| cell_id.to_token())
+            .unwrap_or_default();
+        format!(
+            "{}\n{}\n{}\n{}",
+            PAGE_TOKEN_VERSION, self.shard, cell, self.offset
+        )
+        .bytes()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+    }
+
+    /// Decodes a token produced by `encode`. Fails with `GeoshardError::InvalidPageToken` if the
+    /// token is malformed or from another version of the format
+    pub fn decode(token: &str) -> Result<Self, GeoshardError> {
+        let invalid = |reason: &str| GeoshardError::InvalidPageToken {
+            reason: reason.to_owned(),
+        };
+        if !token.len().is_multiple_of(2) || !token.is_ascii() {
+            return Err(invalid("not a page token"));
+        }
+        let bytes = (0..token.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&token[index..index + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid("not a page token"))?;
+        let decoded = String::from_utf8(bytes).map_err(|_| invalid("not a page token"))?;
+
+        // the shard name comes between the version and the last two fields, so it may hold any
+        // character
+        let (version, rest) = decoded
+            .split_once('\n')
+            .ok_or_else(|| invalid("not a page token"))?;
+        if version != PAGE_TOKEN_VERSION {
+            return Err(invalid("unsupported page token version"));
+        }
+        let mut fields = rest.rsplitn(3, '\n');
+        let (offset, cell, shard) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(offset), Some(cell), Some(shard)) => (offset, cell, shard),
+            _ => return Err(invalid("not a page token")),
+        };
+        let offset = offset.parse().map_err(|_| invalid("invalid offset"))?;
+        let cell = if cell.is_empty() {
+            None
+        } else {
+            let cell_id = CellID::from_token(cell);
+            if !cell_id.is_valid() {
+                return Err(invalid("invalid cell"));
+            }
+            Some(cell_id)
+        };
+        Ok(Self::new(shard, cell, offset))
+    }
+
+    /// Returns the shards left to scan, starting with the token's shard, out of the same
+    /// `shards` (in the same order) the scan started with. Fails with
+    /// `GeoshardError::InvalidPageToken` if the token's shard is not among them, e.g. because
+    /// the shard map changed between pages
+    pub fn remaining<'a>(
+        &self,
+        shards: &'a [&'a Geoshard],
+    ) -> Result<&'a [&'a Geoshard], GeoshardError> {
+        let index = shards
+            .iter()
+            .position(|shard| shard.name() == self.shard)
+            .ok_or_else(|| GeoshardError::InvalidPageToken {
+                reason: format!("shard {} is not being scanned", self.shard),
+            })?;
+        Ok(&shards[index..])
+    }
+
+    /// returns the cells of the token's shard left to scan, starting with the token's cell (of
+    /// which `offset` results should be skipped). Other shards are scanned from their first cell
+    pub fn remaining_cells<'a>(&self, shard: &'a Geoshard) -> &'a [CellID] {
+        let cells = &shard.cell_union().0;
+        match self.cell {
+            Some(cell_id) if shard.name() == self.shard => {
+                &cells[cells.partition_point(|other| *other < cell_id)..]
+            }
+            _ => cells,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use s2::cellunion::CellUnion;
+
+    use super::*;
+
+    fn shard(name: &str, face: u8) -> Geoshard {
+        let face_cell = CellID::from_face(face as u64);
+        let cells = std::iter::successors(Some(face_cell.child_begin_at_level(2)), |cell_id| {
+            Some(cell_id.next())
+        })
+        .take(16)
+        .collect();
+        Geoshard::new(name.to_owned(), 0, 2, CellUnion(cells))
+    }
+
+    #[test]
+    fn test_page_token() {
+        let cell_id = CellID::from_face(1).child_begin_at_level(2).next().next();
+        let token = PageToken::new("geoshard_user_index_2\nwith a newline", Some(cell_id), 40);
+        let encoded = token.encode();
+        assert!(encoded.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(PageToken::decode(&encoded).unwrap(), token);
+        let start = PageToken::new("geoshard_user_index_1", None, 0);
+        assert_eq!(PageToken::decode(&start.encode()).unwrap(), start);
+
+        assert!(matches!(
+            PageToken::decode("not a token"),
+            Err(GeoshardError::InvalidPageToken { .. })
+        ));
+        assert!(PageToken::decode(&encoded.replacen("31", "32", 1)).is_err());
+
+        let (first, second) = (
+            shard("geoshard_user_index_1", 0),
+            shard("geoshard_user_index_2", 1),
+        );
+        let shards = [&first, &second];
+        let token = PageToken::new("geoshard_user_index_2", Some(cell_id), 40);
+        let remaining = token.remaining(&shards).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(token.remaining_cells(remaining[0])[0], cell_id);
+        assert_eq!(token.remaining_cells(remaining[0]).len(), 14);
+        assert_eq!(token.remaining_cells(&first).len(), 16);
+        assert!(PageToken::new("geoshard_user_index_3", None, 0)
+            .remaining(&shards)
+            .is_err());
+    }
+}
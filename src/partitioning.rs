@@ -0,0 +1,282 @@
+#![deny(missing_docs)]
+//! partitioning maps shards onto a fixed number of partitions, such as the partitions of a
+//! Kafka topic that location events are produced into, balancing the score on each partition.
+//! It also maps shards onto contiguous ranges of a fixed slot space, like Redis Cluster hash slots
+use std::{collections::BTreeMap, ops::Range};
+
+use s2::cellid::CellID;
+#[cfg(feature = "searcher")]
+use s2::latlng::LatLng;
+use serde_derive::{Deserialize, Serialize};
+
+#[cfg(feature = "builder")]
+use crate::geoshard::GeoshardCollection;
+#[cfg(feature = "searcher")]
+use crate::geoshard::GeoshardSearcher;
+
+/// `PartitionMap` assigns every shard in a collection to one of `num_partitions` partitions
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PartitionMap {
+    num_partitions: u32,
+    assignments: BTreeMap<String, u32>,
+    partition_scores: Vec<i64>,
+}
+
+impl PartitionMap {
+    /// returns the number of partitions shards are assigned to
+    pub fn num_partitions(&self) -> u32 {
+        self.num_partitions
+    }
+
+    /// returns the partition for the shard with the given name
+    pub fn partition_for_shard(&self, shard_name: &str) -> Option<u32> {
+        self.assignments.get(shard_name).copied()
+    }
+
+    /// returns the partition for the shard the location is in, as found by the `searcher`
+    #[cfg(feature = "searcher")]
+    pub fn partition_for_location(
+        &self,
+        searcher: &GeoshardSearcher,
+        location: &LatLng,
+    ) -> Option<u32> {
+        self.partition_for_shard(searcher.get_shard_from_location(location).name())
+    }
+
+    /// returns the shard to partition assignments
+    pub fn assignments(&self) -> &BTreeMap<String, u32> {
+        &self.assignments
+    }
+
+    /// returns the total score of the shards assigned to each partition
+    pub fn partition_scores(&self) -> &[i64] {
+        &self.partition_scores
+    }
+}
+
+/// `REDIS_CLUSTER_SLOTS` is the number of hash slots in a Redis Cluster
+pub const REDIS_CLUSTER_SLOTS: u16 = 16384;
+
+/// `SlotMap` assigns every shard in a collection a contiguous range of slots out of a fixed
+/// slot space, so slot based systems (such as Redis Cluster) can be resharded from the shard map
+#[derive(Debug, Clone)]
+pub struct SlotMap {
+    slot_count: u16,
+    shards: Vec<ShardSlots>,
+}
+
+#[derive(Debug, Clone)]
+struct ShardSlots {
+    name: String,
+    slots: Range<u16>,
+    cells: Vec<CellID>,
+}
+
+impl SlotMap {
+    /// returns the size of the slot space
+    pub fn slot_count(&self) -> u16 {
+        self.slot_count
+    }
+
+    /// returns the range of slots assigned to the shard with the given name
+    pub fn slots_for_shard(&self, shard_name: &str) -> Option<Range<u16>> {
+        self.shards
+            .iter()
+            .find(|shard| shard.name == shard_name)
+            .map(|shard| shard.slots.clone())
+    }
+
+    /// returns the slot for the given cell. The cells of a shard are spread evenly, in order,
+    /// across the shard's slot range, so slots can be moved between nodes a few cells at a time
+    pub fn slot_for_cell(&self, cell_id: &CellID) -> Option<u16> {
+        self.shards.iter().find_map(|shard| {
+            let index = shard.cells.binary_search(cell_id).ok()?;
+            let slot_len = (shard.slots.end - shard.slots.start) as usize;
+            Some(shard.slots.start + (index * slot_len / shard.cells.len()) as u16)
+        })
+    }
+
+    /// returns the slot ranges assigned to each shard, in order
+    pub fn slot_ranges(&self) -> Vec<(&str, Range<u16>)> {
+        self.shards
+            .iter()
+            .map(|shard| (shard.name.as_str(), shard.slots.clone()))
+            .collect()
+    }
+}
+
+#[cfg(feature = "builder")]
+impl GeoshardCollection {
+    /// Assigns each shard to one of `num_partitions` partitions, balancing the total score
+    /// of each partition. The heaviest shards are placed first, each onto the partition with the
+    /// lowest score so far.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_partitions` is 0
+    pub fn to_partition_map(&self, num_partitions: u32) -> PartitionMap {
+        assert!(num_partitions > 0, "num_partitions must be at least 1");
+
+        let mut shards: Vec<(&str, i32)> = self
+            .shards()
+            .iter()
+            .map(|shard| (shard.name(), shard.score()))
+            .collect();
+        shards.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+
+        let mut partition_scores = vec![0i64; num_partitions as usize];
+        let mut assignments = BTreeMap::new();
+        for (name, score) in shards {
+            let (partition, _) = partition_scores
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, partition_score)| **partition_score)
+                .unwrap();
+            partition_scores[partition] += score as i64;
+            assignments.insert(name.to_owned(), partition as u32);
+        }
+
+        PartitionMap {
+            num_partitions,
+            assignments,
+            partition_scores,
+        }
+    }
+
+    /// Assigns each shard a contiguous range out of `slot_count` slots (e.g. `REDIS_CLUSTER_SLOTS`).
+    /// Every shard gets at least one slot, and the rest are split in proportion to shard score
+    /// (or cell count, when nothing is scored)
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more shards than slots
+    pub fn to_slot_map(&self, slot_count: u16) -> SlotMap {
+        let shards = self.shards();
+        assert!(
+            shards.len() <= slot_count as usize,
+            "{} shards can't fit in {} slots",
+            shards.len(),
+            slot_count
+        );
+
+        let mut weights: Vec<u64> = shards
+            .iter()
+            .map(|shard| shard.score().max(0) as u64)
+            .collect();
+        if weights.iter().all(|weight| *weight == 0) {
+            weights = shards
+                .iter()
+                .map(|shard| shard.cell_count() as u64)
+                .collect();
+        }
+        let total_weight = weights.iter().sum::<u64>().max(1);
+
+        // every shard gets a slot, the remaining slots are split by largest remainder
+        let spare_slots = (slot_count as usize - shards.len()) as u64;
+        let mut slot_lens: Vec<u64> = weights
+            .iter()
+            .map(|weight| 1 + weight * spare_slots / total_weight)
+            .collect();
+        let mut remainders: Vec<(usize, u64)> = weights
+            .iter()
+            .map(|weight| weight * spare_slots % total_weight)
+            .enumerate()
+            .collect();
+        remainders.sort_by_key(|(_, remainder)| std::cmp::Reverse(*remainder));
+        let assigned: u64 = slot_lens.iter().sum();
+        for (index, _) in remainders
+            .into_iter()
+            .take((slot_count as u64).saturating_sub(assigned) as usize)
+        {
+            slot_lens[index] += 1;
+        }
+
+        let mut start = 0u16;
+        let shards = shards
+            .iter()
+            .zip(slot_lens)
+            .map(|(shard, slot_len)| {
+                let end = start + slot_len as u16;
+                let mut cells = shard.cell_union().0.clone();
+                cells.sort();
+                let slots = ShardSlots {
+                    name: shard.name().to_owned(),
+                    slots: start..end,
+                    cells,
+                };
+                start = end;
+                slots
+            })
+            .collect();
+
+        SlotMap { slot_count, shards }
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{cell_list::CellList, utils::ll};
+
+    #[test]
+    fn test_partition_map() {
+        let mut cell_list = CellList::new(2);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 7) as i32;
+        }
+        let geoshards = GeoshardCollection::new(20, cell_list.cell_list(), 2);
+        let total_score: i64 = cell_list
+            .cell_list()
+            .values()
+            .map(|score| *score as i64)
+            .sum();
+
+        let partition_map = geoshards.to_partition_map(4);
+        assert_eq!(partition_map.assignments().len(), geoshards.shards().len());
+        assert_eq!(
+            partition_map.partition_scores().iter().sum::<i64>(),
+            total_score
+        );
+
+        let max = *partition_map.partition_scores().iter().max().unwrap();
+        let min = *partition_map.partition_scores().iter().min().unwrap();
+        assert!(max - min <= 20);
+
+        let location = ll!(-74.0060, 40.7128);
+        let searcher = GeoshardSearcher::from(geoshards);
+        let shard_name = searcher
+            .get_shard_from_location(&location)
+            .name()
+            .to_owned();
+        assert_eq!(
+            partition_map.partition_for_location(&searcher, &location),
+            partition_map.partition_for_shard(&shard_name)
+        );
+        assert!(partition_map.partition_for_shard(&shard_name).unwrap() < 4);
+    }
+
+    #[test]
+    fn test_slot_map() {
+        let mut cell_list = CellList::new(2);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 7) as i32;
+        }
+        let geoshards = GeoshardCollection::new(20, cell_list.cell_list(), 2);
+        let slot_map = geoshards.to_slot_map(REDIS_CLUSTER_SLOTS);
+
+        let slot_ranges = slot_map.slot_ranges();
+        assert_eq!(slot_ranges.len(), geoshards.shards().len());
+        assert_eq!(slot_ranges[0].1.start, 0);
+        assert_eq!(slot_ranges.last().unwrap().1.end, REDIS_CLUSTER_SLOTS);
+        for window in slot_ranges.windows(2) {
+            assert_eq!(window[0].1.end, window[1].1.start);
+        }
+
+        for shard in geoshards.shards() {
+            let slots = slot_map.slots_for_shard(shard.name()).unwrap();
+            for cell_id in shard.cell_union().0.iter() {
+                assert!(slots.contains(&slot_map.slot_for_cell(cell_id).unwrap()));
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+#![deny(missing_docs)]
+//! placement assigns shards to the storage nodes serving them, packing shards by score under
+//! each node's capacity while keeping geographically adjacent shards on the same node, so radius
+//! queries fan out to fewer nodes
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{error::GeoshardError, geoshard::GeoshardCollection};
+
+/// `Node` is a storage node shards can be placed on
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Node {
+    /// name of the node
+    pub name: String,
+    /// the most total shard score the node may serve
+    pub capacity: i64,
+}
+
+impl Node {
+    /// Constructs a new `Node`
+    pub fn new(name: impl Into<String>, capacity: i64) -> Self {
+        Self {
+            name: name.into(),
+            capacity,
+        }
+    }
+}
+
+/// `Placement` maps every shard of a collection to the node serving it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Placement {
+    nodes: BTreeMap<String, String>,
+    loads: BTreeMap<String, i64>,
+    colocated_neighbors: usize,
+}
+
+impl Placement {
+    /// Places the shards of the map onto the nodes. Shards are placed from the highest score
+    /// down, each onto the node with room already holding the most of its neighbors (see
+    /// `GeoshardCollection::neighbors`), and otherwise the node with the most room left. Fails
+    /// with `GeoshardError::UnplaceableShard` if a shard fits on no node
+    pub fn place(map: &GeoshardCollection, nodes: &[Node]) -> Result<Self, GeoshardError> {
+        let mut shards: Vec<_> = map.shards().iter().collect();
+        shards.sort_by_key(|shard| std::cmp::Reverse(shard.score()));
+
+        let mut placed: BTreeMap<String, usize> = BTreeMap::new();
+        let mut loads = vec![0i64; nodes.len()];
+        for shard in shards {
+            let neighbors = map.neighbors(shard.name()).unwrap_or_default();
+            let score = shard.score() as i64;
+            let node = (0..nodes.len())
+                .filter(|node| loads[*node] + score <= nodes[*node].capacity)
+                .max_by_key(|node| {
+                    let colocated = neighbors
+                        .iter()
+                        .filter(|neighbor| placed.get(neighbor.name()) == Some(node))
+                        .count();
+                    // on ties, the first node with the most room left
+                    (
+                        colocated,
+                        nodes[*node].capacity - loads[*node],
+                        std::cmp::Reverse(*node),
+                    )
+                })
+                .ok_or_else(|| GeoshardError::UnplaceableShard {
+                    shard: shard.name().to_owned(),
+                    score: shard.score(),
+                })?;
+            loads[node] += score;
+            placed.insert(shard.name().to_owned(), node);
+        }
+
+        let colocated_neighbors = placed
+            .iter()
+            .map(|(shard, node)| {
+                map.neighbors(shard)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|neighbor| placed.get(neighbor.name()) == Some(node))
+                    .count()
+            })
+            .sum::<usize>()
+            / 2;
+        Ok(Self {
+            nodes: placed
+                .into_iter()
+                .map(|(shard, node)| (shard, nodes[node].name.clone()))
+                .collect(),
+            loads: nodes
+                .iter()
+                .zip(loads)
+                .map(|(node, load)| (node.name.clone(), load))
+                .collect(),
+            colocated_neighbors,
+        })
+    }
+
+    /// returns the name of the node serving the shard, if the shard was placed
+    pub fn node(&self, shard_name: &str) -> Option<&str> {
+        self.nodes.get(shard_name).map(String::as_str)
+    }
+
+    /// returns the names of the shards placed on the node
+    pub fn shards_on(&self, node_name: &str) -> Vec<&str> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| *node == node_name)
+            .map(|(shard, _)| shard.as_str())
+            .collect()
+    }
+
+    /// returns the total score of the shards placed on the node
+    pub fn load(&self, node_name: &str) -> i64 {
+        self.loads.get(node_name).copied().unwrap_or(0)
+    }
+
+    /// returns the placement map, from shard name to node name
+    pub fn placement_map(&self) -> &BTreeMap<String, String> {
+        &self.nodes
+    }
+
+    /// returns the number of pairs of adjacent shards placed on the same node
+    pub fn colocated_neighbors(&self) -> usize {
+        self.colocated_neighbors
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::testing::uniform_collection;
+
+    #[test]
+    fn test_place() {
+        let shards = uniform_collection(4, 128);
+        let nodes: Vec<Node> = (0..4)
+            .map(|node| Node::new(format!("node_{}", node), 448))
+            .collect();
+        let placement = Placement::place(&shards, &nodes).unwrap();
+
+        assert_eq!(placement.placement_map().len(), shards.shards().len());
+        for node in nodes.iter() {
+            assert!(placement.load(&node.name) <= node.capacity);
+            let load: i32 = placement
+                .shards_on(&node.name)
+                .iter()
+                .map(|name| {
+                    shards
+                        .shards()
+                        .iter()
+                        .find(|shard| shard.name() == *name)
+                        .unwrap()
+                        .score()
+                })
+                .sum();
+            assert_eq!(load as i64, placement.load(&node.name));
+        }
+        let total: i64 = nodes.iter().map(|node| placement.load(&node.name)).sum();
+        assert_eq!(total, 1536);
+
+        // more adjacent shards end up together than when dealt out in turn
+        let dealt = shards
+            .shards()
+            .iter()
+            .enumerate()
+            .map(|(index, shard)| {
+                shards
+                    .neighbors(shard.name())
+                    .unwrap()
+                    .iter()
+                    .filter(|neighbor| {
+                        let other = shards
+                            .shards()
+                            .iter()
+                            .position(|shard| shard.name() == neighbor.name())
+                            .unwrap();
+                        other % nodes.len() == index % nodes.len()
+                    })
+                    .count()
+            })
+            .sum::<usize>()
+            / 2;
+        assert!(placement.colocated_neighbors() > dealt);
+    }
+
+    #[test]
+    fn test_place_without_room() {
+        let shards = uniform_collection(4, 128);
+        let nodes = vec![Node::new("node_0", 1000), Node::new("node_1", 100)];
+        match Placement::place(&shards, &nodes) {
+            Err(GeoshardError::UnplaceableShard { score, .. }) => assert_eq!(score, 128),
+            other => panic!("expected an unplaceable shard, got {:?}", other),
+        }
+    }
+}
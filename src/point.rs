@@ -0,0 +1,128 @@
+#![deny(missing_docs)]
+//! point contains `GeoPoint`, the crate's own location type, so users can be located and shards
+//! searched without depending on the s2 crate. Points from other coordinate types (such as
+//! `geo-types` or H3 cell centers) convert to `GeoPoint` through their latitude and longitude
+use std::fmt;
+
+use s2::{latlng::LatLng, s1::Deg};
+
+/// `GeoPoint` is a location, as latitude and longitude in degrees. It converts to and from s2's
+/// `LatLng`, which it wraps, so it can be passed wherever the crate takes a location
+#[derive(Clone)]
+pub struct GeoPoint(LatLng);
+
+impl GeoPoint {
+    /// Constructs a new `GeoPoint` at the latitude and longitude, in degrees
+    pub fn new(lat: f64, lng: f64) -> Self {
+        Self(LatLng {
+            lat: Deg(lat).into(),
+            lng: Deg(lng).into(),
+        })
+    }
+
+    /// returns the latitude, in degrees
+    pub fn lat(&self) -> f64 {
+        self.0.lat.deg()
+    }
+
+    /// returns the longitude, in degrees
+    pub fn lng(&self) -> f64 {
+        self.0.lng.deg()
+    }
+
+    /// returns the point as a `LatLng`, to pass to the searcher's lookups
+    pub fn lat_lng(&self) -> &LatLng {
+        &self.0
+    }
+}
+
+impl fmt::Debug for GeoPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeoPoint")
+            .field("lat", &self.lat())
+            .field("lng", &self.lng())
+            .finish()
+    }
+}
+
+impl PartialEq for GeoPoint {
+    fn eq(&self, other: &Self) -> bool {
+        self.lat() == other.lat() && self.lng() == other.lng()
+    }
+}
+
+impl From<LatLng> for GeoPoint {
+    fn from(lat_lng: LatLng) -> Self {
+        Self(lat_lng)
+    }
+}
+
+impl From<&LatLng> for GeoPoint {
+    fn from(lat_lng: &LatLng) -> Self {
+        Self(lat_lng.clone())
+    }
+}
+
+impl From<GeoPoint> for LatLng {
+    fn from(point: GeoPoint) -> Self {
+        point.0
+    }
+}
+
+impl AsRef<LatLng> for GeoPoint {
+    fn as_ref(&self) -> &LatLng {
+        &self.0
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        geoshard::{GeoshardBuilder, GeoshardSearcher},
+        testing::{FakeUser, RandCityFactory},
+        users::{PointUser, User},
+    };
+
+    /// a user type that never names an s2 type
+    struct Rider {
+        point: GeoPoint,
+    }
+
+    impl PointUser for Rider {
+        fn point(&self) -> &GeoPoint {
+            &self.point
+        }
+    }
+
+    #[test]
+    fn test_geo_point() {
+        let point = GeoPoint::new(37.77, -122.42);
+        assert!((point.lat() - 37.77).abs() < 1e-9);
+        assert!((point.lng() + 122.42).abs() < 1e-9);
+        assert_eq!(GeoPoint::from(LatLng::from(point.clone())), point);
+
+        let users = FakeUser::seeded(1000, 13, &RandCityFactory::default());
+        let riders: Vec<Rider> = users
+            .iter()
+            .map(|user| Rider {
+                point: GeoPoint::from(user.location()),
+            })
+            .collect();
+        let built = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let from_points = GeoshardBuilder::user_count_scorer(4, riders.iter(), 4, 8).build();
+        assert_eq!(
+            serde_json::to_string(&from_points).unwrap(),
+            serde_json::to_string(&built).unwrap()
+        );
+
+        let searcher = GeoshardSearcher::from(from_points);
+        let rider = &riders[0];
+        assert_eq!(
+            searcher.get_shard_for_user(rider).name(),
+            searcher
+                .get_shard_from_location(rider.point().lat_lng())
+                .name()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+#![deny(missing_docs)]
+//! polygon contains a simple lat/lng polygon used to describe regions such as country boundaries
+use s2::{
+    latlng::LatLng,
+    r1,
+    rect::Rect,
+    s1::interval::{Interval, FULL},
+};
+
+/// Polygon is a simple (non self-intersecting) polygon with vertices in lat/lng degrees.
+/// Edges are treated as straight lines in lat/lng space, joining consecutive vertices the short
+/// way around, so polygons can cross the antimeridian (e.g. Fiji or Chukotka)
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    vertices: Vec<(f64, f64)>,
+}
+
+impl Polygon {
+    /// Constructs a new polygon from its vertices, in order. The polygon is closed automatically
+    pub fn new(vertices: &[LatLng]) -> Self {
+        // unwrap longitudes so no edge is over 180 degrees wide, letting them run past ±180
+        let mut unwrapped: Vec<(f64, f64)> = Vec::with_capacity(vertices.len());
+        for vertex in vertices {
+            let mut lng = vertex.lng.deg();
+            if let Some((previous, _)) = unwrapped.last() {
+                lng -= ((lng - previous) / 360.0).round() * 360.0;
+            }
+            unwrapped.push((lng, vertex.lat.deg()));
+        }
+        Self {
+            vertices: unwrapped,
+        }
+    }
+
+    /// returns true if the location is inside the polygon
+    pub fn contains(&self, location: &LatLng) -> bool {
+        let lng = location.lng.deg();
+        [lng, lng - 360.0, lng + 360.0]
+            .iter()
+            .any(|x| self.contains_point(*x, location.lat.deg()))
+    }
+
+    /// returns the smallest lat/lng rectangle containing the polygon
+    pub fn bound(&self) -> Rect {
+        if self.vertices.is_empty() {
+            return Rect::empty();
+        }
+        let (mut west, mut east, mut south, mut north) = (f64::MAX, f64::MIN, f64::MAX, f64::MIN);
+        for (lng, lat) in self.vertices.iter() {
+            west = west.min(*lng);
+            east = east.max(*lng);
+            south = south.min(*lat);
+            north = north.max(*lat);
+        }
+        let lng = if east - west >= 360.0 {
+            FULL
+        } else {
+            let wrap = |lng: f64| (lng + 180.0).rem_euclid(360.0) - 180.0;
+            Interval::new(wrap(west).to_radians(), wrap(east).to_radians())
+        };
+        Rect {
+            lat: r1::interval::Interval::new(south.to_radians(), north.to_radians()),
+            lng,
+        }
+    }
+
+    /// Returns true if the polygon and the lat/lng rectangle overlap, i.e. one contains a corner
+    /// of the other or their edges cross. Used to cover the polygon with the rectangles bounding
+    /// S2 cells
+    pub fn intersects_rect(&self, rect: &Rect) -> bool {
+        if rect.is_empty() || self.vertices.is_empty() {
+            return false;
+        }
+        let (south, north) = (rect.lat.lo.to_degrees(), rect.lat.hi.to_degrees());
+        let (west, mut east) = if rect.lng.is_full() {
+            (-180.0, 180.0)
+        } else {
+            (rect.lng.lo.to_degrees(), rect.lng.hi.to_degrees())
+        };
+        if west > east {
+            east += 360.0;
+        }
+        // unwrapped longitudes can run a turn past ±180 either way
+        (-2..=2).any(|turns| {
+            let shift = turns as f64 * 360.0;
+            let (west, east) = (west + shift, east + shift);
+            let corners = [(west, south), (east, south), (east, north), (west, north)];
+            corners.iter().any(|(x, y)| self.contains_point(*x, *y))
+                || self
+                    .vertices
+                    .iter()
+                    .any(|(x, y)| (west..=east).contains(x) && (south..=north).contains(y))
+                || self.edges().any(|edge| {
+                    (0..4).any(|index| {
+                        segments_cross(edge, (corners[index], corners[(index + 1) % 4]))
+                    })
+                })
+        })
+    }
+
+    /// returns the edges of the polygon, joining each vertex to the next
+    fn edges(&self) -> impl Iterator<Item = ((f64, f64), (f64, f64))> + '_ {
+        let previous = self.vertices.iter().cycle().skip(self.vertices.len() - 1);
+        previous.copied().zip(self.vertices.iter().copied())
+    }
+
+    fn contains_point(&self, x: f64, y: f64) -> bool {
+        let mut inside = false;
+        let mut previous = match self.vertices.last() {
+            Some(vertex) => *vertex,
+            None => return false,
+        };
+        // cast a ray east of the location and count the edges it crosses
+        for vertex in self.vertices.iter() {
+            let ((x1, y1), (x2, y2)) = (previous, *vertex);
+            if (y1 > y) != (y2 > y) && x < (x2 - x1) * (y - y1) / (y2 - y1) + x1 {
+                inside = !inside;
+            }
+            previous = *vertex;
+        }
+        inside
+    }
+}
+
+/// returns true if the segments cross or touch
+fn segments_cross(a: ((f64, f64), (f64, f64)), b: ((f64, f64), (f64, f64))) -> bool {
+    let orientation = |(x1, y1): (f64, f64), (x2, y2): (f64, f64), (x3, y3): (f64, f64)| {
+        let turn = (x2 - x1) * (y3 - y1) - (y2 - y1) * (x3 - x1);
+        (turn > 0.0) as i8 - (turn < 0.0) as i8
+    };
+    orientation(a.0, a.1, b.0) * orientation(a.0, a.1, b.1) <= 0
+        && orientation(b.0, b.1, a.0) * orientation(b.0, b.1, a.1) <= 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::ll;
+
+    #[test]
+    fn test_polygon_contains() {
+        // rough outline of Colorado
+        let colorado = Polygon::new(&[
+            ll!(-109.05, 41.0),
+            ll!(-102.05, 41.0),
+            ll!(-102.05, 37.0),
+            ll!(-109.05, 37.0),
+        ]);
+        assert!(colorado.contains(&ll!(-104.99, 39.74)));
+        assert!(!colorado.contains(&ll!(-111.89, 40.76)));
+        assert!(!Polygon::new(&[]).contains(&ll!(-104.99, 39.74)));
+    }
+
+    #[test]
+    fn test_polygon_crossing_antimeridian() {
+        // rough outline of Fiji's main islands
+        let fiji = Polygon::new(&[
+            ll!(176.8, -16.0),
+            ll!(-179.8, -16.0),
+            ll!(-179.8, -19.2),
+            ll!(176.8, -19.2),
+        ]);
+        assert!(fiji.contains(&ll!(178.44, -18.14)));
+        assert!(fiji.contains(&ll!(-179.9, -17.0)));
+        assert!(fiji.contains(&ll!(180.0, -17.0)));
+        assert!(!fiji.contains(&ll!(0.0, -17.0)));
+        assert!(!fiji.contains(&ll!(-170.0, -17.0)));
+
+        let bound = fiji.bound();
+        assert!(bound.lng.is_inverted());
+        assert!(bound.contains_latlng(&ll!(-179.9, -17.0)));
+        assert!(!bound.contains_latlng(&ll!(0.0, -17.0)));
+        let rect = |lng: f64, lat: f64| Rect::from_center_size(ll!(lng, lat), ll!(1.0, 1.0));
+        assert!(fiji.intersects_rect(&rect(-179.8, -19.2)));
+        assert!(fiji.intersects_rect(&rect(179.9, -17.5)));
+        assert!(!fiji.intersects_rect(&rect(-178.0, -17.5)));
+        // a rectangle crossed by an edge, with no corner inside the other
+        let thin = Rect::from_center_size(ll!(178.0, -17.5), ll!(0.1, 10.0));
+        assert!(fiji.intersects_rect(&thin));
+    }
+}
@@ -0,0 +1,60 @@
+#![deny(missing_docs)]
+//! preset contains starting point configurations of the builder for common workloads, see
+//! `GeoshardBuilder::preset`
+use crate::geoshard::PartitionObjective;
+
+/// `Preset` is a starting point builder configuration for a common workload. The values are
+/// starting points to tune from, and are reported in the `BuildReport` of every build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// users matched with people nearby: city sized cells, and shards kept local so match
+    /// queries stay within a few shards
+    DatingApp,
+    /// riders and drivers in dense metros: fine cells, many shards, and tight shard extents
+    RideSharing,
+    /// devices reporting from fixed sites: coarse cells and few shards, balanced on a blend of
+    /// device count and the number of cells, since per cell storage overhead adds up
+    IoTFleet,
+    /// players grouped by region for latency: coarse cells and shards no wider than a region
+    Gaming,
+}
+
+impl Preset {
+    /// returns the S2 storage level
+    pub fn storage_level(&self) -> u64 {
+        match self {
+            Preset::DatingApp => 8,
+            Preset::RideSharing => 9,
+            Preset::IoTFleet => 6,
+            Preset::Gaming => 6,
+        }
+    }
+
+    /// returns the minimum and maximum shard counts
+    pub fn shard_count_range(&self) -> (i32, i32) {
+        match self {
+            Preset::DatingApp => (40, 100),
+            Preset::RideSharing => (100, 400),
+            Preset::IoTFleet => (8, 32),
+            Preset::Gaming => (16, 64),
+        }
+    }
+
+    /// returns the maximum geographic extent of a shard in meters, if limited
+    pub fn max_shard_extent(&self) -> Option<f64> {
+        match self {
+            Preset::DatingApp => Some(3_000_000.0),
+            Preset::RideSharing => Some(500_000.0),
+            Preset::IoTFleet => None,
+            Preset::Gaming => Some(2_000_000.0),
+        }
+    }
+
+    /// returns what is balanced across shards
+    pub fn objective(&self) -> PartitionObjective {
+        match self {
+            Preset::IoTFleet => PartitionObjective::Blend { score_weight: 0.5 },
+            _ => PartitionObjective::Score,
+        }
+    }
+}
@@ -0,0 +1,442 @@
+#![deny(missing_docs)]
+//! publish contains the protocol for publishing shard maps to object storage (such as S3 or
+//! GCS) behind the `publish` feature, and for routers to poll for them. Each map is written with
+//! its metadata under a versioned key, then a `latest.json` pointer is replaced to point at it,
+//! so a router either reads the previous map or the new one, never a partial upload:
+//!
+//! - `{prefix}/{version}/shard_map.json`: the map, as serialized by `GeoshardCollection`
+//! - `{prefix}/{version}/metadata.json`: its `MapMetadata`
+//! - `{prefix}/latest.json`: a `LatestPointer` to the latest version
+//!
+//! Maps published with `publish_signed` also have their `MapSignature` at
+//! `{prefix}/{version}/shard_map.json.sig`, and publishers constructed `with_verifier` only fetch
+//! maps whose signature verifies.
+//!
+//! Versions are zero padded to 20 digits, so they list in order
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::GeoshardError,
+    geoshard::GeoshardCollection,
+    signing::{MapSignature, MapSigner, MapVerifier},
+    subscriber::{FetchedMap, MapSource},
+};
+
+/// ObjectStore is the trait for the object storage maps are published to. Implementing this for
+/// an S3 or GCS client lets `MapPublisher` publish to it. Puts must replace the whole object at
+/// once, as S3 and GCS do
+pub trait ObjectStore {
+    /// the error of a failed request
+    type Error: fmt::Display;
+
+    /// writes the object at `key`, replacing any object there
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// reads the object at `key`, or returns `None` if there is none
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error>;
+}
+
+/// `DirObjectStore` stores objects as files under a directory, written to a temporary file and
+/// renamed into place so they are replaced at once. Useful for shared volumes and tests
+#[derive(Debug, Clone)]
+pub struct DirObjectStore {
+    root: PathBuf,
+}
+
+impl DirObjectStore {
+    /// Constructs a new `DirObjectStore` storing objects under `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ObjectStore for DirObjectStore {
+    type Error = io::Error;
+
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("tmp");
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(temporary, path)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// `MapMetadata` describes a published map
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapMetadata {
+    /// version of the map, increasing with every map published
+    pub version: u64,
+    /// when the map was built, in seconds since the Unix epoch
+    pub built_at: u64,
+    /// the standard deviation between the shards' scores
+    pub standard_deviation: f64,
+    /// the number of users the map was built from
+    pub user_count: u64,
+    /// the number of shards in the map
+    pub shard_count: usize,
+}
+
+impl MapMetadata {
+    /// Constructs the metadata of `map`, built now from `user_count` users, as `version`
+    pub fn new(version: u64, map: &GeoshardCollection, user_count: u64) -> Self {
+        Self {
+            version,
+            built_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            standard_deviation: map.standard_deviation(),
+            user_count,
+            shard_count: map.shards().len(),
+        }
+    }
+
+    /// sets when the map was built
+    pub fn with_built_at(mut self, built_at: SystemTime) -> Self {
+        self.built_at = built_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self
+    }
+}
+
+/// `LatestPointer` is the content of `latest.json`, pointing at the latest published map
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatestPointer {
+    /// version of the latest map
+    pub version: u64,
+    /// key of the map
+    pub map_key: String,
+    /// key of the map's metadata
+    pub metadata_key: String,
+    /// key of the map's signature, if it was published signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_key: Option<String>,
+}
+
+/// `MapPublisher` publishes maps to object storage under a key prefix, and reads back the latest
+/// one. Builder jobs publish with `publish`, and routers poll `latest` for a newer version than
+/// they serve, then `fetch` it
+pub struct MapPublisher<Store> {
+    store: Store,
+    prefix: String,
+    verifier: Option<Box<dyn MapVerifier + Send>>,
+}
+
+impl<Store: ObjectStore> MapPublisher<Store> {
+    /// Constructs a new `MapPublisher` publishing under `prefix`, e.g. `shard-maps/users`
+    pub fn new(store: Store, prefix: impl Into<String>) -> Self {
+        Self {
+            store,
+            prefix: prefix.into().trim_end_matches('/').to_owned(),
+            verifier: None,
+        }
+    }
+
+    /// sets the verifier of the maps fetched, so maps published without a signature, or with one
+    /// that doesn't verify, fail to fetch with `GeoshardError::UnverifiedMap`
+    pub fn with_verifier(mut self, verifier: impl MapVerifier + Send + 'static) -> Self {
+        self.verifier = Some(Box::new(verifier));
+        self
+    }
+
+    /// returns the store maps are published to
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Uploads the map and its metadata under the metadata's version, then points `latest.json`
+    /// at them, returning the new pointer. Fails with `GeoshardError::PublishFailed` if the
+    /// version isn't newer than the latest published version, or an upload fails. A failed
+    /// publish leaves the latest pointer as it was. Publishers racing each other should use
+    /// distinct versions, e.g. build timestamps
+    pub fn publish(
+        &self,
+        map: &GeoshardCollection,
+        metadata: &MapMetadata,
+    ) -> Result<LatestPointer, GeoshardError> {
+        self.publish_with(map, metadata, None::<&dyn MapSigner>)
+    }
+
+    /// `publish`, also uploading the map's signature by `signer` before pointing `latest.json` at
+    /// the map, so routers verifying maps never see it unsigned
+    pub fn publish_signed(
+        &self,
+        map: &GeoshardCollection,
+        metadata: &MapMetadata,
+        signer: &impl MapSigner,
+    ) -> Result<LatestPointer, GeoshardError> {
+        self.publish_with(map, metadata, Some(signer))
+    }
+
+    fn publish_with<Signer: MapSigner + ?Sized>(
+        &self,
+        map: &GeoshardCollection,
+        metadata: &MapMetadata,
+        signer: Option<&Signer>,
+    ) -> Result<LatestPointer, GeoshardError> {
+        let latest_key = self.key("latest.json");
+        if let Some(latest) = self.latest()? {
+            if latest.version >= metadata.version {
+                return Err(GeoshardError::PublishFailed {
+                    key: latest_key,
+                    reason: format!(
+                        "version {} is not newer than the latest version {}",
+                        metadata.version, latest.version
+                    ),
+                });
+            }
+        }
+
+        let pointer = LatestPointer {
+            version: metadata.version,
+            map_key: self.key(&format!("{:020}/shard_map.json", metadata.version)),
+            metadata_key: self.key(&format!("{:020}/metadata.json", metadata.version)),
+            signature_key: signer
+                .map(|_| self.key(&format!("{:020}/shard_map.json.sig", metadata.version))),
+        };
+        let failed = |reason: String| GeoshardError::PublishFailed {
+            key: pointer.map_key.clone(),
+            reason,
+        };
+        let bytes = serde_json::to_vec(map).map_err(|error| failed(error.to_string()))?;
+        if let (Some(signer), Some(signature_key)) = (signer, &pointer.signature_key) {
+            self.put_json(signature_key, &MapSignature::sign(&bytes, signer))?;
+        }
+        self.store
+            .put(&pointer.map_key, &bytes)
+            .map_err(|error| failed(error.to_string()))?;
+        self.put_json(&pointer.metadata_key, metadata)?;
+        self.put_json(&latest_key, &pointer)?;
+        Ok(pointer)
+    }
+
+    /// returns the pointer to the latest published map, or `None` if none was published
+    pub fn latest(&self) -> Result<Option<LatestPointer>, GeoshardError> {
+        self.get_json(&self.key("latest.json"))
+    }
+
+    /// Reads the map and metadata the pointer points at. Fails with
+    /// `GeoshardError::MapUnavailable` if either is missing or can't be parsed, and with
+    /// `GeoshardError::UnverifiedMap` if the publisher has a verifier and the map's signature is
+    /// missing or doesn't verify
+    pub fn fetch(
+        &self,
+        pointer: &LatestPointer,
+    ) -> Result<(GeoshardCollection, MapMetadata), GeoshardError> {
+        let missing = |key: &str| GeoshardError::MapUnavailable {
+            path: key.to_owned(),
+            reason: "no such object".to_owned(),
+        };
+        let bytes = self
+            .get_bytes(&pointer.map_key)?
+            .ok_or_else(|| missing(&pointer.map_key))?;
+        if let Some(verifier) = &self.verifier {
+            let signature: MapSignature = match &pointer.signature_key {
+                Some(signature_key) => self.get_json(signature_key)?,
+                None => None,
+            }
+            .ok_or_else(|| GeoshardError::UnverifiedMap {
+                path: pointer.map_key.clone(),
+                reason: "the map was published without a signature".to_owned(),
+            })?;
+            signature.verify(&pointer.map_key, &bytes, verifier.as_ref())?;
+        }
+        let map =
+            serde_json::from_slice(&bytes).map_err(|error| GeoshardError::MapUnavailable {
+                path: pointer.map_key.clone(),
+                reason: error.to_string(),
+            })?;
+        let metadata = self
+            .get_json(&pointer.metadata_key)?
+            .ok_or_else(|| missing(&pointer.metadata_key))?;
+        Ok((map, metadata))
+    }
+
+    /// returns the key of the object under the prefix
+    fn key(&self, name: &str) -> String {
+        match self.prefix.is_empty() {
+            true => name.to_owned(),
+            false => format!("{}/{}", self.prefix, name),
+        }
+    }
+
+    fn put_json<T: serde::Serialize>(&self, key: &str, value: &T) -> Result<(), GeoshardError> {
+        let failed = |reason: String| GeoshardError::PublishFailed {
+            key: key.to_owned(),
+            reason,
+        };
+        let bytes = serde_json::to_vec(value).map_err(|error| failed(error.to_string()))?;
+        self.store
+            .put(key, &bytes)
+            .map_err(|error| failed(error.to_string()))
+    }
+
+    fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<T>, GeoshardError> {
+        match self.get_bytes(key)? {
+            Some(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(|error| {
+                GeoshardError::MapUnavailable {
+                    path: key.to_owned(),
+                    reason: error.to_string(),
+                }
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>, GeoshardError> {
+        self.store
+            .get(key)
+            .map_err(|error| GeoshardError::MapUnavailable {
+                path: key.to_owned(),
+                reason: error.to_string(),
+            })
+    }
+}
+
+/// a `MapPublisher` is a source of the maps published to it, versioned by their published
+/// versions, so routers can subscribe to them with a `ShardMapSubscriber`
+impl<Store: ObjectStore> MapSource for MapPublisher<Store> {
+    fn fetch(&mut self, current: Option<&str>) -> Result<Option<FetchedMap>, GeoshardError> {
+        let latest = match self.latest()? {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        let version = latest.version.to_string();
+        if current == Some(version.as_str()) {
+            return Ok(None);
+        }
+        let (map, _) = MapPublisher::fetch(self, &latest)?;
+        Ok(Some(FetchedMap {
+            version,
+            map: Ok(map),
+        }))
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        geoshard::GeoshardBuilder,
+        signing::HmacSha256Key,
+        testing::{FakeUser, RandCityFactory},
+    };
+
+    #[test]
+    fn test_map_publisher() {
+        let root = std::env::temp_dir().join("geoshard_test_publish");
+        let _ = fs::remove_dir_all(&root);
+        let publisher = MapPublisher::new(DirObjectStore::new(&root), "shard-maps/users/");
+        assert_eq!(publisher.latest().unwrap(), None);
+
+        let users = FakeUser::seeded(500, 47, &RandCityFactory::default());
+        let map = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let metadata = MapMetadata::new(7, &map, 500).with_built_at(UNIX_EPOCH);
+        let pointer = publisher.publish(&map, &metadata).unwrap();
+        assert_eq!(
+            pointer.map_key,
+            "shard-maps/users/00000000000000000007/shard_map.json"
+        );
+        assert!(root.join(&pointer.metadata_key).exists());
+
+        let latest = publisher.latest().unwrap().unwrap();
+        assert_eq!(latest, pointer);
+        let (fetched, fetched_metadata) = publisher.fetch(&latest).unwrap();
+        assert_eq!(fetched_metadata, metadata);
+        assert_eq!(fetched_metadata.shard_count, map.shards().len());
+        assert_eq!(
+            serde_json::to_string(&fetched).unwrap(),
+            serde_json::to_string(&map).unwrap()
+        );
+
+        // versions only move forward, leaving the latest map in place
+        let error = publisher
+            .publish(&map, &MapMetadata::new(7, &map, 500))
+            .unwrap_err();
+        assert!(matches!(error, GeoshardError::PublishFailed { .. }));
+        assert_eq!(publisher.latest().unwrap().unwrap().version, 7);
+        publisher
+            .publish(&map, &MapMetadata::new(8, &map, 500))
+            .unwrap();
+        assert_eq!(publisher.latest().unwrap().unwrap().version, 8);
+
+        let mut source = MapPublisher::new(DirObjectStore::new(&root), "shard-maps/users");
+        let fetched = MapSource::fetch(&mut source, Some("7")).unwrap().unwrap();
+        assert_eq!(fetched.version, "8");
+        assert!(MapSource::fetch(&mut source, Some("8")).unwrap().is_none());
+
+        fs::remove_file(root.join(&pointer.map_key)).unwrap();
+        assert!(matches!(
+            publisher.fetch(&pointer),
+            Err(GeoshardError::MapUnavailable { .. })
+        ));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_publish_signed() {
+        let root = std::env::temp_dir().join("geoshard_test_publish_signed");
+        let _ = fs::remove_dir_all(&root);
+        let key = HmacSha256Key::new(b"resharding pipeline key");
+        let publisher = MapPublisher::new(DirObjectStore::new(&root), "shard-maps");
+        let router =
+            MapPublisher::new(DirObjectStore::new(&root), "shard-maps").with_verifier(key.clone());
+
+        let users = FakeUser::seeded(500, 53, &RandCityFactory::default());
+        let map = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let pointer = publisher
+            .publish_signed(&map, &MapMetadata::new(1, &map, 500), &key)
+            .unwrap();
+        assert_eq!(
+            pointer.signature_key.as_deref(),
+            Some("shard-maps/00000000000000000001/shard_map.json.sig")
+        );
+        assert!(router.fetch(&pointer).is_ok());
+
+        // maps published unsigned, or tampered with, are refused
+        let unsigned = publisher
+            .publish(&map, &MapMetadata::new(2, &map, 500))
+            .unwrap();
+        assert_eq!(unsigned.signature_key, None);
+        assert!(publisher.fetch(&unsigned).is_ok());
+        assert!(matches!(
+            router.fetch(&unsigned),
+            Err(GeoshardError::UnverifiedMap { .. })
+        ));
+
+        let tampered = fs::read_to_string(root.join(&pointer.map_key))
+            .unwrap()
+            .replacen("geoshard_user_index_1", "geoshard_user_index_9", 1);
+        fs::write(root.join(&pointer.map_key), tampered).unwrap();
+        assert!(matches!(
+            router.fetch(&pointer),
+            Err(GeoshardError::UnverifiedMap { .. })
+        ));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+#![deny(missing_docs)]
+//! query plans radius and polygon searches (e.g. finding users near a location) across shards, so
+//! every service consuming the shard map pushes the same shard fan out and cell filters to its
+//! backends. Each shard's cell tokens are within that shard, so backends can scan the token
+//! prefixes (see `CellID::range_min` and `range_max`) instead of the whole shard
+use s2::{cellid::CellID, latlng::LatLng};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    geoshard::{Geoshard, GeoshardSearcher},
+    polygon::Polygon,
+};
+
+/// `ShardQuery` is the part of a query plan sent to one shard: only documents in one of the
+/// cells need to be searched
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ShardQuery {
+    /// name of the shard to query
+    pub shard: String,
+    /// tokens of the cells to filter the shard's documents on, at the storage level unless the
+    /// searcher has a `CoveringConfig` allowing other levels. Cells spanning several shards are
+    /// split into the cells of this shard within them
+    pub cell_tokens: Vec<String>,
+}
+
+/// `QueryPlan` lists the shards a radius search fans out to, with the cells to search in each
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QueryPlan {
+    /// the queries to send, one per shard
+    pub queries: Vec<ShardQuery>,
+}
+
+impl QueryPlan {
+    /// returns the number of shards the search fans out to
+    pub fn fan_out(&self) -> usize {
+        self.queries.len()
+    }
+
+    /// returns the query for the shard with the given name, if the search touches it
+    pub fn query_for_shard(&self, shard_name: &str) -> Option<&ShardQuery> {
+        self.queries.iter().find(|query| query.shard == shard_name)
+    }
+}
+
+/// `ShardQueryPlanner` assembles query plans from a `GeoshardSearcher`
+pub struct ShardQueryPlanner<'a> {
+    searcher: &'a GeoshardSearcher,
+}
+
+impl<'a> ShardQueryPlanner<'a> {
+    /// Constructs a new `ShardQueryPlanner` planning against the searcher's shard map
+    pub fn new(searcher: &'a GeoshardSearcher) -> Self {
+        Self { searcher }
+    }
+
+    /// plans a search for users within `radius` meters of the location. The location's own
+    /// shard is always queried first
+    pub fn find_users_near(&self, location: &LatLng, radius: f64) -> QueryPlan {
+        let own_shard = self.searcher.get_shard_from_location(location).name();
+        let mut plan = Self::plan(self.searcher.cells_by_shard_from_radius(location, radius));
+        plan.queries.sort_by_key(|query| query.shard != own_shard);
+        plan
+    }
+
+    /// plans a search for users within the polygon, e.g. a delivery zone
+    pub fn find_users_in(&self, polygon: &Polygon) -> QueryPlan {
+        Self::plan(self.searcher.cells_by_shard_from_polygon(polygon))
+    }
+
+    /// returns the plan querying each shard for its cells
+    fn plan(cells_by_shard: Vec<(&Geoshard, Vec<CellID>)>) -> QueryPlan {
+        QueryPlan {
+            queries: cells_by_shard
+                .into_iter()
+                .map(|(shard, cells)| ShardQuery {
+                    shard: shard.name().to_owned(),
+                    cell_tokens: cells.iter().map(|cell_id| cell_id.to_token()).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{geoshard::CoveringConfig, testing::uniform_collection, utils::ll};
+
+    #[test]
+    fn test_find_users_near() {
+        let searcher = GeoshardSearcher::from(uniform_collection(5, 300));
+        let planner = ShardQueryPlanner::new(&searcher);
+        let nyc = ll!(-74.0060, 40.7128);
+
+        let local = planner.find_users_near(&nyc, 1.0);
+        assert_eq!(local.fan_out(), 1);
+        assert_eq!(
+            local.queries[0].cell_tokens,
+            vec![CellID::from(&nyc).parent(5).to_token()]
+        );
+
+        let wide = planner.find_users_near(&nyc, 2_000_000.0);
+        assert!(wide.fan_out() > 1);
+        assert_eq!(
+            wide.queries[0].shard,
+            searcher.get_shard_from_location(&nyc).name()
+        );
+        for query in wide.queries.iter() {
+            for token in query.cell_tokens.iter() {
+                let cell_id = CellID::from_token(token);
+                assert_eq!(
+                    searcher.get_shard_from_cell_id(&cell_id).name(),
+                    query.shard
+                );
+            }
+        }
+        assert!(wide.query_for_shard(&local.queries[0].shard).is_some());
+    }
+
+    #[test]
+    fn test_query_cells_within_shards() {
+        let searcher = GeoshardSearcher::from(uniform_collection(5, 300)).with_covering_config(
+            CoveringConfig {
+                min_level: Some(2),
+                max_cells: 8,
+                ..Default::default()
+            },
+        );
+        let planner = ShardQueryPlanner::new(&searcher);
+        let nyc = ll!(-74.0060, 40.7128);
+
+        // coarse covering cells are split at shard boundaries, so every token is in its shard
+        let within_shards = |plan: &QueryPlan| {
+            plan.queries.iter().all(|query| {
+                query.cell_tokens.iter().all(|token| {
+                    let shards = searcher.get_shards_from_cell_id(&CellID::from_token(token));
+                    shards.len() == 1 && shards[0].name() == query.shard
+                })
+            })
+        };
+        let wide = planner.find_users_near(&nyc, 2_000_000.0);
+        assert!(wide.fan_out() > 1);
+        assert!(wide
+            .queries
+            .iter()
+            .flat_map(|query| query.cell_tokens.iter())
+            .any(|token| CellID::from_token(token).level() < 5));
+        assert!(within_shards(&wide));
+
+        let manhattan = Polygon::new(&[
+            ll!(-74.02, 40.70),
+            ll!(-73.97, 40.71),
+            ll!(-73.93, 40.80),
+            ll!(-73.99, 40.77),
+        ]);
+        let plan = planner.find_users_in(&manhattan);
+        assert_eq!(plan.fan_out(), 1);
+        assert_eq!(
+            plan.queries[0].shard,
+            searcher.get_shard_from_location(&nyc).name()
+        );
+        assert!(plan.queries[0]
+            .cell_tokens
+            .iter()
+            .any(|token| CellID::from_token(token).contains(&CellID::from(&nyc))));
+        assert!(within_shards(&plan));
+    }
+}
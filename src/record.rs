@@ -0,0 +1,292 @@
+#![deny(missing_docs)]
+//! record contains `ShardRecord`, a plain, s2 free representation of a shard map for
+//! databases and RPC layers, with conversions to and from `GeoshardCollection`. Shard names,
+//! cells and scores round trip exactly, while per cell scores are summed into each record's
+//! score and not restored, so rebuilt shards have no `Geoshard::cell_scores`
+use s2::{cellid::CellID, cellunion::CellUnion};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::GeoshardError,
+    geoshard::{Geoshard, GeoshardCollection},
+};
+
+/// `ShardRecord` is a contiguous range of cells, from `start_token` to `end_token` inclusive,
+/// belonging to the shard `name`. Shards whose cells are not contiguous (such as pinned clusters
+/// and regions) are stored as several records with the same name
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ShardRecord {
+    /// name of the shard the range belongs to
+    pub name: String,
+    /// token of the first cell in the range
+    pub start_token: String,
+    /// token of the last cell in the range
+    pub end_token: String,
+    /// score of the cells in the range
+    pub score: i32,
+    /// number of cells in the range
+    pub cell_count: usize,
+}
+
+impl From<&GeoshardCollection> for Vec<ShardRecord> {
+    fn from(collection: &GeoshardCollection) -> Self {
+        collection.shards().iter().flat_map(shard_records).collect()
+    }
+}
+
+impl From<GeoshardCollection> for Vec<ShardRecord> {
+    fn from(collection: GeoshardCollection) -> Self {
+        Vec::from(&collection)
+    }
+}
+
+impl TryFrom<Vec<ShardRecord>> for GeoshardCollection {
+    type Error = GeoshardError;
+
+    /// rebuilds the collection, merging records with the same name into one shard in the order
+    /// they first appear. Per cell scores are not part of a record, so rebuilt shards only keep
+    /// their total score. Records are checked before any cells are allocated: each range must
+    /// hold exactly `cell_count` cells at the storage level, and no two ranges may share a cell
+    fn try_from(records: Vec<ShardRecord>) -> Result<Self, Self::Error> {
+        let invalid = |shard: &str, reason: String| GeoshardError::InvalidShardMap {
+            reason,
+            shard: Some(shard.to_owned()),
+            cell: None,
+        };
+        let mut storage_level = None;
+        let mut ranges: Vec<(CellID, CellID, ShardRecord)> = Vec::with_capacity(records.len());
+        for record in records {
+            let start = parse_token(&record.start_token).ok_or_else(|| {
+                invalid(
+                    &record.name,
+                    format!("shard {} has an invalid start token", record.name),
+                )
+            })?;
+            let end = parse_token(&record.end_token).ok_or_else(|| {
+                invalid(
+                    &record.name,
+                    format!("shard {} has an invalid end token", record.name),
+                )
+            })?;
+            if start.level() != end.level() || start > end {
+                return Err(invalid(
+                    &record.name,
+                    format!(
+                        "shard {} has an invalid range {}..={}",
+                        record.name, record.start_token, record.end_token
+                    ),
+                ));
+            }
+            if *storage_level.get_or_insert(start.level()) != start.level() {
+                return Err(invalid(
+                    &record.name,
+                    format!(
+                        "shard {} is at level {}, expected {}",
+                        record.name,
+                        start.level(),
+                        storage_level.unwrap_or_default()
+                    ),
+                ));
+            }
+
+            // cells of a level are 2 * lsb apart, across faces too
+            let range_len = (end.0 - start.0) / (2 * start.lsb()) + 1;
+            if range_len != record.cell_count as u64 {
+                return Err(invalid(
+                    &record.name,
+                    format!(
+                        "shard {} has {} cells in range, expected {}",
+                        record.name, range_len, record.cell_count
+                    ),
+                ));
+            }
+            ranges.push((start, end, record));
+        }
+
+        let mut sorted: Vec<&(CellID, CellID, ShardRecord)> = ranges.iter().collect();
+        sorted.sort_by_key(|(start, _, _)| *start);
+        for pair in sorted.windows(2) {
+            let ((_, previous_end, previous), (start, _, record)) = (pair[0], pair[1]);
+            if start <= previous_end {
+                return Err(GeoshardError::InvalidShardMap {
+                    reason: format!(
+                        "shards {} and {} overlap at cell {}",
+                        previous.name,
+                        record.name,
+                        start.to_token()
+                    ),
+                    shard: Some(record.name.clone()),
+                    cell: Some(start.to_token()),
+                });
+            }
+        }
+
+        let mut shards: Vec<(String, i32, Vec<CellID>)> = vec![];
+        for (start, end, record) in ranges {
+            let mut cells = vec![];
+            cells
+                .try_reserve_exact(record.cell_count)
+                .map_err(|error| {
+                    invalid(
+                        &record.name,
+                        format!("shard {} is too large: {}", record.name, error),
+                    )
+                })?;
+            cells.push(start);
+            while *cells.last().unwrap() != end {
+                cells.push(cells.last().unwrap().next());
+            }
+
+            match shards.iter_mut().find(|(name, _, _)| *name == record.name) {
+                Some((_, score, shard_cells)) => {
+                    *score += record.score;
+                    shard_cells.extend(cells);
+                }
+                None => shards.push((record.name, record.score, cells)),
+            }
+        }
+
+        let storage_level = storage_level.ok_or_else(|| GeoshardError::InvalidShardMap {
+            reason: "shard map has no shards".to_owned(),
+            shard: None,
+            cell: None,
+        })?;
+        let shards = shards
+            .into_iter()
+            .map(|(name, score, cells)| Geoshard::new(name, score, storage_level, CellUnion(cells)))
+            .collect();
+        Ok(GeoshardCollection::from_shards(storage_level, shards))
+    }
+}
+
+/// splits the shard into records of contiguous cells
+fn shard_records(shard: &Geoshard) -> Vec<ShardRecord> {
+    let cells = &shard.cell_union().0;
+    let cell_scores = shard.cell_scores();
+    let has_scores = cell_scores.len() == cells.len();
+
+    let mut records: Vec<ShardRecord> = vec![];
+    let mut range_start = 0;
+    for index in 1..=cells.len() {
+        if index < cells.len() && cells[index] == cells[index - 1].next() {
+            continue;
+        }
+        // without per cell scores, the whole score goes to the first range
+        let score = if has_scores {
+            cell_scores[range_start..index].iter().sum()
+        } else if records.is_empty() {
+            shard.score()
+        } else {
+            0
+        };
+        records.push(ShardRecord {
+            name: shard.name().to_owned(),
+            start_token: cells[range_start].to_token(),
+            end_token: cells[index - 1].to_token(),
+            score,
+            cell_count: index - range_start,
+        });
+        range_start = index;
+    }
+    records
+}
+
+/// parses a cell token, returning `None` if it is not a valid cell
+fn parse_token(token: &str) -> Option<CellID> {
+    Some(CellID::from_token(token)).filter(CellID::is_valid)
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::cell_list::CellList;
+
+    #[test]
+    fn test_shard_records() {
+        let mut cell_list = CellList::new(3);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 7) as i32;
+        }
+        let mut geoshards = GeoshardCollection::new(100, cell_list.cell_list(), 3);
+        let first = geoshards.shards()[0].cell_union().0.clone();
+        let second = geoshards.shards()[1].cell_union().0.clone();
+        // interleave the first two shards, so they are no longer contiguous
+        let (mut first_cells, mut second_cells) = (vec![], vec![]);
+        for (index, cell_id) in first.iter().chain(second.iter()).enumerate() {
+            if index % 2 == 0 {
+                first_cells.push(*cell_id);
+            } else {
+                second_cells.push(*cell_id);
+            }
+        }
+        let shards: Vec<Geoshard> = geoshards
+            .shards()
+            .iter()
+            .enumerate()
+            .map(|(index, shard)| match index {
+                0 => Geoshard::new(
+                    shard.name().to_owned(),
+                    10,
+                    3,
+                    CellUnion(first_cells.clone()),
+                ),
+                1 => Geoshard::new(
+                    shard.name().to_owned(),
+                    20,
+                    3,
+                    CellUnion(second_cells.clone()),
+                ),
+                _ => Geoshard::new(
+                    shard.name().to_owned(),
+                    shard.score(),
+                    3,
+                    shard.cell_union().clone(),
+                )
+                .with_cell_scores(shard.cell_scores().to_vec()),
+            })
+            .collect();
+        geoshards = GeoshardCollection::from_shards(3, shards);
+
+        let records = Vec::<ShardRecord>::from(&geoshards);
+        assert_eq!(
+            records.len(),
+            first_cells.len() + second_cells.len() + geoshards.shards().len() - 2
+        );
+        let rebuilt = GeoshardCollection::try_from(records.clone()).unwrap();
+        assert_eq!(rebuilt.storage_level(), 3);
+        assert_eq!(rebuilt.shards().len(), geoshards.shards().len());
+        for (rebuilt, shard) in rebuilt.shards().iter().zip(geoshards.shards()) {
+            assert_eq!(rebuilt.name(), shard.name());
+            assert_eq!(rebuilt.score(), shard.score());
+            assert_eq!(rebuilt.cell_union().0, shard.cell_union().0);
+        }
+        assert_eq!(Vec::<ShardRecord>::from(&rebuilt), records);
+
+        let mut broken = records.clone();
+        broken[0].cell_count += 1;
+        assert!(GeoshardCollection::try_from(broken).is_err());
+
+        // a range over a whole face of leaf cells is rejected by its count, without allocating
+        let huge = ShardRecord {
+            name: "huge".to_owned(),
+            start_token: CellID::from_face(0).child_begin_at_level(30).to_token(),
+            end_token: CellID::from_face(0)
+                .child_end_at_level(30)
+                .prev()
+                .to_token(),
+            score: 1,
+            cell_count: 1,
+        };
+        assert!(GeoshardCollection::try_from(vec![huge]).is_err());
+
+        let mut overlapping = records;
+        let mut copy = overlapping[1].clone();
+        copy.name = "copy".to_owned();
+        overlapping.push(copy);
+        assert!(matches!(
+            GeoshardCollection::try_from(overlapping),
+            Err(GeoshardError::InvalidShardMap { cell: Some(_), .. })
+        ));
+        assert!(GeoshardCollection::try_from(vec![]).is_err());
+    }
+}
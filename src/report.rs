@@ -0,0 +1,387 @@
+#![deny(missing_docs)]
+//! report contains the `BuildReport`, which records the configuration a shard map was built with
+//! and summarizes the result, the `BalanceReport` of a shard map's score distribution, and the
+//! `ScorerComparison` of two scorers on the same users
+use std::collections::BTreeMap;
+
+use s2::cellid::CellID;
+use serde_derive::Serialize;
+
+use crate::{
+    geoshard::{GeoshardCollection, PartitionObjective},
+    preset::Preset,
+};
+
+/// `BuildReport` records the builder configuration used for a build, including the values picked
+/// by a `Preset`, along with a summary of the shards that were built
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildReport {
+    /// the preset the builder started from, if any
+    pub preset: Option<Preset>,
+    /// the S2 storage level
+    pub storage_level: u64,
+    /// the minimum shard count
+    pub min_shard_count: i32,
+    /// the maximum shard count
+    pub max_shard_count: i32,
+    /// what was balanced across shards
+    pub objective: PartitionObjective,
+    /// the minimum score of a pinned cluster, if clusters were pinned
+    pub cluster_pin_score: Option<i32>,
+    /// the hard limit on shard score, if any
+    pub max_shard_score: Option<i32>,
+    /// the floor under which neighboring shards were coalesced, if any
+    pub min_shard_score: Option<i32>,
+    /// the maximum geographic extent of a shard in meters, if any
+    pub max_shard_extent: Option<f64>,
+    /// the names of the pinned regions
+    pub pinned_regions: Vec<String>,
+    /// the names of the residency zones
+    pub residency_zones: Vec<String>,
+    /// the number of shards built
+    pub shard_count: usize,
+    /// the total score of every shard
+    pub total_score: i64,
+    /// the standard deviation of the shard scores
+    pub standard_deviation: f64,
+    /// how accurate the scores are, if they were estimated from a sample of the users
+    pub sampling: Option<SamplingEstimate>,
+}
+
+/// `SamplingEstimate` describes a build that scored a sample of the users and scaled the scores
+/// up, see `GeoshardBuilder::with_sampling`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SamplingEstimate {
+    /// the fraction of users scored
+    pub rate: f64,
+    /// the seed the sample was drawn with
+    pub seed: u64,
+    /// the number of users scored
+    pub sampled_users: u64,
+    /// the estimated number of users, the sampled users scaled up by the rate
+    pub estimated_users: u64,
+    /// the relative error of the score of the least scored shard within a 95% confidence
+    /// interval, e.g. 0.02 if its true score is within 2% of the estimate. Larger shards have
+    /// smaller errors. Exact for user counts, and an approximation for weighted scores
+    pub max_relative_error: f64,
+}
+
+impl SamplingEstimate {
+    /// Estimates the error of shards built from a sample of `sampled_users` users at `rate`,
+    /// the least scored of which scored `min_shard_score` once scaled up
+    pub fn new(rate: f64, seed: u64, sampled_users: u64, min_shard_score: i64) -> Self {
+        // a shard holding n users has about n * rate of them sampled, and its scaled up score
+        // has a relative standard error of sqrt((1 - rate) / (n * rate))
+        let sampled_score = (min_shard_score as f64 * rate).max(1.0);
+        Self {
+            rate,
+            seed,
+            sampled_users,
+            estimated_users: (sampled_users as f64 / rate).round() as u64,
+            max_relative_error: 1.96 * ((1.0 - rate) / sampled_score).sqrt(),
+        }
+    }
+}
+
+/// `BalanceReport` summarizes how evenly score is spread across the shards of a map, see
+/// `GeoshardCollection::balance_report`. It serializes to JSON for release gates
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BalanceReport {
+    /// the number of shards
+    pub shard_count: usize,
+    /// the total score of every shard
+    pub total_score: i64,
+    /// the mean shard score
+    pub mean_score: f64,
+    /// the standard deviation of the shard scores
+    pub standard_deviation: f64,
+    /// the heaviest shard's score over the mean score. 1 is perfectly balanced, and a map
+    /// without score has a ratio of 0
+    pub imbalance_ratio: f64,
+    /// the number of shards by score, in equal width buckets from 0 to the heaviest score
+    pub histogram: Vec<HistogramBucket>,
+    /// the heaviest shards, heaviest first
+    pub heaviest_shards: Vec<ShardScore>,
+}
+
+/// `HistogramBucket` counts the shards scoring from `min_score` up to `max_score`. The bounds are
+/// inclusive for the last bucket and exclusive of `max_score` for the others
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistogramBucket {
+    /// lower bound of the bucket
+    pub min_score: i64,
+    /// upper bound of the bucket
+    pub max_score: i64,
+    /// the number of shards in the bucket
+    pub shard_count: usize,
+}
+
+/// `ShardScore` is the name and score of a shard
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShardScore {
+    /// name of the shard
+    pub name: String,
+    /// score of the shard
+    pub score: i32,
+}
+
+/// `ScorerComparison` compares two scorers on the same users, see
+/// `GeoshardBuilder::compare_scorers`. As scorers can score on different scales, divergence is
+/// measured as the difference between the shares of each scorer's total score
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScorerComparison {
+    /// the total score of every cell under the first scorer
+    pub total_score_a: i64,
+    /// the total score of every cell under the second scorer
+    pub total_score_b: i64,
+    /// the cells the scorers score differently, most divergent first
+    pub cells: Vec<CellDivergence>,
+    /// the shards the first scorer builds, with their scores under both scorers, most divergent
+    /// first
+    pub shards: Vec<ShardDivergence>,
+    /// the number of shards built from the first scorer's scores
+    pub shard_count_a: usize,
+    /// the number of shards built from the second scorer's scores
+    pub shard_count_b: usize,
+}
+
+/// `CellDivergence` is a cell's score under both scorers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CellDivergence {
+    /// token of the cell
+    pub cell: String,
+    /// score of the cell under the first scorer
+    pub score_a: i32,
+    /// score of the cell under the second scorer
+    pub score_b: i32,
+    /// the cell's share of the second scorer's total minus its share of the first's
+    pub share_divergence: f64,
+}
+
+/// `ShardDivergence` is a shard's score under both scorers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShardDivergence {
+    /// name of the shard
+    pub name: String,
+    /// score of the shard under the first scorer
+    pub score_a: i64,
+    /// score of the shard under the second scorer
+    pub score_b: i64,
+    /// the shard's share of the second scorer's total minus its share of the first's
+    pub share_divergence: f64,
+}
+
+impl ScorerComparison {
+    /// compares the scored cells, attributing each cell to the shard of `shards_a` holding it
+    pub(crate) fn new(
+        cells_a: &BTreeMap<CellID, i32>,
+        cells_b: &BTreeMap<CellID, i32>,
+        shards_a: &GeoshardCollection,
+        shard_count_b: usize,
+    ) -> Self {
+        let total_score_a: i64 = cells_a.values().map(|score| *score as i64).sum();
+        let total_score_b: i64 = cells_b.values().map(|score| *score as i64).sum();
+        let share_divergence = |score_a: i64, score_b: i64| {
+            share(score_b, total_score_b) - share(score_a, total_score_a)
+        };
+
+        let mut cell_ids: Vec<&CellID> = cells_a.keys().chain(cells_b.keys()).collect();
+        cell_ids.sort();
+        cell_ids.dedup();
+        let mut cells: Vec<CellDivergence> = cell_ids
+            .into_iter()
+            .filter_map(|cell_id| {
+                let score_a = cells_a.get(cell_id).copied().unwrap_or(0);
+                let score_b = cells_b.get(cell_id).copied().unwrap_or(0);
+                (score_a != score_b).then(|| CellDivergence {
+                    cell: cell_id.to_token(),
+                    score_a,
+                    score_b,
+                    share_divergence: share_divergence(score_a as i64, score_b as i64),
+                })
+            })
+            .collect();
+        cells.sort_by(|a, b| {
+            b.share_divergence
+                .abs()
+                .total_cmp(&a.share_divergence.abs())
+        });
+
+        let mut ranges: Vec<(CellID, CellID, usize)> = shards_a
+            .shards()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, shard)| {
+                shard
+                    .cell_union()
+                    .0
+                    .iter()
+                    .map(move |cell_id| (cell_id.range_min(), cell_id.range_max(), index))
+            })
+            .collect();
+        ranges.sort();
+        let mut scores_b = vec![0i64; shards_a.shards().len()];
+        for (cell_id, score) in cells_b.iter() {
+            let position =
+                ranges.partition_point(|(_, range_max, _)| *range_max < cell_id.range_min());
+            if let Some((range_min, _, index)) = ranges.get(position) {
+                if *range_min <= cell_id.range_min() {
+                    scores_b[*index] += *score as i64;
+                }
+            }
+        }
+        let mut shards: Vec<ShardDivergence> = shards_a
+            .shards()
+            .iter()
+            .zip(scores_b)
+            .map(|(shard, score_b)| ShardDivergence {
+                name: shard.name().to_owned(),
+                score_a: shard.score() as i64,
+                score_b,
+                share_divergence: share_divergence(shard.score() as i64, score_b),
+            })
+            .collect();
+        shards.sort_by(|a, b| {
+            b.share_divergence
+                .abs()
+                .total_cmp(&a.share_divergence.abs())
+        });
+
+        Self {
+            total_score_a,
+            total_score_b,
+            cells,
+            shards,
+            shard_count_a: shards_a.shards().len(),
+            shard_count_b,
+        }
+    }
+
+    /// returns the largest difference between a shard's shares of each scorer's total
+    pub fn max_shard_divergence(&self) -> f64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.share_divergence.abs())
+            .fold(0.0, f64::max)
+    }
+}
+
+/// returns `score` as a fraction of `total`, or 0 if there is no total
+fn share(score: i64, total: i64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        score as f64 / total as f64
+    }
+}
+
+impl GeoshardCollection {
+    /// Summarizes the balance of the map, with a histogram of `bucket_count` buckets and the
+    /// `top_k` heaviest shards
+    pub fn balance_report(&self, bucket_count: usize, top_k: usize) -> BalanceReport {
+        let shard_count = self.shards().len();
+        let total_score: i64 = self.shards().iter().map(|shard| shard.score() as i64).sum();
+        let mean_score = if shard_count == 0 {
+            0.0
+        } else {
+            total_score as f64 / shard_count as f64
+        };
+        let max_score = self
+            .shards()
+            .iter()
+            .map(|shard| shard.score().max(0) as i64)
+            .max()
+            .unwrap_or(0);
+        let imbalance_ratio = if mean_score > 0.0 {
+            max_score as f64 / mean_score
+        } else {
+            0.0
+        };
+
+        let width = (max_score / bucket_count.max(1) as i64).max(1);
+        let mut histogram: Vec<HistogramBucket> = (0..bucket_count as i64)
+            .map(|index| HistogramBucket {
+                min_score: index * width,
+                max_score: (index + 1) * width,
+                shard_count: 0,
+            })
+            .collect();
+        if let Some(last) = histogram.last_mut() {
+            last.max_score = last.max_score.max(max_score);
+        }
+        for shard in self.shards() {
+            let index = (shard.score().max(0) as i64 / width).min(bucket_count as i64 - 1);
+            if let Some(bucket) = histogram.get_mut(index as usize) {
+                bucket.shard_count += 1;
+            }
+        }
+
+        let mut heaviest_shards: Vec<ShardScore> = self
+            .shards()
+            .iter()
+            .map(|shard| ShardScore {
+                name: shard.name().to_owned(),
+                score: shard.score(),
+            })
+            .collect();
+        heaviest_shards.sort_by_key(|shard| std::cmp::Reverse(shard.score));
+        heaviest_shards.truncate(top_k);
+
+        BalanceReport {
+            shard_count,
+            total_score,
+            mean_score,
+            standard_deviation: if shard_count == 0 {
+                0.0
+            } else {
+                self.standard_deviation()
+            },
+            imbalance_ratio,
+            histogram,
+            heaviest_shards,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cell_list::CellList;
+
+    #[test]
+    fn test_balance_report() {
+        let mut cell_list = CellList::new(2);
+        for (index, score) in cell_list.mut_cell_list().values_mut().enumerate() {
+            *score = (index % 7) as i32;
+        }
+        let geoshards = GeoshardCollection::new(20, cell_list.cell_list(), 2);
+        let report = geoshards.balance_report(4, 3);
+
+        assert_eq!(report.shard_count, geoshards.shards().len());
+        assert_eq!(report.total_score, 283);
+        assert_eq!(report.standard_deviation, geoshards.standard_deviation());
+        assert_eq!(report.histogram.len(), 4);
+        assert_eq!(
+            report
+                .histogram
+                .iter()
+                .map(|bucket| bucket.shard_count)
+                .sum::<usize>(),
+            report.shard_count
+        );
+        assert_eq!(report.histogram.last().unwrap().max_score, 20);
+        assert_eq!(report.heaviest_shards.len(), 3);
+        assert!(report.heaviest_shards[0].score >= report.heaviest_shards[2].score);
+        assert_eq!(
+            report.imbalance_ratio,
+            report.heaviest_shards[0].score as f64 / report.mean_score
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["heaviest_shards"][0]["score"], 20);
+
+        let empty = GeoshardCollection::from_shards(2, vec![]).balance_report(4, 3);
+        assert_eq!(empty.imbalance_ratio, 0.0);
+        assert!(empty.heaviest_shards.is_empty());
+    }
+}
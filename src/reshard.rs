@@ -0,0 +1,260 @@
+#![deny(missing_docs)]
+//! reshard contains `ReshardPolicy`, which decides from a `UserMovementTracker` when a shard map
+//! should be rebuilt, the threshold, schedule and hotspot policies, and the `ReshardEngine` that
+//! evaluates them and calls back to rebuild
+use std::time::{Duration, SystemTime};
+
+use serde_derive::Serialize;
+
+use crate::tracking::UserMovementTracker;
+
+/// `ReshardContext` is what policies are evaluated against
+pub struct ReshardContext<'a> {
+    /// the live scores of the map being served
+    pub tracker: &'a UserMovementTracker,
+    /// when the policies are evaluated
+    pub now: SystemTime,
+    /// when the map being served was built
+    pub last_reshard: SystemTime,
+}
+
+/// ReshardPolicy is the trait for a rule deciding when to reshard. Implementing this lets a
+/// `ReshardEngine` trigger rebuilds on custom conditions
+pub trait ReshardPolicy {
+    /// name of the policy, as reported in a `ReshardTrigger`
+    fn name(&self) -> &str;
+
+    /// returns why the map should be resharded, or `None` if it shouldn't yet
+    fn evaluate(&self, context: &ReshardContext) -> Option<String>;
+}
+
+/// `ThresholdPolicy` reshards once at least `min_shards` shards drifted past the tracker's
+/// allowed drift (see `UserMovementTracker::shards_under_pressure`)
+#[derive(Debug, Clone, Copy)]
+pub struct ThresholdPolicy {
+    /// the number of shards under pressure that triggers a reshard
+    pub min_shards: usize,
+}
+
+impl ReshardPolicy for ThresholdPolicy {
+    fn name(&self) -> &str {
+        "threshold"
+    }
+
+    fn evaluate(&self, context: &ReshardContext) -> Option<String> {
+        let shards = context.tracker.shards_under_pressure();
+        (!shards.is_empty() && shards.len() >= self.min_shards).then(|| {
+            format!(
+                "{} shards drifted from their build score: {}",
+                shards.len(),
+                shards.join(", ")
+            )
+        })
+    }
+}
+
+/// `SchedulePolicy` reshards once `interval` has passed since the last reshard
+#[derive(Debug, Clone, Copy)]
+pub struct SchedulePolicy {
+    /// the time between reshards
+    pub interval: Duration,
+}
+
+impl ReshardPolicy for SchedulePolicy {
+    fn name(&self) -> &str {
+        "schedule"
+    }
+
+    fn evaluate(&self, context: &ReshardContext) -> Option<String> {
+        let age = context
+            .now
+            .duration_since(context.last_reshard)
+            .unwrap_or_default();
+        (age >= self.interval).then(|| format!("map is {}s old", age.as_secs()))
+    }
+}
+
+/// `HotspotPolicy` reshards once the heaviest shard's live score is over `max_imbalance` times
+/// the mean live score, like `BalanceReport::imbalance_ratio`
+#[derive(Debug, Clone, Copy)]
+pub struct HotspotPolicy {
+    /// the heaviest shard's score over the mean score that triggers a reshard
+    pub max_imbalance: f64,
+}
+
+impl ReshardPolicy for HotspotPolicy {
+    fn name(&self) -> &str {
+        "hotspot"
+    }
+
+    fn evaluate(&self, context: &ReshardContext) -> Option<String> {
+        let scores = context.tracker.live_scores();
+        let total: i64 = scores.iter().map(|(_, score)| *score).sum();
+        if scores.is_empty() || total <= 0 {
+            return None;
+        }
+        let mean = total as f64 / scores.len() as f64;
+        let (shard, score) = scores.iter().max_by_key(|(_, score)| *score)?;
+        let imbalance = *score as f64 / mean;
+        (imbalance > self.max_imbalance).then(|| {
+            format!(
+                "shard {} scores {:.2} times the mean score",
+                shard, imbalance
+            )
+        })
+    }
+}
+
+/// `ReshardTrigger` is the policy that triggered a reshard, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReshardTrigger {
+    /// name of the policy
+    pub policy: String,
+    /// why the policy triggered
+    pub reason: String,
+}
+
+/// `ReshardEngine` evaluates its policies in the order they were added, and calls back to
+/// rebuild or rebalance on the first one that triggers, e.g. rebuilding from
+/// `UserMovementTracker::snapshot` with `GeoshardBuilder::rebuild_from_snapshot`
+pub struct ReshardEngine<F> {
+    policies: Vec<Box<dyn ReshardPolicy + Send + Sync>>,
+    on_reshard: F,
+    last_reshard: SystemTime,
+}
+
+impl<F> ReshardEngine<F>
+where
+    F: FnMut(&ReshardTrigger, &UserMovementTracker),
+{
+    /// Constructs a new `ReshardEngine` without policies for a map built at `built_at`, calling
+    /// `on_reshard` with the trigger and tracker when a policy triggers
+    pub fn new(built_at: SystemTime, on_reshard: F) -> Self {
+        Self {
+            policies: vec![],
+            on_reshard,
+            last_reshard: built_at,
+        }
+    }
+
+    /// adds a policy, evaluated after the policies already added
+    pub fn with_policy<Policy>(mut self, policy: Policy) -> Self
+    where
+        Policy: ReshardPolicy + Send + Sync + 'static,
+    {
+        self.policies.push(Box::new(policy));
+        self
+    }
+
+    /// Evaluates the policies at `now`, calling back on the first one that triggers and
+    /// returning its trigger. The map is then considered resharded at `now`
+    pub fn evaluate(
+        &mut self,
+        tracker: &UserMovementTracker,
+        now: SystemTime,
+    ) -> Option<ReshardTrigger> {
+        let context = ReshardContext {
+            tracker,
+            now,
+            last_reshard: self.last_reshard,
+        };
+        let trigger = self.policies.iter().find_map(|policy| {
+            policy.evaluate(&context).map(|reason| ReshardTrigger {
+                policy: policy.name().to_owned(),
+                reason,
+            })
+        })?;
+        (self.on_reshard)(&trigger, tracker);
+        self.last_reshard = now;
+        Some(trigger)
+    }
+
+    /// returns when the map was last resharded
+    pub fn last_reshard(&self) -> SystemTime {
+        self.last_reshard
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cell_list::ScoredCellSnapshot,
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+        tracking::UserMove,
+        users::User,
+    };
+
+    fn tracker(users: &[FakeUser]) -> UserMovementTracker {
+        let snapshot: ScoredCellSnapshot =
+            GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).snapshot();
+        let shards = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .rebuild_from_snapshot(&snapshot)
+            .unwrap();
+        UserMovementTracker::new(shards, snapshot, 0.1)
+    }
+
+    #[test]
+    fn test_reshard_engine() {
+        let users = FakeUser::seeded(1000, 19, &RandCityFactory::default());
+        let mut tracker = tracker(&users);
+        let built_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut triggers = vec![];
+        let mut engine = ReshardEngine::new(built_at, |trigger: &ReshardTrigger, _: &_| {
+            triggers.push(trigger.clone())
+        })
+        .with_policy(ThresholdPolicy { min_shards: 1 })
+        .with_policy(HotspotPolicy {
+            max_imbalance: 10.0,
+        })
+        .with_policy(SchedulePolicy {
+            interval: Duration::from_secs(86400),
+        });
+
+        assert!(engine.evaluate(&tracker, built_at).is_none());
+
+        // a day later, the schedule triggers
+        let tomorrow = built_at + Duration::from_secs(86400);
+        let trigger = engine.evaluate(&tracker, tomorrow).unwrap();
+        assert_eq!(trigger.policy, "schedule");
+        assert_eq!(engine.last_reshard(), tomorrow);
+        assert!(engine.evaluate(&tracker, tomorrow).is_none());
+
+        // a wave of signups in one place puts its shard under pressure
+        for index in 0..200 {
+            tracker.record(&UserMove {
+                user_id: index.to_string(),
+                from: None,
+                to: Some(users[0].location().clone()),
+            });
+        }
+        let trigger = engine.evaluate(&tracker, tomorrow).unwrap();
+        assert_eq!(trigger.policy, "threshold");
+        drop(engine);
+        assert_eq!(triggers.len(), 2);
+    }
+
+    #[test]
+    fn test_hotspot_policy() {
+        let users = FakeUser::seeded(1000, 19, &RandCityFactory::default());
+        let mut tracker = tracker(&users);
+        let policy = HotspotPolicy { max_imbalance: 2.0 };
+        fn context(tracker: &UserMovementTracker) -> ReshardContext<'_> {
+            ReshardContext {
+                tracker,
+                now: SystemTime::UNIX_EPOCH,
+                last_reshard: SystemTime::UNIX_EPOCH,
+            }
+        }
+        assert!(policy.evaluate(&context(&tracker)).is_none());
+        for index in 0..5000 {
+            tracker.record(&UserMove {
+                user_id: index.to_string(),
+                from: None,
+                to: Some(users[0].location().clone()),
+            });
+        }
+        assert!(policy.evaluate(&context(&tracker)).is_some());
+    }
+}
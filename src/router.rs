@@ -0,0 +1,167 @@
+#![deny(missing_docs)]
+//! router contains `ShardRouter`, which holds a connection (a database pool, a client, ...) per
+//! shard of a map and routes users and radius queries straight to the connections serving them
+use std::collections::HashMap;
+
+use s2::latlng::LatLng;
+
+use crate::{
+    geoshard::{Geoshard, GeoshardSearcher},
+    users::User,
+};
+
+/// `ShardRouter<C>` routes with a searcher to the connection of each shard, made when the router
+/// is constructed by a factory given the shard
+pub struct ShardRouter<C> {
+    searcher: GeoshardSearcher,
+    connections: Vec<C>,
+    indexes: HashMap<String, usize>,
+}
+
+impl<C> ShardRouter<C> {
+    /// Constructs a new `ShardRouter`, connecting to every shard of the searcher's map with
+    /// `connect`, e.g. `|shard| Pool::new(&format!("postgres://{}.db.internal/users", shard.name()))`
+    pub fn new<F>(searcher: GeoshardSearcher, mut connect: F) -> Self
+    where
+        F: FnMut(&Geoshard) -> C,
+    {
+        match Self::try_new(searcher, |shard| {
+            Ok::<_, std::convert::Infallible>(connect(shard))
+        }) {
+            Ok(router) => router,
+            Err(never) => match never {},
+        }
+    }
+
+    /// `new` with a fallible `connect`, returning the first error
+    pub fn try_new<F, E>(searcher: GeoshardSearcher, connect: F) -> Result<Self, E>
+    where
+        F: FnMut(&Geoshard) -> Result<C, E>,
+    {
+        let connections = searcher
+            .shards()
+            .shards()
+            .iter()
+            .map(connect)
+            .collect::<Result<Vec<C>, E>>()?;
+        let indexes = searcher
+            .shards()
+            .shards()
+            .iter()
+            .enumerate()
+            .map(|(index, shard)| (shard.name().to_owned(), index))
+            .collect();
+        Ok(Self {
+            searcher,
+            connections,
+            indexes,
+        })
+    }
+
+    /// returns the searcher routing to the connections
+    pub fn searcher(&self) -> &GeoshardSearcher {
+        &self.searcher
+    }
+
+    /// returns the connection of the shard, if the map has a shard of that name
+    pub fn connection(&self, shard_name: &str) -> Option<&C> {
+        self.indexes
+            .get(shard_name)
+            .map(|index| &self.connections[*index])
+    }
+
+    /// returns every shard's name with its connection, in map order
+    pub fn connections(&self) -> impl Iterator<Item = (&str, &C)> {
+        self.searcher
+            .shards()
+            .shards()
+            .iter()
+            .map(Geoshard::name)
+            .zip(self.connections.iter())
+    }
+
+    /// returns the connection of the shard the user is in
+    pub fn route<T: User>(&self, user: T) -> &C {
+        self.route_location(user.location())
+    }
+
+    /// returns the connection of the shard the location is in
+    pub fn route_location(&self, location: &LatLng) -> &C {
+        self.shard_connection(self.searcher.get_shard_from_location(location))
+    }
+
+    /// returns the connection of every shard within `radius` meters of the location, once each
+    pub fn route_radius(&self, location: &LatLng, radius: u32) -> Vec<&C> {
+        let mut indexes: Vec<usize> = self
+            .searcher
+            .get_shards_from_radius(location, radius)
+            .into_iter()
+            .map(|shard| self.indexes[shard.name()])
+            .collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes
+            .into_iter()
+            .map(|index| &self.connections[index])
+            .collect()
+    }
+
+    /// returns the connection of a shard of the searcher's map
+    fn shard_connection(&self, shard: &Geoshard) -> &C {
+        &self.connections[self.indexes[shard.name()]]
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+    };
+
+    #[test]
+    fn test_shard_router() {
+        let users = FakeUser::seeded(1000, 19, &RandCityFactory::default());
+        let searcher = GeoshardSearcher::from(
+            GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build(),
+        );
+        let router = ShardRouter::new(searcher, |shard| format!("pool:{}", shard.name()));
+        assert_eq!(
+            router.connections().count(),
+            router.searcher().shards().shards().len()
+        );
+
+        for user in users.iter().take(100) {
+            let shard = router.searcher().get_shard_for_user(user);
+            assert_eq!(*router.route(user), format!("pool:{}", shard.name()));
+            assert_eq!(router.connection(shard.name()), Some(router.route(user)));
+        }
+        assert_eq!(router.connection("no_such_shard"), None);
+
+        let location = users[0].location();
+        let expected: Vec<String> = router
+            .searcher()
+            .get_shards_from_radius(location, 2_000_000)
+            .iter()
+            .map(|shard| format!("pool:{}", shard.name()))
+            .collect();
+        let mut routed: Vec<&String> = router.route_radius(location, 2_000_000);
+        routed.sort();
+        routed.dedup();
+        assert_eq!(routed.len(), router.route_radius(location, 2_000_000).len());
+        assert!(routed
+            .iter()
+            .all(|connection| expected.contains(connection)));
+        assert!(routed.contains(&router.route_location(location)));
+
+        let searcher = GeoshardSearcher::from(
+            GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build(),
+        );
+        let failed = ShardRouter::try_new(searcher, |shard| match shard.name().ends_with("_2") {
+            true => Err(shard.name().to_owned()),
+            false => Ok(()),
+        });
+        assert_eq!(failed.err(), Some("geoshard_user_index_2".to_owned()));
+    }
+}
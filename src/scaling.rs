@@ -0,0 +1,109 @@
+#![deny(missing_docs)]
+//! scaling turns shard scores into suggested read replica counts, which can feed
+//! autoscaler policies straight from the shard map
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geoshard::{Geoshard, GeoshardCollection};
+
+/// `ScalingAdvisor` estimates the load on each shard from its score, using a simple linear cost
+/// model (`qps_per_score` queries per second for every point of score), and suggests how many
+/// replicas are needed to serve it
+#[derive(Debug, Clone)]
+pub struct ScalingAdvisor {
+    qps_per_score: f64,
+    min_replicas: u32,
+}
+
+/// `ReplicaSuggestion` is the suggested replica count for one shard
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ReplicaSuggestion {
+    /// name of the shard
+    pub shard: String,
+    /// estimated queries per second for the shard
+    pub estimated_qps: f64,
+    /// replica count the shard currently has, 0 if unknown
+    pub current: u32,
+    /// suggested replica count
+    pub suggested: u32,
+    /// replicas to add (positive) or remove (negative) to reach the suggestion
+    pub delta: i64,
+}
+
+impl ScalingAdvisor {
+    /// Constructs a new `ScalingAdvisor` where each point of score is expected to produce
+    /// `qps_per_score` queries per second, and every shard keeps at least `min_replicas`
+    pub fn new(qps_per_score: f64, min_replicas: u32) -> Self {
+        Self {
+            qps_per_score,
+            min_replicas,
+        }
+    }
+
+    /// returns the estimated queries per second for the shard
+    pub fn estimated_qps(&self, shard: &Geoshard) -> f64 {
+        shard.score().max(0) as f64 * self.qps_per_score
+    }
+
+    /// returns the number of replicas needed to serve the shard when each replica can handle
+    /// `target_qps_per_replica` queries per second
+    pub fn replicas_for(&self, shard: &Geoshard, target_qps_per_replica: f64) -> u32 {
+        let replicas = (self.estimated_qps(shard) / target_qps_per_replica).ceil() as u32;
+        replicas.max(self.min_replicas)
+    }
+
+    /// suggests replica counts for every shard in the collection, along with the change from
+    /// the `current` replica counts (keyed by shard name)
+    pub fn suggest(
+        &self,
+        geoshards: &GeoshardCollection,
+        target_qps_per_replica: f64,
+        current: &BTreeMap<String, u32>,
+    ) -> Vec<ReplicaSuggestion> {
+        geoshards
+            .shards()
+            .iter()
+            .map(|shard| {
+                let current = current.get(shard.name()).copied().unwrap_or(0);
+                let suggested = self.replicas_for(shard, target_qps_per_replica);
+                ReplicaSuggestion {
+                    shard: shard.name().to_owned(),
+                    estimated_qps: self.estimated_qps(shard),
+                    current,
+                    suggested,
+                    delta: suggested as i64 - current as i64,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cell_list::CellList;
+
+    #[test]
+    fn test_replica_suggestions() {
+        let mut cell_list = CellList::new(2);
+        cell_list
+            .mut_cell_list()
+            .values_mut()
+            .for_each(|score| *score = 10);
+        let geoshards = GeoshardCollection::new(480, cell_list.cell_list(), 2);
+        assert_eq!(geoshards.shards().len(), 2);
+
+        let advisor = ScalingAdvisor::new(2.5, 1);
+        let shard = &geoshards.shards()[0];
+        assert_eq!(advisor.estimated_qps(shard), 1200.0);
+        assert_eq!(advisor.replicas_for(shard, 500.0), 3);
+        assert_eq!(advisor.replicas_for(shard, 5000.0), 1);
+
+        let current = BTreeMap::from([(shard.name().to_owned(), 5)]);
+        let suggestions = advisor.suggest(&geoshards, 500.0, &current);
+        assert_eq!(suggestions[0].delta, -2);
+        assert_eq!(suggestions[1].current, 0);
+        assert_eq!(suggestions[1].delta, 3);
+    }
+}
@@ -0,0 +1,371 @@
+#![deny(missing_docs)]
+//! scatter contains `ScatterGather`, which runs a radius query against every shard it touches
+//! through a `ShardRouter`'s connections, with a limit on the queries in flight, per shard
+//! timeouts and retries, and gathers what the shards answered even when some of them fail. It is
+//! a plain future, so it runs on any async runtime: timeouts sleep with the runtime's timer,
+//! e.g. `tokio::time::sleep`
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::{poll_fn, Future},
+    pin::Pin,
+    task::Poll,
+    time::Duration,
+};
+
+use s2::latlng::LatLng;
+
+use crate::{
+    query::{ShardQuery, ShardQueryPlanner},
+    router::ShardRouter,
+};
+
+/// a boxed future, as the futures in flight have different types
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// an attempt of the query of the shard at an index in the plan, with its result
+type Attempt<'a, T, E> = BoxFuture<'a, (usize, u32, Result<T, ShardFailure<E>>)>;
+
+/// the runtime's sleep, making a future finishing after the duration
+type Sleep<'a> = Box<dyn Fn(Duration) -> BoxFuture<'a, ()> + Send + Sync + 'a>;
+
+/// `ShardFailure` is why a shard's query failed, after its last attempt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShardFailure<E> {
+    /// the query returned an error
+    Error(E),
+    /// the query didn't finish within the timeout
+    TimedOut,
+    /// the router has no connection for the shard, e.g. the map was swapped mid query
+    NoConnection,
+}
+
+impl<E: fmt::Display> fmt::Display for ShardFailure<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShardFailure::Error(error) => write!(f, "{}", error),
+            ShardFailure::TimedOut => write!(f, "timed out"),
+            ShardFailure::NoConnection => write!(f, "no connection"),
+        }
+    }
+}
+
+/// `GatherResult` is what a scatter gather gathered, in the order of the query plan (the
+/// location's own shard first)
+#[derive(Debug)]
+pub struct GatherResult<T, E> {
+    /// the answer of every shard that answered, by shard name
+    pub results: Vec<(String, T)>,
+    /// the failure of every shard that didn't, by shard name
+    pub failures: Vec<(String, ShardFailure<E>)>,
+    /// the number of queries sent, including retries
+    pub attempts: usize,
+}
+
+impl<T, E> GatherResult<T, E> {
+    /// returns true if every shard answered
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// `ScatterGather` fans queries out to the shards of a `ShardRouter`, see the module
+/// documentation
+pub struct ScatterGather<'a, C> {
+    router: &'a ShardRouter<C>,
+    concurrency: usize,
+    retries: u32,
+    timeout: Option<(Duration, Sleep<'a>)>,
+}
+
+impl<'a, C: Sync> ScatterGather<'a, C> {
+    /// Constructs a new `ScatterGather` through the router's connections, with at most 8 queries
+    /// in flight, no retries and no timeout
+    pub fn new(router: &'a ShardRouter<C>) -> Self {
+        Self {
+            router,
+            concurrency: 8,
+            retries: 0,
+            timeout: None,
+        }
+    }
+
+    /// sets the most queries in flight at once, at least 1
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// sets how many times a shard's failed or timed out query is sent again
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how long each attempt of a shard's query may take. `sleep` is the runtime's sleep,
+    /// e.g. `|timeout| tokio::time::sleep(timeout)`
+    pub fn with_timeout<S, Fut>(mut self, timeout: Duration, sleep: S) -> Self
+    where
+        S: Fn(Duration) -> Fut + Send + Sync + 'a,
+        Fut: Future<Output = ()> + Send + 'a,
+    {
+        self.timeout = Some((
+            timeout,
+            Box::new(move |timeout| Box::pin(sleep(timeout)) as BoxFuture<'a, ()>),
+        ));
+        self
+    }
+
+    /// Runs `query` with the connection of every shard within `radius` meters of the location,
+    /// and the `ShardQuery` of the cells to search in it, gathering the answers
+    pub async fn radius<F, Fut, T, E>(
+        &self,
+        location: &LatLng,
+        radius: u32,
+        query: F,
+    ) -> GatherResult<T, E>
+    where
+        F: Fn(&'a C, ShardQuery) -> Fut + Sync,
+        Fut: Future<Output = Result<T, E>> + Send + 'a,
+        T: Send,
+        E: Send,
+    {
+        let plan =
+            ShardQueryPlanner::new(self.router.searcher()).find_users_near(location, radius as f64);
+        let mut outcomes: Vec<Option<Result<T, ShardFailure<E>>>> =
+            (0..plan.queries.len()).map(|_| None).collect();
+        let mut pending: VecDeque<(usize, u32)> =
+            (0..plan.queries.len()).map(|index| (index, 0)).collect();
+        let mut running: Vec<Attempt<'a, T, E>> = vec![];
+        let mut attempts = 0;
+
+        poll_fn(|cx| loop {
+            while running.len() < self.concurrency {
+                let Some((index, attempt)) = pending.pop_front() else {
+                    break;
+                };
+                attempts += 1;
+                let shard_query = plan.queries[index].clone();
+                running.push(Box::pin(self.attempt(&query, shard_query, index, attempt)));
+            }
+
+            let mut finished = false;
+            let mut position = 0;
+            while position < running.len() {
+                let Poll::Ready((index, attempt, result)) = running[position].as_mut().poll(cx)
+                else {
+                    position += 1;
+                    continue;
+                };
+                drop(running.swap_remove(position));
+                finished = true;
+                match result {
+                    Err(ShardFailure::Error(_) | ShardFailure::TimedOut)
+                        if attempt < self.retries =>
+                    {
+                        pending.push_back((index, attempt + 1))
+                    }
+                    result => outcomes[index] = Some(result),
+                }
+            }
+
+            if running.is_empty() && pending.is_empty() {
+                return Poll::Ready(());
+            }
+            // start the queries that can now run, or wait for a query to wake us
+            if !finished {
+                return Poll::Pending;
+            }
+        })
+        .await;
+
+        let mut gathered = GatherResult {
+            results: vec![],
+            failures: vec![],
+            attempts,
+        };
+        for (shard_query, outcome) in plan.queries.into_iter().zip(outcomes) {
+            match outcome {
+                Some(Ok(result)) => gathered.results.push((shard_query.shard, result)),
+                Some(Err(failure)) => gathered.failures.push((shard_query.shard, failure)),
+                None => unreachable!("every query finishes before gathering"),
+            }
+        }
+        gathered
+    }
+
+    /// sends an attempt of the shard's query, racing it against the timeout if any
+    fn attempt<F, Fut, T, E>(
+        &self,
+        query: &F,
+        shard_query: ShardQuery,
+        index: usize,
+        attempt: u32,
+    ) -> impl Future<Output = (usize, u32, Result<T, ShardFailure<E>>)> + Send + 'a
+    where
+        F: Fn(&'a C, ShardQuery) -> Fut,
+        Fut: Future<Output = Result<T, E>> + Send + 'a,
+    {
+        let call = self
+            .router
+            .connection(&shard_query.shard)
+            .map(|connection| query(connection, shard_query));
+        let sleep = self
+            .timeout
+            .as_ref()
+            .map(|(timeout, sleep)| sleep(*timeout));
+        async move {
+            let Some(call) = call else {
+                return (index, attempt, Err(ShardFailure::NoConnection));
+            };
+            let mut call = Box::pin(call);
+            let result = match sleep {
+                None => call.await.map_err(ShardFailure::Error),
+                Some(mut sleep) => {
+                    poll_fn(|cx| match call.as_mut().poll(cx) {
+                        Poll::Ready(result) => Poll::Ready(result.map_err(ShardFailure::Error)),
+                        Poll::Pending => {
+                            sleep.as_mut().poll(cx).map(|_| Err(ShardFailure::TimedOut))
+                        }
+                    })
+                    .await
+                }
+            };
+            (index, attempt, result)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        task::{Context, Wake, Waker},
+        thread,
+    };
+
+    use super::*;
+    use crate::{
+        geoshard::{GeoshardBuilder, GeoshardSearcher},
+        testing::{FakeUser, RandCityFactory},
+        users::User,
+    };
+
+    /// runs the future to completion on this thread
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct ThreadWaker(thread::Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark()
+            }
+        }
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    /// sleeps on a thread of its own, as a runtime's timer would
+    fn sleep(duration: Duration) -> impl Future<Output = ()> + Send {
+        let done = Arc::new(AtomicBool::new(false));
+        let waker: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+        let mut started = false;
+        poll_fn(move |cx| {
+            if done.load(Ordering::SeqCst) {
+                return Poll::Ready(());
+            }
+            *waker.lock().unwrap() = Some(cx.waker().clone());
+            if !started {
+                started = true;
+                let (done, waker) = (done.clone(), waker.clone());
+                thread::spawn(move || {
+                    thread::sleep(duration);
+                    done.store(true, Ordering::SeqCst);
+                    if let Some(waker) = waker.lock().unwrap().take() {
+                        waker.wake();
+                    }
+                });
+            }
+            Poll::Pending
+        })
+    }
+
+    #[test]
+    fn test_scatter_gather() {
+        let users = FakeUser::seeded(1000, 23, &RandCityFactory::default());
+        let searcher = GeoshardSearcher::from(
+            GeoshardBuilder::user_count_scorer(4, users.iter(), 6, 8).build(),
+        );
+        let router = ShardRouter::new(searcher, |shard| shard.name().to_owned());
+        let location = users[0].location();
+        let radius = 3_000_000;
+        let shard_count = ShardQueryPlanner::new(router.searcher())
+            .find_users_near(location, radius as f64)
+            .fan_out();
+        assert!(shard_count > 2);
+
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let gathered = block_on(ScatterGather::new(&router).with_concurrency(2).radius(
+            location,
+            radius,
+            |connection, query| {
+                let in_flight = &in_flight;
+                let max_in_flight = &max_in_flight;
+                async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(now, Ordering::SeqCst);
+                    sleep(Duration::from_millis(5)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    Ok::<_, String>(format!("{}:{}", connection, query.cell_tokens.len()))
+                }
+            },
+        ));
+        assert!(gathered.is_complete());
+        assert_eq!(gathered.results.len(), shard_count);
+        assert_eq!(gathered.attempts, shard_count);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+        // the location's own shard comes first
+        assert_eq!(
+            gathered.results[0].0,
+            router.searcher().get_shard_from_location(location).name()
+        );
+
+        // one shard hangs and one fails once, the rest answer
+        let own_shard = gathered.results[0].0.clone();
+        let flaky_shard = gathered.results[1].0.clone();
+        let flaked = AtomicBool::new(false);
+        let gathered = block_on(
+            ScatterGather::new(&router)
+                .with_retries(1)
+                .with_timeout(Duration::from_millis(20), sleep)
+                .radius(location, radius, |connection, _| {
+                    let hangs = *connection == own_shard;
+                    let fails = *connection == flaky_shard && !flaked.swap(true, Ordering::SeqCst);
+                    async move {
+                        if hangs {
+                            sleep(Duration::from_secs(5)).await;
+                        }
+                        match fails {
+                            true => Err("unavailable"),
+                            false => Ok(connection.clone()),
+                        }
+                    }
+                }),
+        );
+        assert!(!gathered.is_complete());
+        assert_eq!(gathered.failures, vec![(own_shard, ShardFailure::TimedOut)]);
+        assert_eq!(gathered.results.len(), shard_count - 1);
+        assert_eq!(gathered.results[0].0, flaky_shard);
+        // every shard once, then the flaky and hanging shards again
+        assert_eq!(gathered.attempts, shard_count + 2);
+    }
+}
@@ -0,0 +1,226 @@
+#![deny(missing_docs)]
+//! server contains `ShardLookupService`, the shard lookup service described by
+//! `proto/shard_lookup.proto`. It answers lookups from the current generation of a
+//! `SharedGeoshardSearcher`, so a sidecar only has to bind its messages to a transport. The
+//! crate has no gRPC transport of its own: the service is plain Rust calls, and sidecars serving
+//! it over gRPC generate their bindings from the proto (e.g. with `tonic-build`) and forward to it
+use s2::latlng::LatLng;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{error::GeoshardError, generation::SharedGeoshardSearcher, utils::ll};
+
+/// `LookupRequest` asks for the shard holding a location, in degrees
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct LookupRequest {
+    /// latitude of the location
+    pub lat: f64,
+    /// longitude of the location
+    pub lng: f64,
+}
+
+/// `LookupResponse` is the shard holding the location, and the generation it was found in
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LookupResponse {
+    /// name of the shard
+    pub shard: String,
+    /// generation of the shard map searched
+    pub generation: u64,
+}
+
+/// `RadiusLookupRequest` asks for the shards within `radius` meters of a location, in degrees
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct RadiusLookupRequest {
+    /// latitude of the location
+    pub lat: f64,
+    /// longitude of the location
+    pub lng: f64,
+    /// radius around the location, in meters
+    pub radius: u32,
+}
+
+/// `RadiusLookupResponse` is the shards within the radius, each named once
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RadiusLookupResponse {
+    /// names of the shards, in the order they were found
+    pub shards: Vec<String>,
+    /// generation of the shard map searched
+    pub generation: u64,
+}
+
+/// `GetShardMapResponse` is the whole shard map, serialized as JSON
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GetShardMapResponse {
+    /// the shard map, as `GeoshardCollection` serializes it
+    pub shard_map: String,
+    /// generation of the shard map
+    pub generation: u64,
+}
+
+/// `ShardLookupService` implements the `ShardLookup` service. Each call loads the current
+/// generation once, so a call is answered from a single generation even if it is swapped
+#[derive(Debug, Clone)]
+pub struct ShardLookupService {
+    searcher: SharedGeoshardSearcher,
+}
+
+impl ShardLookupService {
+    /// Constructs a new `ShardLookupService` answering from the searcher's current generation
+    pub fn new(searcher: SharedGeoshardSearcher) -> Self {
+        Self { searcher }
+    }
+
+    /// returns the shard holding the location. Fails with `GeoshardError::InvalidRequest` for
+    /// locations outside of the valid range of degrees, and `GeoshardError::UnmappedCell` if no
+    /// shard holds it and the searcher's miss policy is `MissPolicy::Error`
+    pub fn lookup(&self, request: &LookupRequest) -> Result<LookupResponse, GeoshardError> {
+        let location = location(request.lat, request.lng)?;
+        let (generation, searcher) = self.searcher.load_generation();
+        let shard = searcher
+            .try_get_shard_from_location(&location)
+            .map_err(|error| error.with_generation(generation))?;
+        Ok(LookupResponse {
+            shard: shard.name().to_owned(),
+            generation,
+        })
+    }
+
+    /// returns the shards within the radius of the location. Fails with
+    /// `GeoshardError::InvalidRequest` for locations outside of the valid range of degrees
+    pub fn radius_lookup(
+        &self,
+        request: &RadiusLookupRequest,
+    ) -> Result<RadiusLookupResponse, GeoshardError> {
+        let location = location(request.lat, request.lng)?;
+        let (generation, searcher) = self.searcher.load_generation();
+        let mut shards: Vec<String> = vec![];
+        for shard in searcher.get_shards_from_radius(&location, request.radius) {
+            if !shards.iter().any(|name| name == shard.name()) {
+                shards.push(shard.name().to_owned());
+            }
+        }
+        Ok(RadiusLookupResponse { shards, generation })
+    }
+
+    /// returns the current shard map, serialized as JSON
+    pub fn get_shard_map(&self) -> Result<GetShardMapResponse, GeoshardError> {
+        let (generation, searcher) = self.searcher.load_generation();
+        let shard_map = serde_json::to_string(searcher.shards()).map_err(|error| {
+            GeoshardError::InvalidShardMap {
+                reason: error.to_string(),
+                shard: None,
+                cell: None,
+            }
+        })?;
+        Ok(GetShardMapResponse {
+            shard_map,
+            generation,
+        })
+    }
+}
+
+/// returns the location at the given degrees, if they are in range
+fn location(lat: f64, lng: f64) -> Result<LatLng, GeoshardError> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return Err(GeoshardError::InvalidRequest {
+            reason: format!("location ({}, {}) is out of range", lat, lng),
+        });
+    }
+    Ok(ll!(lng, lat))
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        geoshard::{GeoshardCollection, GeoshardSearcher},
+        testing::uniform_collection,
+    };
+
+    fn searcher(container_size: i32) -> GeoshardSearcher {
+        GeoshardSearcher::from(uniform_collection(4, container_size))
+    }
+
+    #[test]
+    fn test_shard_lookup_service() {
+        let shared = SharedGeoshardSearcher::new(searcher(64));
+        let service = ShardLookupService::new(shared.clone());
+        let new_york = LookupRequest {
+            lat: 40.7128,
+            lng: -74.0060,
+        };
+
+        let response = service.lookup(&new_york).unwrap();
+        let expected = shared
+            .load()
+            .get_shard_from_location(&ll!(new_york.lng, new_york.lat))
+            .name()
+            .to_owned();
+        assert_eq!(response.shard, expected);
+        assert_eq!(response.generation, 0);
+        assert!(matches!(
+            service.lookup(&LookupRequest {
+                lat: 91.0,
+                lng: 0.0
+            }),
+            Err(GeoshardError::InvalidRequest { .. })
+        ));
+
+        let response = service
+            .radius_lookup(&RadiusLookupRequest {
+                lat: new_york.lat,
+                lng: new_york.lng,
+                radius: 50_000_000,
+            })
+            .unwrap();
+        assert!(response.shards.len() > 1);
+        assert!(response.shards.contains(&expected));
+        let mut unique = response.shards.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), response.shards.len());
+
+        // radii are in meters, so 5 km around New York stays well clear of London
+        let london = shared
+            .load()
+            .get_shard_from_location(&ll!(-0.1278, 51.5074))
+            .name()
+            .to_owned();
+        assert_ne!(london, expected);
+        let response = service
+            .radius_lookup(&RadiusLookupRequest {
+                lat: new_york.lat,
+                lng: new_york.lng,
+                radius: 5_000,
+            })
+            .unwrap();
+        assert!(response.shards.contains(&expected));
+        assert!(!response.shards.contains(&london));
+        assert!(response.shards.len() <= 2);
+        // while a city 1,000 to 2,000 km away in another shard is within 2,000 km
+        let current = shared.load();
+        let nearby = [
+            ll!(-87.6298, 41.8781),
+            ll!(-84.3880, 33.7490),
+            ll!(-80.1918, 25.7617),
+            ll!(-66.5795, 51.6485),
+        ]
+        .iter()
+        .map(|location| current.get_shard_from_location(location).name().to_owned())
+        .find(|shard| *shard != expected)
+        .unwrap();
+        let response = service
+            .radius_lookup(&RadiusLookupRequest {
+                lat: new_york.lat,
+                lng: new_york.lng,
+                radius: 2_000_000,
+            })
+            .unwrap();
+        assert!(response.shards.contains(&nearby));
+
+        shared.swap(searcher(1024));
+        let response = service.get_shard_map().unwrap();
+        assert_eq!(response.generation, 1);
+        let shard_map: GeoshardCollection = serde_json::from_str(&response.shard_map).unwrap();
+        assert_eq!(shard_map.shards().len(), 2);
+    }
+}
@@ -0,0 +1,136 @@
+#![deny(missing_docs)]
+//! shadow replays a recorded lookup workload against two shard maps (e.g. the current and the
+//! next generation) and compares them, before traffic is switched to the new map
+use std::collections::{BTreeMap, BTreeSet};
+
+use s2::latlng::LatLng;
+
+use crate::geoshard::GeoshardSearcher;
+
+/// `Lookup` is a single recorded lookup: a user at a location, searching within `radius` meters
+/// (0 for a lookup of the user's own shard)
+#[derive(Debug, Clone)]
+pub struct Lookup {
+    /// id of the user making the lookup
+    pub user_id: String,
+    /// location of the user
+    pub location: LatLng,
+    /// search radius in meters, 0 for a lookup of the user's own shard
+    pub radius: f64,
+}
+
+/// `MapReport` is how a workload played out against one shard map
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapReport {
+    /// number of lookups each shard served, keyed by shard name
+    pub load_by_shard: BTreeMap<String, u64>,
+    /// number of lookups replayed
+    pub lookups: u64,
+    /// total number of shards queried across every lookup
+    pub shards_queried: u64,
+}
+
+impl MapReport {
+    /// returns the mean number of shards a lookup fans out to
+    pub fn mean_fan_out(&self) -> f64 {
+        if self.lookups == 0 {
+            return 0.0;
+        }
+        self.shards_queried as f64 / self.lookups as f64
+    }
+
+    /// returns the load of the busiest shard divided by the mean load, 1.0 being perfectly even
+    pub fn load_imbalance(&self) -> f64 {
+        let busiest = self.load_by_shard.values().copied().max().unwrap_or(0) as f64;
+        let total = self.load_by_shard.values().sum::<u64>() as f64;
+        if total == 0.0 {
+            return 1.0;
+        }
+        busiest / (total / self.load_by_shard.len() as f64)
+    }
+}
+
+/// `ShadowReport` compares a workload replayed against two shard maps
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShadowReport {
+    /// the workload against the first map
+    pub map_a: MapReport,
+    /// the workload against the second map
+    pub map_b: MapReport,
+    /// ids of the users whose own shard differs between the maps
+    pub moved_users: BTreeSet<String>,
+}
+
+/// `ShadowEvaluator` replays workloads against pairs of shard maps
+pub struct ShadowEvaluator;
+
+impl ShadowEvaluator {
+    /// replays every lookup in the workload against both maps and reports the load on each
+    /// map's shards, the fan out of radius lookups and the users whose own shard differs. Maps are
+    /// compared by shard name, so two generations should name their shards consistently
+    pub fn compare<Workload>(
+        map_a: &GeoshardSearcher,
+        map_b: &GeoshardSearcher,
+        workload: Workload,
+    ) -> ShadowReport
+    where
+        Workload: IntoIterator<Item = Lookup>,
+    {
+        let mut report = ShadowReport::default();
+        for lookup in workload {
+            let shard_a = replay(map_a, &lookup, &mut report.map_a);
+            let shard_b = replay(map_b, &lookup, &mut report.map_b);
+            if shard_a != shard_b {
+                report.moved_users.insert(lookup.user_id);
+            }
+        }
+        report
+    }
+}
+
+/// replays the lookup against the searcher, returning the name of the user's own shard
+fn replay<'a>(searcher: &'a GeoshardSearcher, lookup: &Lookup, report: &mut MapReport) -> &'a str {
+    let own_shard = searcher.get_shard_from_location(&lookup.location).name();
+    let shards = if lookup.radius > 0.0 {
+        searcher.shards_near_location(&lookup.location, lookup.radius)
+    } else {
+        vec![searcher.get_shard_from_location(&lookup.location)]
+    };
+
+    report.lookups += 1;
+    report.shards_queried += shards.len() as u64;
+    for shard in shards {
+        *report
+            .load_by_shard
+            .entry(shard.name().to_owned())
+            .or_insert(0) += 1;
+    }
+    own_shard
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{testing::uniform_collection, utils::ll};
+
+    #[test]
+    fn test_compare() {
+        let map_a = GeoshardSearcher::from(uniform_collection(4, 300));
+        let map_b = GeoshardSearcher::from(uniform_collection(4, 400));
+
+        let workload = (0..100).map(|index| Lookup {
+            user_id: format!("user_{}", index),
+            location: ll!(-180.0 + 3.6 * index as f64, 40.0),
+            radius: if index % 2 == 0 { 0.0 } else { 1_000_000.0 },
+        });
+        let same = ShadowEvaluator::compare(&map_a, &map_a, workload.clone());
+        assert!(same.moved_users.is_empty());
+        assert_eq!(same.map_a, same.map_b);
+
+        let report = ShadowEvaluator::compare(&map_a, &map_b, workload);
+        assert_eq!(report.map_a.lookups, 100);
+        assert!(report.map_a.mean_fan_out() >= 1.0);
+        assert!(report.map_a.load_by_shard.len() > report.map_b.load_by_shard.len());
+        assert!(!report.moved_users.is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+#![deny(missing_docs)]
+//! signing contains signatures of serialized shard maps, so routing services refuse to load a
+//! map that was corrupted or tampered with, or that wasn't produced by the resharding pipeline
+//! holding the key. A map is signed over its exact serialized bytes, and its `MapSignature` is
+//! stored beside it (`{map}.sig` for files, see `write_signed` and `load_verified`).
+//!
+//! `HmacSha256Key`, the only scheme built in, is a shared secret MAC rather than a public key
+//! signature: the pipeline and every router hold the same key, so anyone who can verify a map can
+//! also sign one, and a leaked router key lets maps be forged. It detects corruption and maps
+//! from outside the key holders, but doesn't authenticate the publisher. Asymmetric schemes such
+//! as ed25519, where routers only hold the public key, plug in by implementing `MapSigner` and
+//! `MapVerifier` over a crypto library
+use std::{fs, path::Path};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{error::GeoshardError, geoshard::GeoshardCollection};
+
+/// MapSigner is the trait for signing serialized shard maps
+pub trait MapSigner {
+    /// the name of the signature scheme, recorded in the signature, e.g. `hmac-sha256`
+    fn algorithm(&self) -> &str;
+
+    /// returns the signature of the bytes
+    fn sign(&self, bytes: &[u8]) -> Vec<u8>;
+}
+
+/// MapVerifier is the trait for verifying signatures of serialized shard maps
+pub trait MapVerifier {
+    /// the name of the signature scheme, which signatures must have been made with
+    fn algorithm(&self) -> &str;
+
+    /// returns true if `signature` is a valid signature of the bytes
+    fn verify(&self, bytes: &[u8], signature: &[u8]) -> bool;
+}
+
+/// `MapSignature` is the signature of a serialized map, stored beside it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapSignature {
+    /// the name of the signature scheme
+    pub algorithm: String,
+    /// the signature, hex encoded
+    pub signature: String,
+}
+
+impl MapSignature {
+    /// signs the serialized map
+    pub fn sign(bytes: &[u8], signer: &(impl MapSigner + ?Sized)) -> Self {
+        Self {
+            algorithm: signer.algorithm().to_owned(),
+            signature: signer
+                .sign(bytes)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        }
+    }
+
+    /// Checks the signature is a valid signature of the serialized map. Fails with
+    /// `GeoshardError::UnverifiedMap` if it was made with another scheme, isn't hex, or doesn't
+    /// match, where `path` names the map in the error
+    pub fn verify(
+        &self,
+        path: &str,
+        bytes: &[u8],
+        verifier: &(impl MapVerifier + ?Sized),
+    ) -> Result<(), GeoshardError> {
+        let unverified = |reason: String| GeoshardError::UnverifiedMap {
+            path: path.to_owned(),
+            reason,
+        };
+        if self.algorithm != verifier.algorithm() {
+            return Err(unverified(format!(
+                "signed with {}, expected {}",
+                self.algorithm,
+                verifier.algorithm()
+            )));
+        }
+        let signature = decode_hex(&self.signature)
+            .ok_or_else(|| unverified("signature is not hex".to_owned()))?;
+        match verifier.verify(bytes, &signature) {
+            true => Ok(()),
+            false => Err(unverified("signature does not match".to_owned())),
+        }
+    }
+}
+
+/// Serializes the map to `path` and its signature to `{path}.sig`, each written to a temporary
+/// file and renamed into place. The signature is written first, so a router never sees a new
+/// map with an old signature
+pub fn write_signed(
+    path: impl AsRef<Path>,
+    map: &GeoshardCollection,
+    signer: &impl MapSigner,
+) -> Result<(), GeoshardError> {
+    let path = path.as_ref();
+    let failed = |reason: String| GeoshardError::MapUnavailable {
+        path: path.display().to_string(),
+        reason,
+    };
+    let bytes = serde_json::to_vec(map).map_err(|error| failed(error.to_string()))?;
+    let signature = serde_json::to_vec(&MapSignature::sign(&bytes, signer))
+        .map_err(|error| failed(error.to_string()))?;
+    for (path, bytes) in [(signature_path(path), signature), (path.to_owned(), bytes)] {
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, bytes)
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|error| failed(error.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Reads the map at `path` and its signature at `{path}.sig`, and parses the map only once the
+/// signature is verified. Fails with `GeoshardError::UnverifiedMap` if the signature is missing
+/// or doesn't match, and `GeoshardError::MapUnavailable` if the map can't be read or parsed
+pub fn load_verified(
+    path: impl AsRef<Path>,
+    verifier: &impl MapVerifier,
+) -> Result<GeoshardCollection, GeoshardError> {
+    let path = path.as_ref();
+    let name = path.display().to_string();
+    let bytes = fs::read(path).map_err(|error| GeoshardError::MapUnavailable {
+        path: name.clone(),
+        reason: error.to_string(),
+    })?;
+    let signature: MapSignature = fs::read(signature_path(path))
+        .map_err(|error| error.to_string())
+        .and_then(|signature| serde_json::from_slice(&signature).map_err(|error| error.to_string()))
+        .map_err(|reason| GeoshardError::UnverifiedMap {
+            path: name.clone(),
+            reason: format!("no valid signature: {}", reason),
+        })?;
+    signature.verify(&name, &bytes, verifier)?;
+    serde_json::from_slice(&bytes).map_err(|error| GeoshardError::MapUnavailable {
+        path: name,
+        reason: error.to_string(),
+    })
+}
+
+/// returns the path of the signature of the map at `path`
+fn signature_path(path: &Path) -> std::path::PathBuf {
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(".sig");
+    signature.into()
+}
+
+/// returns the bytes of a hex string, or `None` if it isn't hex
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// `HmacSha256Key` signs and verifies maps with HMAC-SHA256 (RFC 2104) under a shared secret
+/// key. Verifying needs the same secret as signing, so every router holding it can sign maps too
+#[derive(Clone)]
+pub struct HmacSha256Key {
+    key: [u8; 64],
+}
+
+impl HmacSha256Key {
+    /// Constructs a new `HmacSha256Key` from the secret key, of at least 32 random bytes
+    pub fn new(key: &[u8]) -> Self {
+        let mut block = [0; 64];
+        match key.len() > 64 {
+            true => block[..32].copy_from_slice(&sha256(&[key])),
+            false => block[..key.len()].copy_from_slice(key),
+        }
+        Self { key: block }
+    }
+
+    /// returns the HMAC of the bytes
+    fn mac(&self, bytes: &[u8]) -> [u8; 32] {
+        let pad = |byte: u8| self.key.map(|key| key ^ byte);
+        let inner = sha256(&[&pad(0x36), bytes]);
+        sha256(&[&pad(0x5c), &inner])
+    }
+}
+
+impl std::fmt::Debug for HmacSha256Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("HmacSha256Key(..)")
+    }
+}
+
+impl MapSigner for HmacSha256Key {
+    fn algorithm(&self) -> &str {
+        "hmac-sha256"
+    }
+
+    fn sign(&self, bytes: &[u8]) -> Vec<u8> {
+        self.mac(bytes).to_vec()
+    }
+}
+
+impl MapVerifier for HmacSha256Key {
+    fn algorithm(&self) -> &str {
+        "hmac-sha256"
+    }
+
+    fn verify(&self, bytes: &[u8], signature: &[u8]) -> bool {
+        // compares every byte, so the time taken doesn't leak how much of the signature matched
+        signature.len() == 32
+            && self
+                .mac(bytes)
+                .iter()
+                .zip(signature)
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+}
+
+/// SHA-256 round constants
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// returns the SHA-256 (FIPS 180-4) digest of the concatenated parts
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let length: usize = parts.iter().map(|part| part.len()).sum();
+    let mut message = Vec::with_capacity(length + 72);
+    for part in parts {
+        message.extend_from_slice(part);
+    }
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(length as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+    };
+
+    #[test]
+    fn test_hmac_sha256() {
+        let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+        assert_eq!(
+            hex(&sha256(&[b"abc"])),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // RFC 4231 test cases 2 and 6
+        assert_eq!(
+            hex(&HmacSha256Key::new(b"Jefe").sign(b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&HmacSha256Key::new(&[0xaa; 131])
+                .sign(b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_signed_map() {
+        let dir =
+            std::env::temp_dir().join(format!("geoshard_test_signing_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shard_map.json");
+
+        let users = FakeUser::seeded(500, 29, &RandCityFactory::default());
+        let map = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let key = HmacSha256Key::new(b"resharding pipeline key");
+        write_signed(&path, &map, &key).unwrap();
+        let loaded = load_verified(&path, &key).unwrap();
+        assert_eq!(
+            serde_json::to_string(&loaded).unwrap(),
+            serde_json::to_string(&map).unwrap()
+        );
+
+        let other_key = HmacSha256Key::new(b"someone else's key");
+        assert!(matches!(
+            load_verified(&path, &other_key),
+            Err(GeoshardError::UnverifiedMap { .. })
+        ));
+
+        // a tampered map fails verification before it is parsed
+        let tampered = fs::read_to_string(&path).unwrap().replacen(
+            "geoshard_user_index_1",
+            "geoshard_user_index_9",
+            1,
+        );
+        fs::write(&path, tampered).unwrap();
+        assert!(matches!(
+            load_verified(&path, &key),
+            Err(GeoshardError::UnverifiedMap { .. })
+        ));
+
+        fs::remove_file(dir.join("shard_map.json.sig")).unwrap();
+        assert!(matches!(
+            load_verified(&path, &key),
+            Err(GeoshardError::UnverifiedMap { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+#![deny(missing_docs)]
+//! simulation replays a stream of located events (e.g. last week's requests) against a shard map,
+//! to forecast the load on each shard before deploying the map. `simulate_load` counts the events
+//! routed to each shard in fixed windows of time, and the resulting `LoadReport` has each shard's
+//! load over time, its peak QPS, and the windows over a capacity
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use s2::latlng::LatLng;
+use serde_derive::Serialize;
+
+use crate::geoshard::GeoshardSearcher;
+
+/// `ShardLoad` is the load on a shard over the simulated period
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShardLoad {
+    /// name of the shard
+    pub name: String,
+    /// the number of events in each window, from the report's start
+    pub events: Vec<u64>,
+    /// the total number of events
+    pub total: u64,
+    /// the highest QPS in a window
+    pub peak_qps: f64,
+    /// the start of the window with the highest QPS, the first of them on a tie
+    pub peak_window: SystemTime,
+}
+
+/// `Hotspot` is a window in which a shard's load went over a capacity
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Hotspot {
+    /// name of the shard
+    pub shard: String,
+    /// the start of the window
+    pub window: SystemTime,
+    /// the QPS in the window
+    pub qps: f64,
+}
+
+/// `LoadReport` is the load on every shard of a map over the windows of a simulated period, see
+/// `simulate_load`. It serializes to JSON for release gates
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoadReport {
+    /// the start of the first window, the window holding the earliest event
+    pub start: SystemTime,
+    /// the width of the windows
+    pub window: Duration,
+    /// the number of windows from the first event's to the last event's
+    pub window_count: usize,
+    /// the load on each shard, in map order
+    pub shards: Vec<ShardLoad>,
+}
+
+impl LoadReport {
+    /// returns the start of the window at the index
+    pub fn window_start(&self, index: usize) -> SystemTime {
+        self.start + self.window * index as u32
+    }
+
+    /// returns every window in which a shard's QPS went over `max_qps`, by shard in map order and
+    /// then in time order
+    pub fn hotspots(&self, max_qps: f64) -> Vec<Hotspot> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .events
+                    .iter()
+                    .enumerate()
+                    .map(|(index, events)| (index, qps(*events, self.window)))
+                    .filter(|(_, qps)| *qps > max_qps)
+                    .map(|(index, qps)| Hotspot {
+                        shard: shard.name.clone(),
+                        window: self.window_start(index),
+                        qps,
+                    })
+            })
+            .collect()
+    }
+
+    /// returns the highest QPS of any shard in any window
+    pub fn peak_qps(&self) -> f64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.peak_qps)
+            .fold(0.0, f64::max)
+    }
+}
+
+/// returns the QPS of the number of events in a window
+fn qps(events: u64, window: Duration) -> f64 {
+    events as f64 / window.as_secs_f64()
+}
+
+/// Routes each event, a location at a time, with the searcher, and counts the events of each
+/// shard in windows of `window` (at least a second) aligned to the Unix epoch. Events can be in
+/// any order. Without events, the report has no windows
+pub fn simulate_load<Events>(
+    searcher: &GeoshardSearcher,
+    events: Events,
+    window: Duration,
+) -> LoadReport
+where
+    Events: IntoIterator<Item = (SystemTime, LatLng)>,
+{
+    let window = Duration::from_secs(window.as_secs().max(1));
+    let shard_indexes: HashMap<&str, usize> = searcher
+        .shards()
+        .shards()
+        .iter()
+        .enumerate()
+        .map(|(index, shard)| (shard.name(), index))
+        .collect();
+
+    // events by window, since the epoch, and shard
+    let mut counts: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for (time, location) in events {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let shard = shard_indexes[searcher.get_shard_from_location(&location).name()];
+        counts
+            .entry(since_epoch.as_secs() / window.as_secs())
+            .or_insert_with(|| vec![0; shard_indexes.len()])[shard] += 1;
+    }
+
+    let first = counts.keys().next().copied().unwrap_or(0);
+    let window_count = counts
+        .keys()
+        .next_back()
+        .map_or(0, |last| (last - first + 1) as usize);
+    let start = UNIX_EPOCH + Duration::from_secs(first * window.as_secs());
+    let shards = searcher
+        .shards()
+        .shards()
+        .iter()
+        .enumerate()
+        .map(|(shard, geoshard)| {
+            let mut events = vec![0; window_count];
+            for (index, counts) in counts.iter() {
+                events[(index - first) as usize] = counts[shard];
+            }
+            let (peak_index, peak) =
+                events
+                    .iter()
+                    .enumerate()
+                    .fold((0, 0), |peak, (index, events)| match *events > peak.1 {
+                        true => (index, *events),
+                        false => peak,
+                    });
+            ShardLoad {
+                name: geoshard.name().to_owned(),
+                total: events.iter().sum(),
+                peak_qps: qps(peak, window),
+                peak_window: start + window * peak_index as u32,
+                events,
+            }
+        })
+        .collect();
+
+    LoadReport {
+        start,
+        window,
+        window_count,
+        shards,
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+        users::User,
+    };
+
+    #[test]
+    fn test_simulate_load() {
+        let users = FakeUser::seeded(1000, 17, &RandCityFactory::default());
+        let searcher = GeoshardSearcher::from(
+            GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build(),
+        );
+        let minute = Duration::from_secs(60);
+        let start = UNIX_EPOCH + minute * 1000;
+
+        // a user a second for ten minutes, then a burst of 600 from one user in the next minute
+        let mut events: Vec<(SystemTime, LatLng)> = users[..600]
+            .iter()
+            .enumerate()
+            .map(|(second, user)| {
+                (
+                    start + Duration::from_secs(second as u64),
+                    user.location().clone(),
+                )
+            })
+            .collect();
+        let hot = users[0].location().clone();
+        events.extend((0..600).map(|_| (start + minute * 10, hot.clone())));
+        events.reverse();
+
+        let report = simulate_load(&searcher, events, minute);
+        assert_eq!(report.start, start);
+        assert_eq!(report.window_count, 11);
+        assert_eq!(
+            report.shards.iter().map(|shard| shard.total).sum::<u64>(),
+            1200
+        );
+        let hot_shard = searcher.get_shard_from_location(&hot).name();
+        let hot_load = report
+            .shards
+            .iter()
+            .find(|shard| shard.name == hot_shard)
+            .unwrap();
+        assert!(hot_load.peak_qps >= 10.0);
+        assert_eq!(hot_load.peak_window, start + minute * 10);
+        assert_eq!(report.peak_qps(), hot_load.peak_qps);
+
+        let hotspots = report.hotspots(1.0);
+        assert_eq!(hotspots.len(), 1);
+        assert_eq!(hotspots[0].shard, hot_shard);
+        assert_eq!(hotspots[0].window, report.window_start(10));
+        assert!(report.hotspots(hot_load.peak_qps).is_empty());
+
+        let empty = simulate_load(&searcher, vec![], minute);
+        assert_eq!(empty.window_count, 0);
+        assert!(empty.shards.iter().all(|shard| shard.peak_qps == 0.0));
+    }
+}
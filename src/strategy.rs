@@ -0,0 +1,228 @@
+#![deny(missing_docs)]
+//! strategy contains `PartitionStrategy`, how the partitioner splits the ordered cells into
+//! contiguous shards, with the greedy container size sweep and an optimal min-max partition
+use std::cmp::Ordering;
+
+/// PartitionStrategy splits the scores of the cells, in cell order, into contiguous runs, one
+/// per shard. Implementing this lets `GeoshardBuilder::with_partition_strategy` trade build time
+/// for balance
+pub trait PartitionStrategy {
+    /// Returns the number of cells in each shard, in order, summing to `scores.len()`. Should
+    /// return between `min_shard_count` and `max_shard_count` runs, and no empty run, where the
+    /// number of cells allows it
+    fn partition(
+        &self,
+        scores: &[i32],
+        min_shard_count: usize,
+        max_shard_count: usize,
+    ) -> Vec<usize>;
+}
+
+/// `GreedySweep` packs the cells greedily into shards of every container size allowed by the
+/// shard counts, keeping the packing with the lowest standard deviation. This is what the
+/// builder does by default, where it also honors deadlines, cancellation and shard extents.
+/// It is fast, but can be up to twice as unbalanced as optimal on skewed scores
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GreedySweep;
+
+impl PartitionStrategy for GreedySweep {
+    fn partition(
+        &self,
+        scores: &[i32],
+        min_shard_count: usize,
+        max_shard_count: usize,
+    ) -> Vec<usize> {
+        let total: i64 = scores.iter().map(|score| *score as i64).sum();
+        let max_size = total / min_shard_count.max(1) as i64;
+        let min_size = total / max_shard_count.max(1) as i64;
+        let mut best: Option<(f64, Vec<usize>)> = None;
+        for container_size in min_size..=max_size {
+            let runs = pack(scores, container_size);
+            let deviation = standard_deviation(scores, &runs);
+            if best
+                .as_ref()
+                .is_none_or(|(best_deviation, _)| deviation < *best_deviation)
+            {
+                best = Some((deviation, runs));
+            }
+        }
+        best.map(|(_, runs)| runs)
+            .unwrap_or_else(|| pack(scores, max_size))
+    }
+}
+
+/// `OptimalContiguous` splits the cells into exactly `shard_count` shards (the builder's
+/// `max_shard_count` if unset, and never more than there are cells), minimizing the score of
+/// the heaviest shard. The optimal maximum is found by binary search over the scores, each step
+/// a linear pass over the cells, so it runs in O(cells * log(total score)). Negative scores
+/// count as 0
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OptimalContiguous {
+    /// the exact number of shards to build, within the builder's shard count constraints
+    pub shard_count: Option<usize>,
+}
+
+impl PartitionStrategy for OptimalContiguous {
+    fn partition(
+        &self,
+        scores: &[i32],
+        min_shard_count: usize,
+        max_shard_count: usize,
+    ) -> Vec<usize> {
+        if scores.is_empty() {
+            return vec![];
+        }
+        let shard_count = self
+            .shard_count
+            .unwrap_or(max_shard_count)
+            .clamp(min_shard_count.max(1), max_shard_count.max(1))
+            .min(scores.len());
+        let scores: Vec<i64> = scores.iter().map(|score| (*score).max(0) as i64).collect();
+
+        // the lowest maximum for which a greedy packing needs at most `shard_count` shards
+        let (mut low, mut high) = (
+            scores.iter().copied().max().unwrap_or(0),
+            scores.iter().sum::<i64>(),
+        );
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match cut_count(&scores, middle).cmp(&(shard_count - 1)) {
+                Ordering::Greater => low = middle + 1,
+                _ => high = middle,
+            }
+        }
+
+        // splitting a run never raises its score, so extra cuts go anywhere there isn't one
+        let mut cuts = cuts(&scores, low);
+        let mut position = scores.len() - 1;
+        while cuts.len() < shard_count - 1 {
+            if cuts.binary_search(&position).is_err() {
+                let index = cuts.partition_point(|cut| *cut < position);
+                cuts.insert(index, position);
+            }
+            position -= 1;
+        }
+
+        let mut runs = vec![];
+        let mut start = 0;
+        for cut in cuts.into_iter().chain(std::iter::once(scores.len())) {
+            runs.push(cut - start);
+            start = cut;
+        }
+        runs
+    }
+}
+
+/// returns the indexes of the cells starting a new shard when packing greedily under `limit`
+fn cuts(scores: &[i64], limit: i64) -> Vec<usize> {
+    let mut cuts = vec![];
+    let mut current = 0;
+    for (index, score) in scores.iter().enumerate() {
+        if index > 0 && current + score > limit {
+            cuts.push(index);
+            current = 0;
+        }
+        current += score;
+    }
+    cuts
+}
+
+/// returns the number of cuts a greedy packing under `limit` needs
+fn cut_count(scores: &[i64], limit: i64) -> usize {
+    cuts(scores, limit).len()
+}
+
+/// packs the scores greedily into runs scoring at most `container_size`, except for single cells
+/// over it
+fn pack(scores: &[i32], container_size: i64) -> Vec<usize> {
+    let scores: Vec<i64> = scores.iter().map(|score| *score as i64).collect();
+    let mut runs = vec![];
+    let mut start = 0;
+    for cut in cuts(&scores, container_size)
+        .into_iter()
+        .chain(std::iter::once(scores.len()))
+    {
+        if cut > start {
+            runs.push(cut - start);
+        }
+        start = cut;
+    }
+    runs
+}
+
+/// returns the standard deviation of the scores of the runs
+fn standard_deviation(scores: &[i32], runs: &[usize]) -> f64 {
+    let mut start = 0;
+    let sums: Vec<f64> = runs
+        .iter()
+        .map(|run| {
+            let sum: i64 = scores[start..start + run].iter().map(|s| *s as i64).sum();
+            start += run;
+            sum as f64
+        })
+        .collect();
+    let mean = sums.iter().sum::<f64>() / sums.len().max(1) as f64;
+    (sums
+        .iter()
+        .map(|sum| (sum - mean) * (sum - mean))
+        .sum::<f64>()
+        / sums.len().max(1) as f64)
+        .sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn max_run(scores: &[i32], runs: &[usize]) -> i32 {
+        let mut start = 0;
+        runs.iter()
+            .map(|run| {
+                let sum = scores[start..start + run].iter().sum();
+                start += run;
+                sum
+            })
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_optimal_contiguous() {
+        // a skewed distribution the greedy sweep splits badly
+        let scores = [9, 1, 1, 1, 1, 1, 1, 1, 1, 9, 0, 0];
+        let greedy = GreedySweep.partition(&scores, 2, 3);
+        let optimal = OptimalContiguous::default().partition(&scores, 2, 3);
+        assert_eq!(optimal.len(), 3);
+        assert_eq!(optimal.iter().sum::<usize>(), scores.len());
+        assert!(optimal.iter().all(|run| *run > 0));
+        assert_eq!(max_run(&scores, &optimal), 9);
+        assert!(max_run(&scores, &greedy) >= max_run(&scores, &optimal));
+
+        // brute force every split of a small list into three runs
+        let scores = [4, 0, 7, 2, 2, 8, 1, 3, 5];
+        let mut best = i32::MAX;
+        for first in 1..scores.len() - 1 {
+            for second in first + 1..scores.len() {
+                let runs = [first, second - first, scores.len() - second];
+                best = best.min(max_run(&scores, &runs));
+            }
+        }
+        let optimal = OptimalContiguous {
+            shard_count: Some(3),
+        }
+        .partition(&scores, 1, 10);
+        assert_eq!(optimal.len(), 3);
+        assert_eq!(max_run(&scores, &optimal), best);
+
+        // more shards than cells, and all zero scores
+        assert_eq!(
+            OptimalContiguous::default().partition(&[5, 5], 1, 4),
+            vec![1, 1]
+        );
+        assert_eq!(
+            OptimalContiguous::default().partition(&[0; 6], 3, 3).len(),
+            3
+        );
+        assert!(OptimalContiguous::default().partition(&[], 3, 3).is_empty());
+    }
+}
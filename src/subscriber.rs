@@ -0,0 +1,525 @@
+#![deny(missing_docs)]
+//! subscriber contains `ShardMapSubscriber`, which polls a `MapSource` (a file, a directory of
+//! generations, an HTTP URL, or object storage written by a `MapPublisher`) for new shard map
+//! versions, verifies them, and hot-swaps a `SharedGeoshardSearcher` to each one, calling back on
+//! every change
+use std::{
+    fs,
+    io::{Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{
+    error::GeoshardError,
+    generation::{GenerationSwitcher, SharedGeoshardSearcher, SwitchHooks},
+    geoshard::{GeoshardCollection, GeoshardSearcher},
+    utils::stable_hash,
+};
+
+/// `FetchedMap` is a map fetched from a source, with the version it was published as
+#[derive(Debug)]
+pub struct FetchedMap {
+    /// version of the map, as named by the source
+    pub version: String,
+    /// the map, or why the version isn't a valid map
+    pub map: Result<GeoshardCollection, GeoshardError>,
+}
+
+/// MapSource is the trait for where a `ShardMapSubscriber` polls maps from. Implementing this
+/// lets any store of maps feed a subscriber
+pub trait MapSource {
+    /// Returns the latest map if its version isn't `current` (so always when `current` is
+    /// `None`), or `None` if it is. Fails if the
+    /// source can't be read, to be retried on the next poll, while a version that can be read
+    /// but isn't a valid map is returned with the error as its map, and isn't retried
+    fn fetch(&mut self, current: Option<&str>) -> Result<Option<FetchedMap>, GeoshardError>;
+}
+
+/// `FileSource` reads a map from a JSON file, versioned by the file's modification time and
+/// size, so the file is only read again once it changes. Replace the file by renaming a new one
+/// over it, so it is never read half written
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    /// Constructs a new `FileSource` reading the map at `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl MapSource for FileSource {
+    fn fetch(&mut self, current: Option<&str>) -> Result<Option<FetchedMap>, GeoshardError> {
+        let version = file_version(&self.path)?;
+        read_map(&self.path, version, current)
+    }
+}
+
+/// `DirSource` reads the latest map in a directory of map generations. Maps are JSON files, and
+/// the latest is the one with the greatest file name, so generations should be named in order,
+/// e.g. `00042.json` or by timestamp. Versions are the file name with its modification time and
+/// size, as `00042.json@<nanos>-<len>`, so a map read while still being written is read again
+/// once it changes
+#[derive(Debug, Clone)]
+pub struct DirSource {
+    map_dir: PathBuf,
+}
+
+impl DirSource {
+    /// Constructs a new `DirSource` reading the latest map in `map_dir`
+    pub fn new(map_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            map_dir: map_dir.into(),
+        }
+    }
+}
+
+impl MapSource for DirSource {
+    fn fetch(&mut self, current: Option<&str>) -> Result<Option<FetchedMap>, GeoshardError> {
+        let location = self.map_dir.display().to_string();
+        let path = fs::read_dir(&self.map_dir)
+            .map_err(|error| unavailable(&location, error))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .max()
+            .ok_or_else(|| GeoshardError::MapUnavailable {
+                path: location,
+                reason: "no maps in the directory".to_owned(),
+            })?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let version = format!("{}@{}", name, file_version(&path)?);
+        read_map(&path, version, current)
+    }
+}
+
+/// `HttpSource` fetches a map as JSON from a plain `http://` URL, such as a bucket behind an
+/// internal endpoint. The response's `ETag` is the version when there is one, and is sent back
+/// as `If-None-Match` so unchanged maps aren't downloaded again. Otherwise the version is a hash
+/// of the body. TLS isn't supported
+#[derive(Debug, Clone)]
+pub struct HttpSource {
+    url: String,
+    timeout: Duration,
+}
+
+impl HttpSource {
+    /// Constructs a new `HttpSource` fetching the map at `url`, e.g.
+    /// `http://maps.internal:8080/users/shard_map.json`, with a 10 second timeout
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// sets the timeout to connect, and of every read and write
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// sends a GET request, returning the status, the ETag if any, and the body
+    fn get(&self, current: Option<&str>) -> Result<(u16, Option<String>, Vec<u8>), String> {
+        let target = self
+            .url
+            .strip_prefix("http://")
+            .ok_or("only http:// URLs are supported")?;
+        let (host, path) = match target.find('/') {
+            Some(index) => target.split_at(index),
+            None => (target, "/"),
+        };
+        let address = match host.contains(':') {
+            true => host.to_owned(),
+            false => format!("{}:80", host),
+        };
+        let address = std::net::ToSocketAddrs::to_socket_addrs(&address)
+            .map_err(|error| error.to_string())?
+            .next()
+            .ok_or("host not found")?;
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout)
+            .map_err(|error| error.to_string())?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|_| stream.set_write_timeout(Some(self.timeout)))
+            .map_err(|error| error.to_string())?;
+
+        // HTTP/1.0 responses aren't chunked, and end when the connection closes
+        let mut request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n", path, host);
+        if let Some(current) = current {
+            request.push_str(&format!("If-None-Match: {}\r\n", current));
+        }
+        request.push_str("\r\n");
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|error| error.to_string())?;
+        let mut response = vec![];
+        stream
+            .read_to_end(&mut response)
+            .map_err(|error| error.to_string())?;
+
+        let head_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or("malformed response")?;
+        let head = String::from_utf8_lossy(&response[..head_end]).into_owned();
+        let mut lines = head.lines();
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|status| status.parse().ok())
+            .ok_or("malformed status line")?;
+        let etag = lines
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("etag"))
+            .map(|(_, value)| value.trim().to_owned());
+        Ok((status, etag, response[head_end + 4..].to_vec()))
+    }
+}
+
+impl MapSource for HttpSource {
+    fn fetch(&mut self, current: Option<&str>) -> Result<Option<FetchedMap>, GeoshardError> {
+        let (status, etag, body) =
+            self.get(current)
+                .map_err(|reason| GeoshardError::MapUnavailable {
+                    path: self.url.clone(),
+                    reason,
+                })?;
+        match status {
+            304 => return Ok(None),
+            200 => {}
+            status => {
+                return Err(GeoshardError::MapUnavailable {
+                    path: self.url.clone(),
+                    reason: format!("HTTP status {}", status),
+                })
+            }
+        }
+        let version = etag.unwrap_or_else(|| format!("{:016x}", stable_hash(&[&body])));
+        if current == Some(version.as_str()) {
+            return Ok(None);
+        }
+        Ok(Some(FetchedMap {
+            version,
+            map: parse(&self.url, &body),
+        }))
+    }
+}
+
+/// returns the version of the file at `path`, from its modification time and size
+fn file_version(path: &Path) -> Result<String, GeoshardError> {
+    let location = path.display().to_string();
+    let metadata = fs::metadata(path).map_err(|error| unavailable(&location, error))?;
+    let modified = metadata
+        .modified()
+        .map_err(|error| unavailable(&location, error))?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(format!("{}-{}", modified.as_nanos(), metadata.len()))
+}
+
+/// reads the map at `path` if `version` isn't `current`
+fn read_map(
+    path: &Path,
+    version: String,
+    current: Option<&str>,
+) -> Result<Option<FetchedMap>, GeoshardError> {
+    if current == Some(version.as_str()) {
+        return Ok(None);
+    }
+    let location = path.display().to_string();
+    let json = fs::read(path).map_err(|error| unavailable(&location, error))?;
+    Ok(Some(FetchedMap {
+        version,
+        map: parse(&location, &json),
+    }))
+}
+
+/// returns the error for a map that can't be read
+fn unavailable(location: &str, error: std::io::Error) -> GeoshardError {
+    GeoshardError::MapUnavailable {
+        path: location.to_owned(),
+        reason: error.to_string(),
+    }
+}
+
+/// parses a map serialized as JSON
+fn parse(location: &str, json: &[u8]) -> Result<GeoshardCollection, GeoshardError> {
+    serde_json::from_slice(json).map_err(|error| GeoshardError::InvalidShardMap {
+        reason: format!("{}: {}", location, error),
+        shard: None,
+        cell: None,
+    })
+}
+
+/// `MapChange` reports a switch to a new map version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapChange {
+    /// version of the new map
+    pub version: String,
+    /// the generation of the searcher now serving it
+    pub generation: u64,
+    /// the number of shards in the new map
+    pub shard_count: usize,
+}
+
+/// `ShardMapSubscriber` switches a shared searcher to each new map version from its source.
+/// Each version is fetched once: a version that fails to parse, verify or switch is reported
+/// as an error and not retried, while the current generation keeps serving until a newer
+/// version appears
+pub struct ShardMapSubscriber<Source, Hooks> {
+    source: Source,
+    switcher: GenerationSwitcher<Hooks>,
+    version: Option<String>,
+    on_change: Option<OnChange>,
+}
+
+/// a callback on every switch to a new version
+type OnChange = Box<dyn FnMut(&MapChange) + Send>;
+
+impl<Source: MapSource, Hooks: SwitchHooks> ShardMapSubscriber<Source, Hooks> {
+    /// Constructs a new `ShardMapSubscriber` switching the switcher's searcher to maps from
+    /// `source`
+    pub fn new(source: Source, switcher: GenerationSwitcher<Hooks>) -> Self {
+        Self {
+            source,
+            switcher,
+            version: None,
+            on_change: None,
+        }
+    }
+
+    /// Fetches the source's latest map as the first generation of a new
+    /// `SharedGeoshardSearcher`, and subscribes to the versions after it, e.g. to start a
+    /// sidecar from the map it will be kept up to date with. Fails like `poll` if the source
+    /// can't be read or its map doesn't load
+    pub fn open(mut source: Source, hooks: Hooks) -> Result<Self, GeoshardError> {
+        let fetched = source
+            .fetch(None)?
+            .ok_or_else(|| GeoshardError::MapUnavailable {
+                path: "map source".to_owned(),
+                reason: "no map to open".to_owned(),
+            })?;
+        let map = fetched.map?;
+        map.verify()?;
+        let shared = SharedGeoshardSearcher::new(GeoshardSearcher::from(map));
+        Ok(Self {
+            version: Some(fetched.version),
+            ..Self::new(source, GenerationSwitcher::new(shared, hooks))
+        })
+    }
+
+    /// calls `on_change` after every switch to a new version
+    pub fn with_on_change<F>(mut self, on_change: F) -> Self
+    where
+        F: FnMut(&MapChange) + Send + 'static,
+    {
+        self.on_change = Some(Box::new(on_change));
+        self
+    }
+
+    /// returns the switcher used to swap generations
+    pub fn switcher(&self) -> &GenerationSwitcher<Hooks> {
+        &self.switcher
+    }
+
+    /// returns the latest version fetched, whether or not it was switched to
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Polls the source once, switching to its map if it is a new version. Returns the change,
+    /// or `None` if there was no new version
+    pub fn poll(&mut self) -> Result<Option<MapChange>, GeoshardError> {
+        let fetched = match self.source.fetch(self.version.as_deref())? {
+            Some(fetched) => fetched,
+            None => return Ok(None),
+        };
+        self.version = Some(fetched.version.clone());
+        let map = fetched.map?;
+        map.verify()?;
+        let shard_count = map.shards().len();
+        let generation = self
+            .switcher
+            .prepare(GeoshardSearcher::from(map))?
+            .commit()?;
+        let change = MapChange {
+            version: fetched.version,
+            generation,
+            shard_count,
+        };
+        if let Some(on_change) = self.on_change.as_mut() {
+            on_change(&change);
+        }
+        Ok(Some(change))
+    }
+
+    /// Polls every `interval` until `stop` is set, passing failed polls to `on_error` (e.g. to
+    /// log them). Run it on a thread of its own
+    pub fn run<F>(&mut self, interval: Duration, stop: &AtomicBool, mut on_error: F)
+    where
+        F: FnMut(GeoshardError),
+    {
+        while !stop.load(Ordering::Relaxed) {
+            if let Err(error) = self.poll() {
+                on_error(error);
+            }
+            thread::sleep(interval);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::testing::uniform_collection;
+
+    #[test]
+    fn test_file_source() {
+        let path = std::env::temp_dir().join(format!("subscriber_{}.json", std::process::id()));
+        fs::write(
+            &path,
+            serde_json::to_string(&uniform_collection(2, 48)).unwrap(),
+        )
+        .unwrap();
+
+        let shared = SharedGeoshardSearcher::new(GeoshardSearcher::from(uniform_collection(2, 24)));
+        let changes = Arc::new(Mutex::new(vec![]));
+        let recorded = changes.clone();
+        let mut subscriber = ShardMapSubscriber::new(
+            FileSource::new(&path),
+            GenerationSwitcher::new(shared.clone(), ()),
+        )
+        .with_on_change(move |change| recorded.lock().unwrap().push(change.clone()));
+
+        let change = subscriber.poll().unwrap().unwrap();
+        assert_eq!(change.generation, 1);
+        assert_eq!(change.shard_count, 2);
+        assert_eq!(shared.load().shards().shards().len(), 2);
+        assert_eq!(subscriber.poll().unwrap(), None);
+
+        // an invalid version is rejected once, and the current generation keeps serving
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(
+            subscriber.poll(),
+            Err(GeoshardError::InvalidShardMap { .. })
+        ));
+        assert_eq!(subscriber.poll().unwrap(), None);
+        assert_eq!(shared.generation(), 1);
+
+        fs::write(
+            &path,
+            serde_json::to_string(&uniform_collection(2, 96)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(subscriber.poll().unwrap().unwrap().shard_count, 1);
+        assert_eq!(changes.lock().unwrap().len(), 2);
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            subscriber.poll(),
+            Err(GeoshardError::MapUnavailable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_dir_source() {
+        let map_dir = std::env::temp_dir().join(format!("dir_source_{}", std::process::id()));
+        fs::create_dir_all(&map_dir).unwrap();
+        let write = |name: &str, contents: String| fs::write(map_dir.join(name), contents).unwrap();
+        assert!(matches!(
+            ShardMapSubscriber::open(DirSource::new(&map_dir), ()),
+            Err(GeoshardError::MapUnavailable { .. })
+        ));
+
+        write(
+            "0001.json",
+            serde_json::to_string(&uniform_collection(2, 48)).unwrap(),
+        );
+        write("notes.txt", "not a map".to_owned());
+        let mut subscriber = ShardMapSubscriber::open(DirSource::new(&map_dir), ()).unwrap();
+        let shared = subscriber.switcher().shared().clone();
+        assert_eq!(shared.generation(), 0);
+        assert_eq!(shared.load().shards().shards().len(), 2);
+        assert!(subscriber.version().unwrap().starts_with("0001.json@"));
+        assert_eq!(subscriber.poll().unwrap(), None);
+
+        // an invalid map is rejected once, and the current generation keeps serving
+        write("0002.json", "{".to_owned());
+        assert!(matches!(
+            subscriber.poll(),
+            Err(GeoshardError::InvalidShardMap { .. })
+        ));
+        assert_eq!(subscriber.poll().unwrap(), None);
+        assert_eq!(shared.generation(), 0);
+
+        // a map read part way through being written is read again once it is complete
+        let json = serde_json::to_string(&uniform_collection(2, 96)).unwrap();
+        write("0003.json", json[..json.len() / 2].to_owned());
+        assert!(subscriber.poll().is_err());
+        write("0003.json", json);
+        let change = subscriber.poll().unwrap().unwrap();
+        assert_eq!(change.generation, 1);
+        assert!(change.version.starts_with("0003.json@"));
+        assert_eq!(shared.load().shards().shards().len(), 1);
+
+        fs::remove_dir_all(&map_dir).unwrap();
+        assert!(matches!(
+            subscriber.poll(),
+            Err(GeoshardError::MapUnavailable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_http_source() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/maps/shard_map.json",
+            listener.local_addr().unwrap()
+        );
+        let body = serde_json::to_string(&uniform_collection(2, 48)).unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let served = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let length = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..length]).into_owned();
+                let response = match request.contains("If-None-Match: \"v1\"") {
+                    true => "HTTP/1.0 304 Not Modified\r\n\r\n".to_owned(),
+                    false => format!("HTTP/1.0 200 OK\r\nETag: \"v1\"\r\n\r\n{}", body),
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+                served.lock().unwrap().push(request);
+            }
+        });
+
+        let shared = SharedGeoshardSearcher::new(GeoshardSearcher::from(uniform_collection(2, 24)));
+        let mut subscriber =
+            ShardMapSubscriber::new(HttpSource::new(&url), GenerationSwitcher::new(shared, ()));
+        let change = subscriber.poll().unwrap().unwrap();
+        assert_eq!(change.version, "\"v1\"");
+        assert_eq!(change.shard_count, 2);
+        assert_eq!(subscriber.poll().unwrap(), None);
+        assert!(requests.lock().unwrap()[0].starts_with("GET /maps/shard_map.json HTTP/1.0"));
+
+        assert!(matches!(
+            HttpSource::new("https://example.com/map.json").fetch(None),
+            Err(GeoshardError::MapUnavailable { .. })
+        ));
+    }
+}
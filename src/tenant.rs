@@ -0,0 +1,139 @@
+#![deny(missing_docs)]
+//! tenant contains `TenantShardMap`, which keeps an independent shard topology per tenant
+//! (e.g. per customer) and serializes them together, and the searcher routing `(tenant, location)`
+use std::collections::BTreeMap;
+
+#[cfg(feature = "searcher")]
+use s2::latlng::LatLng;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::geoshard::GeoshardCollection;
+#[cfg(feature = "builder")]
+use crate::{cell_list::CellScorer, error::GeoshardError, geoshard::GeoshardBuilder};
+#[cfg(feature = "searcher")]
+use crate::{
+    geoshard::{Geoshard, GeoshardSearcher},
+    users::User,
+};
+
+/// `TenantShardMap` stores an independent `GeoshardCollection` for every tenant key
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct TenantShardMap {
+    tenants: BTreeMap<String, GeoshardCollection>,
+}
+
+impl TenantShardMap {
+    /// Constructs an empty `TenantShardMap`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// stores the shards for the tenant, returning the shards it replaced, if any
+    pub fn insert(
+        &mut self,
+        tenant: impl Into<String>,
+        shards: GeoshardCollection,
+    ) -> Option<GeoshardCollection> {
+        self.tenants.insert(tenant.into(), shards)
+    }
+
+    /// builds the tenant's shards with the given builder and stores them, see
+    /// `GeoshardBuilder::try_build`
+    #[cfg(feature = "builder")]
+    pub fn build<Scorer, UserCollection, T>(
+        &mut self,
+        tenant: impl Into<String>,
+        builder: GeoshardBuilder<Scorer, UserCollection>,
+    ) -> Result<(), GeoshardError>
+    where
+        Scorer: CellScorer<UserCollection>,
+        UserCollection: Iterator<Item = T>,
+        T: User,
+    {
+        self.insert(tenant, builder.try_build()?);
+        Ok(())
+    }
+
+    /// removes the tenant, returning its shards
+    pub fn remove(&mut self, tenant: &str) -> Option<GeoshardCollection> {
+        self.tenants.remove(tenant)
+    }
+
+    /// returns the shards of the tenant
+    pub fn get(&self, tenant: &str) -> Option<&GeoshardCollection> {
+        self.tenants.get(tenant)
+    }
+
+    /// returns the tenant keys, in order
+    pub fn tenants(&self) -> impl Iterator<Item = &str> {
+        self.tenants.keys().map(String::as_str)
+    }
+}
+
+/// `TenantSearcher` finds shards for a `(tenant, location)`, with a `GeoshardSearcher` per tenant
+#[cfg(feature = "searcher")]
+#[derive(Debug)]
+pub struct TenantSearcher {
+    searchers: BTreeMap<String, GeoshardSearcher>,
+}
+
+#[cfg(feature = "searcher")]
+impl TenantSearcher {
+    /// returns the searcher of the tenant
+    pub fn searcher(&self, tenant: &str) -> Option<&GeoshardSearcher> {
+        self.searchers.get(tenant)
+    }
+
+    /// returns the tenant's shard for the given location, or `None` for an unknown tenant
+    pub fn get_shard_from_location(&self, tenant: &str, location: &LatLng) -> Option<&Geoshard> {
+        self.searcher(tenant)
+            .map(|searcher| searcher.get_shard_from_location(location))
+    }
+
+    /// returns the tenant's shard for the given user, or `None` for an unknown tenant
+    pub fn get_shard_for_user<T>(&self, tenant: &str, user: T) -> Option<&Geoshard>
+    where
+        T: User,
+    {
+        self.get_shard_from_location(tenant, user.location())
+    }
+}
+
+#[cfg(feature = "searcher")]
+impl From<TenantShardMap> for TenantSearcher {
+    fn from(map: TenantShardMap) -> Self {
+        Self {
+            searchers: map
+                .tenants
+                .into_iter()
+                .map(|(tenant, shards)| (tenant, GeoshardSearcher::from(shards)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::{testing::uniform_collection, utils::ll};
+
+    #[test]
+    fn test_tenant_searcher() {
+        let mut map = TenantShardMap::new();
+        map.insert("acme", uniform_collection(2, 48));
+        map.insert("globex", uniform_collection(3, 24));
+        assert_eq!(map.tenants().collect::<Vec<&str>>(), vec!["acme", "globex"]);
+
+        let json = serde_json::to_string(&map).unwrap();
+        let parsed: TenantShardMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.get("globex").unwrap().storage_level(), 3);
+
+        let searcher = TenantSearcher::from(parsed);
+        let nyc = ll!(-74.0060, 40.7128);
+        let acme = searcher.get_shard_from_location("acme", &nyc).unwrap();
+        let globex = searcher.get_shard_from_location("globex", &nyc).unwrap();
+        assert_eq!(acme.storage_level(), 2);
+        assert_eq!(globex.storage_level(), 3);
+        assert!(searcher.get_shard_from_location("initech", &nyc).is_none());
+    }
+}
@@ -0,0 +1,145 @@
+#![deny(missing_docs)]
+//! testing contains helpers for testing scorers and routing code against generated users,
+//! such as `FakeUser` and `RandCityFactory` (re-exported from `datagen`). Enabled by the
+//! `test-util` feature
+use lazy_static::lazy_static;
+use rand::{distributions::Alphanumeric, rngs::StdRng, thread_rng, Rng, SeedableRng};
+use s2::latlng::LatLng;
+
+pub use crate::datagen::RandCityFactory;
+use crate::users::{IdentifiedUser, User};
+#[cfg(feature = "builder")]
+use crate::{cell_list::CellList, geoshard::GeoshardCollection};
+
+lazy_static! {
+    static ref RANDOM_CITY_FACTORY: RandCityFactory = RandCityFactory::default();
+}
+
+/// FakeUser is a randomly named user placed in a random city
+#[derive(Debug, Clone)]
+pub struct FakeUser {
+    /// random name used to compare users
+    pub name: String,
+    location: LatLng,
+}
+
+impl PartialEq for FakeUser {
+    fn eq(&self, other: &Self) -> bool {
+        other.name == self.name
+    }
+}
+
+impl FakeUser {
+    /// returns a new user with a random name and city
+    pub fn new() -> Self {
+        Self::in_city_from(&RANDOM_CITY_FACTORY)
+    }
+
+    /// returns a new user with a random name, in a city picked by the given factory
+    pub fn in_city_from(city_factory: &RandCityFactory) -> Self {
+        Self::at(city_factory.new_city())
+    }
+
+    /// returns a new user with a random name at the given location
+    pub fn at(location: LatLng) -> Self {
+        Self::at_from(location, &mut thread_rng())
+    }
+
+    /// returns a new user with a name and city picked with the given RNG
+    pub fn from_rng<R: Rng + ?Sized>(city_factory: &RandCityFactory, rng: &mut R) -> Self {
+        let location = city_factory.new_city_from(rng);
+        Self::at_from(location, rng)
+    }
+
+    /// returns `count` users generated from the given seed. The same seed and city factory
+    /// always produce the same users, so shard maps built from them are reproducible
+    pub fn seeded(count: usize, seed: u64, city_factory: &RandCityFactory) -> Vec<Self> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| Self::from_rng(city_factory, &mut rng))
+            .collect()
+    }
+
+    fn at_from<R: Rng + ?Sized>(location: LatLng, rng: &mut R) -> Self {
+        let name: String = rng
+            .sample_iter(&Alphanumeric)
+            .take(30)
+            .map(char::from)
+            .collect();
+        Self { name, location }
+    }
+}
+
+impl Default for FakeUser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl User for FakeUser {
+    fn location(&self) -> &LatLng {
+        &self.location
+    }
+}
+
+impl User for &FakeUser {
+    fn location(&self) -> &LatLng {
+        &self.location
+    }
+}
+
+impl IdentifiedUser for FakeUser {
+    fn id(&self) -> &[u8] {
+        self.name.as_bytes()
+    }
+}
+
+impl IdentifiedUser for &FakeUser {
+    fn id(&self) -> &[u8] {
+        self.name.as_bytes()
+    }
+}
+
+/// returns a map at `storage_level` in which every cell scores 1, packed into shards of
+/// `container_size` cells, e.g. to test routing code against a map covering the whole world
+#[cfg(feature = "builder")]
+pub fn uniform_collection(storage_level: u64, container_size: i32) -> GeoshardCollection {
+    let mut cell_list = CellList::new(storage_level);
+    cell_list
+        .mut_cell_list()
+        .values_mut()
+        .for_each(|score| *score = 1);
+    GeoshardCollection::new(container_size, cell_list.cell_list(), storage_level)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::ll;
+
+    #[test]
+    fn test_weighted_city_factory() {
+        let city_factory = RandCityFactory::weighted(vec![
+            (ll!(-74.0060, 40.7128), 1),
+            (ll!(-0.1278, 51.5074), 0),
+        ]);
+        for _ in 0..100 {
+            let user = FakeUser::in_city_from(&city_factory);
+            assert!(user.location().lat == city_factory.cities()[0].lat);
+            assert!(user.location().lng == city_factory.cities()[0].lng);
+        }
+        assert_ne!(FakeUser::new(), FakeUser::new());
+    }
+
+    #[test]
+    fn test_seeded_users() {
+        let city_factory = RandCityFactory::default();
+        let users = FakeUser::seeded(50, 42, &city_factory);
+        let same_users = FakeUser::seeded(50, 42, &city_factory);
+        assert_eq!(users, same_users);
+        for (user, same_user) in users.iter().zip(same_users.iter()) {
+            assert!(user.location().lat == same_user.location().lat);
+        }
+        assert_ne!(users, FakeUser::seeded(50, 43, &city_factory));
+    }
+}
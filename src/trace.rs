@@ -0,0 +1,269 @@
+#![deny(missing_docs)]
+//! trace contains the crate's instrumentation, behind the `tracing` feature: spans around
+//! builds (scoring, cell generation, the container search) and searcher lookups, and events
+//! with their results, such as the number of candidate container sizes and the chosen standard
+//! deviation. Spans and events go to the `TraceSubscriber` set with `set_subscriber`, and cost an
+//! atomic load when none is set. `LogfmtSubscriber` writes them to stderr, and a subscriber
+//! forwarding them to the `tracing` crate (or a metrics library) is a few lines
+use std::{
+    fmt,
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// `Value` is the value of a field of a span or event
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// an unsigned integer, such as a count
+    U64(u64),
+    /// a signed integer, such as a score
+    I64(i64),
+    /// a float, such as a standard deviation
+    F64(f64),
+    /// a string, such as a shard name
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::U64(value) => write!(f, "{}", value),
+            Value::I64(value) => write!(f, "{}", value),
+            Value::F64(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::U64(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::U64(value as u64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::I64(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::I64(value as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::F64(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_owned())
+    }
+}
+
+/// `Field` is a named value of a span or event
+pub type Field = (&'static str, Value);
+
+/// TraceSubscriber is the trait for receiving the crate's spans and events
+pub trait TraceSubscriber: Send + Sync {
+    /// called when a span closes, with its fields and how long it was open
+    fn on_span(&self, name: &'static str, fields: &[Field], elapsed: Duration);
+
+    /// called for an event, with its fields
+    fn on_event(&self, name: &'static str, fields: &[Field]);
+}
+
+/// whether a subscriber is set, checked before any span or event is built
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SUBSCRIBER: RwLock<Option<Arc<dyn TraceSubscriber>>> = RwLock::new(None);
+
+/// sets the subscriber of every span and event in the process, replacing any other
+pub fn set_subscriber(subscriber: impl TraceSubscriber + 'static) {
+    *SUBSCRIBER.write().unwrap() = Some(Arc::new(subscriber));
+    ENABLED.store(true, Ordering::Release);
+}
+
+/// removes the subscriber, so spans and events are dropped
+pub fn clear_subscriber() {
+    ENABLED.store(false, Ordering::Release);
+    *SUBSCRIBER.write().unwrap() = None;
+}
+
+/// returns the subscriber, if one is set
+fn subscriber() -> Option<Arc<dyn TraceSubscriber>> {
+    match ENABLED.load(Ordering::Acquire) {
+        true => SUBSCRIBER.read().unwrap().clone(),
+        false => None,
+    }
+}
+
+/// `Span` times the work until it is dropped, then reports it to the subscriber with its fields.
+/// Spans started without a subscriber record nothing
+#[must_use = "a span reports when it is dropped"]
+pub struct Span {
+    name: &'static str,
+    fields: Vec<Field>,
+    started: Option<(Instant, Arc<dyn TraceSubscriber>)>,
+}
+
+impl Span {
+    /// Starts a span named `name`
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            fields: vec![],
+            started: subscriber().map(|subscriber| (Instant::now(), subscriber)),
+        }
+    }
+
+    /// adds a field to the span, if it is recording
+    pub fn record(&mut self, key: &'static str, value: impl Into<Value>) {
+        if self.started.is_some() {
+            self.fields.push((key, value.into()));
+        }
+    }
+
+    /// returns true if the span is recording, to skip computing fields that aren't needed
+    pub fn is_recording(&self) -> bool {
+        self.started.is_some()
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some((started, subscriber)) = &self.started {
+            subscriber.on_span(self.name, &self.fields, started.elapsed());
+        }
+    }
+}
+
+/// reports an event named `name` to the subscriber, building its fields only if one is set
+pub fn event<F>(name: &'static str, fields: F)
+where
+    F: FnOnce() -> Vec<Field>,
+{
+    if let Some(subscriber) = subscriber() {
+        subscriber.on_event(name, &fields());
+    }
+}
+
+/// `LogfmtSubscriber` writes spans and events to stderr as logfmt lines, e.g.
+/// `span=balance elapsed_ms=812.4 candidates=1201 standard_deviation=41.3`. Spans of lookups are
+/// written too, so it is meant for builds and debugging rather than serving
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogfmtSubscriber;
+
+impl LogfmtSubscriber {
+    /// returns the fields as logfmt pairs
+    fn format(fields: &[Field]) -> String {
+        fields
+            .iter()
+            .map(|(key, value)| format!(" {}={}", key, value))
+            .collect()
+    }
+}
+
+impl TraceSubscriber for LogfmtSubscriber {
+    fn on_span(&self, name: &'static str, fields: &[Field], elapsed: Duration) {
+        let _ = writeln!(
+            std::io::stderr(),
+            "span={} elapsed_ms={:.3}{}",
+            name,
+            elapsed.as_secs_f64() * 1000.0,
+            Self::format(fields)
+        );
+    }
+
+    fn on_event(&self, name: &'static str, fields: &[Field]) {
+        let _ = writeln!(std::io::stderr(), "event={}{}", name, Self::format(fields));
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        geoshard::{GeoshardBuilder, GeoshardSearcher},
+        testing::{FakeUser, RandCityFactory},
+        users::User,
+    };
+
+    /// the name and fields of each span or event recorded
+    type Records = Arc<Mutex<Vec<(&'static str, Vec<Field>)>>>;
+
+    #[derive(Default, Clone)]
+    struct Recorder {
+        spans: Records,
+        events: Records,
+    }
+
+    impl TraceSubscriber for Recorder {
+        fn on_span(&self, name: &'static str, fields: &[Field], _: Duration) {
+            self.spans.lock().unwrap().push((name, fields.to_vec()));
+        }
+
+        fn on_event(&self, name: &'static str, fields: &[Field]) {
+            self.events.lock().unwrap().push((name, fields.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let users = FakeUser::seeded(1000, 31, &RandCityFactory::default());
+        let recorder = Recorder::default();
+        set_subscriber(recorder.clone());
+        let shards = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).build();
+        let searcher = GeoshardSearcher::from(shards);
+        searcher.get_shard_from_location(users[0].location());
+        clear_subscriber();
+
+        // other tests build concurrently, so only look for this build's spans
+        let spans = recorder.spans.lock().unwrap();
+        let span = |name: &str| {
+            spans
+                .iter()
+                .find(|(span, fields)| {
+                    *span == name && fields.contains(&("storage_level", Value::U64(4)))
+                })
+                .map(|(_, fields)| fields.clone())
+                .unwrap_or_else(|| panic!("no {} span", name))
+        };
+        assert!(span("build").contains(&("max_shard_count", Value::I64(8))));
+        assert!(span("score_cell_list")
+            .iter()
+            .any(|(key, _)| *key == "scored_cells"));
+        let balance = span("balance");
+        assert!(balance.iter().any(|(key, _)| *key == "candidates"));
+        assert!(balance.contains(&(
+            "standard_deviation",
+            Value::F64(searcher.shards().standard_deviation())
+        )));
+        assert!(spans
+            .iter()
+            .any(|(span, _)| *span == "get_shard_from_location"));
+
+        assert!(recorder
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(event, _)| *event == "container_size_improved"));
+        assert!(!Span::new("unrecorded").is_recording());
+    }
+}
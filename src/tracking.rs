@@ -0,0 +1,218 @@
+#![deny(missing_docs)]
+//! tracking contains `UserMovementTracker`, which follows user moves from a change stream
+//! between builds, keeping cell scores up to date and raising `ShardPressure` alerts when a
+//! shard's live score drifts too far from the score it was built with, as a trigger to reshard
+use std::collections::BTreeMap;
+
+use s2::{cellid::CellID, latlng::LatLng};
+use serde_derive::Serialize;
+
+use crate::{
+    cell_list::ScoredCellSnapshot,
+    geoshard::{GeoshardCollection, GeoshardSearcher},
+};
+
+/// `UserMove` is a change event for a user's location. Users signing up have no `from`, and
+/// users leaving have no `to`
+#[derive(Debug, Clone)]
+pub struct UserMove {
+    /// ID of the user, as in the change stream
+    pub user_id: String,
+    /// where the user was
+    pub from: Option<LatLng>,
+    /// where the user is now
+    pub to: Option<LatLng>,
+}
+
+/// `ShardPressure` alerts that a shard's live score drifted more than the tracker allows from
+/// its score at build time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShardPressure {
+    /// name of the shard
+    pub shard: String,
+    /// score of the shard when the map was built
+    pub build_score: i64,
+    /// score of the shard now
+    pub live_score: i64,
+    /// the change in score as a fraction of the build score, e.g. 0.25 for 25% more users
+    pub drift: f64,
+}
+
+/// `UserMovementTracker` applies user moves to a `ScoredCellSnapshot` and to the live score of
+/// each shard of a map, counting each user once like `UserCountScorer`. Each shard alerts once
+/// when its drift goes over `max_drift`, and again only after drifting back under it
+pub struct UserMovementTracker {
+    searcher: GeoshardSearcher,
+    snapshot: ScoredCellSnapshot,
+    shard_indexes: BTreeMap<String, usize>,
+    build_scores: Vec<i64>,
+    live_scores: Vec<i64>,
+    under_pressure: Vec<bool>,
+    max_drift: f64,
+}
+
+impl UserMovementTracker {
+    /// Constructs a new `UserMovementTracker` for the map built from `snapshot`, alerting when a
+    /// shard's score drifts more than `max_drift` (e.g. 0.1 for 10%) from its build score
+    pub fn new(shards: GeoshardCollection, snapshot: ScoredCellSnapshot, max_drift: f64) -> Self {
+        let build_scores: Vec<i64> = shards
+            .shards()
+            .iter()
+            .map(|shard| shard.score() as i64)
+            .collect();
+        let shard_indexes = shards
+            .shards()
+            .iter()
+            .enumerate()
+            .map(|(index, shard)| (shard.name().to_owned(), index))
+            .collect();
+        Self {
+            searcher: GeoshardSearcher::from(shards),
+            snapshot,
+            shard_indexes,
+            live_scores: build_scores.clone(),
+            under_pressure: vec![false; build_scores.len()],
+            build_scores,
+            max_drift,
+        }
+    }
+
+    /// applies the move, returning the alerts for shards it pushed over the allowed drift
+    pub fn record(&mut self, user_move: &UserMove) -> Vec<ShardPressure> {
+        let mut touched = vec![];
+        for (location, delta) in [(&user_move.from, -1), (&user_move.to, 1)] {
+            let Some(location) = location else {
+                continue;
+            };
+            self.snapshot.apply_delta(CellID::from(location), delta);
+            let shard = self.searcher.get_shard_from_location(location);
+            let index = self.shard_indexes[shard.name()];
+            self.live_scores[index] += delta as i64;
+            touched.push(index);
+        }
+        touched.dedup();
+        touched
+            .into_iter()
+            .filter_map(|index| self.check_pressure(index))
+            .collect()
+    }
+
+    /// returns an alert if the shard just went over the allowed drift
+    fn check_pressure(&mut self, index: usize) -> Option<ShardPressure> {
+        let build_score = self.build_scores[index];
+        let live_score = self.live_scores[index];
+        let drift = (live_score - build_score) as f64 / build_score.max(1) as f64;
+        let over = drift.abs() > self.max_drift;
+        let was_over = std::mem::replace(&mut self.under_pressure[index], over);
+        (over && !was_over).then(|| ShardPressure {
+            shard: self.searcher.shards().shards()[index].name().to_owned(),
+            build_score,
+            live_score,
+            drift,
+        })
+    }
+
+    /// returns the live score of the shard, if the map has a shard with that name
+    pub fn live_score(&self, shard_name: &str) -> Option<i64> {
+        self.shard_indexes
+            .get(shard_name)
+            .map(|index| self.live_scores[*index])
+    }
+
+    /// returns the name and live score of every shard, in map order
+    pub fn live_scores(&self) -> Vec<(&str, i64)> {
+        self.searcher
+            .shards()
+            .shards()
+            .iter()
+            .zip(self.live_scores.iter())
+            .map(|(shard, score)| (shard.name(), *score))
+            .collect()
+    }
+
+    /// returns the shards currently over the allowed drift, by name
+    pub fn shards_under_pressure(&self) -> Vec<&str> {
+        self.searcher
+            .shards()
+            .shards()
+            .iter()
+            .zip(self.under_pressure.iter())
+            .filter(|(_, over)| **over)
+            .map(|(shard, _)| shard.name())
+            .collect()
+    }
+
+    /// returns the cell scores with every move applied, to rebuild the map from with
+    /// `GeoshardBuilder::rebuild_from_snapshot`
+    pub fn snapshot(&self) -> &ScoredCellSnapshot {
+        &self.snapshot
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        geoshard::GeoshardBuilder,
+        testing::{FakeUser, RandCityFactory},
+        users::User,
+        utils::ll,
+    };
+
+    #[test]
+    fn test_user_movement_tracker() {
+        let users = FakeUser::seeded(1000, 17, &RandCityFactory::default());
+        let snapshot = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8).snapshot();
+        let shards = GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+            .rebuild_from_snapshot(&snapshot)
+            .unwrap();
+        let searcher = GeoshardSearcher::from(
+            GeoshardBuilder::user_count_scorer(4, users.iter(), 4, 8)
+                .rebuild_from_snapshot(&snapshot)
+                .unwrap(),
+        );
+        let mut tracker = UserMovementTracker::new(shards, snapshot, 0.1);
+
+        let from = users[0].location().clone();
+        let shard = searcher.get_shard_from_location(&from).name().to_owned();
+        let build_score = tracker.live_score(&shard).unwrap();
+        let to = ll!(-74.0060, 40.7128);
+        let other = searcher.get_shard_from_location(&to).name().to_owned();
+        assert_ne!(shard, other);
+
+        // moves within the allowed drift don't alert
+        let limit = build_score / 10;
+        for index in 0..limit {
+            let alerts = tracker.record(&UserMove {
+                user_id: index.to_string(),
+                from: Some(from.clone()),
+                to: Some(to.clone()),
+            });
+            assert!(alerts.iter().all(|alert| alert.shard != shard));
+        }
+        assert_eq!(tracker.live_score(&shard), Some(build_score - limit));
+
+        // the move past it alerts once
+        let alerts = tracker.record(&UserMove {
+            user_id: "last".to_owned(),
+            from: Some(from.clone()),
+            to: None,
+        });
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].shard, shard);
+        assert_eq!(alerts[0].live_score, build_score - limit - 1);
+        assert!(alerts[0].drift < -0.1);
+        assert!(tracker.shards_under_pressure().contains(&shard.as_str()));
+        assert!(tracker
+            .record(&UserMove {
+                user_id: "again".to_owned(),
+                from: Some(from.clone()),
+                to: None,
+            })
+            .is_empty());
+
+        // the snapshot follows the moves
+        let total: i64 = tracker.snapshot().total_score();
+        assert_eq!(total, 998);
+    }
+}
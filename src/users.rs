@@ -1,8 +1,24 @@
 #![deny(missing_docs)]
 //! User related things, such as the Collection defintion
 //! and User trait
+use std::time::SystemTime;
+#[cfg(feature = "builder")]
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
 use s2::latlng::LatLng;
 
+use crate::point::GeoPoint;
+
+#[cfg(feature = "builder")]
+pub mod loaders;
+
 /// User is the trait for a given user that needs to be distributed
 /// all that is required is a location in the format thats required
 /// by S2 to find the correct cell
@@ -10,3 +26,207 @@ pub trait User {
     /// location returns the S2 LatLng that is used to find the given cell_id
     fn location(&self) -> &LatLng;
 }
+
+/// A location is a user at that location, e.g. for collections of bare locations
+impl User for LatLng {
+    fn location(&self) -> &LatLng {
+        self
+    }
+}
+
+/// PointUser is `User` for users located by a `GeoPoint`, so they can be implemented without
+/// depending on the s2 crate. Every `PointUser` is a `User`
+pub trait PointUser {
+    /// point returns the user's location
+    fn point(&self) -> &GeoPoint;
+}
+
+impl<T: PointUser> User for T {
+    fn location(&self) -> &LatLng {
+        self.point().lat_lng()
+    }
+}
+
+impl<T: PointUser> PointUser for &T {
+    fn point(&self) -> &GeoPoint {
+        (*self).point()
+    }
+}
+
+/// A point is a user at that point, e.g. for collections of bare points
+impl PointUser for GeoPoint {
+    fn point(&self) -> &GeoPoint {
+        self
+    }
+}
+
+/// MultiLocationUser extends `User` with every location the user is at, each weighted, such as a
+/// home and a work location, so scorers like `MultiLocationScorer` can count the user in each of
+/// their cells. `location` remains the user's primary location
+pub trait MultiLocationUser: User {
+    /// locations returns each of the user's locations with its weight. Weights summing to 1
+    /// count the user once across their cells
+    fn locations(&self) -> impl Iterator<Item = (&LatLng, f64)>;
+}
+
+/// TrajectoryUser extends `User` with the user's recent location history, for scorers that
+/// weight the cells a user moves through such as `TrajectoryScorer`
+pub trait TrajectoryUser: User {
+    /// trajectory returns the user's recent locations with when they were seen there, oldest
+    /// first
+    fn trajectory(&self) -> impl Iterator<Item = (&LatLng, SystemTime)>;
+}
+
+/// `LocationSelector` selects which of a `MultiLocationUser`'s locations to route them by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LocationSelector {
+    /// the primary location, `User::location`
+    #[default]
+    Primary,
+    /// the location with the largest weight, the first of them on a tie
+    Heaviest,
+    /// the location at the index in `MultiLocationUser::locations`
+    Nth(usize),
+}
+
+impl LocationSelector {
+    /// returns the selected location of the user, or `None` if they have no such location
+    pub fn select<'a, T: MultiLocationUser>(&self, user: &'a T) -> Option<&'a LatLng> {
+        match self {
+            LocationSelector::Primary => Some(user.location()),
+            LocationSelector::Heaviest => user
+                .locations()
+                .fold(
+                    None,
+                    |heaviest: Option<(&LatLng, f64)>, (location, weight)| match heaviest {
+                        Some((_, max)) if max >= weight => heaviest,
+                        _ => Some((location, weight)),
+                    },
+                )
+                .map(|(location, _)| location),
+            LocationSelector::Nth(index) => {
+                user.locations().nth(*index).map(|(location, _)| location)
+            }
+        }
+    }
+}
+
+/// ActiveUser extends `User` with when the user was last active, for scorers that weight users
+/// by recency such as `TimeDecayScorer`
+pub trait ActiveUser: User {
+    /// last_active returns when the user was last seen
+    fn last_active(&self) -> SystemTime;
+}
+
+/// IdentifiedUser extends `User` with a stable ID, so a user can be assigned deterministically
+/// where the location alone is ambiguous, such as on a shard boundary
+pub trait IdentifiedUser: User {
+    /// id returns the bytes of an ID that never changes for the user, e.g. a UUID
+    fn id(&self) -> &[u8];
+}
+
+/// FallibleUsers adapts a collection of `Result<User, E>` (such as a paginated database scan)
+/// into a collection of users that scorers can iterate over. Iteration stops at the first
+/// error, which is kept so the builder can return it once scoring is done.
+#[cfg(feature = "builder")]
+pub struct FallibleUsers<UserCollection, E> {
+    users: UserCollection,
+    error: Rc<RefCell<Option<E>>>,
+}
+
+#[cfg(feature = "builder")]
+impl<UserCollection, E> FallibleUsers<UserCollection, E> {
+    /// wraps the given fallible collection of users
+    pub fn new(users: UserCollection) -> Self {
+        Self {
+            users,
+            error: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// returns a handle to the first error hit while iterating, if any
+    pub fn error(&self) -> Rc<RefCell<Option<E>>> {
+        self.error.clone()
+    }
+}
+
+#[cfg(feature = "builder")]
+impl<UserCollection, T, E> Iterator for FallibleUsers<UserCollection, E>
+where
+    UserCollection: Iterator<Item = Result<T, E>>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.borrow().is_some() {
+            return None;
+        }
+        match self.users.next()? {
+            Ok(user) => Some(user),
+            Err(error) => {
+                self.error.replace(Some(error));
+                None
+            }
+        }
+    }
+}
+
+/// SampledUsers keeps a random sample of a collection of users, each user with probability
+/// `rate`, as it is iterated. Users are drawn with a generator seeded by `seed`, so the same
+/// seed keeps the same users of the same collection
+#[cfg(feature = "builder")]
+pub struct SampledUsers<UserCollection> {
+    users: UserCollection,
+    /// users are kept when the next random number is below it, or always without one
+    threshold: Option<u64>,
+    state: u64,
+    sampled: Arc<AtomicU64>,
+}
+
+#[cfg(feature = "builder")]
+impl<UserCollection> SampledUsers<UserCollection> {
+    /// samples the given collection of users at `rate`, from 0 to 1
+    pub fn new(users: UserCollection, rate: f64, seed: u64) -> Self {
+        Self {
+            users,
+            threshold: (rate < 1.0).then(|| (rate.max(0.0) * u64::MAX as f64) as u64),
+            state: seed,
+            sampled: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// returns a handle to the number of users kept so far
+    pub fn sampled(&self) -> Arc<AtomicU64> {
+        self.sampled.clone()
+    }
+
+    /// returns the next number of a splitmix64 sequence
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut random = self.state;
+        random = (random ^ (random >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        random = (random ^ (random >> 27)).wrapping_mul(0x94d049bb133111eb);
+        random ^ (random >> 31)
+    }
+}
+
+#[cfg(feature = "builder")]
+impl<UserCollection> Iterator for SampledUsers<UserCollection>
+where
+    UserCollection: Iterator,
+{
+    type Item = UserCollection::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let user = self.users.next()?;
+            if self
+                .threshold
+                .is_none_or(|threshold| self.next_random() < threshold)
+            {
+                self.sampled.fetch_add(1, Ordering::Relaxed);
+                return Some(user);
+            }
+        }
+    }
+}
@@ -0,0 +1,728 @@
+#![deny(missing_docs)]
+//! loaders reads users from files, so a builder can be tried on an export without writing a
+//! loader first: `CsvUserSource` reads CSV files with latitude and longitude columns, and
+//! `JsonlUserSource` reads JSON lines with latitude and longitude fields. Both are iterators of
+//! `LoadedUser`s, and handle rows that don't parse by their `MalformedRowPolicy`, recording
+//! what was skipped in a `LoadReport`
+//!
+//! PostGIS exports are read as they are: `CsvUserSource::geometry` reads a geometry column of
+//! `COPY ... TO ... CSV HEADER` (EWKB hex by default, or WKT with `ST_AsText`/`ST_AsEWKT`), and
+//! `WktUserSource` reads a point per line. Points must be in WGS 84 longitude and latitude,
+//! i.e. without an SRID or with SRID 4326
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    rc::Rc,
+};
+
+use serde_json::Value;
+
+use crate::{error::GeoshardError, point::GeoPoint, users::PointUser};
+
+/// `LoadedUser` is a user read from a file, located by its row's latitude and longitude
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedUser {
+    point: GeoPoint,
+    line: usize,
+}
+
+impl LoadedUser {
+    /// returns the line of the user's row, from 1
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl PointUser for LoadedUser {
+    fn point(&self) -> &GeoPoint {
+        &self.point
+    }
+}
+
+/// `MalformedRowPolicy` is what a loader does with rows it can't parse into a user, such as
+/// rows missing a column or with a latitude out of range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MalformedRowPolicy {
+    /// stops loading at the row, keeping its error in the `LoadReport`
+    #[default]
+    Stop,
+    /// skips the row, keeping its error in the `LoadReport`, and loads the rest
+    Skip,
+}
+
+/// `LoadReport` is what a loader has read so far: the number of users loaded, the rows skipped
+/// and the error that stopped loading, if any
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// number of users loaded
+    pub loaded: usize,
+    /// the rows skipped by `MalformedRowPolicy::Skip`, as `GeoshardError::MalformedRow`s
+    pub skipped: Vec<GeoshardError>,
+    /// the error loading stopped at, either a malformed row or a failed read
+    pub error: Option<GeoshardError>,
+}
+
+/// `Rows` reads the lines of a file of users, applying the malformed row policy
+struct Rows<R> {
+    reader: R,
+    path: String,
+    line: usize,
+    policy: MalformedRowPolicy,
+    report: Rc<RefCell<LoadReport>>,
+}
+
+impl<R: BufRead> Rows<R> {
+    fn new(reader: R, path: String) -> Self {
+        Self {
+            reader,
+            path,
+            line: 0,
+            policy: MalformedRowPolicy::default(),
+            report: Rc::new(RefCell::new(LoadReport::default())),
+        }
+    }
+
+    /// returns the next line that isn't blank, without its line ending
+    fn next_line(&mut self) -> Option<Result<String, GeoshardError>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            self.line += 1;
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) if line.trim().is_empty() => continue,
+                Ok(_) => return Some(Ok(line.trim_end_matches(['\r', '\n']).to_owned())),
+                Err(error) => {
+                    return Some(Err(GeoshardError::UserSourceUnavailable {
+                        path: self.path.clone(),
+                        reason: error.to_string(),
+                    }))
+                }
+            }
+        }
+    }
+
+    /// returns the error of a malformed row at the current line
+    fn malformed(&self, reason: String) -> GeoshardError {
+        GeoshardError::MalformedRow {
+            path: self.path.clone(),
+            line: self.line,
+            reason,
+        }
+    }
+
+    /// returns the next user, parsing each row's location with `parse`
+    fn next_user<F>(&mut self, parse: F) -> Option<LoadedUser>
+    where
+        F: Fn(&str) -> Result<(f64, f64), String>,
+    {
+        if self.report.borrow().error.is_some() {
+            return None;
+        }
+        loop {
+            let row = match self.next_line()? {
+                Ok(row) => row,
+                Err(error) => {
+                    self.report.borrow_mut().error = Some(error);
+                    return None;
+                }
+            };
+            match parse(&row).and_then(|(lat, lng)| check_location(lat, lng)) {
+                Ok(point) => {
+                    self.report.borrow_mut().loaded += 1;
+                    return Some(LoadedUser {
+                        point,
+                        line: self.line,
+                    });
+                }
+                Err(reason) => {
+                    let error = self.malformed(reason);
+                    let mut report = self.report.borrow_mut();
+                    match self.policy {
+                        MalformedRowPolicy::Skip => report.skipped.push(error),
+                        MalformedRowPolicy::Stop => {
+                            report.error = Some(error);
+                            return None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// returns the point at the latitude and longitude, or why they aren't a location
+fn check_location(lat: f64, lng: f64) -> Result<GeoPoint, String> {
+    match (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lng) {
+        true => Ok(GeoPoint::new(lat, lng)),
+        false => Err(format!("location ({}, {}) is out of range", lat, lng)),
+    }
+}
+
+/// opens the file at `path` for a loader
+fn open(path: &Path) -> Result<BufReader<File>, GeoshardError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|error| GeoshardError::UserSourceUnavailable {
+            path: path.display().to_string(),
+            reason: error.to_string(),
+        })
+}
+
+/// the columns locating the users of a CSV file
+#[derive(Debug, Clone, Copy)]
+enum CsvColumns {
+    /// latitude and longitude columns
+    LatLng(usize, usize),
+    /// a column of WKT or EWKB points
+    Geometry(usize),
+}
+
+/// `CsvUserSource` reads users from a CSV file with a header row, locating each user by the
+/// latitude and longitude columns named in the header, or by a geometry column. Fields may be
+/// quoted, with `""` for a quote within a quoted field, but not span lines
+pub struct CsvUserSource<R = BufReader<File>> {
+    rows: Rows<R>,
+    delimiter: char,
+    columns: CsvColumns,
+}
+
+impl CsvUserSource {
+    /// Opens the CSV file at `path`, with the latitude and longitude in the columns named
+    /// `lat_column` and `lng_column`. Fails with `GeoshardError::UserSourceUnavailable` if the
+    /// file can't be read, and `GeoshardError::MalformedRow` if the header lacks either column
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        lat_column: &str,
+        lng_column: &str,
+    ) -> Result<Self, GeoshardError> {
+        let path = path.as_ref();
+        Self::with_delimiter(
+            open(path)?,
+            &path.display().to_string(),
+            lat_column,
+            lng_column,
+            ',',
+        )
+    }
+
+    /// Opens the CSV file at `path`, with each user's location in the geometry column named
+    /// `geometry_column`, as WKT or EWKB hex points (e.g. a PostGIS `COPY` of a table). Fails
+    /// like `new`
+    pub fn geometry<P: AsRef<Path>>(path: P, geometry_column: &str) -> Result<Self, GeoshardError> {
+        let path = path.as_ref();
+        Self::geometry_with_delimiter(
+            open(path)?,
+            &path.display().to_string(),
+            geometry_column,
+            ',',
+        )
+    }
+}
+
+impl<R: BufRead> CsvUserSource<R> {
+    /// Constructs a new `CsvUserSource` reading from `reader`, named `name` in errors, with
+    /// fields separated by `delimiter` (e.g. `'\t'` for TSV), see `new`
+    pub fn with_delimiter(
+        reader: R,
+        name: &str,
+        lat_column: &str,
+        lng_column: &str,
+        delimiter: char,
+    ) -> Result<Self, GeoshardError> {
+        let (rows, columns) =
+            Self::read_header(reader, name, &[lat_column, lng_column], delimiter)?;
+        Ok(Self {
+            rows,
+            delimiter,
+            columns: CsvColumns::LatLng(columns[0], columns[1]),
+        })
+    }
+
+    /// Constructs a new `CsvUserSource` reading from `reader`, named `name` in errors, with
+    /// fields separated by `delimiter`, see `geometry`
+    pub fn geometry_with_delimiter(
+        reader: R,
+        name: &str,
+        geometry_column: &str,
+        delimiter: char,
+    ) -> Result<Self, GeoshardError> {
+        let (rows, columns) = Self::read_header(reader, name, &[geometry_column], delimiter)?;
+        Ok(Self {
+            rows,
+            delimiter,
+            columns: CsvColumns::Geometry(columns[0]),
+        })
+    }
+
+    /// reads the header, returning the rows after it and the index of each of the columns
+    fn read_header(
+        reader: R,
+        name: &str,
+        columns: &[&str],
+        delimiter: char,
+    ) -> Result<(Rows<R>, Vec<usize>), GeoshardError> {
+        let mut rows = Rows::new(reader, name.to_owned());
+        let header = match rows.next_line() {
+            Some(header) => split_csv(&header?, delimiter),
+            None => vec![],
+        };
+        let columns = columns
+            .iter()
+            .map(|name| {
+                header
+                    .iter()
+                    .position(|column| column == name)
+                    .ok_or_else(|| rows.malformed(format!("no {} column in the header", name)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((rows, columns))
+    }
+
+    /// sets what to do with rows that don't parse, stopping at the first one by default
+    pub fn with_policy(mut self, policy: MalformedRowPolicy) -> Self {
+        self.rows.policy = policy;
+        self
+    }
+
+    /// returns a handle to what has been loaded so far, which stays valid while the source is
+    /// consumed, e.g. by a builder
+    pub fn report(&self) -> Rc<RefCell<LoadReport>> {
+        self.rows.report.clone()
+    }
+}
+
+impl<R: BufRead> Iterator for CsvUserSource<R> {
+    type Item = LoadedUser;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (delimiter, columns) = (self.delimiter, self.columns);
+        self.rows.next_user(|row| {
+            let fields = split_csv(row, delimiter);
+            let field = |index: usize| {
+                fields
+                    .get(index)
+                    .ok_or_else(|| format!("missing column {}", index + 1))
+            };
+            let number = |index: usize| {
+                let value = field(index)?;
+                value
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| format!("{:?} in column {} is not a number", value, index + 1))
+            };
+            match columns {
+                CsvColumns::LatLng(lat_column, lng_column) => {
+                    Ok((number(lat_column)?, number(lng_column)?))
+                }
+                CsvColumns::Geometry(column) => parse_point(field(column)?),
+            }
+        })
+    }
+}
+
+/// splits a CSV row into its fields, unquoting quoted fields
+fn split_csv(row: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// `JsonlUserSource` reads users from a file of JSON objects, one per line, locating each user
+/// by its latitude and longitude fields. Fields starting with `/` are JSON pointers into nested
+/// objects, e.g. `/location/lat`. Numbers in strings, such as `"40.71"`, are read as numbers
+pub struct JsonlUserSource<R = BufReader<File>> {
+    rows: Rows<R>,
+    lat_field: String,
+    lng_field: String,
+}
+
+impl JsonlUserSource {
+    /// Opens the JSONL file at `path`, with the latitude and longitude in the fields
+    /// `lat_field` and `lng_field`. Fails with `GeoshardError::UserSourceUnavailable` if the file
+    /// can't be read
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        lat_field: &str,
+        lng_field: &str,
+    ) -> Result<Self, GeoshardError> {
+        let path = path.as_ref();
+        Ok(Self::from_reader(
+            open(path)?,
+            &path.display().to_string(),
+            lat_field,
+            lng_field,
+        ))
+    }
+}
+
+impl<R: BufRead> JsonlUserSource<R> {
+    /// Constructs a new `JsonlUserSource` reading from `reader`, named `name` in errors, see
+    /// `new`
+    pub fn from_reader(reader: R, name: &str, lat_field: &str, lng_field: &str) -> Self {
+        Self {
+            rows: Rows::new(reader, name.to_owned()),
+            lat_field: lat_field.to_owned(),
+            lng_field: lng_field.to_owned(),
+        }
+    }
+
+    /// sets what to do with rows that don't parse, stopping at the first one by default
+    pub fn with_policy(mut self, policy: MalformedRowPolicy) -> Self {
+        self.rows.policy = policy;
+        self
+    }
+
+    /// returns a handle to what has been loaded so far, which stays valid while the source is
+    /// consumed, e.g. by a builder
+    pub fn report(&self) -> Rc<RefCell<LoadReport>> {
+        self.rows.report.clone()
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlUserSource<R> {
+    type Item = LoadedUser;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (lat_field, lng_field) = (&self.lat_field, &self.lng_field);
+        self.rows.next_user(|row| {
+            let object: Value = serde_json::from_str(row).map_err(|error| error.to_string())?;
+            let field = |name: &str| {
+                let value = match name.starts_with('/') {
+                    true => object.pointer(name),
+                    false => object.get(name),
+                }
+                .ok_or_else(|| format!("missing field {}", name))?;
+                match value {
+                    Value::Number(number) => number.as_f64(),
+                    Value::String(string) => string.trim().parse().ok(),
+                    _ => None,
+                }
+                .ok_or_else(|| format!("field {} is not a number: {}", name, value))
+            };
+            Ok((field(lat_field)?, field(lng_field)?))
+        })
+    }
+}
+
+/// `WktUserSource` reads users from a file of points, one per line, as WKT (`POINT(lng lat)`,
+/// optionally with an `SRID=4326;` prefix) or EWKB hex, e.g. the output of
+/// `psql -At -c "SELECT ST_AsEWKT(location) FROM users"`
+pub struct WktUserSource<R = BufReader<File>> {
+    rows: Rows<R>,
+}
+
+impl WktUserSource {
+    /// Opens the file of points at `path`. Fails with `GeoshardError::UserSourceUnavailable` if
+    /// the file can't be read
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, GeoshardError> {
+        let path = path.as_ref();
+        Ok(Self::from_reader(open(path)?, &path.display().to_string()))
+    }
+}
+
+impl<R: BufRead> WktUserSource<R> {
+    /// Constructs a new `WktUserSource` reading from `reader`, named `name` in errors
+    pub fn from_reader(reader: R, name: &str) -> Self {
+        Self {
+            rows: Rows::new(reader, name.to_owned()),
+        }
+    }
+
+    /// sets what to do with rows that don't parse, stopping at the first one by default
+    pub fn with_policy(mut self, policy: MalformedRowPolicy) -> Self {
+        self.rows.policy = policy;
+        self
+    }
+
+    /// returns a handle to what has been loaded so far, which stays valid while the source is
+    /// consumed, e.g. by a builder
+    pub fn report(&self) -> Rc<RefCell<LoadReport>> {
+        self.rows.report.clone()
+    }
+}
+
+impl<R: BufRead> Iterator for WktUserSource<R> {
+    type Item = LoadedUser;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next_user(parse_point)
+    }
+}
+
+/// returns the latitude and longitude of a WKT or EWKB hex point
+fn parse_point(value: &str) -> Result<(f64, f64), String> {
+    let value = value.trim();
+    let is_hex = !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_hexdigit());
+    let (srid, lng, lat) = match is_hex {
+        true => parse_ewkb(value)?,
+        false => parse_wkt(value)?,
+    };
+    match srid {
+        None | Some(4326) => Ok((lat, lng)),
+        Some(srid) => Err(format!(
+            "SRID {} is not WGS 84 longitude and latitude (4326)",
+            srid
+        )),
+    }
+}
+
+/// returns the SRID and coordinates of a WKT point, such as `SRID=4326;POINT Z (2.35 48.86 35)`
+fn parse_wkt(value: &str) -> Result<(Option<u32>, f64, f64), String> {
+    let (srid, geometry) = match value.split_once(';') {
+        Some((srid, geometry)) => {
+            let srid = srid
+                .trim()
+                .strip_prefix("SRID=")
+                .and_then(|srid| srid.parse().ok())
+                .ok_or_else(|| format!("invalid SRID in {:?}", value))?;
+            (Some(srid), geometry.trim())
+        }
+        None => (None, value),
+    };
+    let coordinates = geometry
+        .get(..5)
+        .filter(|kind| kind.eq_ignore_ascii_case("POINT"))
+        .and_then(|_| geometry[5..].trim_start().split_once('('))
+        .filter(|(dimensions, _)| {
+            ["", "Z", "M", "ZM"]
+                .iter()
+                .any(|expected| dimensions.trim().eq_ignore_ascii_case(expected))
+        })
+        .and_then(|(_, coordinates)| coordinates.trim_end().strip_suffix(')'))
+        .ok_or_else(|| format!("{:?} is not a WKT point", value))?;
+    let numbers: Vec<f64> = coordinates
+        .split_whitespace()
+        .map(|number| number.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid coordinates in {:?}", value))?;
+    match numbers[..] {
+        [lng, lat, ..] if numbers.len() <= 4 => Ok((srid, lng, lat)),
+        _ => Err(format!("invalid coordinates in {:?}", value)),
+    }
+}
+
+/// returns the SRID and coordinates of an EWKB (or WKB) point in hex, as PostGIS writes
+/// geometries in text
+fn parse_ewkb(value: &str) -> Result<(Option<u32>, f64, f64), String> {
+    if !value.len().is_multiple_of(2) {
+        return Err("EWKB hex has an odd number of digits".to_owned());
+    }
+    let bytes: Vec<u8> = (0..value.len() / 2)
+        .map(|index| u8::from_str_radix(&value[2 * index..2 * index + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|error| error.to_string())?;
+    let little_endian = match bytes.first() {
+        Some(0) => false,
+        Some(1) => true,
+        _ => return Err("invalid EWKB byte order".to_owned()),
+    };
+    let mut offset = 1;
+    let mut word = |size: usize| -> Result<u64, String> {
+        let mut word = [0u8; 8];
+        let chunk = bytes
+            .get(offset..offset + size)
+            .ok_or_else(|| "truncated EWKB".to_owned())?;
+        offset += size;
+        match little_endian {
+            true => word[..size].copy_from_slice(chunk),
+            false => word[8 - size..].copy_from_slice(chunk),
+        }
+        Ok(match little_endian {
+            true => u64::from_le_bytes(word),
+            false => u64::from_be_bytes(word),
+        })
+    };
+    let geometry_type = word(4)? as u32;
+    // EWKB flags dimensions and the SRID in the high bits, ISO WKB adds 1000s to the type
+    if (geometry_type & 0xffff) % 1000 != 1 {
+        return Err(format!(
+            "EWKB geometry type {} is not a point",
+            geometry_type
+        ));
+    }
+    let srid = match geometry_type & 0x2000_0000 {
+        0 => None,
+        _ => Some(word(4)? as u32),
+    };
+    let lng = f64::from_bits(word(8)?);
+    let lat = f64::from_bits(word(8)?);
+    match lng.is_nan() || lat.is_nan() {
+        true => Err("empty point".to_owned()),
+        false => Ok((srid, lng, lat)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::users::User;
+
+    #[test]
+    fn test_csv_user_source() {
+        let csv = "id,name,lat,lng\n\
+                   1,\"Smith, \"\"Jo\"\"\",40.7128,-74.0060\n\
+                   \n\
+                   2,Ana,not a number,2.3522\n\
+                   3,Lee,51.5074,-0.1278\n\
+                   4,Kim,95.0,10.0\n";
+        let users: Vec<LoadedUser> =
+            CsvUserSource::with_delimiter(Cursor::new(csv), "users.csv", "lat", "lng", ',')
+                .unwrap()
+                .collect();
+        // stops at the first malformed row by default
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].point(), &GeoPoint::new(40.7128, -74.0060));
+
+        let source =
+            CsvUserSource::with_delimiter(Cursor::new(csv), "users.csv", "lat", "lng", ',')
+                .unwrap()
+                .with_policy(MalformedRowPolicy::Skip);
+        let report = source.report();
+        let users: Vec<LoadedUser> = source.collect();
+        assert_eq!(
+            users.iter().map(LoadedUser::line).collect::<Vec<_>>(),
+            vec![2, 5]
+        );
+        assert_eq!(users[1].location().lat.deg(), 51.5074);
+        let report = report.borrow();
+        assert_eq!(report.loaded, 2);
+        assert!(report.error.is_none());
+        assert!(matches!(
+            &report.skipped[..],
+            [
+                GeoshardError::MalformedRow { line: 4, .. },
+                GeoshardError::MalformedRow { line: 6, .. }
+            ]
+        ));
+
+        let tsv = "lng\tlat\n2.3522\t48.8566\n";
+        let users: Vec<LoadedUser> =
+            CsvUserSource::with_delimiter(Cursor::new(tsv), "users.tsv", "lat", "lng", '\t')
+                .unwrap()
+                .collect();
+        assert_eq!(users[0].point(), &GeoPoint::new(48.8566, 2.3522));
+        assert!(matches!(
+            CsvUserSource::with_delimiter(Cursor::new(tsv), "users.tsv", "latitude", "lng", '\t'),
+            Err(GeoshardError::MalformedRow { line: 1, .. })
+        ));
+        assert!(matches!(
+            CsvUserSource::new("/nonexistent/users.csv", "lat", "lng"),
+            Err(GeoshardError::UserSourceUnavailable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_wkt_user_source() {
+        let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{:02X}", b)).collect() };
+        // SRID=4326;POINT(2.3522 48.8566) as PostGIS writes it, and a big endian WKB point
+        let ewkb = format!(
+            "0101000020E6100000{}{}",
+            hex(&2.3522f64.to_le_bytes()),
+            hex(&48.8566f64.to_le_bytes())
+        );
+        let wkb = format!(
+            "0000000001{}{}",
+            hex(&139.6503f64.to_be_bytes()),
+            hex(&35.6762f64.to_be_bytes())
+        );
+        let points = format!(
+            "POINT(-74.006 40.7128)\n\
+             SRID=4326;point z (-0.1278 51.5074 11)\n\
+             {}\n\
+             {}\n\
+             SRID=3857;POINT(-8238310 4970072)\n\
+             POINT EMPTY\n\
+             LINESTRING(0 0, 1 1)\n",
+            ewkb, wkb
+        );
+        let source = WktUserSource::from_reader(Cursor::new(points), "points.txt")
+            .with_policy(MalformedRowPolicy::Skip);
+        let report = source.report();
+        let users: Vec<LoadedUser> = source.collect();
+        assert_eq!(
+            users
+                .iter()
+                .map(|user| user.point().clone())
+                .collect::<Vec<_>>(),
+            vec![
+                GeoPoint::new(40.7128, -74.006),
+                GeoPoint::new(51.5074, -0.1278),
+                GeoPoint::new(48.8566, 2.3522),
+                GeoPoint::new(35.6762, 139.6503),
+            ]
+        );
+        assert_eq!(report.borrow().skipped.len(), 3);
+        // a trailing half byte isn't dropped
+        assert_eq!(
+            parse_point(&format!("{}0", ewkb)),
+            Err("EWKB hex has an odd number of digits".to_owned())
+        );
+
+        let csv = format!(
+            "id,geom\n1,{}\n2,\"SRID=4326;POINT(2.3522 48.8566)\"\n",
+            ewkb
+        );
+        let users: Vec<LoadedUser> =
+            CsvUserSource::geometry_with_delimiter(Cursor::new(csv), "users.csv", "geom", ',')
+                .unwrap()
+                .collect();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].point(), users[1].point());
+    }
+
+    #[test]
+    fn test_jsonl_user_source() {
+        let jsonl = r#"{"id": 1, "lat": 40.7128, "lng": -74.0060}
+{"id": 2, "lat": "48.8566", "lng": "2.3522"}
+{"id": 3, "lat": 51.5074}
+not json
+"#;
+        let path =
+            std::env::temp_dir().join(format!("geoshard_test_users_{}.jsonl", std::process::id()));
+        std::fs::write(&path, jsonl).unwrap();
+        let source = JsonlUserSource::new(&path, "lat", "lng").unwrap();
+        let report = source.report();
+        assert_eq!(source.count(), 2);
+        assert!(matches!(
+            report.borrow().error,
+            Some(GeoshardError::MalformedRow { line: 3, .. })
+        ));
+
+        let source = JsonlUserSource::new(&path, "lat", "lng")
+            .unwrap()
+            .with_policy(MalformedRowPolicy::Skip);
+        let report = source.report();
+        assert_eq!(source.count(), 2);
+        assert_eq!(report.borrow().skipped.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+
+        let nested = r#"{"location": {"lat": 35.6762, "lng": 139.6503}}"#;
+        let users: Vec<LoadedUser> = JsonlUserSource::from_reader(
+            Cursor::new(nested),
+            "nested.jsonl",
+            "/location/lat",
+            "/location/lng",
+        )
+        .collect();
+        assert_eq!(users[0].point(), &GeoPoint::new(35.6762, 139.6503));
+    }
+}
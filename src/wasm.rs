@@ -0,0 +1,101 @@
+#![deny(missing_docs)]
+//! wasm contains `WasmShardRouter`, a lookup only wrapper around `GeoshardSearcher` for
+//! `wasm32-unknown-unknown` builds (e.g. `--no-default-features --features wasm`), so browsers
+//! can find their home shard from a downloaded shard map without a round trip. The crate doesn't
+//! export it to JavaScript: it only takes and returns numbers, strings and lists of strings, so
+//! callers write the bindings in their own crate, e.g. a `#[wasm_bindgen]` wrapper forwarding
+//! each method
+use crate::{
+    geoshard::{GeoshardCollection, GeoshardSearcher},
+    utils::ll,
+};
+
+/// `WasmShardRouter` routes locations, in degrees, against a shard map loaded from JSON
+#[derive(Debug)]
+pub struct WasmShardRouter {
+    searcher: GeoshardSearcher,
+}
+
+impl WasmShardRouter {
+    /// Loads the router from a JSON shard map, as `GeoshardCollection` serializes it. Fails with
+    /// a description of the problem if the map doesn't parse or verify
+    pub fn from_json(json: &str) -> Result<WasmShardRouter, String> {
+        let shards: GeoshardCollection =
+            serde_json::from_str(json).map_err(|error| error.to_string())?;
+        shards.verify().map_err(|error| error.to_string())?;
+        Ok(WasmShardRouter {
+            searcher: GeoshardSearcher::from(shards),
+        })
+    }
+
+    /// returns the name of the shard holding the location
+    pub fn home_shard(&self, lat: f64, lng: f64) -> Result<String, String> {
+        check_location(lat, lng)?;
+        self.searcher
+            .try_get_shard_from_location(&ll!(lng, lat))
+            .map(|shard| shard.name().to_owned())
+            .map_err(|error| error.to_string())
+    }
+
+    /// returns the names of the shards within `radius` meters of the location, each named once
+    pub fn shards_in_radius(&self, lat: f64, lng: f64, radius: u32) -> Result<Vec<String>, String> {
+        check_location(lat, lng)?;
+        let mut shards: Vec<String> = vec![];
+        for shard in self.searcher.get_shards_from_radius(&ll!(lng, lat), radius) {
+            if !shards.iter().any(|name| name == shard.name()) {
+                shards.push(shard.name().to_owned());
+            }
+        }
+        Ok(shards)
+    }
+
+    /// returns the number of shards in the map
+    pub fn shard_count(&self) -> u32 {
+        self.searcher.shards().shards().len() as u32
+    }
+}
+
+/// returns an error if the degrees are out of range
+fn check_location(lat: f64, lng: f64) -> Result<(), String> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return Err(format!("location ({}, {}) is out of range", lat, lng));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod test {
+    use super::*;
+    use crate::testing::uniform_collection;
+
+    #[test]
+    fn test_wasm_shard_router() {
+        let shards = uniform_collection(4, 64);
+        let json = serde_json::to_string(&shards).unwrap();
+        let searcher = GeoshardSearcher::from(shards);
+
+        let router = WasmShardRouter::from_json(&json).unwrap();
+        assert_eq!(router.shard_count(), 24);
+        assert_eq!(
+            router.home_shard(40.7128, -74.0060).unwrap(),
+            searcher
+                .get_shard_from_location(&ll!(-74.0060, 40.7128))
+                .name()
+        );
+        assert!(router.home_shard(0.0, 181.0).is_err());
+        let shards = router
+            .shards_in_radius(40.7128, -74.0060, 50_000_000)
+            .unwrap();
+        assert!(shards.len() > 1);
+
+        // radii are in meters, so 5 km around New York is only its own shard or a neighbor
+        let london = searcher
+            .get_shard_from_location(&ll!(-0.1278, 51.5074))
+            .name();
+        let shards = router.shards_in_radius(40.7128, -74.0060, 5_000).unwrap();
+        assert!(shards.contains(&router.home_shard(40.7128, -74.0060).unwrap()));
+        assert!(!shards.iter().any(|shard| shard == london));
+        assert!(shards.len() <= 2);
+        assert!(WasmShardRouter::from_json("{").is_err());
+    }
+}